@@ -70,6 +70,13 @@ fn open_and_cat(
     cat_reader(BufReader::new(f), opts, line_num, stdout)
 }
 
+/// Report a per-file error after flushing the output of earlier files, so the
+/// message appears after them rather than ahead of a partially buffered line.
+fn report_error(stdout: &mut impl Write, name: &str, e: &io::Error) {
+    let _ = stdout.flush();
+    eprintln!("cat: {name}: {e}");
+}
+
 fn run() -> i32 {
     let args: Vec<String> = env::args().collect();
     let mut opts = CatOptions {
@@ -132,7 +139,7 @@ fn run() -> i32 {
                 if e.kind() == io::ErrorKind::BrokenPipe {
                     return 0;
                 }
-                eprintln!("cat: stdin: {}", e);
+                report_error(&mut stdout, "stdin", &e);
                 exit_code = 1;
             }
         } else {
@@ -144,14 +151,14 @@ fn run() -> i32 {
                         if e.kind() == io::ErrorKind::BrokenPipe {
                             return 0;
                         }
-                        eprintln!("cat: stdin: {}", e);
+                        report_error(&mut stdout, "stdin", &e);
                         exit_code = 1;
                     }
                 } else if let Err(e) = open_and_cat(file, &opts, &mut line_num, &mut stdout) {
                     if e.kind() == io::ErrorKind::BrokenPipe {
                         return 0;
                     }
-                    eprintln!("cat: {}: {}", file, e);
+                    report_error(&mut stdout, file, &e);
                     exit_code = 1;
                 }
             }
@@ -162,7 +169,7 @@ fn run() -> i32 {
                 if e.kind() == io::ErrorKind::BrokenPipe {
                     return 0;
                 }
-                eprintln!("cat: {}: {}", file, e);
+                report_error(&mut stdout, file, &e);
                 exit_code = 1;
             }
         }
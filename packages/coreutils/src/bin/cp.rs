@@ -18,49 +18,68 @@ fn copy_file(src: &Path, dst: &Path) -> Result<(), String> {
     })
 }
 
-fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<(), String> {
+/// Copy a directory tree, recording a failure for each entry that cannot be
+/// copied and carrying on with the rest.
+fn copy_dir_recursive(src: &Path, dst: &Path, errors: &mut Vec<String>) {
     if !dst.exists() {
-        fs::create_dir(dst)
-            .map_err(|e| format!("cannot create directory '{}': {}", dst.display(), e))?;
+        if let Err(e) = fs::create_dir(dst) {
+            errors.push(format!(
+                "cannot create directory '{}': {}",
+                dst.display(),
+                e
+            ));
+            return;
+        }
     }
 
-    let entries = fs::read_dir(src)
-        .map_err(|e| format!("cannot read directory '{}': {}", src.display(), e))?;
+    let entries = match fs::read_dir(src) {
+        Ok(entries) => entries,
+        Err(e) => {
+            errors.push(format!("cannot read directory '{}': {}", src.display(), e));
+            return;
+        }
+    };
 
     for entry in entries {
-        let entry =
-            entry.map_err(|e| format!("error reading entry in '{}': {}", src.display(), e))?;
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(format!("error reading entry in '{}': {}", src.display(), e));
+                continue;
+            }
+        };
         let src_path = entry.path();
         let dst_path = dst.join(entry.file_name());
 
         if src_path.is_dir() {
-            copy_dir_recursive(&src_path, &dst_path)?;
-        } else {
-            copy_file(&src_path, &dst_path)?;
+            copy_dir_recursive(&src_path, &dst_path, errors);
+        } else if let Err(e) = copy_file(&src_path, &dst_path) {
+            errors.push(e);
         }
     }
-
-    Ok(())
 }
 
-fn copy_source(src: &Path, dst: &Path, opts: &Options) -> Result<(), String> {
+/// Copy one source operand, returning every failure (empty on success).
+fn copy_source(src: &Path, dst: &Path, opts: &Options) -> Vec<String> {
     if !src.exists() {
-        return Err(format!(
+        return vec![format!(
             "cannot stat '{}': No such file or directory",
             src.display()
-        ));
+        )];
     }
 
     if src.is_dir() {
         if !opts.recursive {
-            return Err(format!(
+            return vec![format!(
                 "omitting directory '{}' (use -r to copy recursively)",
                 src.display()
-            ));
+            )];
         }
-        copy_dir_recursive(src, dst)
+        let mut errors = Vec::new();
+        copy_dir_recursive(src, dst, &mut errors);
+        errors
     } else {
-        copy_file(src, dst)
+        copy_file(src, dst).err().into_iter().collect()
     }
 }
 
@@ -116,7 +135,7 @@ fn main() {
                 src.file_name()
                     .unwrap_or_else(|| std::ffi::OsStr::new(src_arg)),
             );
-            if let Err(e) = copy_source(src, &target, &opts) {
+            for e in copy_source(src, &target, &opts) {
                 eprintln!("cp: {}", e);
                 exit_code = 1;
             }
@@ -132,7 +151,7 @@ fn main() {
         } else {
             dst.to_path_buf()
         };
-        if let Err(e) = copy_source(src, &target, &opts) {
+        for e in copy_source(src, &target, &opts) {
             eprintln!("cp: {}", e);
             exit_code = 1;
         }
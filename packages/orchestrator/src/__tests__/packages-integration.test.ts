@@ -254,6 +254,313 @@ print('ok')
     expect(result.stdout.trim()).toBe('ok');
   }, 30000);
 
+  it.skip('sqlite3 cursor close, factory and DB-API no-ops', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+conn = sqlite3.connect(':memory:')
+class MyCursor(sqlite3.Cursor):
+    pass
+cur = conn.cursor(factory=MyCursor)
+assert isinstance(cur, MyCursor), type(cur)
+assert cur.connection is conn
+cur.setinputsizes([10])
+cur.setoutputsize(10)
+cur.execute('SELECT 1')
+cur.close()
+try:
+    cur.fetchone()
+    raise AssertionError('fetchone on closed cursor succeeded')
+except sqlite3.ProgrammingError:
+    pass
+conn.close()
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 savepoints and transaction()', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+conn = sqlite3.connect(':memory:')
+conn.execute('CREATE TABLE t (x INTEGER)')
+conn.execute('BEGIN')
+conn.execute('INSERT INTO t VALUES (1)')
+conn.savepoint('inner')
+conn.execute('INSERT INTO t VALUES (2)')
+conn.rollback_to('inner')
+conn.release('inner')
+conn.commit()
+rows = conn.execute('SELECT x FROM t').fetchall()
+assert rows == [(1,)], f'savepoint: {rows}'
+with conn.transaction():
+    conn.execute('INSERT INTO t VALUES (3)')
+    try:
+        with conn.transaction():
+            conn.execute('INSERT INTO t VALUES (4)')
+            raise ValueError('boom')
+    except ValueError:
+        pass
+rows = conn.execute('SELECT x FROM t ORDER BY x').fetchall()
+assert rows == [(1,), (3,)], f'transaction: {rows}'
+txn = conn.transaction()
+conn.close()
+try:
+    with txn:
+        pass
+    raise AssertionError('transaction ran on a closed connection')
+except sqlite3.ProgrammingError:
+    pass
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 progress handler aborts a long query', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+conn = sqlite3.connect(':memory:')
+calls = []
+def handler():
+    calls.append(1)
+    return len(calls) >= 5
+conn.set_progress_handler(handler, 100)
+query = 'WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c LIMIT 10000000) SELECT count(*) FROM c'
+try:
+    conn.execute(query)
+    raise AssertionError('query was not aborted')
+except sqlite3.OperationalError as e:
+    assert 'interrupted' in str(e), e
+assert len(calls) == 5, len(calls)
+conn.set_progress_handler(None, 0)
+assert conn.execute('SELECT 1').fetchone() == (1,)
+conn.close()
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 lastrowid is only set by inserts', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+conn = sqlite3.connect(':memory:')
+conn.execute('CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, v TEXT)')
+cur = conn.execute('INSERT INTO t (v) VALUES (?)', ('a',))
+assert cur.lastrowid == 1, cur.lastrowid
+cur = conn.execute('INSERT INTO t (v) VALUES (?)', ('b',))
+assert cur.lastrowid == 2, cur.lastrowid
+assert conn.last_insert_rowid() == 2
+assert conn.execute('SELECT * FROM t').lastrowid is None
+assert conn.execute('UPDATE t SET v = ?', ('c',)).lastrowid is None
+assert conn.last_insert_rowid() == 2
+conn.close()
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 exposes version tuples and threadsafety', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+info = sqlite3.sqlite_version_info
+assert isinstance(info, tuple) and len(info) == 3, info
+assert all(isinstance(n, int) for n in info), info
+assert '.'.join(map(str, info)) == sqlite3.sqlite_version, (info, sqlite3.sqlite_version)
+assert sqlite3.version_info == (2, 6, 0), sqlite3.version_info
+assert sqlite3.version == '2.6.0'
+assert sqlite3.threadsafety == 1
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 blobopen reads and writes slices of a blob', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+conn = sqlite3.connect(':memory:')
+conn.execute('CREATE TABLE t (id INTEGER PRIMARY KEY, data BLOB)')
+conn.execute('INSERT INTO t VALUES (1, zeroblob(10))')
+with conn.blobopen('t', 'data', 1) as blob:
+    blob.write(b'hello')
+    assert blob.tell() == 5, blob.tell()
+    blob.seek(-3, 2)
+    blob.write(b'xyz')
+    blob.seek(0)
+    assert blob.read(5) == b'hello'
+    assert blob.read() == b'\\x00\\x00xyz'
+    try:
+        blob.write(b'!')
+        raise AssertionError('write past end succeeded')
+    except ValueError:
+        pass
+row = conn.execute('SELECT data FROM t').fetchone()
+assert row == (b'hello\\x00\\x00xyz',), row
+blob = conn.blobopen('t', 'data', 1, readonly=True)
+blob.seek(1)
+assert blob.read(4) == b'ello'
+blob.close()
+try:
+    blob.read()
+    raise AssertionError('read on closed blob succeeded')
+except sqlite3.ProgrammingError:
+    pass
+conn.close()
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 detect_types runs registered converters', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+sqlite3.register_converter('bool', lambda b: b == b'1')
+conn = sqlite3.connect(':memory:', detect_types=sqlite3.PARSE_DECLTYPES | sqlite3.PARSE_COLNAMES)
+conn.execute('CREATE TABLE t (flag BOOL, n INTEGER)')
+conn.execute('INSERT INTO t VALUES (1, 1), (0, 0), (NULL, NULL)')
+rows = conn.execute('SELECT flag, n FROM t').fetchall()
+assert rows == [(True, 1), (False, 0), (None, None)], rows
+cur = conn.execute('SELECT n AS \\"n [bool]\\" FROM t')
+assert cur.fetchone() == (True,)
+assert cur.description[0][0] == 'n', cur.description
+plain = sqlite3.connect(':memory:')
+plain.execute('CREATE TABLE t (flag BOOL)')
+plain.execute('INSERT INTO t VALUES (1)')
+assert plain.execute('SELECT flag FROM t').fetchone() == (1,)
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 binds custom objects through registered adapters', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import datetime, sqlite3
+class Point:
+    def __init__(self, x, y):
+        self.x, self.y = x, y
+sqlite3.register_adapter(Point, lambda p: f'{p.x};{p.y}')
+conn = sqlite3.connect(':memory:')
+conn.execute('CREATE TABLE t (v)')
+conn.execute('INSERT INTO t VALUES (?)', (Point(1, 2),))
+conn.execute('INSERT INTO t VALUES (?)', (datetime.datetime(2024, 1, 2, 3, 4, 5),))
+conn.execute('INSERT INTO t VALUES (?)', (datetime.date(2024, 1, 2),))
+rows = conn.execute('SELECT v FROM t').fetchall()
+assert rows == [('1;2',), ('2024-01-02 03:04:05',), ('2024-01-02',)], rows
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 binds bytearray/memoryview as blobs and Decimal as text', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+from decimal import Decimal
+conn = sqlite3.connect(':memory:')
+conn.execute('CREATE TABLE t (v)')
+conn.execute('INSERT INTO t VALUES (?)', (bytearray(b'\\x00ab'),))
+conn.execute('INSERT INTO t VALUES (?)', (memoryview(b'xyz')[1:],))
+conn.execute('INSERT INTO t VALUES (?)', (Decimal('19.990'),))
+rows = conn.execute('SELECT v, typeof(v) FROM t').fetchall()
+assert rows == [(b'\\x00ab', 'blob'), (b'yz', 'blob'), ('19.990', 'text')], rows
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 execute rejects multiple statements', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import sqlite3
+conn = sqlite3.connect(':memory:')
+cur = conn.cursor()
+try:
+    cur.execute('SELECT 1; SELECT 2')
+    raise AssertionError('expected ProgrammingError')
+except sqlite3.ProgrammingError:
+    pass
+assert cur.execute('SELECT 1; -- trailing comment').fetchall() == [(1,)]
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
+  it.skip('sqlite3 connections clean up on collection and reject use after close', async () => {
+    sandbox = await Sandbox.create({
+      wasmDir: WASM_DIR,
+      adapter: new NodeAdapter(),
+      packages: ['sqlite3'],
+    });
+    const result = await sandbox.run(`python3 -c "
+import gc, sqlite3
+leaked = sqlite3.connect(':memory:')
+leaked.execute('CREATE TABLE t (x)')
+leaked.cursor().execute('INSERT INTO t VALUES (1)')
+del leaked
+gc.collect()
+conn = sqlite3.connect(':memory:')
+cur = conn.cursor()
+assert repr(conn).startswith('<sqlite3.Connection object at 0x'), repr(conn)
+assert repr(cur).startswith('<sqlite3.Cursor object at 0x'), repr(cur)
+conn.close()
+conn.close()
+for op in (lambda: conn.execute('SELECT 1'), lambda: cur.execute('SELECT 1')):
+    try:
+        op()
+        raise AssertionError('operation after close succeeded')
+    except sqlite3.ProgrammingError:
+        pass
+print('ok')
+"`);
+    expect(result.stdout.trim()).toBe('ok');
+  }, 30000);
+
   it.skip('PILImage.new and size', async () => {
     sandbox = await Sandbox.create({
       wasmDir: WASM_DIR,
@@ -131,6 +131,7 @@ export class VfsProxy {
       mtime: new Date(metadata.mtime as string),
       ctime: new Date(metadata.ctime as string),
       atime: new Date(metadata.atime as string),
+      ino: metadata.ino as number | undefined,
     };
   }
 
@@ -143,6 +144,7 @@ export class VfsProxy {
       mtime: new Date(metadata.mtime as string),
       ctime: new Date(metadata.ctime as string),
       atime: new Date(metadata.atime as string),
+      ino: metadata.ino as number | undefined,
     };
   }
 
@@ -259,6 +259,7 @@ export class WorkerExecutor {
             mtime: st.mtime.toISOString(),
             ctime: st.ctime.toISOString(),
             atime: st.atime.toISOString(),
+            ino: st.ino,
           });
           Atomics.store(this.int32, 0, STATUS_RESPONSE);
           break;
@@ -272,6 +273,7 @@ export class WorkerExecutor {
             mtime: lst.mtime.toISOString(),
             ctime: lst.ctime.toISOString(),
             atime: lst.atime.toISOString(),
+            ino: lst.ino,
           });
           Atomics.store(this.int32, 0, STATUS_RESPONSE);
           break;
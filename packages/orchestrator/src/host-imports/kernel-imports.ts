@@ -13,7 +13,12 @@
  *
  *   Network / extensions:
  *   - host_network_fetch: HTTP fetch via NetworkBridge (async/JSPI)
+ *   - host_network_fetch_stream / host_network_stream_read / host_network_stream_close:
+ *     the same fetch with the body buffered host-side and handed out in chunks
+ *     (Python fetch_stream)
  *   - host_extension_invoke: call a host extension (Python only; shell uses host_spawn)
+ *   - host_is_extension: check whether a host extension is registered (Python only)
+ *   - host_extension_list: JSON array of registered extension names (Python only)
  *   - host_run_command: run a shell command and collect output (async/JSPI, Python subprocess)
  */
 
@@ -72,6 +77,29 @@ export function createKernelImports(opts: KernelImportsOptions): Record<string,
   const { memory } = opts;
   const callerPid = opts.callerPid ?? 0;
 
+  // Fully buffered bodies of chunked fetches, drained by host_network_stream_read.
+  const fetchStreams = new Map<number, { data: Uint8Array; offset: number }>();
+  let nextStreamId = 1;
+
+  // Run a fetch request (JSON from the guest) through the network bridge.
+  const runFetch = async (reqJson: string) => {
+    const req = JSON.parse(reqJson) as {
+      url?: string;
+      method?: string;
+      headers?: Record<string, string>;
+      body?: string;
+    };
+    const url = req.url as string;
+    const method = (req.method as string) ?? 'GET';
+    const headers = (req.headers as Record<string, string>) ?? {};
+    const body = req.body as string | undefined;
+
+    // Use async fetch if available (browser), otherwise fall back to sync (SAB bridge)
+    return opts.networkBridge!.fetchAsync
+      ? await opts.networkBridge!.fetchAsync(url, method, headers, body)
+      : opts.networkBridge!.fetchSync(url, method, headers, body);
+  };
+
   return {
     // ── Process management (new) ──
 
@@ -162,12 +190,9 @@ export function createKernelImports(opts: KernelImportsOptions): Record<string,
         return writeJson(memory, outPtr, outCap, { error: `not a readable fd: ${fd}` });
       }
       const data = target.pipe.drainSync();
-      const str = new TextDecoder().decode(data);
-      const buf = new Uint8Array(memory.buffer, outPtr, outCap);
-      const encoded = new TextEncoder().encode(str);
-      if (encoded.length > outCap) return encoded.length; // signal retry with larger buffer
-      buf.set(encoded);
-      return encoded.length;
+      if (data.length > outCap) return data.length; // signal retry with larger buffer
+      new Uint8Array(memory.buffer, outPtr, outCap).set(data);
+      return data.length;
     },
 
     // host_write_fd(fd, data_ptr, data_len) -> i32
@@ -273,21 +298,7 @@ export function createKernelImports(opts: KernelImportsOptions): Record<string,
       }
 
       try {
-        const req = JSON.parse(reqJson) as {
-          url?: string;
-          method?: string;
-          headers?: Record<string, string>;
-          body?: string;
-        };
-        const url = req.url as string;
-        const method = (req.method as string) ?? 'GET';
-        const headers = (req.headers as Record<string, string>) ?? {};
-        const body = req.body as string | undefined;
-
-        // Use async fetch if available (browser), otherwise fall back to sync (SAB bridge)
-        const result = opts.networkBridge.fetchAsync
-          ? await opts.networkBridge.fetchAsync(url, method, headers, body)
-          : opts.networkBridge.fetchSync(url, method, headers, body);
+        const result = await runFetch(reqJson);
         return writeJson(memory, outPtr, outCap, {
           ok: !result.error && result.status >= 200 && result.status < 400,
           status: result.status,
@@ -302,6 +313,64 @@ export function createKernelImports(opts: KernelImportsOptions): Record<string,
       }
     },
 
+    // host_network_fetch_stream(req_ptr, req_len, out_ptr, out_cap) -> i32
+    // Like host_network_fetch, but the body is parked host-side and the
+    // response carries a stream_id for host_network_stream_read instead.
+    // The network bridge only returns whole bodies, so the download has
+    // finished before the first chunk is read; nothing is streamed.
+    async host_network_fetch_stream(reqPtr: number, reqLen: number, outPtr: number, outCap: number): Promise<number> {
+      const reqJson = readString(memory, reqPtr, reqLen);
+
+      const fetchError = (error: string) =>
+        writeJson(memory, outPtr, outCap, { ok: false, status: 0, headers: {}, stream_id: null, error });
+
+      if (!opts.networkBridge) {
+        return fetchError('networking not configured');
+      }
+
+      try {
+        const result = await runFetch(reqJson);
+        const data = result.body_base64
+          ? Uint8Array.from(atob(result.body_base64), c => c.charCodeAt(0))
+          : new TextEncoder().encode(result.body ?? '');
+        const streamId = nextStreamId++;
+        fetchStreams.set(streamId, { data, offset: 0 });
+        return writeJson(memory, outPtr, outCap, {
+          ok: !result.error && result.status >= 200 && result.status < 400,
+          status: result.status,
+          headers: result.headers,
+          stream_id: streamId,
+          error: result.error ?? null,
+        });
+      } catch (e: unknown) {
+        const msg = e instanceof Error ? e.message : String(e);
+        return fetchError(msg);
+      }
+    },
+
+    // host_network_stream_read(stream_id, out_ptr, out_cap) -> i32
+    // Copies up to out_cap bytes of the body into the output buffer.
+    // Returns the byte count, 0 once the body is exhausted (the stream is
+    // then released), or -1 for an unknown stream.
+    host_network_stream_read(streamId: number, outPtr: number, outCap: number): number {
+      const stream = fetchStreams.get(streamId);
+      if (!stream) return -1;
+      const chunk = stream.data.subarray(stream.offset, stream.offset + outCap);
+      if (chunk.length === 0) {
+        fetchStreams.delete(streamId);
+        return 0;
+      }
+      new Uint8Array(memory.buffer, outPtr, chunk.length).set(chunk);
+      stream.offset += chunk.length;
+      return chunk.length;
+    },
+
+    // host_network_stream_close(stream_id) -> i32
+    // Drops a stream the guest stopped reading. Returns 0, or -1 if unknown.
+    host_network_stream_close(streamId: number): number {
+      return fetchStreams.delete(streamId) ? 0 : -1;
+    },
+
     // ── Native module bridge ──
 
     // host_native_invoke(module_ptr, module_len, method_ptr, method_len,
@@ -492,6 +561,22 @@ export function createKernelImports(opts: KernelImportsOptions): Record<string,
       });
     },
 
+    // host_is_extension(name_ptr, name_len) -> i32
+    // Returns 1 if the named extension is registered, 0 otherwise.
+    // Python's _codepod.is_extension() caches the answer per interpreter.
+    host_is_extension(namePtr: number, nameLen: number): number {
+      const name = readString(memory, namePtr, nameLen);
+      return opts.extensionRegistry?.has(name) ? 1 : 0;
+    },
+
+    // host_extension_list(out_ptr, out_cap) -> i32
+    // Writes a JSON array of registered extension names.
+    // Used by Python's _codepod.extension_list().
+    host_extension_list(outPtr: number, outCap: number): number {
+      const names = opts.extensionRegistry?.list().map((ext) => ext.name) ?? [];
+      return writeJson(memory, outPtr, outCap, names);
+    },
+
     // host_run_command(req_ptr, req_len, out_ptr, out_cap) -> i32 (async/JSPI)
     // Runs a shell command and captures output. Used by Python _codepod.spawn().
     async host_run_command(
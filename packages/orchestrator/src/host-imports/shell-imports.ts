@@ -210,6 +210,7 @@ export function createShellImports(opts: ShellImportsOptions): Record<string, We
           size: s.size,
           mode: s.permissions,
           mtime_ms: s.mtime ? s.mtime.getTime() : 0,
+          inode: s.ino ?? 0,
         };
         return writeJson(memory, outPtr, outCap, info);
       } catch {
@@ -89,6 +89,15 @@ describe('Coreutils Integration', () => {
       const result = await runner.run('echo hello | cat');
       expect(result.stdout).toBe('hello\n');
     });
+
+    it('prints the files it can read and reports the missing one', async () => {
+      vfs.writeFile('/home/user/a.txt', new TextEncoder().encode('aaa\n'));
+      vfs.writeFile('/home/user/b.txt', new TextEncoder().encode('bbb\n'));
+      const result = await runner.run('cat a.txt missing.txt b.txt');
+      expect(result.exitCode).toBe(1);
+      expect(result.stdout).toBe('aaa\nbbb\n');
+      expect(result.stderr).toContain('cat: missing.txt: No such file or directory');
+    });
   });
 
   describe('head', () => {
@@ -245,6 +254,17 @@ describe('Coreutils Integration', () => {
       expect(new TextDecoder().decode(vfs.readFile('/home/user/dst.txt'))).toBe('content');
     });
 
+    it('copies the remaining sources when one is missing', async () => {
+      vfs.writeFile('/home/user/one.txt', new TextEncoder().encode('1'));
+      vfs.writeFile('/home/user/two.txt', new TextEncoder().encode('2'));
+      vfs.mkdir('/home/user/out');
+      const result = await runner.run('cp one.txt nope.txt two.txt out');
+      expect(result.exitCode).toBe(1);
+      expect(result.stderr).toContain("cp: cannot stat 'nope.txt'");
+      expect(new TextDecoder().decode(vfs.readFile('/home/user/out/one.txt'))).toBe('1');
+      expect(new TextDecoder().decode(vfs.readFile('/home/user/out/two.txt'))).toBe('2');
+    });
+
     it('moves a file', async () => {
       vfs.writeFile('/home/user/old.txt', new TextEncoder().encode('data'));
       await runner.run('mv /home/user/old.txt /home/user/new.txt');
@@ -1212,6 +1212,10 @@ function spawnAsyncProcess(
     imports.codepod.host_network_fetch = wrapAsyncImport(
       childKernelImports.host_network_fetch as (...args: number[]) => Promise<number>,
     );
+    // Python uses host_network_fetch_stream for _codepod.fetch_stream()
+    imports.codepod.host_network_fetch_stream = wrapAsyncImport(
+      childKernelImports.host_network_fetch_stream as (...args: number[]) => Promise<number>,
+    );
     // Python uses host_extension_invoke for _codepod.extension_call()
     imports.codepod.host_extension_invoke = wrapAsyncImport(
       childKernelImports.host_extension_invoke as (...args: number[]) => Promise<number>,
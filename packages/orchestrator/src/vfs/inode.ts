@@ -55,6 +55,8 @@ export interface StatResult {
   mtime: Date;
   ctime: Date;
   atime: Date;
+  /** Stable identity of the underlying inode; absent for virtual providers. */
+  ino?: number;
 }
 
 export interface DirEntry {
@@ -22,6 +22,23 @@ import { ProcProvider } from './proc-provider.js';
 
 const MAX_SYMLINK_DEPTH = 40;
 
+/**
+ * Inode numbers are assigned lazily on first stat and keyed by object
+ * identity, so a number survives renames and is shared by every path
+ * (including symlinks) that reaches the same inode.
+ */
+const inodeNumbers = new WeakMap<Inode, number>();
+let nextInodeNumber = 1;
+
+function inodeNumber(inode: Inode): number {
+  let ino = inodeNumbers.get(inode);
+  if (ino === undefined) {
+    ino = nextInodeNumber++;
+    inodeNumbers.set(inode, ino);
+  }
+  return ino;
+}
+
 export interface VfsOptions {
   /** Maximum total bytes stored in the VFS. Undefined = no limit. */
   fsLimitBytes?: number;
@@ -441,6 +458,7 @@ export class VFS {
       mtime: metadata.mtime,
       ctime: metadata.ctime,
       atime: metadata.atime,
+      ino: inodeNumber(inode),
     };
   }
 
@@ -470,6 +488,7 @@ export class VFS {
       mtime: metadata.mtime,
       ctime: metadata.ctime,
       atime: metadata.atime,
+      ino: inodeNumber(inode),
     };
   }
 
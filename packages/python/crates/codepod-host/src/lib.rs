@@ -6,8 +6,14 @@
 //!
 //! Functions:
 //! - `_codepod.fetch(method, url, headers=None, body=None)` -> dict
+//! - `_codepod.fetch_stream(method, url, on_chunk, headers=None, body=None)` -> dict
 //! - `_codepod.extension_call(extension, method, **kwargs)` -> result
-//! - `_codepod.extension_call(extension, method, **kwargs)` -> result (also checks existence)
+//! - `_codepod.is_extension(name)` -> bool (cached per interpreter)
+//! - `_codepod.clear_extension_cache()`
+//! - `_codepod.extension_list()` -> list[str]
+
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 use rustpython_vm as vm;
 use vm::AsObject;
@@ -23,6 +29,24 @@ extern "C" {
     /// Returns bytes written to out_ptr, or negative error code.
     fn host_network_fetch(req_ptr: *const u8, req_len: u32, out_ptr: *mut u8, out_cap: u32) -> i32;
 
+    /// Start a chunked fetch. Same request JSON as `host_network_fetch`;
+    /// the host buffers the whole body and the response JSON carries a
+    /// `stream_id` for reading it back in place of the body.
+    fn host_network_fetch_stream(
+        req_ptr: *const u8,
+        req_len: u32,
+        out_ptr: *mut u8,
+        out_cap: u32,
+    ) -> i32;
+
+    /// Copy the next body chunk of a stream to out_ptr. Returns bytes
+    /// written, 0 once the body is exhausted (releasing the stream), or a
+    /// negative error code.
+    fn host_network_stream_read(stream_id: u32, out_ptr: *mut u8, out_cap: u32) -> i32;
+
+    /// Release a stream whose body was not read to the end.
+    fn host_network_stream_close(stream_id: u32) -> i32;
+
     /// Invoke a host extension method. Request is JSON, response is JSON.
     fn host_extension_invoke(
         req_ptr: *const u8,
@@ -55,6 +79,13 @@ extern "C" {
 
     /// Run a shell command and collect output (for Python subprocess).
     fn host_run_command(req_ptr: *const u8, req_len: u32, out_ptr: *mut u8, out_cap: u32) -> i32;
+
+    /// Check whether a host extension is registered. Returns 1 if so, 0 if not.
+    fn host_is_extension(name_ptr: *const u8, name_len: u32) -> i32;
+
+    /// List registered host extensions as a JSON array of names.
+    /// Returns bytes written to out_ptr, or the required size if too small.
+    fn host_extension_list(out_ptr: *mut u8, out_cap: u32) -> i32;
 }
 
 // ---------------------------------------------------------------------------
@@ -106,6 +137,39 @@ fn call_host_json(import_fn: HostJsonFn, request: &str) -> Result<String, String
     String::from_utf8(out_buf).map_err(|e| format!("invalid UTF-8 in response: {}", e))
 }
 
+/// Largest body chunk `fetch_stream` asks the host for at a time.
+const STREAM_CHUNK_SIZE: usize = 65536;
+
+/// Pull a body from the host chunk by chunk. `read` fills the buffer and
+/// returns the byte count (0 at the end, negative on a host error); each
+/// chunk goes to `on_chunk`, and the first error from either side stops
+/// the loop.
+fn pull_chunks<E>(
+    mut read: impl FnMut(&mut [u8]) -> i32,
+    mut on_chunk: impl FnMut(&[u8]) -> Result<(), E>,
+    read_error: impl Fn(i32) -> E,
+) -> Result<(), E> {
+    let mut buf = vec![0u8; STREAM_CHUNK_SIZE];
+    loop {
+        match read(&mut buf) {
+            0 => return Ok(()),
+            rc if rc < 0 => return Err(read_error(rc)),
+            n => on_chunk(&buf[..(n as usize).min(buf.len())])?,
+        }
+    }
+}
+
+/// Adapt `host_extension_list` (no request) to `call_host_json`'s retry logic.
+#[cfg(target_arch = "wasm32")]
+unsafe extern "C" fn extension_list_json(
+    _req_ptr: *const u8,
+    _req_len: u32,
+    out_ptr: *mut u8,
+    out_cap: u32,
+) -> i32 {
+    host_extension_list(out_ptr, out_cap)
+}
+
 // ---------------------------------------------------------------------------
 // JSON helpers — minimal manual builders to avoid serde dependency
 // ---------------------------------------------------------------------------
@@ -197,13 +261,37 @@ fn py_to_json(obj: &vm::PyObjectRef, py_vm: &vm::VirtualMachine) -> String {
 }
 
 /// Parse a JSON response string into a Python object using the `json` stdlib module.
+///
+/// A body that is not valid JSON raises RuntimeError naming `what` and
+/// quoting the start of the body, rather than a bare JSONDecodeError.
 #[cfg(target_arch = "wasm32")]
-fn json_to_py(json_str: &str, py_vm: &vm::VirtualMachine) -> vm::PyResult<vm::PyObjectRef> {
+fn json_to_py(
+    what: &str,
+    json_str: &str,
+    py_vm: &vm::VirtualMachine,
+) -> vm::PyResult<vm::PyObjectRef> {
     // Import json module and call json.loads(json_str)
     let json_mod = py_vm.import("json", 0)?;
     let loads_fn = json_mod.get_attr("loads", py_vm)?;
     let py_str: vm::PyObjectRef = py_vm.ctx.new_str(json_str).into();
-    loads_fn.call((py_str,), py_vm)
+    loads_fn.call((py_str,), py_vm).map_err(|_| {
+        py_vm.new_exception_msg(
+            py_vm.ctx.exceptions.runtime_error.to_owned(),
+            non_json_message(what, json_str),
+        )
+    })
+}
+
+/// How many characters of a non-JSON response to quote in the error.
+const RESPONSE_PREVIEW_CHARS: usize = 200;
+
+/// Error message for a host response that failed to parse as JSON.
+fn non_json_message(what: &str, response: &str) -> String {
+    let mut preview: String = response.chars().take(RESPONSE_PREVIEW_CHARS).collect();
+    if response.chars().nth(RESPONSE_PREVIEW_CHARS).is_some() {
+        preview.push_str("...");
+    }
+    format!("{what} failed: host returned a non-JSON response: {preview:?}")
 }
 
 // ---------------------------------------------------------------------------
@@ -215,6 +303,27 @@ fn os_err(py_vm: &vm::VirtualMachine, msg: &str) -> vm::PyRef<vm::builtins::PyBa
     py_vm.new_exception_msg(py_vm.ctx.exceptions.os_error.to_owned(), msg.to_owned())
 }
 
+/// Build the request JSON shared by `fetch` and `fetch_stream`.
+fn fetch_request_json(
+    method: &str,
+    url: &str,
+    headers: &vm::function::OptionalArg<vm::PyObjectRef>,
+    body: &vm::function::OptionalArg<vm::PyObjectRef>,
+    py_vm: &vm::VirtualMachine,
+) -> String {
+    let to_json = |arg: &vm::function::OptionalArg<vm::PyObjectRef>| match arg {
+        vm::function::OptionalArg::Present(v) if !py_vm.is_none(v) => py_to_json(v, py_vm),
+        _ => "null".to_string(),
+    };
+    format!(
+        "{{\"method\":\"{}\",\"url\":\"{}\",\"headers\":{},\"body\":{}}}",
+        json_escape(method),
+        json_escape(url),
+        to_json(headers),
+        to_json(body),
+    )
+}
+
 /// Extract a string value from a JSON response by key (minimal parser).
 fn extract_json_string(json: &str, key: &str) -> Option<String> {
     let pattern = format!("\"{}\":\"", key);
@@ -246,6 +355,79 @@ fn extract_json_number(json: &str, key: &str) -> Option<i64> {
     rest[..end].parse().ok()
 }
 
+/// Parse a flat JSON array of strings, e.g. `["pdf","ocr"]` (minimal parser).
+fn parse_json_string_list(json: &str) -> Option<Vec<String>> {
+    let mut chars = json.trim().chars().peekable();
+    if chars.next()? != '[' {
+        return None;
+    }
+    let mut items = Vec::new();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        match chars.next()? {
+            ']' if items.is_empty() => break,
+            '"' => {}
+            _ => return None,
+        }
+        let mut item = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    'n' => item.push('\n'),
+                    't' => item.push('\t'),
+                    'r' => item.push('\r'),
+                    'b' => item.push('\u{8}'),
+                    'f' => item.push('\u{c}'),
+                    'u' => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        let code = u32::from_str_radix(&hex, 16).ok()?;
+                        item.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    other => item.push(other),
+                },
+                c => item.push(c),
+            }
+        }
+        items.push(item);
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        match chars.next()? {
+            ',' => continue,
+            ']' => break,
+            _ => return None,
+        }
+    }
+    Some(items)
+}
+
+// ---------------------------------------------------------------------------
+// Extension lookup cache
+// ---------------------------------------------------------------------------
+
+thread_local! {
+    /// Extension name -> registered. Each interpreter runs in its own WASM
+    /// instance, so a thread-local is per interpreter.
+    static EXTENSION_CACHE: RefCell<HashMap<String, bool>> = RefCell::new(HashMap::new());
+}
+
+/// Return the cached answer for `name`, calling `lookup` only on a miss.
+fn cached_is_extension(name: &str, lookup: impl FnOnce(&str) -> bool) -> bool {
+    if let Some(known) = EXTENSION_CACHE.with(|c| c.borrow().get(name).copied()) {
+        return known;
+    }
+    let found = lookup(name);
+    EXTENSION_CACHE.with(|c| c.borrow_mut().insert(name.to_string(), found));
+    found
+}
+
+fn clear_extension_cache() {
+    EXTENSION_CACHE.with(|c| c.borrow_mut().clear());
+}
+
 // ---------------------------------------------------------------------------
 // Base64 encode/decode — minimal implementation to avoid extra dependencies
 // ---------------------------------------------------------------------------
@@ -326,25 +508,8 @@ pub mod _codepod {
         body: vm::function::OptionalArg<vm::PyObjectRef>,
         py_vm: &VirtualMachine,
     ) -> PyResult<vm::PyObjectRef> {
-        // Build headers JSON
-        let headers_json = match headers {
-            vm::function::OptionalArg::Present(ref h) if !py_vm.is_none(h) => py_to_json(h, py_vm),
-            _ => "null".to_string(),
-        };
-
-        // Build body JSON
-        let body_json = match body {
-            vm::function::OptionalArg::Present(ref b) if !py_vm.is_none(b) => py_to_json(b, py_vm),
-            _ => "null".to_string(),
-        };
-
-        let request_json = format!(
-            "{{\"method\":\"{}\",\"url\":\"{}\",\"headers\":{},\"body\":{}}}",
-            json_escape(method.as_str()),
-            json_escape(url.as_str()),
-            headers_json,
-            body_json,
-        );
+        let request_json =
+            fetch_request_json(method.as_str(), url.as_str(), &headers, &body, py_vm);
 
         #[cfg(target_arch = "wasm32")]
         {
@@ -354,7 +519,7 @@ pub mod _codepod {
                     format!("fetch failed: {}", e),
                 )
             })?;
-            json_to_py(&response_str, py_vm)
+            json_to_py("fetch", &response_str, py_vm)
         }
 
         #[cfg(not(target_arch = "wasm32"))]
@@ -367,6 +532,72 @@ pub mod _codepod {
         }
     }
 
+    /// Fetch a URL, handing the body to `on_chunk(bytes)` in chunks of at
+    /// most 64 KiB instead of returning it in one piece.
+    ///
+    /// This is not incremental download: the host fetches and buffers the
+    /// whole body before the first chunk arrives. What it saves is copying
+    /// the body into the guest's response buffer all at once.
+    ///
+    /// Usage: `_codepod.fetch_stream(method, url, on_chunk, headers=None, body=None) -> dict`
+    ///
+    /// Returns the same dict as `fetch()` without the body (plus the host's
+    /// `stream_id`). An exception from `on_chunk` abandons the download and
+    /// propagates. On non-WASM platforms, always raises RuntimeError.
+    #[pyfunction]
+    fn fetch_stream(
+        method: vm::builtins::PyStrRef,
+        url: vm::builtins::PyStrRef,
+        on_chunk: vm::PyObjectRef,
+        headers: vm::function::OptionalArg<vm::PyObjectRef>,
+        body: vm::function::OptionalArg<vm::PyObjectRef>,
+        py_vm: &VirtualMachine,
+    ) -> PyResult<vm::PyObjectRef> {
+        let request_json =
+            fetch_request_json(method.as_str(), url.as_str(), &headers, &body, py_vm);
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let fetch_error = |e: String| {
+                py_vm.new_exception_msg(
+                    py_vm.ctx.exceptions.runtime_error.to_owned(),
+                    format!("fetch_stream failed: {}", e),
+                )
+            };
+            let response_str =
+                call_host_json(host_network_fetch_stream, &request_json).map_err(fetch_error)?;
+            let response = json_to_py("fetch_stream", &response_str, py_vm)?;
+            // No stream_id means the request itself failed; the dict says why.
+            if let Some(stream_id) = extract_json_number(&response_str, "stream_id") {
+                let stream_id = stream_id as u32;
+                let pulled = pull_chunks(
+                    |buf| unsafe {
+                        host_network_stream_read(stream_id, buf.as_mut_ptr(), buf.len() as u32)
+                    },
+                    |chunk| {
+                        let data: vm::PyObjectRef = py_vm.ctx.new_bytes(chunk.to_vec()).into();
+                        on_chunk.call((data,), py_vm).map(drop)
+                    },
+                    |rc| fetch_error(format!("stream read failed with error code {}", rc)),
+                );
+                if pulled.is_err() {
+                    unsafe { host_network_stream_close(stream_id) };
+                }
+                pulled?;
+            }
+            Ok(response)
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let _ = (request_json, on_chunk);
+            Err(py_vm.new_exception_msg(
+                py_vm.ctx.exceptions.runtime_error.to_owned(),
+                "_codepod.fetch_stream() is only available inside a WASM sandbox".to_owned(),
+            ))
+        }
+    }
+
     /// Call a host extension method.
     ///
     /// Usage: `_codepod.extension_call(extension, method, **kwargs) -> result`
@@ -406,7 +637,7 @@ pub mod _codepod {
                         format!("extension_call failed: {}", e),
                     )
                 })?;
-            json_to_py(&response_str, py_vm)
+            json_to_py("extension_call", &response_str, py_vm)
         }
 
         #[cfg(not(target_arch = "wasm32"))]
@@ -419,6 +650,70 @@ pub mod _codepod {
         }
     }
 
+    /// Check whether a host extension is registered.
+    ///
+    /// Usage: `_codepod.is_extension(name) -> bool`
+    ///
+    /// Answers are cached, so repeated checks do not cross into the host.
+    /// Always False outside a WASM sandbox.
+    #[pyfunction]
+    fn is_extension(name: vm::builtins::PyStrRef) -> bool {
+        cached_is_extension(name.as_str(), |name| {
+            #[cfg(target_arch = "wasm32")]
+            {
+                let bytes = name.as_bytes();
+                unsafe { host_is_extension(bytes.as_ptr(), bytes.len() as u32) == 1 }
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                let _ = name;
+                false
+            }
+        })
+    }
+
+    /// Forget cached `is_extension` answers, e.g. after extensions change.
+    ///
+    /// Usage: `_codepod.clear_extension_cache()`
+    #[pyfunction]
+    fn clear_extension_cache() {
+        super::clear_extension_cache();
+    }
+
+    /// List the names of registered host extensions.
+    ///
+    /// Usage: `_codepod.extension_list() -> list[str]`
+    ///
+    /// Always an empty list outside a WASM sandbox.
+    #[pyfunction]
+    fn extension_list(py_vm: &VirtualMachine) -> PyResult<vm::PyObjectRef> {
+        #[cfg(target_arch = "wasm32")]
+        let names = {
+            let json = call_host_json(extension_list_json, "").map_err(|e| {
+                py_vm.new_exception_msg(
+                    py_vm.ctx.exceptions.runtime_error.to_owned(),
+                    format!("extension_list failed: {}", e),
+                )
+            })?;
+            parse_json_string_list(&json).ok_or_else(|| {
+                py_vm.new_exception_msg(
+                    py_vm.ctx.exceptions.runtime_error.to_owned(),
+                    format!("invalid extension list from host: {}", json),
+                )
+            })?
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let names: Vec<String> = Vec::new();
+
+        let items = names
+            .into_iter()
+            .map(|name| py_vm.ctx.new_str(name).into())
+            .collect();
+        Ok(py_vm.ctx.new_list(items).into())
+    }
+
     // ----- Native module bridge -----
 
     /// Call a method on a dynamically loaded native module.
@@ -534,7 +829,7 @@ pub mod _codepod {
                         format!("spawn failed: {}", e),
                     )
                 })?;
-            json_to_py(&response_str, py_vm)
+            json_to_py("spawn", &response_str, py_vm)
         }
 
         #[cfg(not(target_arch = "wasm32"))]
@@ -714,3 +1009,89 @@ pub mod _codepod {
 pub fn module_def(ctx: &vm::Context) -> &'static vm::builtins::PyModuleDef {
     _codepod::module_def(ctx)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn extension_lookups_are_cached() {
+        clear_extension_cache();
+        let calls = Cell::new(0);
+        let lookup = |name: &str| {
+            calls.set(calls.get() + 1);
+            name == "pdf"
+        };
+        assert!(cached_is_extension("pdf", lookup));
+        assert!(cached_is_extension("pdf", lookup));
+        assert!(!cached_is_extension("nope", lookup));
+        assert!(!cached_is_extension("nope", lookup));
+        assert_eq!(calls.get(), 2);
+
+        clear_extension_cache();
+        assert!(cached_is_extension("pdf", lookup));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn extension_list_parses_json_names() {
+        assert_eq!(
+            parse_json_string_list(r#"["pdf", "my\"ext", "caf\u00e9"]"#),
+            Some(vec!["pdf".to_string(), "my\"ext".to_string(), "café".to_string()])
+        );
+        assert_eq!(parse_json_string_list(" [ ] "), Some(Vec::new()));
+        assert_eq!(parse_json_string_list(r#"["pdf",]"#), None);
+        assert_eq!(parse_json_string_list(r#"{"a":1}"#), None);
+    }
+
+    #[test]
+    fn non_json_message_quotes_truncated_body() {
+        assert_eq!(
+            non_json_message("fetch", "<html>Bad Gateway</html>\n"),
+            "fetch failed: host returned a non-JSON response: \"<html>Bad Gateway</html>\\n\""
+        );
+        let long = "x".repeat(RESPONSE_PREVIEW_CHARS + 50);
+        assert_eq!(
+            non_json_message("extension_call", &long),
+            format!(
+                "extension_call failed: host returned a non-JSON response: \"{}...\"",
+                "x".repeat(RESPONSE_PREVIEW_CHARS)
+            )
+        );
+        let exact = "y".repeat(RESPONSE_PREVIEW_CHARS);
+        assert!(!non_json_message("spawn", &exact).contains("..."));
+    }
+
+    #[test]
+    fn pull_chunks_delivers_the_whole_body() {
+        let body: Vec<u8> = (0..STREAM_CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        let mut offset = 0;
+        let mock_read = |buf: &mut [u8]| {
+            // Hand out uneven pieces, never more than the guest asked for.
+            let n = (body.len() - offset).min(buf.len()).min(40_000);
+            buf[..n].copy_from_slice(&body[offset..offset + n]);
+            offset += n;
+            n as i32
+        };
+        let mut received = Vec::new();
+        let mut chunks = 0;
+        let result: Result<(), String> = pull_chunks(
+            mock_read,
+            |chunk| {
+                chunks += 1;
+                received.extend_from_slice(chunk);
+                Ok(())
+            },
+            |rc| format!("read error {rc}"),
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(received, body);
+        assert_eq!(chunks, 4);
+
+        let failing = pull_chunks(|_| -5, |_| Ok(()), |rc| format!("read error {rc}"));
+        assert_eq!(failing, Err("read error -5".to_string()));
+        let stopped: Result<(), &str> = pull_chunks(|_| 10, |_| Err("callback raised"), |_| "");
+        assert_eq!(stopped, Err("callback raised"));
+    }
+}
@@ -2,14 +2,20 @@
 //!
 //! Wraps the C sqlite3 library via FFI, providing the `_sqlite3` module that
 //! CPython's stdlib `sqlite3` package (frozen into RustPython) imports.
-//! This module exposes `connect()`, `Connection`, `Cursor`, `Row`, and the
-//! compatibility attributes that `sqlite3.dbapi2` expects.
+//! This module exposes `connect()`, `Connection`, `Cursor`, `Row`,
+//! `Transaction`, `Blob`, and the compatibility attributes that
+//! `sqlite3.dbapi2` expects.
 
 use rustpython_vm as vm;
+use vm::types::{Constructor, Destructor, Representable};
+use vm::AsObject;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::fmt;
-use std::os::raw::{c_char, c_double, c_int};
+use std::os::raw::{c_char, c_double, c_int, c_void};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 
 // ---------------------------------------------------------------------------
@@ -30,8 +36,14 @@ mod ffi {
         _private: [u8; 0],
     }
 
+    #[repr(C)]
+    pub struct sqlite3_blob {
+        _private: [u8; 0],
+    }
+
     // Result codes
     pub const SQLITE_OK: c_int = 0;
+    pub const SQLITE_INTERRUPT: c_int = 9;
     pub const SQLITE_ROW: c_int = 100;
     pub const SQLITE_DONE: c_int = 101;
 
@@ -48,7 +60,15 @@ mod ffi {
         pub fn sqlite3_errmsg(db: *mut sqlite3) -> *const c_char;
         pub fn sqlite3_changes(db: *mut sqlite3) -> c_int;
         pub fn sqlite3_last_insert_rowid(db: *mut sqlite3) -> i64;
+        pub fn sqlite3_get_autocommit(db: *mut sqlite3) -> c_int;
         pub fn sqlite3_libversion() -> *const c_char;
+        pub fn sqlite3_interrupt(db: *mut sqlite3);
+        pub fn sqlite3_progress_handler(
+            db: *mut sqlite3,
+            nOps: c_int,
+            xProgress: Option<unsafe extern "C" fn(*mut c_void) -> c_int>,
+            pArg: *mut c_void,
+        );
 
         pub fn sqlite3_prepare_v2(
             db: *mut sqlite3,
@@ -63,6 +83,7 @@ mod ffi {
         pub fn sqlite3_column_count(stmt: *mut sqlite3_stmt) -> c_int;
         pub fn sqlite3_column_type(stmt: *mut sqlite3_stmt, iCol: c_int) -> c_int;
         pub fn sqlite3_column_name(stmt: *mut sqlite3_stmt, N: c_int) -> *const c_char;
+        pub fn sqlite3_column_decltype(stmt: *mut sqlite3_stmt, N: c_int) -> *const c_char;
 
         pub fn sqlite3_column_int64(stmt: *mut sqlite3_stmt, iCol: c_int) -> i64;
         pub fn sqlite3_column_double(stmt: *mut sqlite3_stmt, iCol: c_int) -> c_double;
@@ -87,6 +108,30 @@ mod ffi {
             destructor: isize,
         ) -> c_int;
         pub fn sqlite3_bind_null(stmt: *mut sqlite3_stmt, idx: c_int) -> c_int;
+
+        pub fn sqlite3_blob_open(
+            db: *mut sqlite3,
+            zDb: *const c_char,
+            zTable: *const c_char,
+            zColumn: *const c_char,
+            iRow: i64,
+            flags: c_int,
+            ppBlob: *mut *mut sqlite3_blob,
+        ) -> c_int;
+        pub fn sqlite3_blob_close(blob: *mut sqlite3_blob) -> c_int;
+        pub fn sqlite3_blob_bytes(blob: *mut sqlite3_blob) -> c_int;
+        pub fn sqlite3_blob_read(
+            blob: *mut sqlite3_blob,
+            z: *mut c_void,
+            n: c_int,
+            iOffset: c_int,
+        ) -> c_int;
+        pub fn sqlite3_blob_write(
+            blob: *mut sqlite3_blob,
+            z: *const c_void,
+            n: c_int,
+            iOffset: c_int,
+        ) -> c_int;
     }
 
     /// SQLITE_TRANSIENT sentinel: tells sqlite3 to make its own copy of the data.
@@ -120,6 +165,17 @@ impl fmt::Debug for DbRef {
     }
 }
 
+/// Owning wrapper around a `*mut sqlite3_blob` handle (closed on drop).
+struct BlobPtr(*mut ffi::sqlite3_blob);
+unsafe impl Send for BlobPtr {}
+unsafe impl Sync for BlobPtr {}
+
+impl fmt::Debug for BlobPtr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BlobPtr").field(&self.0).finish()
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Helper types
 // ---------------------------------------------------------------------------
@@ -138,6 +194,8 @@ enum SqlValue {
 #[derive(Clone, Debug)]
 struct ColumnDesc {
     name: String,
+    /// Declared type of the source table column (`None` for expressions).
+    decltype: Option<String>,
 }
 
 /// Result of executing a SQL statement.
@@ -145,7 +203,9 @@ struct ExecResult {
     columns: Vec<ColumnDesc>,
     rows: Vec<Vec<SqlValue>>,
     rowcount: i32,
-    lastrowid: i64,
+    /// Rowid of the inserted row; `None` unless the statement was an
+    /// INSERT or REPLACE.
+    lastrowid: Option<i64>,
 }
 
 // ---------------------------------------------------------------------------
@@ -163,6 +223,55 @@ fn get_errmsg(db: *mut ffi::sqlite3) -> String {
     }
 }
 
+/// Whether `sql` is an INSERT or REPLACE, judged by its first keyword.
+fn is_insert_statement(sql: &str) -> bool {
+    let keyword: String = sql
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect();
+    keyword.eq_ignore_ascii_case("insert") || keyword.eq_ignore_ascii_case("replace")
+}
+
+/// Error message for SQL holding more than one statement; mapped to
+/// `ProgrammingError` by [`execution_error`].
+const MULTIPLE_STATEMENTS: &str = "You can only execute one statement at a time.";
+
+/// Error message for a statement cut short by `sqlite3_interrupt` or a
+/// progress handler; mapped to `OperationalError` by [`execution_error`].
+const INTERRUPTED: &str = "interrupted";
+
+/// The error for a failed sqlite call that returned `rc`.
+fn sqlite_error(db: *mut ffi::sqlite3, rc: c_int) -> String {
+    if rc == ffi::SQLITE_INTERRUPT {
+        INTERRUPTED.to_string()
+    } else {
+        get_errmsg(db)
+    }
+}
+
+/// Whether `tail` (the SQL left over after the first statement) holds
+/// anything besides whitespace and comments, as CPython's `lstrip_sql` does.
+fn has_trailing_statement(tail: &str) -> bool {
+    let mut rest = tail;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', '\n', '\r', '\x0c']);
+        if let Some(comment) = rest.strip_prefix("--") {
+            match comment.find('\n') {
+                Some(end) => rest = &comment[end + 1..],
+                None => return false,
+            }
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.find("*/") {
+                Some(end) => rest = &comment[end + 2..],
+                None => return false,
+            }
+        } else {
+            return !rest.is_empty();
+        }
+    }
+}
+
 fn execute_sql(
     db: *mut ffi::sqlite3,
     sql: &str,
@@ -171,10 +280,20 @@ fn execute_sql(
     let c_sql = CString::new(sql).map_err(|e| format!("invalid SQL string: {e}"))?;
 
     let mut stmt: *mut ffi::sqlite3_stmt = std::ptr::null_mut();
-    let rc =
-        unsafe { ffi::sqlite3_prepare_v2(db, c_sql.as_ptr(), -1, &mut stmt, std::ptr::null_mut()) };
+    let mut tail: *const c_char = std::ptr::null();
+    let rc = unsafe { ffi::sqlite3_prepare_v2(db, c_sql.as_ptr(), -1, &mut stmt, &mut tail) };
     if rc != ffi::SQLITE_OK {
-        return Err(get_errmsg(db));
+        return Err(sqlite_error(db, rc));
+    }
+
+    // sqlite3 only compiles the first statement; refuse rather than
+    // silently dropping the rest.
+    if !tail.is_null() {
+        let consumed = unsafe { tail.offset_from(c_sql.as_ptr()) } as usize;
+        if has_trailing_statement(&sql[consumed..]) {
+            unsafe { ffi::sqlite3_finalize(stmt) };
+            return Err(MULTIPLE_STATEMENTS.to_string());
+        }
     }
 
     // Bind parameters
@@ -223,7 +342,11 @@ fn execute_sql(
                     CStr::from_ptr(ptr).to_string_lossy().into_owned()
                 }
             };
-            ColumnDesc { name }
+            let decltype = unsafe {
+                let ptr = ffi::sqlite3_column_decltype(stmt, i);
+                (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+            };
+            ColumnDesc { name, decltype }
         })
         .collect();
 
@@ -271,8 +394,8 @@ fn execute_sql(
                 rows.push(row);
             }
             ffi::SQLITE_DONE => break,
-            _ => {
-                let err = get_errmsg(db);
+            rc => {
+                let err = sqlite_error(db, rc);
                 unsafe { ffi::sqlite3_finalize(stmt) };
                 return Err(err);
             }
@@ -280,7 +403,7 @@ fn execute_sql(
     }
 
     let rowcount = unsafe { ffi::sqlite3_changes(db) };
-    let lastrowid = unsafe { ffi::sqlite3_last_insert_rowid(db) };
+    let lastrowid = is_insert_statement(sql).then(|| unsafe { ffi::sqlite3_last_insert_rowid(db) });
     unsafe { ffi::sqlite3_finalize(stmt) };
 
     Ok(ExecResult {
@@ -300,7 +423,7 @@ fn py_to_sql_params(
     params: &vm::PyObjectRef,
     py_vm: &vm::VirtualMachine,
 ) -> vm::PyResult<Vec<SqlValue>> {
-    use vm::builtins::{PyBytes, PyFloat, PyInt, PyList, PyStr, PyTuple};
+    use vm::builtins::{PyList, PyTuple};
 
     // Accept either a tuple or a list of params
     let items: Vec<vm::PyObjectRef> = if let Some(t) = params.payload::<PyTuple>() {
@@ -311,26 +434,97 @@ fn py_to_sql_params(
         return Err(py_vm.new_type_error("parameters must be a tuple or list".to_owned()));
     };
 
-    let mut out = Vec::with_capacity(items.len());
-    for obj in &items {
-        if py_vm.is_none(obj) {
-            out.push(SqlValue::Null);
-        } else if let Some(i) = obj.payload::<PyInt>() {
-            let val = i.try_to_primitive::<i64>(py_vm)?;
-            out.push(SqlValue::Integer(val));
-        } else if let Some(f) = obj.payload::<PyFloat>() {
-            out.push(SqlValue::Float(f.to_f64()));
-        } else if let Some(s) = obj.payload::<PyStr>() {
-            out.push(SqlValue::Text(s.as_str().to_owned()));
-        } else if let Some(b) = obj.payload::<PyBytes>() {
-            out.push(SqlValue::Blob(b.as_bytes().to_vec()));
-        } else {
-            // Fall back to str representation
-            let s = obj.str(py_vm)?;
-            out.push(SqlValue::Text(s.as_str().to_owned()));
+    items.iter().map(|obj| py_to_sql(obj, py_vm)).collect()
+}
+
+/// Convert one bound parameter. Types sqlite stores natively bind as-is;
+/// anything else goes through its adapter (if any) and then `str()`.
+fn py_to_sql(obj: &vm::PyObjectRef, py_vm: &vm::VirtualMachine) -> vm::PyResult<SqlValue> {
+    if let Some(val) = native_sql_value(obj, py_vm)? {
+        return Ok(val);
+    }
+    let adapted = adapt(obj, py_vm)?;
+    let obj = adapted.as_ref().unwrap_or(obj);
+    if let Some(val) = native_sql_value(obj, py_vm)? {
+        return Ok(val);
+    }
+    // Fall back to str representation
+    let s = obj.str(py_vm)?;
+    Ok(SqlValue::Text(s.as_str().to_owned()))
+}
+
+#[allow(deprecated)] // payload() is deprecated in favour of downcast_ref()
+fn native_sql_value(
+    obj: &vm::PyObjectRef,
+    py_vm: &vm::VirtualMachine,
+) -> vm::PyResult<Option<SqlValue>> {
+    use vm::builtins::{PyByteArray, PyBytes, PyFloat, PyInt, PyMemoryView, PyStr};
+    use vm::function::ArgBytesLike;
+
+    let val = if py_vm.is_none(obj) {
+        SqlValue::Null
+    } else if let Some(i) = obj.payload::<PyInt>() {
+        SqlValue::Integer(i.try_to_primitive::<i64>(py_vm)?)
+    } else if let Some(f) = obj.payload::<PyFloat>() {
+        SqlValue::Float(f.to_f64())
+    } else if let Some(s) = obj.payload::<PyStr>() {
+        SqlValue::Text(s.as_str().to_owned())
+    } else if let Some(b) = obj.payload::<PyBytes>() {
+        SqlValue::Blob(b.as_bytes().to_vec())
+    } else if obj.payload_is::<PyByteArray>() || obj.payload_is::<PyMemoryView>() {
+        let buf = obj.try_to_value::<ArgBytesLike>(py_vm)?;
+        SqlValue::Blob(buf.with_ref(|b| b.to_vec()))
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(val))
+}
+
+thread_local! {
+    /// Adapters from `register_adapter`, keyed by the id of the exact type
+    /// they were registered for (the type is kept alive alongside).
+    static ADAPTERS: RefCell<HashMap<usize, (vm::builtins::PyTypeRef, vm::PyObjectRef)>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Run the adapter for `obj`'s type. Without a registered one, `Decimal`
+/// adapts to its exact `str()` form, and `datetime` and `date` values to
+/// ISO strings as in CPython (`2024-01-02 03:04:05` and `2024-01-02`).
+fn adapt(
+    obj: &vm::PyObjectRef,
+    py_vm: &vm::VirtualMachine,
+) -> vm::PyResult<Option<vm::PyObjectRef>> {
+    let adapter = ADAPTERS.with(|m| {
+        m.borrow()
+            .get(&obj.class().get_id())
+            .map(|(_, callable)| callable.clone())
+    });
+    if let Some(adapter) = adapter {
+        return adapter.call((obj.clone(),), py_vm).map(Some);
+    }
+
+    // A Decimal can only exist once `decimal` is loaded, so look it up in
+    // sys.modules rather than paying for the import on every parameter.
+    let modules = py_vm.sys_module.get_attr("modules", py_vm)?;
+    if let Ok(decimal) = modules.get_item("decimal", py_vm) {
+        if obj.is_instance(&decimal.get_attr("Decimal", py_vm)?, py_vm)? {
+            return Ok(Some(obj.str(py_vm)?.into()));
         }
     }
-    Ok(out)
+
+    let datetime = py_vm.import("datetime", 0)?;
+    let is_datetime = obj.is_instance(&datetime.get_attr("datetime", py_vm)?, py_vm)?;
+    if !is_datetime && !obj.is_instance(&datetime.get_attr("date", py_vm)?, py_vm)? {
+        return Ok(None);
+    }
+    let isoformat = obj.get_attr("isoformat", py_vm)?;
+    let iso = if is_datetime {
+        let sep: vm::PyObjectRef = py_vm.ctx.new_str(" ").into();
+        isoformat.call((sep,), py_vm)?
+    } else {
+        isoformat.call((), py_vm)?
+    };
+    Ok(Some(iso))
 }
 
 /// Convert a SqlValue to a Python object.
@@ -344,6 +538,163 @@ fn sql_to_py(val: &SqlValue, py_vm: &vm::VirtualMachine) -> vm::PyObjectRef {
     }
 }
 
+/// `detect_types` flag: pick converters by the column's declared type.
+const PARSE_DECLTYPES: c_int = 1;
+/// `detect_types` flag: pick converters by a `[typename]` suffix in the
+/// column name (`SELECT x AS "x [bool]"`).
+const PARSE_COLNAMES: c_int = 2;
+
+thread_local! {
+    /// Converters from `register_converter`, keyed by upper-cased type name.
+    static CONVERTERS: RefCell<HashMap<String, vm::PyObjectRef>> = RefCell::new(HashMap::new());
+}
+
+/// The `[typename]` part of a column name, if any.
+fn colname_type(name: &str) -> Option<&str> {
+    let start = name.find('[')?;
+    let len = name[start + 1..].find(']')?;
+    Some(&name[start + 1..start + 1 + len])
+}
+
+/// Column name as reported in `cursor.description`: with PARSE_COLNAMES
+/// the `[typename]` suffix (and the space before it) is dropped.
+fn display_column_name(name: &str, detect_types: c_int) -> &str {
+    if detect_types & PARSE_COLNAMES == 0 {
+        return name;
+    }
+    match name.find('[') {
+        Some(pos) => name[..pos].strip_suffix(' ').unwrap_or(&name[..pos]),
+        None => name,
+    }
+}
+
+/// Find the registered converter for a result column. Column-name types
+/// take precedence over declared types, and a declared type is matched on
+/// its first word (`NUMERIC(10, 2)` looks up `NUMERIC`).
+fn find_converter(column: &ColumnDesc, detect_types: c_int) -> Option<vm::PyObjectRef> {
+    let mut type_name = None;
+    if detect_types & PARSE_COLNAMES != 0 {
+        type_name = colname_type(&column.name);
+    }
+    if type_name.is_none() && detect_types & PARSE_DECLTYPES != 0 {
+        type_name = column
+            .decltype
+            .as_deref()
+            .and_then(|decl| decl.split(|c: char| c == '(' || c.is_whitespace()).next());
+    }
+    let key = type_name?.to_uppercase();
+    CONVERTERS.with(|m| m.borrow().get(&key).cloned())
+}
+
+/// The raw bytes a converter receives: sqlite's text form of the value.
+fn sql_bytes(val: &SqlValue) -> Vec<u8> {
+    match val {
+        SqlValue::Null => Vec::new(),
+        SqlValue::Integer(i) => i.to_string().into_bytes(),
+        SqlValue::Float(f) => format!("{f:?}").into_bytes(),
+        SqlValue::Text(s) => s.as_bytes().to_vec(),
+        SqlValue::Blob(b) => b.clone(),
+    }
+}
+
+/// DB-API exception hierarchy: (name, base). `None` means `Exception`.
+const EXCEPTION_TYPES: &[(&str, Option<&str>)] = &[
+    ("Warning", None),
+    ("Error", None),
+    ("InterfaceError", Some("Error")),
+    ("DatabaseError", Some("Error")),
+    ("DataError", Some("DatabaseError")),
+    ("OperationalError", Some("DatabaseError")),
+    ("IntegrityError", Some("DatabaseError")),
+    ("InternalError", Some("DatabaseError")),
+    ("ProgrammingError", Some("DatabaseError")),
+    ("NotSupportedError", Some("DatabaseError")),
+];
+
+thread_local! {
+    static EXCEPTIONS: RefCell<HashMap<&'static str, vm::builtins::PyTypeRef>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Get (creating on first use) one of the `sqlite3` DB-API exception types.
+fn exception_type(py_vm: &vm::VirtualMachine, name: &'static str) -> vm::builtins::PyTypeRef {
+    if let Some(ty) = EXCEPTIONS.with(|m| m.borrow().get(name).cloned()) {
+        return ty;
+    }
+    let base = match EXCEPTION_TYPES.iter().find(|(n, _)| *n == name) {
+        Some(&(_, Some(base))) => exception_type(py_vm, base),
+        _ => py_vm.ctx.exceptions.exception_type.to_owned(),
+    };
+    let ty = py_vm
+        .ctx
+        .new_exception_type("sqlite3", name, Some(vec![base]));
+    EXCEPTIONS.with(|m| m.borrow_mut().insert(name, ty.clone()));
+    ty
+}
+
+/// Build a `sqlite3.ProgrammingError` (misuse of the API, e.g. a closed cursor).
+fn programming_error(py_vm: &vm::VirtualMachine, msg: &str) -> vm::builtins::PyBaseExceptionRef {
+    py_vm.new_exception_msg(exception_type(py_vm, "ProgrammingError"), msg.to_owned())
+}
+
+/// Quote an identifier (e.g. a savepoint name) for interpolation into SQL.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Run a statement that produces no rows, raising on failure.
+fn execute_simple(
+    db: *mut ffi::sqlite3,
+    sql: &str,
+    py_vm: &vm::VirtualMachine,
+) -> vm::PyResult<()> {
+    execute_sql(db, sql, &[])
+        .map(|_| ())
+        .map_err(|e| py_vm.new_exception_msg(py_vm.ctx.exceptions.runtime_error.to_owned(), e))
+}
+
+/// Map a statement failure to a Python exception. An interrupted statement
+/// (`conn.interrupt()` or a progress handler returning true) raises
+/// `OperationalError`, SQL holding several statements raises
+/// `ProgrammingError`, and other failures keep raising `RuntimeError`.
+fn execution_error(py_vm: &vm::VirtualMachine, msg: String) -> vm::builtins::PyBaseExceptionRef {
+    if msg == INTERRUPTED {
+        py_vm.new_exception_msg(exception_type(py_vm, "OperationalError"), msg)
+    } else if msg == MULTIPLE_STATEMENTS {
+        programming_error(py_vm, &msg)
+    } else {
+        py_vm.new_exception_msg(py_vm.ctx.exceptions.runtime_error.to_owned(), msg)
+    }
+}
+
+/// A Python progress callback registered with `sqlite3_progress_handler`.
+///
+/// Boxed and owned by the connection so the pointer handed to sqlite stays
+/// valid until the handler is replaced or the connection is dropped. The VM
+/// pointer is sound because the module only runs on single-threaded wasm32,
+/// where the interpreter's VM outlives every connection.
+#[derive(Debug)]
+struct ProgressHandler {
+    callable: vm::PyObjectRef,
+    vm: *const vm::VirtualMachine,
+}
+unsafe impl Send for ProgressHandler {}
+unsafe impl Sync for ProgressHandler {}
+
+/// C trampoline for `sqlite3_progress_handler`: a truthy return value (or an
+/// exception) from the Python callable aborts the running statement.
+unsafe extern "C" fn progress_trampoline(arg: *mut c_void) -> c_int {
+    let handler = &*(arg as *const ProgressHandler);
+    let py_vm = &*handler.vm;
+    match handler.callable.call((), py_vm) {
+        Ok(ret) => ret.try_to_bool(py_vm).map_or(1, |abort| abort as c_int),
+        Err(_) => 1,
+    }
+}
+
+/// Counter used to generate unique savepoint names for nested transactions.
+static TRANSACTION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 /// Get the SQLite library version string.
 fn sqlite_version_string() -> String {
     unsafe {
@@ -356,72 +707,110 @@ fn sqlite_version_string() -> String {
     }
 }
 
+/// DB-API module version, matching what CPython's `sqlite3` reports.
+const MODULE_VERSION: &str = "2.6.0";
+
+/// Split a dotted version into `[major, minor, patch]`, treating missing or
+/// non-numeric parts as 0.
+fn version_triple(version: &str) -> [i64; 3] {
+    let mut triple = [0; 3];
+    for (slot, part) in triple.iter_mut().zip(version.split('.')) {
+        *slot = part.parse().unwrap_or(0);
+    }
+    triple
+}
+
+/// Build the `(major, minor, patch)` tuple for a dotted version.
+fn version_tuple(version: &str, py_vm: &vm::VirtualMachine) -> vm::PyObjectRef {
+    let items: Vec<vm::PyObjectRef> = version_triple(version)
+        .iter()
+        .map(|&n| py_vm.ctx.new_int(n).into())
+        .collect();
+    py_vm.ctx.new_tuple(items).into()
+}
+
 // ---------------------------------------------------------------------------
 // PyConnection
 // ---------------------------------------------------------------------------
 
+#[derive(vm::FromArgs)]
+struct ConnectArgs {
+    #[pyarg(any)]
+    database: vm::builtins::PyStrRef,
+    #[pyarg(named, optional)]
+    detect_types: vm::function::OptionalArg<c_int>,
+}
+
 #[vm::pyclass(module = "_sqlite3", name = "Connection")]
 #[derive(Debug, vm::PyPayload)]
 struct PyConnection {
     db: Mutex<Option<DbPtr>>,
+    progress_handler: Mutex<Option<Box<ProgressHandler>>>,
+    /// `PARSE_DECLTYPES` / `PARSE_COLNAMES` bits passed to `connect()`.
+    detect_types: c_int,
 }
 
-#[vm::pyclass]
+#[vm::pyclass(with(Destructor, Representable))]
 impl PyConnection {
     fn get_db(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<*mut ffi::sqlite3> {
         let guard = self.db.lock().unwrap();
         match &*guard {
             Some(ptr) => Ok(ptr.0),
-            None => Err(py_vm.new_exception_msg(
-                py_vm.ctx.exceptions.runtime_error.to_owned(),
-                "Cannot operate on a closed database.".to_owned(),
+            None => Err(programming_error(
+                py_vm,
+                "Cannot operate on a closed database.",
             )),
         }
     }
 
+    /// Close the sqlite handle if it is still open and return sqlite's
+    /// result code. Later calls are no-ops.
+    fn close_handle(&self) -> c_int {
+        match self.db.lock().ok().and_then(|mut guard| guard.take()) {
+            Some(ptr) => unsafe { ffi::sqlite3_close(ptr.0) },
+            None => ffi::SQLITE_OK,
+        }
+    }
+
     #[pymethod]
     fn execute(
-        &self,
+        zelf: vm::PyRef<Self>,
         sql: vm::builtins::PyStrRef,
         params: vm::function::OptionalArg<vm::PyObjectRef>,
         py_vm: &vm::VirtualMachine,
     ) -> vm::PyResult<PyCursor> {
-        let db = self.get_db(py_vm)?;
+        let db = zelf.get_db(py_vm)?;
         let sql_params = match params {
             vm::function::OptionalArg::Present(ref p) => py_to_sql_params(p, py_vm)?,
             vm::function::OptionalArg::Missing => Vec::new(),
         };
-        let result = execute_sql(db, sql.as_str(), &sql_params).map_err(|e| {
-            py_vm.new_exception_msg(py_vm.ctx.exceptions.runtime_error.to_owned(), e)
-        })?;
+        let result =
+            execute_sql(db, sql.as_str(), &sql_params).map_err(|e| execution_error(py_vm, e))?;
 
-        let description = if result.columns.is_empty() {
-            None
-        } else {
-            Some(result.columns.iter().map(|c| c.name.clone()).collect())
-        };
-
-        Ok(PyCursor {
-            db: Mutex::new(Some(DbRef(db))),
-            rows: Mutex::new(result.rows),
-            row_index: Mutex::new(0),
-            description: Mutex::new(description),
-            rowcount: Mutex::new(result.rowcount),
-            lastrowid: Mutex::new(result.lastrowid),
-        })
+        let cursor = PyCursor::new(db, Some(zelf));
+        cursor.set_result(result);
+        Ok(cursor)
     }
 
+    /// `cursor(factory=Cursor)`: the factory is called with the connection
+    /// and may be a `Cursor` subclass or any callable returning a cursor.
     #[pymethod]
-    fn cursor(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<PyCursor> {
-        let db = self.get_db(py_vm)?;
-        Ok(PyCursor {
-            db: Mutex::new(Some(DbRef(db))),
-            rows: Mutex::new(Vec::new()),
-            row_index: Mutex::new(0),
-            description: Mutex::new(None),
-            rowcount: Mutex::new(-1),
-            lastrowid: Mutex::new(0),
-        })
+    fn cursor(
+        zelf: vm::PyRef<Self>,
+        args: CursorArgs,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<vm::PyObjectRef> {
+        let db = zelf.get_db(py_vm)?;
+        match args.factory {
+            vm::function::OptionalArg::Present(factory) if !py_vm.is_none(&factory) => {
+                let conn: vm::PyObjectRef = zelf.into();
+                factory.call((conn,), py_vm)
+            }
+            _ => Ok(vm::PyPayload::into_pyobject(
+                PyCursor::new(db, Some(zelf)),
+                py_vm,
+            )),
+        }
     }
 
     #[pymethod]
@@ -441,15 +830,11 @@ impl PyConnection {
 
     #[pymethod]
     fn close(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<()> {
-        let mut guard = self.db.lock().unwrap();
-        if let Some(ptr) = guard.take() {
-            let rc = unsafe { ffi::sqlite3_close(ptr.0) };
-            if rc != ffi::SQLITE_OK {
-                return Err(py_vm.new_exception_msg(
-                    py_vm.ctx.exceptions.runtime_error.to_owned(),
-                    "Failed to close database".to_owned(),
-                ));
-            }
+        if self.close_handle() != ffi::SQLITE_OK {
+            return Err(py_vm.new_exception_msg(
+                py_vm.ctx.exceptions.runtime_error.to_owned(),
+                "Failed to close database".to_owned(),
+            ));
         }
         Ok(())
     }
@@ -467,6 +852,161 @@ impl PyConnection {
         Ok(())
     }
 
+    /// `set_progress_handler(handler, n)`: call `handler()` every `n` VM
+    /// instructions; a truthy result aborts the statement with
+    /// `OperationalError`. Passing `None` (or `n <= 0`) removes the handler.
+    #[pymethod]
+    fn set_progress_handler(
+        &self,
+        handler: vm::PyObjectRef,
+        n: c_int,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<()> {
+        let db = self.get_db(py_vm)?;
+        let mut slot = self.progress_handler.lock().unwrap();
+        if py_vm.is_none(&handler) || n <= 0 {
+            unsafe { ffi::sqlite3_progress_handler(db, 0, None, std::ptr::null_mut()) };
+            *slot = None;
+            return Ok(());
+        }
+        let boxed = Box::new(ProgressHandler {
+            callable: handler,
+            vm: py_vm as *const vm::VirtualMachine,
+        });
+        let arg = &*boxed as *const ProgressHandler as *mut c_void;
+        unsafe { ffi::sqlite3_progress_handler(db, n, Some(progress_trampoline), arg) };
+        // Replace only after sqlite holds the new pointer, so the old
+        // handler is never freed while still registered.
+        *slot = Some(boxed);
+        Ok(())
+    }
+
+    /// Abort any statement running on this connection; it fails with
+    /// `OperationalError: interrupted`.
+    #[pymethod]
+    fn interrupt(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<()> {
+        let db = self.get_db(py_vm)?;
+        unsafe { ffi::sqlite3_interrupt(db) };
+        Ok(())
+    }
+
+    /// Rowid of the most recent successful INSERT on this connection.
+    #[pymethod]
+    fn last_insert_rowid(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<i64> {
+        let db = self.get_db(py_vm)?;
+        Ok(unsafe { ffi::sqlite3_last_insert_rowid(db) })
+    }
+
+    /// Open a named savepoint (`SAVEPOINT name`).
+    #[pymethod]
+    fn savepoint(
+        &self,
+        name: vm::builtins::PyStrRef,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<()> {
+        let db = self.get_db(py_vm)?;
+        execute_simple(
+            db,
+            &format!("SAVEPOINT {}", quote_identifier(name.as_str())),
+            py_vm,
+        )
+    }
+
+    /// Release a savepoint, folding its changes into the enclosing transaction.
+    #[pymethod]
+    fn release(
+        &self,
+        name: vm::builtins::PyStrRef,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<()> {
+        let db = self.get_db(py_vm)?;
+        execute_simple(
+            db,
+            &format!("RELEASE {}", quote_identifier(name.as_str())),
+            py_vm,
+        )
+    }
+
+    /// Discard everything done since the savepoint was opened. The savepoint
+    /// itself stays open, matching `ROLLBACK TO` semantics.
+    #[pymethod]
+    fn rollback_to(
+        &self,
+        name: vm::builtins::PyStrRef,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<()> {
+        let db = self.get_db(py_vm)?;
+        execute_simple(
+            db,
+            &format!("ROLLBACK TO {}", quote_identifier(name.as_str())),
+            py_vm,
+        )
+    }
+
+    /// `blobopen(table, column, row, /, *, readonly=False, name="main")`:
+    /// open the BLOB stored in `table.column` at rowid `row` for incremental
+    /// I/O. The blob's size is fixed; writes cannot grow it.
+    #[pymethod]
+    fn blobopen(
+        zelf: vm::PyRef<Self>,
+        args: BlobOpenArgs,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<PyBlob> {
+        let db = zelf.get_db(py_vm)?;
+        let readonly = args.readonly.unwrap_or(false);
+        let schema = match args.name {
+            vm::function::OptionalArg::Present(name) => name.as_str().to_owned(),
+            vm::function::OptionalArg::Missing => "main".to_owned(),
+        };
+        let to_c = |s: &str| CString::new(s).map_err(|e| py_vm.new_value_error(e.to_string()));
+        let (c_schema, c_table, c_column) = (
+            to_c(&schema)?,
+            to_c(args.table.as_str())?,
+            to_c(args.column.as_str())?,
+        );
+
+        let mut blob: *mut ffi::sqlite3_blob = std::ptr::null_mut();
+        let rc = unsafe {
+            ffi::sqlite3_blob_open(
+                db,
+                c_schema.as_ptr(),
+                c_table.as_ptr(),
+                c_column.as_ptr(),
+                args.row,
+                if readonly { 0 } else { 1 },
+                &mut blob,
+            )
+        };
+        if rc != ffi::SQLITE_OK {
+            if !blob.is_null() {
+                unsafe { ffi::sqlite3_blob_close(blob) };
+            }
+            return Err(
+                py_vm.new_exception_msg(exception_type(py_vm, "OperationalError"), get_errmsg(db))
+            );
+        }
+        Ok(PyBlob {
+            blob: Mutex::new(Some(BlobPtr(blob))),
+            offset: Mutex::new(0),
+            connection: zelf,
+        })
+    }
+
+    /// Return a context manager that begins a transaction on enter and
+    /// commits (or rolls back, if an exception escaped) on exit. Nested use
+    /// inside an open transaction is backed by a savepoint.
+    #[pymethod]
+    fn transaction(
+        zelf: vm::PyRef<Self>,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<PyTransaction> {
+        zelf.get_db(py_vm)?;
+        Ok(PyTransaction {
+            connection: zelf,
+            savepoint: Mutex::new(None),
+        })
+    }
+
     #[pymethod]
     fn __enter__(zelf: vm::PyRef<Self>) -> vm::PyRef<Self> {
         zelf
@@ -484,11 +1024,257 @@ impl PyConnection {
     }
 }
 
+/// `__del__`: a connection that is garbage-collected without `close()`
+/// releases its sqlite handle.
+impl Destructor for PyConnection {
+    fn del(zelf: &vm::Py<Self>, _py_vm: &vm::VirtualMachine) -> vm::PyResult<()> {
+        zelf.close_handle();
+        Ok(())
+    }
+}
+
+impl Representable for PyConnection {
+    fn repr_str(zelf: &vm::Py<Self>, _py_vm: &vm::VirtualMachine) -> vm::PyResult<String> {
+        Ok(format!(
+            "<sqlite3.Connection object at {:#x}>",
+            zelf.get_id()
+        ))
+    }
+}
+
 impl Drop for PyConnection {
     fn drop(&mut self) {
-        if let Ok(mut guard) = self.db.lock() {
+        self.close_handle();
+    }
+}
+
+// ---------------------------------------------------------------------------
+// PyTransaction
+// ---------------------------------------------------------------------------
+
+#[vm::pyclass(module = "_sqlite3", name = "Transaction")]
+#[derive(Debug, vm::PyPayload)]
+struct PyTransaction {
+    /// Keeps the connection alive; its handle is fetched on each use so a
+    /// closed connection raises the usual "closed database" error.
+    connection: vm::PyRef<PyConnection>,
+    /// Savepoint name when this transaction is nested inside another one.
+    savepoint: Mutex<Option<String>>,
+}
+
+#[vm::pyclass]
+impl PyTransaction {
+    #[pymethod]
+    fn __enter__(
+        zelf: vm::PyRef<Self>,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<vm::PyRef<Self>> {
+        let db = zelf.connection.get_db(py_vm)?;
+        let in_transaction = unsafe { ffi::sqlite3_get_autocommit(db) } == 0;
+        if in_transaction {
+            let id = TRANSACTION_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let name = quote_identifier(&format!("_sqlite3_txn_{id}"));
+            execute_simple(db, &format!("SAVEPOINT {name}"), py_vm)?;
+            *zelf.savepoint.lock().unwrap() = Some(name);
+        } else {
+            execute_simple(db, "BEGIN", py_vm)?;
+        }
+        Ok(zelf)
+    }
+
+    #[pymethod]
+    fn __exit__(
+        &self,
+        exc_type: vm::PyObjectRef,
+        _exc_val: vm::PyObjectRef,
+        _exc_tb: vm::PyObjectRef,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<bool> {
+        let db = self.connection.get_db(py_vm)?;
+        let failed = !py_vm.is_none(&exc_type);
+        match self.savepoint.lock().unwrap().take() {
+            Some(name) => {
+                if failed {
+                    execute_simple(db, &format!("ROLLBACK TO {name}"), py_vm)?;
+                }
+                execute_simple(db, &format!("RELEASE {name}"), py_vm)?;
+            }
+            None => {
+                execute_simple(db, if failed { "ROLLBACK" } else { "COMMIT" }, py_vm)?;
+            }
+        }
+        // Never suppress the exception.
+        Ok(false)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// PyBlob
+// ---------------------------------------------------------------------------
+
+#[derive(vm::FromArgs)]
+struct BlobOpenArgs {
+    #[pyarg(positional)]
+    table: vm::builtins::PyStrRef,
+    #[pyarg(positional)]
+    column: vm::builtins::PyStrRef,
+    #[pyarg(positional)]
+    row: i64,
+    #[pyarg(named, optional)]
+    readonly: vm::function::OptionalArg<bool>,
+    #[pyarg(named, optional)]
+    name: vm::function::OptionalArg<vm::builtins::PyStrRef>,
+}
+
+/// File-like handle on a single BLOB value, as returned by
+/// `Connection.blobopen()`.
+#[vm::pyclass(module = "_sqlite3", name = "Blob")]
+#[derive(Debug, vm::PyPayload)]
+struct PyBlob {
+    blob: Mutex<Option<BlobPtr>>,
+    offset: Mutex<c_int>,
+    /// Keeps the connection open for as long as the blob handle is.
+    connection: vm::PyRef<PyConnection>,
+}
+
+impl PyBlob {
+    fn handle(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<*mut ffi::sqlite3_blob> {
+        match &*self.blob.lock().unwrap() {
+            Some(ptr) => Ok(ptr.0),
+            None => Err(programming_error(py_vm, "Cannot operate on a closed blob.")),
+        }
+    }
+
+    /// Raise `OperationalError` with the connection's message unless `rc` is OK.
+    fn check(&self, rc: c_int, py_vm: &vm::VirtualMachine) -> vm::PyResult<()> {
+        if rc == ffi::SQLITE_OK {
+            return Ok(());
+        }
+        let msg = match self.connection.get_db(py_vm) {
+            Ok(db) => get_errmsg(db),
+            Err(_) => format!("blob I/O failed with code {rc}"),
+        };
+        Err(py_vm.new_exception_msg(exception_type(py_vm, "OperationalError"), msg))
+    }
+}
+
+#[vm::pyclass]
+impl PyBlob {
+    /// `read(length=-1)`: read up to `length` bytes from the current offset
+    /// (everything remaining when negative or omitted).
+    #[pymethod]
+    fn read(
+        &self,
+        length: vm::function::OptionalArg<isize>,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<vm::PyObjectRef> {
+        let blob = self.handle(py_vm)?;
+        let mut offset = self.offset.lock().unwrap();
+        let remaining = unsafe { ffi::sqlite3_blob_bytes(blob) } - *offset;
+        let n = match length {
+            vm::function::OptionalArg::Present(len) if len >= 0 => {
+                len.min(remaining as isize) as c_int
+            }
+            _ => remaining,
+        };
+        let mut buf = vec![0u8; n as usize];
+        if n > 0 {
+            let rc = unsafe {
+                ffi::sqlite3_blob_read(blob, buf.as_mut_ptr() as *mut c_void, n, *offset)
+            };
+            self.check(rc, py_vm)?;
+            *offset += n;
+        }
+        Ok(py_vm.ctx.new_bytes(buf).into())
+    }
+
+    /// `write(data)`: write `data` at the current offset. Raises
+    /// `ValueError` if it would run past the end of the blob.
+    #[pymethod]
+    fn write(
+        &self,
+        data: vm::builtins::PyBytesRef,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<()> {
+        let blob = self.handle(py_vm)?;
+        let bytes = data.as_bytes();
+        let mut offset = self.offset.lock().unwrap();
+        let size = unsafe { ffi::sqlite3_blob_bytes(blob) };
+        if bytes.len() > (size - *offset) as usize {
+            return Err(py_vm.new_value_error("data longer than blob length".to_owned()));
+        }
+        let n = bytes.len() as c_int;
+        let rc =
+            unsafe { ffi::sqlite3_blob_write(blob, bytes.as_ptr() as *const c_void, n, *offset) };
+        self.check(rc, py_vm)?;
+        *offset += n;
+        Ok(())
+    }
+
+    /// `seek(offset, origin=os.SEEK_SET)`: move the read/write position.
+    #[pymethod]
+    fn seek(
+        &self,
+        offset: i64,
+        origin: vm::function::OptionalArg<i32>,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<()> {
+        let blob = self.handle(py_vm)?;
+        let size = unsafe { ffi::sqlite3_blob_bytes(blob) } as i64;
+        let mut current = self.offset.lock().unwrap();
+        let base = match origin.unwrap_or(0) {
+            0 => 0,
+            1 => *current as i64,
+            2 => size,
+            _ => {
+                return Err(py_vm.new_value_error(
+                    "'origin' should be os.SEEK_SET, os.SEEK_CUR, or os.SEEK_END".to_owned(),
+                ))
+            }
+        };
+        let target = base + offset;
+        if !(0..=size).contains(&target) {
+            return Err(py_vm.new_value_error("offset out of blob range".to_owned()));
+        }
+        *current = target as c_int;
+        Ok(())
+    }
+
+    #[pymethod]
+    fn tell(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<c_int> {
+        self.handle(py_vm)?;
+        Ok(*self.offset.lock().unwrap())
+    }
+
+    #[pymethod]
+    fn close(&self) {
+        if let Some(ptr) = self.blob.lock().unwrap().take() {
+            unsafe { ffi::sqlite3_blob_close(ptr.0) };
+        }
+    }
+
+    #[pymethod]
+    fn __enter__(zelf: vm::PyRef<Self>) -> vm::PyRef<Self> {
+        zelf
+    }
+
+    #[pymethod]
+    fn __exit__(
+        &self,
+        _exc_type: vm::PyObjectRef,
+        _exc_val: vm::PyObjectRef,
+        _exc_tb: vm::PyObjectRef,
+    ) -> bool {
+        self.close();
+        false
+    }
+}
+
+impl Drop for PyBlob {
+    fn drop(&mut self) {
+        if let Ok(mut guard) = self.blob.lock() {
             if let Some(ptr) = guard.take() {
-                unsafe { ffi::sqlite3_close(ptr.0) };
+                unsafe { ffi::sqlite3_blob_close(ptr.0) };
             }
         }
     }
@@ -498,20 +1284,138 @@ impl Drop for PyConnection {
 // PyCursor
 // ---------------------------------------------------------------------------
 
+#[derive(vm::FromArgs)]
+struct CursorArgs {
+    #[pyarg(any, optional)]
+    factory: vm::function::OptionalArg<vm::PyObjectRef>,
+}
+
 #[vm::pyclass(module = "_sqlite3", name = "Cursor")]
 #[derive(Debug, vm::PyPayload)]
 struct PyCursor {
     db: Mutex<Option<DbRef>>,
+    connection: Option<vm::PyRef<PyConnection>>,
+    closed: Mutex<bool>,
     rows: Mutex<Vec<Vec<SqlValue>>>,
+    /// Per-column converter for the current result set (see `detect_types`).
+    converters: Mutex<Vec<Option<vm::PyObjectRef>>>,
     row_index: Mutex<usize>,
     description: Mutex<Option<Vec<String>>>,
     rowcount: Mutex<i32>,
-    lastrowid: Mutex<i64>,
+    lastrowid: Mutex<Option<i64>>,
 }
 
-#[vm::pyclass]
+impl PyCursor {
+    fn new(db: *mut ffi::sqlite3, connection: Option<vm::PyRef<PyConnection>>) -> Self {
+        PyCursor {
+            db: Mutex::new(Some(DbRef(db))),
+            connection,
+            closed: Mutex::new(false),
+            rows: Mutex::new(Vec::new()),
+            converters: Mutex::new(Vec::new()),
+            row_index: Mutex::new(0),
+            description: Mutex::new(None),
+            rowcount: Mutex::new(-1),
+            lastrowid: Mutex::new(None),
+        }
+    }
+
+    /// Replace the cursor's result set with the outcome of a statement.
+    fn set_result(&self, result: ExecResult) {
+        let detect_types = self.connection.as_ref().map_or(0, |c| c.detect_types);
+        let desc = if result.columns.is_empty() {
+            None
+        } else {
+            Some(
+                result
+                    .columns
+                    .iter()
+                    .map(|c| display_column_name(&c.name, detect_types).to_owned())
+                    .collect(),
+            )
+        };
+        *self.description.lock().unwrap() = desc;
+        *self.converters.lock().unwrap() = result
+            .columns
+            .iter()
+            .map(|c| find_converter(c, detect_types))
+            .collect();
+        *self.rows.lock().unwrap() = result.rows;
+        *self.row_index.lock().unwrap() = 0;
+        *self.rowcount.lock().unwrap() = result.rowcount;
+        *self.lastrowid.lock().unwrap() = result.lastrowid;
+    }
+
+    /// Build the tuple for one row, passing values through their column's
+    /// converter. NULLs are never converted.
+    fn row_to_py(
+        &self,
+        row: &[SqlValue],
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<vm::PyObjectRef> {
+        let converters = self.converters.lock().unwrap();
+        let items = row
+            .iter()
+            .enumerate()
+            .map(|(i, v)| match converters.get(i) {
+                Some(Some(conv)) if !matches!(v, SqlValue::Null) => {
+                    let raw: vm::PyObjectRef = py_vm.ctx.new_bytes(sql_bytes(v)).into();
+                    conv.call((raw,), py_vm)
+                }
+                _ => Ok(sql_to_py(v, py_vm)),
+            })
+            .collect::<vm::PyResult<Vec<_>>>()?;
+        Ok(py_vm.ctx.new_tuple(items).into())
+    }
+
+    fn check_open(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<()> {
+        if *self.closed.lock().unwrap() {
+            return Err(programming_error(
+                py_vm,
+                "Cannot operate on a closed cursor.",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Constructor for PyCursor {
+    type Args = vm::PyRef<PyConnection>;
+
+    fn py_new(
+        cls: vm::builtins::PyTypeRef,
+        connection: Self::Args,
+        py_vm: &vm::VirtualMachine,
+    ) -> vm::PyResult {
+        let db = connection.get_db(py_vm)?;
+        vm::PyPayload::into_ref_with_type(PyCursor::new(db, Some(connection)), py_vm, cls)
+            .map(Into::into)
+    }
+}
+
+/// `__del__`: drop any buffered rows of a cursor collected without `close()`.
+impl Destructor for PyCursor {
+    fn del(zelf: &vm::Py<Self>, _py_vm: &vm::VirtualMachine) -> vm::PyResult<()> {
+        zelf.close();
+        Ok(())
+    }
+}
+
+impl Representable for PyCursor {
+    fn repr_str(zelf: &vm::Py<Self>, _py_vm: &vm::VirtualMachine) -> vm::PyResult<String> {
+        Ok(format!("<sqlite3.Cursor object at {:#x}>", zelf.get_id()))
+    }
+}
+
+#[vm::pyclass(with(Constructor, Destructor, Representable))]
 impl PyCursor {
     fn get_db(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<*mut ffi::sqlite3> {
+        self.check_open(py_vm)?;
+        // Ask the connection, so a cursor never uses a handle that
+        // `Connection.close()` has already released.
+        if let Some(connection) = &self.connection {
+            return connection.get_db(py_vm);
+        }
         let guard = self.db.lock().unwrap();
         match &*guard {
             Some(ptr) => Ok(ptr.0),
@@ -534,53 +1438,77 @@ impl PyCursor {
             vm::function::OptionalArg::Present(ref p) => py_to_sql_params(p, py_vm)?,
             vm::function::OptionalArg::Missing => Vec::new(),
         };
-        let result = execute_sql(db, sql.as_str(), &sql_params).map_err(|e| {
-            py_vm.new_exception_msg(py_vm.ctx.exceptions.runtime_error.to_owned(), e)
-        })?;
-
-        {
-            let desc = if result.columns.is_empty() {
-                None
-            } else {
-                Some(result.columns.iter().map(|c| c.name.clone()).collect())
-            };
-            *zelf.description.lock().unwrap() = desc;
-        }
-        *zelf.rows.lock().unwrap() = result.rows;
-        *zelf.row_index.lock().unwrap() = 0;
-        *zelf.rowcount.lock().unwrap() = result.rowcount;
-        *zelf.lastrowid.lock().unwrap() = result.lastrowid;
+        let result =
+            execute_sql(db, sql.as_str(), &sql_params).map_err(|e| execution_error(py_vm, e))?;
+        zelf.set_result(result);
 
         Ok(zelf)
     }
 
     #[pymethod]
-    fn fetchone(&self, py_vm: &vm::VirtualMachine) -> vm::PyObjectRef {
+    fn fetchone(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<vm::PyObjectRef> {
+        self.check_open(py_vm)?;
         let rows = self.rows.lock().unwrap();
         let mut idx = self.row_index.lock().unwrap();
         if *idx < rows.len() {
-            let row = &rows[*idx];
+            let row = self.row_to_py(&rows[*idx], py_vm)?;
             *idx += 1;
-            let tuple: Vec<vm::PyObjectRef> = row.iter().map(|v| sql_to_py(v, py_vm)).collect();
-            py_vm.ctx.new_tuple(tuple).into()
+            Ok(row)
         } else {
-            py_vm.ctx.none()
+            Ok(py_vm.ctx.none())
         }
     }
 
     #[pymethod]
-    fn fetchall(&self, py_vm: &vm::VirtualMachine) -> vm::PyObjectRef {
+    fn fetchall(&self, py_vm: &vm::VirtualMachine) -> vm::PyResult<vm::PyObjectRef> {
+        self.check_open(py_vm)?;
         let rows = self.rows.lock().unwrap();
         let mut idx = self.row_index.lock().unwrap();
-        let remaining: Vec<vm::PyObjectRef> = rows[*idx..]
+        let remaining = rows[*idx..]
             .iter()
-            .map(|row| {
-                let tuple: Vec<vm::PyObjectRef> = row.iter().map(|v| sql_to_py(v, py_vm)).collect();
-                py_vm.ctx.new_tuple(tuple).into()
-            })
-            .collect();
+            .map(|row| self.row_to_py(row, py_vm))
+            .collect::<vm::PyResult<Vec<_>>>()?;
         *idx = rows.len();
-        py_vm.ctx.new_list(remaining).into()
+        Ok(py_vm.ctx.new_list(remaining).into())
+    }
+
+    /// Drop any pending rows; later operations raise `ProgrammingError`.
+    #[pymethod]
+    fn close(&self) {
+        self.rows.lock().unwrap().clear();
+        *self.row_index.lock().unwrap() = 0;
+        *self.closed.lock().unwrap() = true;
+    }
+
+    /// DB-API no-op: sqlite binds whatever it is given.
+    #[pymethod]
+    fn setinputsizes(&self, _sizes: vm::PyObjectRef) {}
+
+    /// DB-API no-op: sqlite columns have no fixed output size.
+    #[pymethod]
+    fn setoutputsize(
+        &self,
+        _size: vm::PyObjectRef,
+        _column: vm::function::OptionalArg<vm::PyObjectRef>,
+    ) {
+    }
+
+    /// Alias of `setoutputsize` for tooling that uses the plural spelling.
+    #[pymethod]
+    fn setoutputsizes(
+        &self,
+        size: vm::PyObjectRef,
+        column: vm::function::OptionalArg<vm::PyObjectRef>,
+    ) {
+        self.setoutputsize(size, column)
+    }
+
+    #[pygetset]
+    fn connection(&self, py_vm: &vm::VirtualMachine) -> vm::PyObjectRef {
+        match &self.connection {
+            Some(conn) => conn.clone().into(),
+            None => py_vm.ctx.none(),
+        }
     }
 
     #[pygetset]
@@ -617,7 +1545,7 @@ impl PyCursor {
     }
 
     #[pygetset]
-    fn lastrowid(&self) -> i64 {
+    fn lastrowid(&self) -> Option<i64> {
         *self.lastrowid.lock().unwrap()
     }
 }
@@ -661,15 +1589,107 @@ pub mod _sqlite3 {
         PyRow::make_class(&vm.ctx)
     }
 
+    #[pyattr]
+    fn Transaction(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        PyTransaction::make_class(&vm.ctx)
+    }
+
+    #[pyattr]
+    fn Blob(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        PyBlob::make_class(&vm.ctx)
+    }
+
+    #[pyattr]
+    fn Warning(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "Warning")
+    }
+
+    #[pyattr]
+    fn Error(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "Error")
+    }
+
+    #[pyattr]
+    fn InterfaceError(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "InterfaceError")
+    }
+
+    #[pyattr]
+    fn DatabaseError(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "DatabaseError")
+    }
+
+    #[pyattr]
+    fn DataError(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "DataError")
+    }
+
+    #[pyattr]
+    fn OperationalError(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "OperationalError")
+    }
+
+    #[pyattr]
+    fn IntegrityError(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "IntegrityError")
+    }
+
+    #[pyattr]
+    fn InternalError(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "InternalError")
+    }
+
+    #[pyattr]
+    fn ProgrammingError(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "ProgrammingError")
+    }
+
+    #[pyattr]
+    fn NotSupportedError(vm: &VirtualMachine) -> vm::builtins::PyTypeRef {
+        exception_type(vm, "NotSupportedError")
+    }
+
     #[pyattr]
     fn sqlite_version(_vm: &VirtualMachine) -> String {
         sqlite_version_string()
     }
 
+    #[pyattr]
+    fn sqlite_version_info(vm: &VirtualMachine) -> vm::PyObjectRef {
+        version_tuple(&sqlite_version_string(), vm)
+    }
+
+    #[pyattr]
+    fn version(_vm: &VirtualMachine) -> String {
+        MODULE_VERSION.to_string()
+    }
+
+    #[pyattr]
+    fn version_info(vm: &VirtualMachine) -> vm::PyObjectRef {
+        version_tuple(MODULE_VERSION, vm)
+    }
+
+    /// DB-API level 1: threads may share the module but not connections.
+    #[pyattr]
+    fn threadsafety(_vm: &VirtualMachine) -> i32 {
+        1
+    }
+
+    #[pyattr]
+    fn PARSE_DECLTYPES(_vm: &VirtualMachine) -> c_int {
+        super::PARSE_DECLTYPES
+    }
+
+    #[pyattr]
+    fn PARSE_COLNAMES(_vm: &VirtualMachine) -> c_int {
+        super::PARSE_COLNAMES
+    }
+
+    /// `connect(database, *, detect_types=0)`.
     #[pyfunction]
-    fn connect(path: vm::builtins::PyStrRef, py_vm: &VirtualMachine) -> PyResult<PyConnection> {
-        let c_path =
-            CString::new(path.as_str()).map_err(|e| py_vm.new_value_error(e.to_string()))?;
+    fn connect(args: ConnectArgs, py_vm: &VirtualMachine) -> PyResult<PyConnection> {
+        let c_path = CString::new(args.database.as_str())
+            .map_err(|e| py_vm.new_value_error(e.to_string()))?;
         let mut db: *mut ffi::sqlite3 = std::ptr::null_mut();
         let rc = unsafe { ffi::sqlite3_open(c_path.as_ptr(), &mut db) };
         if rc != ffi::SQLITE_OK {
@@ -684,27 +1704,39 @@ pub mod _sqlite3 {
         }
         Ok(PyConnection {
             db: Mutex::new(Some(DbPtr(db))),
+            progress_handler: Mutex::new(None),
+            detect_types: args.detect_types.unwrap_or(0),
         })
     }
 
-    /// No-op adapter registration for stdlib compatibility.
+    /// `register_adapter(type, callable)`: bind instances of exactly
+    /// `type` as `callable(obj)`, which should return an int, float, str,
+    /// bytes or None.
     #[pyfunction]
     fn register_adapter(
-        _type_obj: vm::PyObjectRef,
-        _callable: vm::PyObjectRef,
+        type_obj: vm::builtins::PyTypeRef,
+        callable: vm::PyObjectRef,
         _py_vm: &VirtualMachine,
     ) {
-        // No-op: adapter/converter system not implemented
+        let key = type_obj.get_id();
+        ADAPTERS.with(|m| {
+            m.borrow_mut().insert(key, (type_obj, callable));
+        });
     }
 
-    /// No-op converter registration for stdlib compatibility.
+    /// `register_converter(typename, callable)`: convert values of columns
+    /// of type `typename` (case-insensitive) on connections opened with
+    /// `detect_types`. The callable receives the value's bytes.
     #[pyfunction]
     fn register_converter(
-        _name: vm::builtins::PyStrRef,
-        _callable: vm::PyObjectRef,
+        name: vm::builtins::PyStrRef,
+        callable: vm::PyObjectRef,
         _py_vm: &VirtualMachine,
     ) {
-        // No-op: adapter/converter system not implemented
+        let key = name.as_str().to_uppercase();
+        CONVERTERS.with(|m| {
+            m.borrow_mut().insert(key, callable);
+        });
     }
 }
 
@@ -119,6 +119,10 @@ fn eval_arith_inner(state: &mut ShellState, expr: &str) -> i64 {
         return value;
     }
 
+    // ---- Parenthesised groups: `(i=1, i+5) * 2` ----
+    let grouped = reduce_paren_groups(state, expr);
+    let expr = grouped.as_deref().unwrap_or(expr);
+
     // ---- Variable expansion ----
     // Replace $VAR with env lookup, then bare variable names with env lookup.
     let mut expanded = expand_dollar_vars(state, expr);
@@ -152,6 +156,45 @@ fn split_comma_top_level(expr: &str) -> Option<Vec<String>> {
     Some(parts)
 }
 
+/// Evaluate each top-level parenthesised group on its own, left to right, so
+/// commas, assignments and increments inside it take effect. Each group is
+/// replaced by its value (kept in parentheses so a negative result stays a
+/// single operand). Returns None if `expr` has no group or is unbalanced.
+fn reduce_paren_groups(state: &mut ShellState, expr: &str) -> Option<String> {
+    if !expr.contains('(') {
+        return None;
+    }
+    let mut out = String::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut last = 0;
+    for (i, b) in expr.bytes().enumerate() {
+        match b {
+            b'(' => {
+                if depth == 0 {
+                    start = i;
+                }
+                depth += 1;
+            }
+            b')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    let value = eval_arith_inner(state, &expr[start + 1..i]);
+                    out.push_str(&expr[last..start]);
+                    out.push_str(&format!("({value})"));
+                    last = i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return None;
+    }
+    out.push_str(&expr[last..]);
+    Some(out)
+}
+
 /// Match `VAR++` or `VAR--` (post-increment/decrement).
 fn match_post_inc_dec(expr: &str) -> Option<(String, String)> {
     let s = expr.trim();
@@ -324,11 +367,10 @@ fn parse_env_int(state: &ShellState, var: &str) -> i64 {
 /// variables in addition to the environment.
 fn resolve_var(state: &ShellState, name: &str) -> String {
     // Special variables
-    match name {
-        "?" => return state.last_exit_code.to_string(),
-        "#" => return state.positional_args.len().to_string(),
-        "@" | "*" => return state.positional_args.join(" "),
-        _ => {}
+    if name.parse::<usize>().is_err() {
+        if let Some(value) = crate::expand::special_param(state, name) {
+            return value;
+        }
     }
     // Positional parameters ($1, $2, ...)
     if let Ok(idx) = name.parse::<usize>() {
@@ -369,6 +411,10 @@ fn expand_dollar_vars(state: &ShellState, expr: &str) -> String {
                     i += 1; // skip '}'
                 }
                 result.push_str(&resolve_var(state, name));
+            } else if i < bytes.len() && b"?#@*$!-".contains(&bytes[i]) {
+                // Special parameter: $?, $#, $$, ...
+                result.push_str(&resolve_var(state, &expr[i..i + 1]));
+                i += 1;
             } else {
                 // $VAR
                 let start = i;
@@ -438,6 +484,7 @@ fn div_trunc(a: i64, b: i64) -> i64 {
 }
 
 /// Integer exponentiation. Negative exponent yields 0 (matches bash).
+/// Overflow wraps around like bash's 64-bit integers rather than saturating.
 fn pow_i64(base: i64, exp: i64) -> i64 {
     if exp < 0 {
         return 0;
@@ -947,6 +994,14 @@ mod tests {
         assert_eq!(eval_arithmetic(&mut s, "2**3**2"), 512);
     }
 
+    #[test]
+    fn exponentiation_wraps_on_overflow() {
+        let mut s = state();
+        assert_eq!(eval_arithmetic(&mut s, "2 ** 63"), i64::MIN);
+        assert_eq!(eval_arithmetic(&mut s, "2 ** 64"), 0);
+        assert_eq!(eval_arithmetic(&mut s, "3 ** 41"), 3i64.wrapping_pow(41));
+    }
+
     // ---- Comparisons ----
 
     #[test]
@@ -1252,6 +1307,16 @@ mod tests {
         assert_eq!(s.env.get("x"), Some(&"5".to_string()));
     }
 
+    #[test]
+    fn comma_expression_in_group() {
+        let mut s = state();
+        assert_eq!(eval_arithmetic(&mut s, "x = (i=1, i+5)"), 6);
+        assert_eq!(s.env.get("i"), Some(&"1".to_string()));
+        assert_eq!(s.env.get("x"), Some(&"6".to_string()));
+        assert_eq!(eval_arithmetic(&mut s, "(3, 4) * 2"), 8);
+        assert_eq!(eval_arithmetic(&mut s, "2 - (i--, i - 1)"), 3);
+    }
+
     // ---- Division by zero ----
 
     #[test]
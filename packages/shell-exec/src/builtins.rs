@@ -6,10 +6,14 @@
 
 use std::collections::HashMap;
 
+use codepod_shell::printer::format_function;
+
 use crate::arithmetic::eval_arithmetic;
 use crate::control::RunResult;
-use crate::host::HostInterface;
-use crate::state::{ShellFlag, ShellState};
+use crate::expand::{quote_value, random_u15};
+use crate::host::{HostInterface, StatInfo, WriteMode};
+use crate::io::FdLines;
+use crate::state::{IndexedArray, ShellFlag, ShellState};
 use crate::{shell_eprint, shell_eprintln, shell_print, shell_println};
 
 // ---------------------------------------------------------------------------
@@ -59,7 +63,7 @@ pub fn try_builtin(
 
     let result = match cmd_name {
         "echo" => Some(builtin_echo(state, args)),
-        "printf" => builtin_printf(state, args),
+        "printf" => builtin_printf(state, host, args),
         "true" | ":" => Some(BuiltinResult::Result(0)),
         "false" => Some(BuiltinResult::Result(1)),
         "pwd" => Some(builtin_pwd(state)),
@@ -68,6 +72,7 @@ pub fn try_builtin(
         "export" => Some(builtin_export(state, args)),
         "unset" => Some(builtin_unset(state, args)),
         "set" => Some(builtin_set(state, args)),
+        "shopt" => Some(builtin_shopt(state, args)),
         "local" => Some(builtin_local(state, args)),
         "declare" | "typeset" => Some(builtin_declare(state, args)),
         "test" => Some(builtin_test(state, host, args)),
@@ -76,6 +81,8 @@ pub fn try_builtin(
         "shift" => Some(builtin_shift(state, args)),
         "type" => Some(builtin_type(state, host, args)),
         "command" => builtin_command(host, args),
+        "builtin" => Some(builtin_builtin(args)),
+        "hash" => Some(builtin_hash(state, host, args)),
         "let" => Some(builtin_let(state, args)),
         "which" => Some(builtin_which(host, args)),
         "source" | "." => Some(builtin_source(state, host, args, run)),
@@ -84,8 +91,10 @@ pub fn try_builtin(
         "history" => Some(builtin_history(state, args)),
         "trap" => Some(builtin_trap(state, args)),
         "getopts" => Some(builtin_getopts(state, args)),
-        "mapfile" | "readarray" => Some(builtin_mapfile(state, host, args)),
+        "mapfile" | "readarray" => Some(builtin_mapfile(state, args)),
         "chmod" => Some(builtin_chmod(state, host, args)),
+        "cp" => Some(builtin_cp(state, host, args)),
+        "mv" => Some(builtin_mv(state, host, args)),
         "date" => Some(builtin_date(host, args)),
         "exec" => Some(builtin_exec_cmd(state, host, args, stdin_data, run)),
         "readonly" => Some(builtin_readonly(state, args)),
@@ -100,6 +109,18 @@ pub fn try_builtin(
         "alias" => Some(builtin_alias(state, args)),
         "unalias" => Some(builtin_unalias(state, args)),
         "nice" => Some(builtin_nice(state, host, args)),
+        "expr" => Some(builtin_expr(args)),
+        "ulimit" => Some(builtin_ulimit(state, args)),
+        "getconf" => Some(builtin_getconf(args)),
+        "yes" => Some(builtin_yes(args)),
+        "complete" => Some(builtin_complete(state, args)),
+        "compgen" => Some(builtin_compgen(state, host, args)),
+        "cmp" => builtin_cmp(state, host, args),
+        "diff" => builtin_diff(state, host, args),
+        "stat" => builtin_stat(state, host, args),
+        "realpath" => builtin_realpath(state, host, args),
+        "readlink" => builtin_readlink(state, host, args),
+        "mktemp" => builtin_mktemp(state, host, args),
         _ => None,
     };
 
@@ -126,6 +147,7 @@ pub fn is_builtin(cmd_name: &str) -> bool {
             | "export"
             | "unset"
             | "set"
+            | "shopt"
             | "local"
             | "declare"
             | "typeset"
@@ -135,6 +157,8 @@ pub fn is_builtin(cmd_name: &str) -> bool {
             | "shift"
             | "type"
             | "command"
+            | "builtin"
+            | "hash"
             | "let"
             | "which"
             | "source"
@@ -147,6 +171,8 @@ pub fn is_builtin(cmd_name: &str) -> bool {
             | "mapfile"
             | "readarray"
             | "chmod"
+            | "cp"
+            | "mv"
             | "date"
             | "exec"
             | "readonly"
@@ -161,6 +187,18 @@ pub fn is_builtin(cmd_name: &str) -> bool {
             | "alias"
             | "unalias"
             | "nice"
+            | "expr"
+            | "ulimit"
+            | "getconf"
+            | "yes"
+            | "complete"
+            | "compgen"
+            | "cmp"
+            | "diff"
+            | "stat"
+            | "realpath"
+            | "readlink"
+            | "mktemp"
     )
 }
 
@@ -204,9 +242,9 @@ pub fn normalize_path(path: &str) -> String {
 
 // -- echo -----------------------------------------------------------------
 
-fn builtin_echo(_state: &ShellState, args: &[String]) -> BuiltinResult {
+fn builtin_echo(state: &ShellState, args: &[String]) -> BuiltinResult {
     let mut newline = true;
-    let mut interpret_escapes = false;
+    let mut interpret_escapes = state.shopt_enabled("xpg_echo");
     let mut arg_start = 0;
 
     // Parse flags: only consecutive leading args matching /^-[neE]+$/
@@ -228,92 +266,141 @@ fn builtin_echo(_state: &ShellState, args: &[String]) -> BuiltinResult {
 
     let body = args[arg_start..].join(" ");
     let output = if interpret_escapes {
-        interpret_echo_escapes(&body)
+        decode_escapes(&body, EscapeStyle::Echo)
     } else {
-        (body, false)
+        (body.into_bytes(), false)
     };
 
     let mut text = output.0;
     // \c in -e mode suppresses trailing newline and stops output
     if !output.1 && newline {
-        text.push('\n');
+        text.push(b'\n');
     }
 
-    shell_print!("{}", text);
+    crate::io::write_stdout(&text);
     BuiltinResult::Result(0)
 }
 
-/// Interpret echo escape sequences. Returns (output, stop) where stop=true
-/// means `\c` was encountered.
-fn interpret_echo_escapes(s: &str) -> (String, bool) {
-    let mut out = String::new();
+/// Which backslash-escape dialect to decode. They differ in how octal is
+/// spelled and in whether `\c` ends the output.
+#[derive(Clone, Copy, PartialEq)]
+enum EscapeStyle {
+    /// `echo -e`: octal is `\0NNN`; `\c` stops output.
+    Echo,
+    /// printf `%b` arguments: octal is `\0NNN` or `\NNN`; `\c` stops output.
+    PrintfArg,
+    /// printf format strings: octal is `\NNN`; `\c` is literal, and `\"`
+    /// and `\?` are recognised.
+    PrintfFormat,
+}
+
+/// Decode the backslash escapes in `s` into bytes, since octal and `\x`
+/// escapes name bytes rather than characters. Returns (output, stop) where
+/// stop=true means `\c` was encountered.
+fn decode_escapes(s: &str, style: EscapeStyle) -> (Vec<u8>, bool) {
+    let mut out = Vec::new();
     let chars: Vec<char> = s.chars().collect();
     let mut i = 0;
 
     while i < chars.len() {
         if chars[i] == '\\' && i + 1 < chars.len() {
             i += 1;
-            match chars[i] {
-                'n' => out.push('\n'),
-                't' => out.push('\t'),
-                'r' => out.push('\r'),
-                '\\' => out.push('\\'),
-                'a' => out.push('\x07'),
-                'b' => out.push('\x08'),
-                'f' => out.push('\x0C'),
-                'v' => out.push('\x0B'),
-                'c' => return (out, true),
-                '0' => {
-                    // Octal: \0NNN (up to 3 octal digits)
-                    let mut val = 0u32;
-                    let mut count = 0;
-                    while count < 3
-                        && i + 1 < chars.len()
-                        && chars[i + 1] >= '0'
-                        && chars[i + 1] <= '7'
-                    {
-                        i += 1;
-                        val = val * 8 + (chars[i] as u32 - '0' as u32);
-                        count += 1;
-                    }
-                    if let Some(c) = char::from_u32(val) {
-                        out.push(c);
-                    }
-                }
-                'x' => {
-                    // Hex: \xHH (up to 2 hex digits)
-                    let mut val = 0u32;
-                    let mut count = 0;
-                    while count < 2 && i + 1 < chars.len() && chars[i + 1].is_ascii_hexdigit() {
-                        i += 1;
-                        val = val * 16 + chars[i].to_digit(16).unwrap();
-                        count += 1;
-                    }
-                    if count > 0 {
-                        if let Some(c) = char::from_u32(val) {
-                            out.push(c);
-                        }
-                    } else {
-                        out.push('\\');
-                        out.push('x');
-                    }
-                }
-                other => {
-                    out.push('\\');
-                    out.push(other);
-                }
+            if decode_escape(&chars, &mut i, style, &mut out) {
+                return (out, true);
             }
         } else {
-            out.push(chars[i]);
+            push_char(&mut out, chars[i]);
         }
         i += 1;
     }
     (out, false)
 }
 
+/// Decode the escape whose first character (after the backslash) is at
+/// `chars[*i]`, leaving `*i` on its last character. Returns true for `\c`.
+fn decode_escape(chars: &[char], i: &mut usize, style: EscapeStyle, out: &mut Vec<u8>) -> bool {
+    let c = chars[*i];
+    match c {
+        'n' => out.push(b'\n'),
+        't' => out.push(b'\t'),
+        'r' => out.push(b'\r'),
+        '\\' => out.push(b'\\'),
+        'a' => out.push(0x07),
+        'b' => out.push(0x08),
+        'f' => out.push(0x0C),
+        'v' => out.push(0x0B),
+        'e' | 'E' => out.push(0x1B),
+        'c' if style != EscapeStyle::PrintfFormat => return true,
+        '"' | '?' if style == EscapeStyle::PrintfFormat => push_char(out, c),
+        '0' if style != EscapeStyle::PrintfFormat => {
+            let val = take_digits(chars, i, 8, 3).unwrap_or(0);
+            push_byte(out, val);
+        }
+        '0'..='7' if style != EscapeStyle::Echo => {
+            *i -= 1;
+            let val = take_digits(chars, i, 8, 3).unwrap_or(0);
+            push_byte(out, val);
+        }
+        'x' | 'u' | 'U' => {
+            let max = match c {
+                'x' => 2,
+                'u' => 4,
+                _ => 8,
+            };
+            match take_digits(chars, i, 16, max) {
+                Some(val) if c == 'x' => push_byte(out, val),
+                Some(val) => {
+                    if let Some(ch) = char::from_u32(val) {
+                        push_char(out, ch);
+                    }
+                }
+                None => {
+                    out.push(b'\\');
+                    push_char(out, c);
+                }
+            }
+        }
+        _ => {
+            out.push(b'\\');
+            push_char(out, c);
+        }
+    }
+    false
+}
+
+/// Append the byte `val` (octal escapes wrap at 256) to `out`.
+fn push_byte(out: &mut Vec<u8>, val: u32) {
+    out.push((val & 0xFF) as u8);
+}
+
+/// Append `c` to `out` as UTF-8.
+fn push_char(out: &mut Vec<u8>, c: char) {
+    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// Consume up to `max` digits in `radix` following `chars[*i]`, advancing
+/// `*i` past them. Returns None if there were none.
+fn take_digits(chars: &[char], i: &mut usize, radix: u32, max: usize) -> Option<u32> {
+    let mut val = 0u32;
+    let mut count = 0;
+    while count < max {
+        match chars.get(*i + 1).and_then(|c| c.to_digit(radix)) {
+            Some(d) => val = val * radix + d,
+            None => break,
+        }
+        *i += 1;
+        count += 1;
+    }
+    (count > 0).then_some(val)
+}
+
 // -- printf ---------------------------------------------------------------
 
-fn builtin_printf(state: &mut ShellState, args: &[String]) -> Option<BuiltinResult> {
+fn builtin_printf(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> Option<BuiltinResult> {
     if args.is_empty() {
         shell_eprint!("{}", "printf: usage: printf [-v var] format [arguments]\n");
         return Some(BuiltinResult::Result(1));
@@ -341,127 +428,354 @@ fn builtin_printf(state: &mut ShellState, args: &[String]) -> Option<BuiltinResu
     arg_idx += 1;
     let fmt_args = &args[arg_idx..];
 
-    let output = format_printf(format, fmt_args);
+    let mut invalid = Vec::new();
+    let output = format_printf(format, fmt_args, host.time() as u64, &mut invalid);
+    for spec in &invalid {
+        shell_eprintln!("printf: {}: invalid conversion specification", spec);
+    }
+    let code = if invalid.is_empty() { 0 } else { 2 };
 
     if let Some(name) = var_name {
         // -v mode: store into variable, no stdout output
-        state.env.insert(name, output);
-        Some(BuiltinResult::Result(0))
+        state
+            .env
+            .insert(name.clone(), String::from_utf8_lossy(&output).into_owned());
+        state.var_changed(&name);
     } else {
         // Normal mode: write to stdout fd
-        shell_print!("{}", output);
-        Some(BuiltinResult::Result(0))
+        crate::io::write_stdout(&output);
     }
+    Some(BuiltinResult::Result(code))
 }
 
-fn format_printf(format: &str, args: &[String]) -> String {
-    let mut out = String::new();
+/// Expand a printf format. Conversions take `[flags][width][.precision]`
+/// with flags from `-+ 0#'` (`'` groups thousands with commas); a `*` width
+/// or precision is taken from the next argument. Besides the usual
+/// `%s %b %c %d %i %u %f %e %g %x %X %o`, `%q` quotes its argument for
+/// reuse as shell input, and bash's `%(datefmt)T` formats an
+/// epoch argument with the `date +FORMAT` subset; `-1`, `-2` (there is no
+/// separate shell start time) and a missing argument all mean `now`.
+///
+/// An unknown conversion such as `%z` is copied to the output literally,
+/// consumes no argument, and is pushed onto `invalid` for the caller to
+/// report.
+fn format_printf(format: &str, args: &[String], now: u64, invalid: &mut Vec<String>) -> Vec<u8> {
+    let mut out = Vec::new();
     let chars: Vec<char> = format.chars().collect();
-    let mut i = 0;
-    let mut arg_idx = 0;
+    let mut args = args.iter();
 
-    while i < chars.len() {
-        if chars[i] == '\\' && i + 1 < chars.len() {
-            i += 1;
-            match chars[i] {
-                'n' => out.push('\n'),
-                't' => out.push('\t'),
-                '\\' => out.push('\\'),
-                '"' => out.push('"'),
-                'r' => out.push('\r'),
-                '0' => {
-                    // Octal escape: \0, \0N, \0NN, \0NNN
-                    let mut octal = String::new();
-                    while i + 1 < chars.len() && octal.len() < 3 && chars[i + 1].is_digit(8) {
-                        i += 1;
-                        octal.push(chars[i]);
-                    }
-                    let val = if octal.is_empty() {
-                        0u32
-                    } else {
-                        u32::from_str_radix(&octal, 8).unwrap_or(0)
-                    };
-                    if let Some(ch) = char::from_u32(val) {
-                        out.push(ch);
-                    }
+    // The format is reused while arguments remain; conversions past the
+    // last argument see "" or 0, and a pass that consumes nothing ends it.
+    loop {
+        let remaining = args.len();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '\\' && i + 1 < chars.len() {
+                i += 1;
+                decode_escape(&chars, &mut i, EscapeStyle::PrintfFormat, &mut out);
+            } else if chars[i] == '%' && i + 1 < chars.len() {
+                i += 1;
+                if chars[i] == '%' {
+                    out.push(b'%');
+                    i += 1;
+                    continue;
                 }
-                _ => {
-                    out.push('\\');
-                    out.push(chars[i]);
+                let spec_start = i;
+                let mut flags = String::new();
+                while i < chars.len() && "-+ 0#'".contains(chars[i]) {
+                    flags.push(chars[i]);
+                    i += 1;
                 }
-            }
-        } else if chars[i] == '%' && i + 1 < chars.len() {
-            i += 1;
-            match chars[i] {
-                '%' => out.push('%'),
-                's' => {
-                    if arg_idx < args.len() {
-                        out.push_str(&args[arg_idx]);
-                        arg_idx += 1;
+                let int_arg = |arg: Option<&String>| arg.and_then(|s| s.parse::<i64>().ok());
+                // A negative `*` width left-aligns; a negative `*`
+                // precision counts as none.
+                let width = if chars.get(i) == Some(&'*') {
+                    i += 1;
+                    let w = int_arg(args.next()).unwrap_or(0);
+                    if w < 0 {
+                        flags.push('-');
                     }
-                }
-                'd' => {
-                    let val = if arg_idx < args.len() {
-                        let s = &args[arg_idx];
-                        arg_idx += 1;
-                        s.parse::<i64>().unwrap_or(0)
-                    } else {
-                        0
-                    };
-                    out.push_str(&val.to_string());
-                }
-                'f' => {
-                    let val = if arg_idx < args.len() {
-                        let s = &args[arg_idx];
-                        arg_idx += 1;
-                        s.parse::<f64>().unwrap_or(0.0)
-                    } else {
-                        0.0
-                    };
-                    out.push_str(&format!("{:.6}", val));
-                }
-                'x' => {
-                    let val = if arg_idx < args.len() {
-                        let s = &args[arg_idx];
-                        arg_idx += 1;
-                        s.parse::<i64>().unwrap_or(0)
-                    } else {
-                        0
-                    };
-                    out.push_str(&format!("{:x}", val));
-                }
-                'o' => {
-                    let val = if arg_idx < args.len() {
-                        let s = &args[arg_idx];
-                        arg_idx += 1;
-                        s.parse::<i64>().unwrap_or(0)
+                    w.unsigned_abs() as usize
+                } else {
+                    take_number(&chars, &mut i).unwrap_or(0)
+                };
+                let precision = if chars.get(i) == Some(&'.') {
+                    i += 1;
+                    if chars.get(i) == Some(&'*') {
+                        i += 1;
+                        usize::try_from(int_arg(args.next()).unwrap_or(0)).ok()
                     } else {
-                        0
-                    };
-                    out.push_str(&format!("{:o}", val));
-                }
-                'c' => {
-                    if arg_idx < args.len() {
-                        let s = &args[arg_idx];
-                        arg_idx += 1;
-                        if let Some(c) = s.chars().next() {
-                            out.push(c);
+                        Some(take_number(&chars, &mut i).unwrap_or(0))
+                    }
+                } else {
+                    None
+                };
+                let Some(&conv) = chars.get(i) else {
+                    out.push(b'%');
+                    for &c in &chars[spec_start..] {
+                        push_char(&mut out, c);
+                    }
+                    break;
+                };
+                let field = match conv {
+                    'b' => {
+                        let (text, stop) = decode_escapes(
+                            args.next().map_or("", |s| s.as_str()),
+                            EscapeStyle::PrintfArg,
+                        );
+                        // `\c` in a %b argument ends all output.
+                        if stop {
+                            out.extend(pad_field(text, width, &flags, false));
+                            return out;
                         }
+                        text
                     }
+                    's' => {
+                        let s = args.next().map_or("", |s| s.as_str());
+                        match precision {
+                            Some(p) => s.chars().take(p).collect::<String>().into_bytes(),
+                            None => s.as_bytes().to_vec(),
+                        }
+                    }
+                    'q' => shell_quote(args.next().map_or("", |s| s.as_str())).into_bytes(),
+                    'c' => args
+                        .next()
+                        .and_then(|s| s.chars().next())
+                        .map(String::from)
+                        .unwrap_or_default()
+                        .into_bytes(),
+                    'd' | 'i' | 'u' => {
+                        let val = int_arg(args.next()).unwrap_or(0);
+                        let digits = val.unsigned_abs().to_string();
+                        let digits = if flags.contains('\'') {
+                            group_thousands(&digits)
+                        } else {
+                            digits
+                        };
+                        format!("{}{}", sign_prefix(val < 0, &flags), digits).into_bytes()
+                    }
+                    'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                        let val = args.next().and_then(|s| s.parse::<f64>().ok());
+                        let val = val.unwrap_or(0.0);
+                        let precision = precision.unwrap_or(6);
+                        let upper = conv.is_ascii_uppercase();
+                        let text = match conv {
+                            'e' | 'E' => format_exponent(val.abs(), precision, upper),
+                            'g' | 'G' => {
+                                format_general(val.abs(), precision, upper, flags.contains('#'))
+                            }
+                            _ => format!("{:.*}", precision, val.abs()),
+                        };
+                        let grouped = flags.contains('\'') && !text.contains(['e', 'E']);
+                        let text = match text.split_once('.') {
+                            Some((int, frac)) if grouped => {
+                                format!("{}.{}", group_thousands(int), frac)
+                            }
+                            None if grouped => group_thousands(&text),
+                            _ => text,
+                        };
+                        let sign = sign_prefix(val.is_sign_negative(), &flags);
+                        format!("{sign}{text}").into_bytes()
+                    }
+                    'x' | 'X' | 'o' => {
+                        let val = int_arg(args.next()).unwrap_or(0);
+                        let alt = flags.contains('#') && val != 0;
+                        let text = match conv {
+                            'x' if alt => format!("0x{:x}", val),
+                            'x' => format!("{:x}", val),
+                            'X' if alt => format!("0X{:X}", val),
+                            'X' => format!("{:X}", val),
+                            _ if alt => format!("0{:o}", val),
+                            _ => format!("{:o}", val),
+                        };
+                        text.into_bytes()
+                    }
+                    '(' => {
+                        let close = (i + 1..chars.len().saturating_sub(1))
+                            .find(|&j| chars[j] == ')' && chars[j + 1] == 'T');
+                        let Some(close) = close else {
+                            out.push(b'%');
+                            for &c in &chars[spec_start..] {
+                                push_char(&mut out, c);
+                            }
+                            break;
+                        };
+                        let date_format: String = chars[i + 1..close].iter().collect();
+                        i = close + 1;
+                        let ts = match int_arg(args.next()) {
+                            Some(ts) if ts >= 0 => ts as u64,
+                            _ => now,
+                        };
+                        apply_date_format(&date_format, ts).into_bytes()
+                    }
+                    _ => {
+                        let spec: String = std::iter::once('%')
+                            .chain(chars[spec_start..=i].iter().copied())
+                            .collect();
+                        out.extend_from_slice(spec.as_bytes());
+                        invalid.push(spec);
+                        i += 1;
+                        continue;
+                    }
+                };
+                let numeric = "diufFeEgGxXo".contains(conv);
+                out.extend(pad_field(field, width, &flags, numeric));
+            } else {
+                push_char(&mut out, chars[i]);
+            }
+            i += 1;
+        }
+        if args.len() == 0 || args.len() == remaining {
+            break;
+        }
+    }
+    out
+}
+
+/// Quote `s` the way `printf %q` does: unchanged when every character is
+/// safe, `$'...'` when it holds control characters, else backslash-escaped.
+fn shell_quote(s: &str) -> String {
+    if s.is_empty() {
+        return "''".to_string();
+    }
+    let mut out = String::new();
+    if s.chars().any(|c| c.is_control()) {
+        out.push_str("$'");
+        for c in s.chars() {
+            match c {
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                '\r' => out.push_str("\\r"),
+                '\x1B' => out.push_str("\\E"),
+                '\x07' => out.push_str("\\a"),
+                '\x08' => out.push_str("\\b"),
+                '\x0C' => out.push_str("\\f"),
+                '\x0B' => out.push_str("\\v"),
+                '\\' | '\'' => {
+                    out.push('\\');
+                    out.push(c);
                 }
-                other => {
-                    out.push('%');
-                    out.push(other);
-                }
+                c if c.is_control() => out.push_str(&format!("\\{:03o}", c as u32)),
+                c => out.push(c),
             }
-        } else {
-            out.push(chars[i]);
         }
-        i += 1;
+        out.push('\'');
+        return out;
+    }
+    for c in s.chars() {
+        if c.is_ascii() && !c.is_ascii_alphanumeric() && !"_@%+=:,./-".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Consume a run of decimal digits at `chars[*i]`, advancing past it.
+fn take_number(chars: &[char], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect::<String>().parse().ok()
+}
+
+/// Format a non-negative `val` like C's `%e`: `1.234500e+03`.
+fn format_exponent(val: f64, precision: usize, upper: bool) -> String {
+    let text = if val.is_finite() {
+        let text = format!("{:.*e}", precision, val);
+        let (mantissa, exp) = text.split_once('e').unwrap_or((&text, "0"));
+        let exp: i32 = exp.parse().unwrap_or(0);
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{mantissa}e{sign}{:02}", exp.unsigned_abs())
+    } else {
+        val.to_string()
+    };
+    if upper {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+/// Format a non-negative `val` like C's `%g`: `precision` significant
+/// digits in `%e` style for very large or small exponents and `%f` style
+/// otherwise, with trailing zeros dropped unless `alt` (`#`) is given.
+fn format_general(val: f64, precision: usize, upper: bool, alt: bool) -> String {
+    let precision = precision.max(1);
+    if !val.is_finite() {
+        return format_exponent(val, precision, upper);
+    }
+    let exp = format!("{:.*e}", precision - 1, val)
+        .split_once('e')
+        .and_then(|(_, exp)| exp.parse::<i32>().ok())
+        .unwrap_or(0);
+    let text = if exp < -4 || exp >= precision as i32 {
+        format_exponent(val, precision - 1, upper)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exp) as usize, val)
+    };
+    if alt {
+        return text;
+    }
+    let (mantissa, exp_part) = text.split_at(text.find(['e', 'E']).unwrap_or(text.len()));
+    let mantissa = if mantissa.contains('.') {
+        mantissa.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        mantissa
+    };
+    format!("{mantissa}{exp_part}")
+}
+
+/// The sign printed before a number under the `+` and ` ` printf flags.
+fn sign_prefix(negative: bool, flags: &str) -> &'static str {
+    if negative {
+        "-"
+    } else if flags.contains('+') {
+        "+"
+    } else if flags.contains(' ') {
+        " "
+    } else {
+        ""
+    }
+}
+
+/// Insert a comma between each group of three digits: `1234567` → `1,234,567`.
+fn group_thousands(digits: &str) -> String {
+    let mut out = String::new();
+    for (n, c) in digits.chars().enumerate() {
+        if n > 0 && (digits.len() - n).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
     }
     out
 }
 
+/// Pad a converted printf field to `width`: left-aligned with `-`, with
+/// zeros after any sign or `0x` prefix for numbers under `0`, else spaces.
+/// Width counts characters, or bytes when the field is not valid UTF-8.
+fn pad_field(field: Vec<u8>, width: usize, flags: &str, numeric: bool) -> Vec<u8> {
+    let len = std::str::from_utf8(&field).map_or(field.len(), |s| s.chars().count());
+    if len >= width {
+        return field;
+    }
+    let fill = width - len;
+    if flags.contains('-') {
+        [field, vec![b' '; fill]].concat()
+    } else if numeric && flags.contains('0') {
+        let prefix_len = if field.starts_with(b"0x") || field.starts_with(b"0X") {
+            2
+        } else {
+            usize::from(matches!(field.first(), Some(b'-' | b'+' | b' ')))
+        };
+        let (prefix, rest) = field.split_at(prefix_len);
+        [prefix, &vec![b'0'; fill], rest].concat()
+    } else {
+        [vec![b' '; fill], field].concat()
+    }
+}
+
 // -- pwd ------------------------------------------------------------------
 
 fn builtin_pwd(state: &ShellState) -> BuiltinResult {
@@ -504,18 +818,15 @@ fn builtin_cd(state: &mut ShellState, host: &dyn HostInterface, args: &[String])
     let normalized = normalize_path(&resolved);
 
     // Check that the target is a directory
+    let shown = args.first().unwrap_or(&target);
     match host.stat(&normalized) {
-        Ok(info) => {
-            if !info.exists || !info.is_dir {
-                shell_eprint!("cd: {}: Not a directory\n", args.first().unwrap_or(&target));
-                return BuiltinResult::Result(1);
-            }
+        Ok(info) if info.exists && info.is_dir => {}
+        Ok(info) if info.exists => {
+            shell_eprint!("cd: {}: Not a directory\n", shown);
+            return BuiltinResult::Result(1);
         }
-        Err(_) => {
-            shell_eprint!(
-                "cd: {}: No such file or directory\n",
-                args.first().unwrap_or(&target)
-            );
+        _ => {
+            shell_eprint!("cd: {}: No such file or directory\n", shown);
             return BuiltinResult::Result(1);
         }
     }
@@ -532,10 +843,16 @@ fn builtin_cd(state: &mut ShellState, host: &dyn HostInterface, args: &[String])
 // -- exit -----------------------------------------------------------------
 
 fn builtin_exit(state: &ShellState, args: &[String]) -> BuiltinResult {
-    let code = if args.is_empty() {
-        state.last_exit_code
-    } else {
-        args[0].parse::<i32>().unwrap_or(2)
+    let code = match args.first() {
+        None => state.last_exit_code,
+        Some(arg) => match arg.parse::<i64>() {
+            // Exit statuses are a byte: `exit 256` is 0, `exit -1` is 255.
+            Ok(n) => (n & 0xff) as i32,
+            Err(_) => {
+                shell_eprintln!("exit: {}: numeric argument required", arg);
+                2
+            }
+        },
     };
     BuiltinResult::Exit(code)
 }
@@ -543,86 +860,123 @@ fn builtin_exit(state: &ShellState, args: &[String]) -> BuiltinResult {
 // -- export ---------------------------------------------------------------
 
 fn builtin_export(state: &mut ShellState, args: &[String]) -> BuiltinResult {
-    if args.is_empty() || (args.len() == 1 && args[0] == "-p") {
-        // Print all env vars sorted
-        let mut vars: Vec<(&String, &String)> = state.env.iter().collect();
-        vars.sort_by_key(|(k, _)| (*k).clone());
-        let mut output = String::new();
-        for (k, v) in vars {
-            output.push_str(&format!("declare -x {}=\"{}\"\n", k, v));
-        }
-        shell_print!("{}", output);
-        return BuiltinResult::Result(0);
-    }
-
-    // Check for -n flag (unexport)
     let mut unexport = false;
-    let mut real_args: Vec<&str> = Vec::new();
+    let mut names: Vec<&str> = Vec::new();
     for arg in args {
-        if arg == "-p" {
-            continue;
-        } else if arg == "-n" {
-            unexport = true;
-        } else {
-            real_args.push(arg);
+        match arg.as_str() {
+            "-p" | "--" => {}
+            "-n" => unexport = true,
+            _ => names.push(arg),
         }
     }
 
-    for arg in &real_args {
-        if unexport {
-            // Remove from env (unexport) — variable value is lost to subshells
-            state.env.remove(*arg);
-        } else if let Some(eq_pos) = arg.find('=') {
-            let name = &arg[..eq_pos];
-            let value = &arg[eq_pos + 1..];
-            state.env.insert(name.to_string(), value.to_string());
-        } else {
-            // export NAME without value: promote from variables to env
-            if state.env.contains_key(*arg) {
-                // Already exported, nothing to do
-            } else {
-                state.env.entry(arg.to_string()).or_default();
+    if names.is_empty() {
+        shell_print!("{}", exported_listing(state));
+        return BuiltinResult::Result(0);
+    }
+
+    let mut code = 0;
+    for arg in names {
+        let name = match arg.find('=') {
+            Some(eq_pos) => {
+                let name = &arg[..eq_pos];
+                if state.readonly_vars.contains(name) {
+                    shell_eprintln!("export: {}: readonly variable", name);
+                    code = 1;
+                    continue;
+                }
+                state
+                    .env
+                    .insert(name.to_string(), arg[eq_pos + 1..].to_string());
+                state.var_changed(name);
+                name
             }
-        }
+            None => {
+                // export NAME without value: promote from variables to env
+                if !unexport {
+                    state.env.entry(arg.to_string()).or_default();
+                }
+                arg
+            }
+        };
+        set_exported(state, name, !unexport);
     }
 
-    BuiltinResult::Result(0)
+    BuiltinResult::Result(code)
+}
+
+/// Mark `name` as exported to child processes, or withhold it while
+/// keeping its value (`export -n`, `declare +x`).
+fn set_exported(state: &mut ShellState, name: &str, exported: bool) {
+    if exported {
+        state.unexported.remove(name);
+    } else {
+        state.unexported.insert(name.to_string());
+    }
+}
+
+/// `declare -x NAME="value"` lines for every exported variable, sorted,
+/// in a form that can be fed back to the shell.
+fn exported_listing(state: &ShellState) -> String {
+    let mut vars = state.exported_env();
+    vars.sort();
+    let mut output = String::new();
+    for (k, v) in vars {
+        output.push_str(&format!("declare -x {}={}\n", k, quote_value(v)));
+    }
+    output
 }
 
 // -- unset ----------------------------------------------------------------
 
 fn builtin_unset(state: &mut ShellState, args: &[String]) -> BuiltinResult {
+    let mut code = 0;
     for arg in args {
-        // Check for array element: arr[idx]
+        let name = arg.split('[').next().unwrap_or(arg);
+        if state.readonly_vars.contains(name) {
+            shell_eprintln!("unset: {}: cannot unset: readonly variable", name);
+            code = 1;
+            continue;
+        }
+
+        // Check for array element: arr[idx] or map[key]
         if let Some(bracket_pos) = arg.find('[') {
             if arg.ends_with(']') {
                 let name = &arg[..bracket_pos];
                 let idx_str = &arg[bracket_pos + 1..arg.len() - 1];
 
-                // Try indexed array
-                if let Ok(idx) = idx_str.parse::<usize>() {
+                if let Some(map) = state.assoc_arrays.get_mut(name) {
+                    map.remove(idx_str);
+                    continue;
+                }
+
+                // Later elements keep their indices; negative indices count
+                // back from the highest one.
+                if let Ok(idx) = idx_str.parse::<i64>() {
                     if let Some(arr) = state.arrays.get_mut(name) {
-                        if idx < arr.len() {
-                            arr[idx] = String::new();
+                        let idx = if idx < 0 { arr.end() as i64 + idx } else { idx };
+                        if let Ok(idx) = usize::try_from(idx) {
+                            arr.unset(idx);
                         }
                         continue;
                     }
-                }
-
-                // Try associative array
-                if let Some(map) = state.assoc_arrays.get_mut(name) {
-                    map.remove(idx_str);
-                    continue;
+                    // A scalar is element 0 of itself.
+                    if idx != 0 {
+                        continue;
+                    }
                 }
             }
         }
 
-        state.env.remove(arg);
-        state.arrays.remove(arg);
-        state.assoc_arrays.remove(arg);
+        state.env.remove(name);
+        state.var_changed(name);
+        state.arrays.remove(name);
+        state.assoc_arrays.remove(name);
+        state.unexported.remove(name);
+        state.integer_vars.remove(name);
     }
 
-    BuiltinResult::Result(0)
+    BuiltinResult::Result(code)
 }
 
 // -- set ------------------------------------------------------------------
@@ -671,6 +1025,13 @@ fn builtin_set(state: &mut ShellState, args: &[String]) -> BuiltinResult {
                                 state.flags.remove(&ShellFlag::Nounset);
                             }
                         }
+                        "posix" => {
+                            if add {
+                                state.flags.insert(ShellFlag::Posix);
+                            } else {
+                                state.flags.remove(&ShellFlag::Posix);
+                            }
+                        }
                         _ => {}
                     }
                 }
@@ -702,33 +1063,104 @@ fn builtin_set(state: &mut ShellState, args: &[String]) -> BuiltinResult {
     BuiltinResult::Result(0)
 }
 
-// -- local ----------------------------------------------------------------
-
-fn builtin_local(state: &mut ShellState, args: &[String]) -> BuiltinResult {
-    if state.local_var_stack.is_empty() {
-        shell_eprint!("{}", "local: can only be used in a function\n");
-        return BuiltinResult::Result(1);
-    }
+// -- shopt ----------------------------------------------------------------
 
+/// `shopt [-pqsu] [optname...]`
+///
+/// `-s`/`-u` turn the named options on or off. Otherwise each option's
+/// state is printed, as reusable `shopt -s`/`shopt -u` lines with `-p`.
+/// When names are given, the exit status is 0 only if all are on.
+fn builtin_shopt(state: &mut ShellState, args: &[String]) -> BuiltinResult {
+    let mut set: Option<bool> = None;
+    let mut print = false;
+    let mut quiet = false;
+    let mut names: Vec<&str> = Vec::new();
     for arg in args {
-        if let Some(eq_pos) = arg.find('=') {
-            let name = &arg[..eq_pos];
-            let value = &arg[eq_pos + 1..];
-
-            // Save previous value in the current local frame
-            let prev = state.env.get(name).cloned();
-            if let Some(frame) = state.local_var_stack.last_mut() {
-                frame.entry(name.to_string()).or_insert(prev);
-            }
-
-            state.env.insert(name.to_string(), value.to_string());
-        } else {
-            // local VAR (no value): save previous and set to empty
-            let prev = state.env.get(arg).cloned();
-            if let Some(frame) = state.local_var_stack.last_mut() {
-                frame.entry(arg.clone()).or_insert(prev);
+        match arg.strip_prefix('-') {
+            Some(flags) if names.is_empty() && !flags.is_empty() => {
+                for c in flags.chars() {
+                    match c {
+                        's' => set = Some(true),
+                        'u' => set = Some(false),
+                        'p' => print = true,
+                        'q' => quiet = true,
+                        _ => {
+                            shell_eprintln!("shopt: -{}: invalid option", c);
+                            return BuiltinResult::Result(2);
+                        }
+                    }
+                }
             }
-            state.env.entry(arg.clone()).or_default();
+            _ => names.push(arg),
+        }
+    }
+
+    if let Some(name) = names.iter().find(|n| !state.shopt.contains_key(**n)) {
+        shell_eprintln!("shopt: {}: invalid shell option name", name);
+        return BuiltinResult::Result(1);
+    }
+
+    if let (Some(on), false) = (set, names.is_empty()) {
+        for name in names {
+            state.shopt.insert(name.to_string(), on);
+        }
+        return BuiltinResult::Result(0);
+    }
+
+    // Bare `shopt -s` / `shopt -u` lists the options in that state.
+    let mut listed: Vec<(&str, bool)> = if names.is_empty() {
+        state
+            .shopt
+            .iter()
+            .map(|(name, on)| (name.as_str(), *on))
+            .filter(|(_, on)| set.is_none() || set == Some(*on))
+            .collect()
+    } else {
+        names.iter().map(|n| (*n, state.shopt_enabled(n))).collect()
+    };
+    if names.is_empty() {
+        listed.sort();
+    }
+
+    let mut all_on = true;
+    for (name, on) in listed {
+        all_on &= on;
+        if quiet {
+            continue;
+        }
+        if print {
+            shell_println!("shopt {} {}", if on { "-s" } else { "-u" }, name);
+        } else {
+            shell_println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+        }
+    }
+    BuiltinResult::Result(if names.is_empty() || all_on { 0 } else { 1 })
+}
+
+// -- local ----------------------------------------------------------------
+
+fn builtin_local(state: &mut ShellState, args: &[String]) -> BuiltinResult {
+    if state.local_var_stack.is_empty() {
+        shell_eprint!("{}", "local: can only be used in a function\n");
+        return BuiltinResult::Result(1);
+    }
+
+    for arg in args {
+        if let Some(eq_pos) = arg.find('=') {
+            let name = &arg[..eq_pos];
+            let value = &arg[eq_pos + 1..];
+
+            // Save previous value in the current local frame
+            state.save_local(name);
+            state.arrays.remove(name);
+            state.assoc_arrays.remove(name);
+
+            state.env.insert(name.to_string(), value.to_string());
+            state.var_changed(name);
+        } else {
+            // local VAR (no value): save previous and set to empty
+            state.save_local(arg);
+            state.env.entry(arg.clone()).or_default();
         }
     }
 
@@ -741,51 +1173,84 @@ fn builtin_declare(state: &mut ShellState, args: &[String]) -> BuiltinResult {
     let mut is_assoc = false;
     let mut is_array = false;
     let mut is_export = false;
+    let mut is_unexport = false;
+    let mut is_integer = false;
+    let mut is_readonly = false;
     let mut is_print = false;
+    let mut is_func = false;
+    let mut names_only = false;
     let mut assignments: Vec<&String> = Vec::new();
 
     for arg in args {
         match arg.as_str() {
             "-A" => is_assoc = true,
             "-a" => is_array = true,
+            "-i" => is_integer = true,
+            "-r" => is_readonly = true,
             "-x" => is_export = true,
+            "+x" => is_unexport = true,
             "-p" => is_print = true,
+            "-f" => is_func = true,
+            "-F" => {
+                is_func = true;
+                names_only = true;
+            }
+            "--" => {}
             _ => assignments.push(arg),
         }
     }
 
+    if is_func {
+        let names: Vec<&String> = if assignments.is_empty() {
+            let mut all: Vec<&String> = state.functions.keys().collect();
+            all.sort();
+            all
+        } else {
+            assignments
+        };
+        let mut exit_code = 0;
+        for name in names {
+            match state.functions.get(name) {
+                Some(_) if names_only => shell_println!("declare -f {}", name),
+                Some(body) => shell_println!("{}", format_function(name, body)),
+                None => exit_code = 1,
+            }
+        }
+        return BuiltinResult::Result(exit_code);
+    }
+
+    if is_export && !is_print && assignments.is_empty() {
+        shell_print!("{}", exported_listing(state));
+        return BuiltinResult::Result(0);
+    }
+
     if is_print {
         let mut output = String::new();
         let mut stderr = String::new();
         let mut exit_code = 0;
-        for arg in &assignments {
-            if let Some(val) = state.env.get(*arg) {
-                output.push_str(&format!("declare -- {}=\"{}\"\n", arg, val));
-            } else if let Some(arr) = state.arrays.get(*arg) {
-                let items: Vec<String> = arr
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, v)| format!("[{}]=\"{}\"", idx, v))
-                    .collect();
-                output.push_str(&format!("declare -a {}=({})\n", arg, items.join(" ")));
-            } else if let Some(map) = state.assoc_arrays.get(*arg) {
-                let mut items: Vec<String> = map
-                    .iter()
-                    .map(|(k, v)| format!("[{}]=\"{}\"", k, v))
-                    .collect();
-                items.sort();
-                output.push_str(&format!("declare -A {}=({})\n", arg, items.join(" ")));
-            } else {
-                stderr.push_str(&format!("declare: {}: not found\n", arg));
-                exit_code = 1;
-            }
-        }
-        if assignments.is_empty() {
-            // Print all variables
-            let mut vars: Vec<(&String, &String)> = state.env.iter().collect();
-            vars.sort_by_key(|(k, _)| (*k).clone());
-            for (k, v) in vars {
-                output.push_str(&format!("declare -- {}=\"{}\"\n", k, v));
+        let names: Vec<&String> = if assignments.is_empty() {
+            let mut all: Vec<&String> = state
+                .env
+                .keys()
+                .chain(state.arrays.keys())
+                .chain(state.assoc_arrays.keys())
+                .collect();
+            all.sort();
+            all.dedup();
+            all
+        } else {
+            assignments
+        };
+        for name in names {
+            match declare_line(state, name) {
+                Some(line) => {
+                    output.push_str(&line);
+                    output.push('\n');
+                }
+                None => {
+                    stderr.push_str(&format!("declare: {}: not found\n", name));
+                    exit_code = 1;
+                }
             }
         }
         shell_print!("{}", output);
@@ -795,11 +1260,27 @@ fn builtin_declare(state: &mut ShellState, args: &[String]) -> BuiltinResult {
         return BuiltinResult::Result(exit_code);
     }
 
+    let mut code = 0;
     for arg in assignments {
+        let name = arg.split('=').next().unwrap_or(arg);
+        if arg.contains('=') && state.readonly_vars.contains(name) {
+            shell_eprintln!("declare: {}: readonly variable", name);
+            code = 1;
+            continue;
+        }
+        if is_readonly {
+            state.readonly_vars.insert(name.to_string());
+        }
         if let Some(eq_pos) = arg.find('=') {
             let name = &arg[..eq_pos];
             let value = &arg[eq_pos + 1..];
 
+            // Save to local frame if in function (before any mutation)
+            state.save_local(name);
+
+            if is_integer {
+                state.integer_vars.insert(name.to_string());
+            }
             if is_assoc {
                 // declare -A map=([key1]=val1 [key2]=val2)
                 let map = parse_assoc_array_literal(value);
@@ -808,34 +1289,28 @@ fn builtin_declare(state: &mut ShellState, args: &[String]) -> BuiltinResult {
                 // declare -a arr=(elem1 elem2)
                 let arr = parse_array_literal(value);
                 state.arrays.insert(name.to_string(), arr);
+            } else if is_integer {
+                let n = eval_arithmetic(state, value);
+                state.env.insert(name.to_string(), n.to_string());
             } else {
-                // Capture previous value BEFORE insert for local frame
-                let prev_value = state.env.get(name).cloned();
                 state.env.insert(name.to_string(), value.to_string());
-                if is_export {
-                    // already in env, which is our "exported" set
-                }
-                // Save to local frame if in function
-                if let Some(frame) = state.local_var_stack.last_mut() {
-                    frame.entry(name.to_string()).or_insert(prev_value);
-                }
             }
-
-            // Save to local frame for arrays
-            if is_assoc || is_array {
-                if let Some(frame) = state.local_var_stack.last_mut() {
-                    frame.entry(name.to_string()).or_insert(None);
-                }
+            state.var_changed(name);
+            if is_export || is_unexport {
+                set_exported(state, name, is_export);
             }
         } else {
             // declare VAR without value
+            if is_integer {
+                state.integer_vars.insert(arg.clone());
+            }
             if is_assoc {
                 state.assoc_arrays.entry(arg.clone()).or_default();
             } else if is_array {
                 // Convert existing scalar to array[0] if not already an array
                 if !state.arrays.contains_key(arg) {
                     if let Some(val) = state.env.remove(arg) {
-                        state.arrays.insert(arg.clone(), vec![val]);
+                        state.arrays.insert(arg.clone(), vec![val].into());
                     } else {
                         state.arrays.entry(arg.clone()).or_default();
                     }
@@ -843,10 +1318,13 @@ fn builtin_declare(state: &mut ShellState, args: &[String]) -> BuiltinResult {
             } else {
                 state.env.entry(arg.clone()).or_default();
             }
+            if is_export || is_unexport {
+                set_exported(state, arg, is_export);
+            }
         }
     }
 
-    BuiltinResult::Result(0)
+    BuiltinResult::Result(code)
 }
 
 /// Parse `([key1]=val1 [key2]=val2)` into a HashMap.
@@ -876,7 +1354,11 @@ fn parse_assoc_array_literal(value: &str) -> HashMap<String, String> {
             while i < chars.len() && chars[i] != ']' {
                 i += 1;
             }
-            let key: String = chars[key_start..i].iter().collect();
+            let mut key: String = chars[key_start..i].iter().collect();
+            if key.starts_with(['"', '\'']) {
+                let key_chars: Vec<char> = key.chars().collect();
+                key = read_value(&key_chars, &mut 0);
+            }
             i += 1; // skip ]
             if i < chars.len() && chars[i] == '=' {
                 i += 1;
@@ -890,37 +1372,120 @@ fn parse_assoc_array_literal(value: &str) -> HashMap<String, String> {
     map
 }
 
-/// Parse `(elem1 elem2 ...)` into a Vec.
-fn parse_array_literal(value: &str) -> Vec<String> {
+/// Parse `(elem1 [3]=elem3 ...)` into an array. Elements without an index
+/// follow the previous one; gaps left by explicit indices stay unset.
+fn parse_array_literal(value: &str) -> IndexedArray {
     let inner = value.trim();
     let inner = if inner.starts_with('(') && inner.ends_with(')') {
         &inner[1..inner.len() - 1]
     } else {
         inner
     };
-    inner.split_whitespace().map(|s| s.to_string()).collect()
+
+    let chars: Vec<char> = inner.chars().collect();
+    let mut arr = IndexedArray::default();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let mut idx = arr.end();
+        if chars[i] == '[' {
+            if let Some(close) = chars[i..].iter().position(|&c| c == ']') {
+                let key: String = chars[i + 1..i + close].iter().collect();
+                let assigns = chars.get(i + close + 1) == Some(&'=');
+                if let (Ok(n), true) = (key.trim().parse::<usize>(), assigns) {
+                    idx = n;
+                    i += close + 2;
+                }
+            }
+        }
+        let val = read_value(&chars, &mut i);
+        arr.set(idx, val);
+    }
+    arr
 }
 
-/// Read a value from characters, handling quotes.
+/// Read one word of an array literal, removing quotes and backslash
+/// escapes the way the shell would.
 fn read_value(chars: &[char], i: &mut usize) -> String {
-    if *i < chars.len() && chars[*i] == '"' {
-        *i += 1;
-        let start = *i;
-        while *i < chars.len() && chars[*i] != '"' {
-            *i += 1;
-        }
-        let val: String = chars[start..*i].iter().collect();
-        if *i < chars.len() {
-            *i += 1; // skip closing quote
+    let mut val = String::new();
+    while *i < chars.len() && !chars[*i].is_whitespace() && chars[*i] != ')' {
+        match chars[*i] {
+            '"' => {
+                *i += 1;
+                while *i < chars.len() && chars[*i] != '"' {
+                    if chars[*i] == '\\'
+                        && matches!(chars.get(*i + 1), Some('"' | '\\' | '$' | '`'))
+                    {
+                        *i += 1;
+                    }
+                    val.push(chars[*i]);
+                    *i += 1;
+                }
+                *i += 1; // skip closing quote
+            }
+            '\'' => {
+                *i += 1;
+                while *i < chars.len() && chars[*i] != '\'' {
+                    val.push(chars[*i]);
+                    *i += 1;
+                }
+                *i += 1; // skip closing quote
+            }
+            '\\' if *i + 1 < chars.len() => {
+                val.push(chars[*i + 1]);
+                *i += 2;
+            }
+            c => {
+                val.push(c);
+                *i += 1;
+            }
         }
-        val
+    }
+    val
+}
+
+/// Format `name` as a `declare` command that recreates it, or `None` if
+/// no such variable exists.
+fn declare_line(state: &ShellState, name: &str) -> Option<String> {
+    let ro = if state.readonly_vars.contains(name) {
+        "r"
     } else {
-        let start = *i;
-        while *i < chars.len() && !chars[*i].is_whitespace() && chars[*i] != ')' {
-            *i += 1;
-        }
-        chars[start..*i].iter().collect()
+        ""
+    };
+    if let Some(val) = state.env.get(name) {
+        let attrs = match (state.integer_vars.contains(name), ro) {
+            (true, r) => format!("-i{r}"),
+            (false, "") => "--".to_string(),
+            (false, r) => format!("-{r}"),
+        };
+        return Some(format!("declare {} {}={}", attrs, name, quote_value(val)));
     }
+    if let Some(arr) = state.arrays.get(name) {
+        let items: Vec<String> = arr
+            .indices()
+            .zip(arr.values())
+            .map(|(idx, v)| format!("[{}]={}", idx, quote_value(v)))
+            .collect();
+        return Some(format!("declare -a{ro} {name}=({})", items.join(" ")));
+    }
+    let map = state.assoc_arrays.get(name)?;
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    let items: Vec<String> = keys
+        .into_iter()
+        .map(|k| {
+            let key = if k.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c)) {
+                k.clone()
+            } else {
+                quote_value(k)
+            };
+            format!("[{}]={}", key, quote_value(&map[k]))
+        })
+        .collect();
+    Some(format!("declare -A{ro} {name}=({})", items.join(" ")))
 }
 
 // -- test / [ -------------------------------------------------------------
@@ -1007,9 +1572,15 @@ fn eval_test_expr(state: &ShellState, host: &dyn HostInterface, args: &[String])
                     .unwrap_or(false)
             }
             "-r" | "-w" | "-x" => {
-                // In our sandbox, just check existence
+                let bits = match op.as_str() {
+                    "-r" => 0o444,
+                    "-w" => 0o222,
+                    _ => 0o111,
+                };
                 let path = state.resolve_path(val);
-                host.stat(&path).map(|s| s.exists).unwrap_or(false)
+                host.stat(&path)
+                    .map(|s| s.exists && (s.mode & bits) != 0)
+                    .unwrap_or(false)
             }
             _ => !val.is_empty(), // single arg: true if non-empty
         };
@@ -1030,6 +1601,7 @@ fn eval_test_expr(state: &ShellState, host: &dyn HostInterface, args: &[String])
             "-le" => left.parse::<i64>().unwrap_or(0) <= right.parse::<i64>().unwrap_or(0),
             "-gt" => left.parse::<i64>().unwrap_or(0) > right.parse::<i64>().unwrap_or(0),
             "-ge" => left.parse::<i64>().unwrap_or(0) >= right.parse::<i64>().unwrap_or(0),
+            "-nt" | "-ot" | "-ef" => compare_files(state, host, left, op, right),
             _ => false,
         };
     }
@@ -1037,6 +1609,64 @@ fn eval_test_expr(state: &ShellState, host: &dyn HostInterface, args: &[String])
     false
 }
 
+/// Evaluate the file comparison operators `-nt`, `-ot` and `-ef`, shared by
+/// `test`/`[` and `[[ ]]`.
+///
+/// As in bash, a missing file counts as older than any existing one, and
+/// `-ef` is false unless both files exist. `-ef` compares inode numbers when
+/// the host reports them and falls back to symlink-resolved paths otherwise.
+pub(crate) fn compare_files(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    left: &str,
+    op: &str,
+    right: &str,
+) -> bool {
+    let stat = |p: &str| {
+        host.stat(&normalize_path(&state.resolve_path(p)))
+            .ok()
+            .filter(|info| info.exists)
+    };
+    let (l, r) = (stat(left), stat(right));
+    match op {
+        "-nt" => match (l, r) {
+            (Some(l), Some(r)) => l.mtime_ms > r.mtime_ms,
+            (Some(_), None) => true,
+            _ => false,
+        },
+        "-ot" => match (l, r) {
+            (Some(l), Some(r)) => l.mtime_ms < r.mtime_ms,
+            (None, Some(_)) => true,
+            _ => false,
+        },
+        "-ef" => match (l, r) {
+            (Some(l), Some(r)) if l.inode != 0 && r.inode != 0 => l.inode == r.inode,
+            (Some(_), Some(_)) => {
+                canonical_path(state, host, left) == canonical_path(state, host, right)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Resolve `path` to a normalized absolute path with a trailing symlink
+/// chain followed, so `./f`, `d/../f` and a link to `f` all compare equal.
+fn canonical_path(state: &ShellState, host: &dyn HostInterface, path: &str) -> String {
+    let mut resolved = normalize_path(&state.resolve_path(path));
+    for _ in 0..40 {
+        match host.readlink(&resolved) {
+            Ok(target) if target.starts_with('/') => resolved = normalize_path(&target),
+            Ok(target) => {
+                let dir = resolved.rsplit_once('/').map(|(d, _)| d).unwrap_or("");
+                resolved = normalize_path(&format!("{dir}/{target}"));
+            }
+            Err(_) => break,
+        }
+    }
+    resolved
+}
+
 // -- read -----------------------------------------------------------------
 
 fn builtin_read(
@@ -1047,47 +1677,138 @@ fn builtin_read(
     let mut raw = false;
     let mut delimiter = '\n';
     let mut nchars: Option<usize> = None;
+    // -N: read exactly nchars, ignoring the delimiter and IFS splitting.
+    let mut exact = false;
+    let mut timeout: Option<f64> = None;
     let mut array_mode = false;
     let mut array_name = String::new();
+    let mut fd = 0;
     let mut var_names: Vec<String> = Vec::new();
     let mut i = 0;
     while i < args.len() {
-        match args[i].as_str() {
-            "-r" => raw = true,
-            "-p" => {
-                // -p prompt: skip the prompt string (no tty output)
-                i += 1; // skip the prompt string
-            }
-            "-d" => {
-                i += 1;
-                if i < args.len() && !args[i].is_empty() {
-                    delimiter = args[i].chars().next().unwrap_or('\n');
+        let arg = &args[i];
+        if !var_names.is_empty() || !arg.starts_with('-') || arg.len() < 2 {
+            var_names.push(arg.clone());
+            i += 1;
+            continue;
+        }
+        // Options may be clustered (`-rs`); one that takes a value uses the
+        // rest of the cluster (`-n3`) or else the next argument.
+        for (pos, c) in arg.char_indices().skip(1) {
+            match c {
+                'r' => raw = true,
+                // No terminal echo to suppress in the sandbox.
+                's' => {}
+                'p' | 'd' | 'n' | 'N' | 't' | 'u' | 'a' => {
+                    let rest = &arg[pos + 1..];
+                    let value = if !rest.is_empty() {
+                        rest.to_string()
+                    } else if let Some(next) = args.get(i + 1) {
+                        i += 1;
+                        next.clone()
+                    } else {
+                        shell_eprintln!("read: -{}: option requires an argument", c);
+                        return BuiltinResult::Result(2);
+                    };
+                    match c {
+                        'p' => shell_eprint!("{}", value),
+                        'd' => delimiter = value.chars().next().unwrap_or('\n'),
+                        'n' | 'N' => {
+                            nchars = value.parse().ok();
+                            exact = c == 'N';
+                        }
+                        't' => match value.parse::<f64>() {
+                            Ok(t) if t >= 0.0 => timeout = Some(t),
+                            _ => {
+                                shell_eprintln!("read: {}: invalid timeout specification", value);
+                                return BuiltinResult::Result(1);
+                            }
+                        },
+                        'u' => match value.parse::<i32>() {
+                            Ok(n) if n >= 0 => fd = n,
+                            _ => {
+                                shell_eprintln!(
+                                    "read: {}: invalid file descriptor specification",
+                                    value
+                                );
+                                return BuiltinResult::Result(1);
+                            }
+                        },
+                        _ => {
+                            array_mode = true;
+                            array_name = value;
+                        }
+                    }
+                    break;
                 }
-            }
-            "-n" => {
-                i += 1;
-                if i < args.len() {
-                    nchars = args[i].parse().ok();
+                _ => {
+                    shell_eprintln!("read: -{}: invalid option", c);
+                    return BuiltinResult::Result(2);
                 }
             }
-            "-a" => {
-                i += 1;
-                if i < args.len() {
-                    array_mode = true;
-                    array_name = args[i].clone();
-                }
+        }
+        i += 1;
+    }
+
+    // `-t 0` only asks whether input is already buffered; it never reads.
+    if timeout == Some(0.0) {
+        let ready = state
+            .pipeline_stdin
+            .as_deref()
+            .is_some_and(|s| !s.is_empty());
+        return BuiltinResult::Result(if ready { 0 } else { 1 });
+    }
+    let started = host.time();
+
+    // `-u FD` reads another descriptor. Its unread data is swapped into
+    // pipeline_stdin for the duration of the read and kept per fd after.
+    if fd != 0 && !state.fd_files.contains(&fd) {
+        match host.dup(fd) {
+            Ok(copy) => {
+                let _ = host.close_fd(copy);
             }
-            other => {
-                var_names.push(other.to_string());
+            Err(_) => {
+                shell_eprintln!("read: {}: invalid file descriptor", fd);
+                return BuiltinResult::Result(1);
             }
         }
-        i += 1;
     }
+    let stdin_buffer = if fd != 0 {
+        let buffered = state.fd_buffers.remove(&fd);
+        std::mem::replace(&mut state.pipeline_stdin, buffered)
+    } else {
+        None
+    };
 
     // Stdin comes from fd 0. Input redirects and pipelines dup2 their
     // data onto fd 0 before we get here. pipeline_stdin holds leftover
     // data from a previous read in the same compound command.
-    let use_pipeline = if state.pipeline_stdin.is_some() {
+    let use_pipeline = if fd != 0 {
+        // Pull lines only until the record is complete; whatever was read
+        // past it is filed under fd_buffers for the next `read -u`.
+        let mut data = state.pipeline_stdin.take().unwrap_or_default();
+        let mut lines = FdLines::new(fd);
+        // Fds opened by `exec N< file` are fully buffered already.
+        let from_file = state.fd_files.contains(&fd);
+        let complete = |data: &str| match nchars {
+            Some(n) if exact => data.chars().count() >= n,
+            Some(n) => data.contains(delimiter) || data.chars().count() >= n,
+            None => data.contains(delimiter),
+        };
+        while !from_file && !complete(&data) {
+            match lines.next() {
+                Some(line) => data.push_str(&line),
+                None => break,
+            }
+        }
+        data.push_str(&String::from_utf8_lossy(&lines.into_remainder()));
+        if data.is_empty() {
+            false
+        } else {
+            state.pipeline_stdin = Some(data);
+            true
+        }
+    } else if state.pipeline_stdin.is_some() {
         true
     } else {
         // On WASM: read line-by-line from stdin via WASI fd_read (JSPI-wrapped),
@@ -1132,28 +1853,37 @@ fn builtin_read(
         String::new()
     };
 
-    // Read input
-    let input = if let Some(n) = nchars {
-        let byte_end = effective_stdin
+    // Read input: up to the delimiter, capped at nchars for -n/-N
+    let line_end = if exact {
+        effective_stdin.len()
+    } else {
+        effective_stdin
+            .find(delimiter)
+            .unwrap_or(effective_stdin.len())
+    };
+    let byte_end = match nchars {
+        Some(n) => effective_stdin
             .char_indices()
             .nth(n)
-            .map(|(i, _)| i)
-            .unwrap_or(effective_stdin.len());
-        &effective_stdin[..byte_end]
-    } else {
-        // Read up to delimiter
-        match effective_stdin.find(delimiter) {
-            Some(pos) => &effective_stdin[..pos],
-            None => &effective_stdin,
-        }
+            .map_or(line_end, |(i, _)| i.min(line_end)),
+        None => line_end,
     };
+    let input = &effective_stdin[..byte_end];
+
+    // The host read can't be interrupted, so a read that overran the
+    // deadline is reported as a timeout afterwards, leaving input unread.
+    if timeout.is_some_and(|t| host.time() - started > t) {
+        restore_stdin_buffer(state, fd, stdin_buffer);
+        return BuiltinResult::Result(142);
+    }
 
     // Advance pipeline_stdin past consumed data
     if use_pipeline {
         let consumed = input.len();
         let remaining = &effective_stdin[consumed..];
-        // Skip the delimiter too
-        let remaining = if remaining.starts_with(delimiter) {
+        // Skip the delimiter too, unless reading stopped at the -n count
+        let count_reached = nchars.is_some_and(|n| input.chars().count() >= n);
+        let remaining = if !exact && !count_reached && remaining.starts_with(delimiter) {
             &remaining[delimiter.len_utf8()..]
         } else {
             remaining
@@ -1164,6 +1894,7 @@ fn builtin_read(
             state.pipeline_stdin = Some(remaining.to_string());
         }
     }
+    restore_stdin_buffer(state, fd, stdin_buffer);
 
     let input = if !raw {
         // Process backslash continuations (simplistic)
@@ -1174,7 +1905,10 @@ fn builtin_read(
 
     if array_mode {
         let parts: Vec<String> = input.split_whitespace().map(|s| s.to_string()).collect();
-        state.arrays.insert(array_name, parts);
+        state.arrays.insert(array_name, parts.into());
+    } else if exact {
+        let name = var_names.first().map_or("REPLY", |n| n.as_str());
+        state.env.insert(name.to_string(), input);
     } else if var_names.is_empty() {
         state.env.insert("REPLY".to_string(), input.to_string());
     } else if var_names.len() == 1 {
@@ -1188,12 +1922,26 @@ fn builtin_read(
             state.env.insert(name.clone(), val);
         }
     }
+    for name in &var_names {
+        state.var_changed(name);
+    }
 
     // Exit code: 0 unless input was empty (EOF)
     let code = if effective_stdin.is_empty() { 1 } else { 0 };
     BuiltinResult::Result(code)
 }
 
+/// Undo the buffer swap done by `read -u FD`: file what is left of the fd's
+/// input under `fd_buffers` and put stdin's own buffer back.
+fn restore_stdin_buffer(state: &mut ShellState, fd: i32, stdin_buffer: Option<String>) {
+    if fd == 0 {
+        return;
+    }
+    if let Some(rest) = std::mem::replace(&mut state.pipeline_stdin, stdin_buffer) {
+        state.fd_buffers.insert(fd, rest);
+    }
+}
+
 // -- shift ----------------------------------------------------------------
 
 fn builtin_shift(state: &mut ShellState, args: &[String]) -> BuiltinResult {
@@ -1287,10 +2035,145 @@ fn builtin_command(host: &dyn HostInterface, args: &[String]) -> Option<BuiltinR
         return Some(BuiltinResult::Result(1));
     }
 
-    // Without -v, return None to fall through to spawn (bypassing functions)
+    // `command name args...` is unwrapped by the executor before function
+    // lookup; anything reaching here falls through to spawn.
+    None
+}
+
+// -- builtin --------------------------------------------------------------
+
+/// `builtin name args...` is unwrapped by the executor when `name` is a
+/// builtin, skipping any function of that name; what reaches here is a bare
+/// `builtin` or an unknown name.
+fn builtin_builtin(args: &[String]) -> BuiltinResult {
+    match args.first() {
+        None => BuiltinResult::Result(0),
+        Some(name) => {
+            shell_eprintln!("builtin: {}: not a shell builtin", name);
+            BuiltinResult::Result(1)
+        }
+    }
+}
+
+// -- hash -----------------------------------------------------------------
+
+/// Resolve `name` the way command dispatch would: a `$PATH` hit first, then
+/// virtual commands and registered tools, which `type` reports under
+/// `/usr/bin`.
+fn hash_lookup(state: &ShellState, host: &dyn HostInterface, name: &str) -> Option<String> {
+    if let Some((path, _)) = crate::executor::find_in_path(state, host, name) {
+        return Some(path);
+    }
+    if crate::virtual_commands::is_virtual_command(name) || host.has_tool(name) {
+        return Some(format!("/usr/bin/{}", name));
+    }
     None
 }
 
+fn builtin_hash(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> BuiltinResult {
+    let mut reset = false;
+    let mut print_paths = false;
+    let mut delete = false;
+    let mut set_path: Option<String> = None;
+    let mut names: Vec<&str> = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-r" => reset = true,
+            "-t" => print_paths = true,
+            "-d" => delete = true,
+            "-l" => {}
+            "-p" => {
+                i += 1;
+                match args.get(i) {
+                    Some(path) => set_path = Some(path.clone()),
+                    None => {
+                        shell_eprint!("hash: -p: option requires an argument\n");
+                        return BuiltinResult::Result(2);
+                    }
+                }
+            }
+            "--" => {
+                names.extend(args[i + 1..].iter().map(|a| a.as_str()));
+                break;
+            }
+            opt if opt.starts_with('-') && opt.len() > 1 => {
+                shell_eprint!("hash: {}: invalid option\n", opt);
+                return BuiltinResult::Result(2);
+            }
+            name => names.push(name),
+        }
+        i += 1;
+    }
+
+    if reset {
+        state.command_hash.clear();
+    }
+
+    if names.is_empty() {
+        if reset {
+            return BuiltinResult::Result(0);
+        }
+        if state.command_hash.is_empty() {
+            shell_print!("hash: hash table empty\n");
+            return BuiltinResult::Result(0);
+        }
+        // Listed in the reusable `hash -l` form.
+        let mut entries: Vec<(&String, &String)> = state.command_hash.iter().collect();
+        entries.sort();
+        let mut output = String::new();
+        for (name, path) in entries {
+            output.push_str(&format!("builtin hash -p {} {}\n", path, name));
+        }
+        shell_print!("{}", output);
+        return BuiltinResult::Result(0);
+    }
+
+    let mut code = 0;
+    for name in &names {
+        if let Some(path) = &set_path {
+            state.command_hash.insert(name.to_string(), path.clone());
+        } else if delete {
+            if state.command_hash.remove(*name).is_none() {
+                shell_eprint!("hash: {}: not found\n", name);
+                code = 1;
+            }
+        } else if print_paths {
+            let resolved = match state.command_hash.get(*name) {
+                Some(path) => Some(path.clone()),
+                None => hash_lookup(state, host, name),
+            };
+            match resolved {
+                Some(path) => {
+                    if names.len() > 1 {
+                        shell_print!("{}\t{}\n", name, path);
+                    } else {
+                        shell_print!("{}\n", path);
+                    }
+                    state.command_hash.insert(name.to_string(), path);
+                }
+                None => {
+                    shell_eprint!("hash: {}: not found\n", name);
+                    code = 1;
+                }
+            }
+        } else if name.contains('/') || is_builtin(name) || state.functions.contains_key(*name) {
+            // Builtins, functions and explicit paths are never hashed.
+        } else if let Some(path) = hash_lookup(state, host, name) {
+            state.command_hash.insert(name.to_string(), path);
+        } else {
+            shell_eprint!("hash: {}: not found\n", name);
+            code = 1;
+        }
+    }
+    BuiltinResult::Result(code)
+}
+
 // -- let ------------------------------------------------------------------
 
 fn builtin_let(state: &mut ShellState, args: &[String]) -> BuiltinResult {
@@ -1346,6 +2229,26 @@ fn builtin_which(host: &dyn HostInterface, args: &[String]) -> BuiltinResult {
 
 // -- source / . -----------------------------------------------------------
 
+/// Locate the file to `source`. As in bash, a name without a slash is
+/// looked up in `$PATH` first (it need not be executable), then in the
+/// current directory.
+fn source_path(state: &ShellState, host: &dyn HostInterface, name: &str) -> String {
+    if !name.contains('/') {
+        let path_env = state.env.get("PATH").map(String::as_str).unwrap_or("");
+        for dir in path_env.split(':').filter(|d| !d.is_empty()) {
+            let candidate = format!("{}/{}", dir.trim_end_matches('/'), name);
+            let candidate = normalize_path(&state.resolve_path(&candidate));
+            if host
+                .stat(&candidate)
+                .is_ok_and(|st| st.exists && st.is_file)
+            {
+                return candidate;
+            }
+        }
+    }
+    normalize_path(&state.resolve_path(name))
+}
+
 fn builtin_source(
     state: &mut ShellState,
     host: &dyn HostInterface,
@@ -1357,7 +2260,7 @@ fn builtin_source(
         return BuiltinResult::Result(1);
     }
 
-    let path = state.resolve_path(&args[0]);
+    let path = source_path(state, host, &args[0]);
     let content = match host.read_file_str(&path) {
         Ok(c) => c,
         Err(e) => {
@@ -1381,7 +2284,13 @@ fn builtin_source(
         let prev_bash_source = state.env.get("BASH_SOURCE").cloned();
         state.env.insert("BASH_SOURCE".to_string(), args[0].clone());
 
-        let result = run_fn(state, script);
+        // Extra arguments become the positional parameters for the duration
+        // of the script; without them the script shares the caller's.
+        let result = if args.len() > 1 {
+            state.with_positional_args(args[1..].to_vec(), |state| run_fn(state, script))
+        } else {
+            run_fn(state, script)
+        };
 
         // Restore BASH_SOURCE
         if let Some(prev) = prev_bash_source {
@@ -1466,8 +2375,14 @@ fn builtin_trap(state: &mut ShellState, args: &[String]) -> BuiltinResult {
         return BuiltinResult::Result(0);
     }
 
-    // trap -p [SIGNAL...] — print traps
-    if args[0] == "-p" {
+    // trap -l — list signal names and numbers
+    if args[0] == "-l" {
+        shell_print!("{}", signal_table());
+        return BuiltinResult::Result(0);
+    }
+
+    // trap -p [SIGNAL...] — print traps
+    if args[0] == "-p" {
         let mut output = String::new();
         if args.len() > 1 {
             for signal in &args[1..] {
@@ -1512,7 +2427,14 @@ fn builtin_getopts(state: &mut ShellState, args: &[String]) -> BuiltinResult {
         return BuiltinResult::Result(1);
     }
 
-    let optstring = &args[0];
+    // A leading ':' selects silent error reporting: the offending option goes
+    // into OPTARG and nothing is printed. Otherwise errors go to stderr
+    // unless OPTERR is 0.
+    let (silent, optstring) = match args[0].strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, args[0].as_str()),
+    };
+    let report = !silent && state.env.get("OPTERR").map(String::as_str) != Some("0");
     let var_name = &args[1];
     let opt_args: Vec<&str> = if args.len() > 2 {
         args[2..].iter().map(|s| s.as_str()).collect()
@@ -1520,10 +2442,13 @@ fn builtin_getopts(state: &mut ShellState, args: &[String]) -> BuiltinResult {
         state.positional_args.iter().map(|s| s.as_str()).collect()
     };
 
+    // OPTIND is re-read on every call, so a script can set `OPTIND=1` to
+    // parse a new set of arguments from the start.
     let optind: usize = state
         .env
         .get("OPTIND")
         .and_then(|s| s.parse().ok())
+        .filter(|&n| n >= 1)
         .unwrap_or(1);
 
     let idx = optind - 1; // Convert 1-based to 0-based
@@ -1536,14 +2461,18 @@ fn builtin_getopts(state: &mut ShellState, args: &[String]) -> BuiltinResult {
     let current = opt_args[idx];
     if !current.starts_with('-') || current == "-" || current == "--" {
         state.env.insert(var_name.clone(), "?".to_string());
+        // `--` ends the options and is consumed.
+        let next = if current == "--" { optind + 1 } else { optind };
+        state.env.insert("OPTIND".to_string(), next.to_string());
         return BuiltinResult::Result(1);
     }
 
     // Get the option character (skip the leading -)
     let opt_char = current.chars().nth(1).unwrap_or('?');
 
-    if optstring.contains(opt_char) {
+    if opt_char != ':' && optstring.contains(opt_char) {
         state.env.insert(var_name.clone(), opt_char.to_string());
+        state.env.remove("OPTARG");
 
         // Check if this option takes an argument (char after opt_char is ':')
         let needs_arg = optstring
@@ -1576,12 +2505,18 @@ fn builtin_getopts(state: &mut ShellState, args: &[String]) -> BuiltinResult {
                     .env
                     .insert("OPTIND".to_string(), (optind + 2).to_string());
             } else {
-                state.env.insert(var_name.clone(), "?".to_string());
                 state
                     .env
                     .insert("OPTIND".to_string(), (optind + 1).to_string());
-                shell_eprint!("getopts: option requires an argument -- {}\n", opt_char);
-                return BuiltinResult::Result(1);
+                if silent {
+                    state.env.insert(var_name.clone(), ":".to_string());
+                    state.env.insert("OPTARG".to_string(), opt_char.to_string());
+                } else {
+                    state.env.insert(var_name.clone(), "?".to_string());
+                    if report {
+                        shell_eprint!("getopts: option requires an argument -- {}\n", opt_char);
+                    }
+                }
             }
         } else {
             state
@@ -1596,17 +2531,21 @@ fn builtin_getopts(state: &mut ShellState, args: &[String]) -> BuiltinResult {
         state
             .env
             .insert("OPTIND".to_string(), (optind + 1).to_string());
+        if silent {
+            state.env.insert("OPTARG".to_string(), opt_char.to_string());
+        } else {
+            state.env.remove("OPTARG");
+            if report {
+                shell_eprint!("getopts: illegal option -- {}\n", opt_char);
+            }
+        }
         BuiltinResult::Result(0)
     }
 }
 
 // -- mapfile / readarray --------------------------------------------------
 
-fn builtin_mapfile(
-    state: &mut ShellState,
-    host: &dyn HostInterface,
-    args: &[String],
-) -> BuiltinResult {
+fn builtin_mapfile(state: &mut ShellState, args: &[String]) -> BuiltinResult {
     let mut strip_newline = false;
     let mut max_lines: Option<usize> = None;
     let mut array_name = "MAPFILE".to_string();
@@ -1618,7 +2557,8 @@ fn builtin_mapfile(
             "-n" => {
                 i += 1;
                 if i < args.len() {
-                    max_lines = args[i].parse().ok();
+                    // `-n 0` means no limit.
+                    max_lines = args[i].parse().ok().filter(|&n| n > 0);
                 }
             }
             other => {
@@ -1630,36 +2570,20 @@ fn builtin_mapfile(
         i += 1;
     }
 
-    // Read stdin from fd 0.
-    let stdin_data = match host.read_fd(0) {
-        Ok(data) if !data.is_empty() => String::from_utf8_lossy(&data).to_string(),
-        _ => String::new(),
-    };
-    let lines: Vec<String> = stdin_data
-        .split('\n')
-        .enumerate()
-        .take_while(|(idx, _)| max_lines.is_none_or(|max| *idx < max))
-        .map(|(_, line)| {
+    // Pull lines from fd 0 only as needed, so `-n` stops reading early
+    // instead of draining an unbounded upstream.
+    let lines: Vec<String> = FdLines::new(0)
+        .take(max_lines.unwrap_or(usize::MAX))
+        .map(|line| {
             if strip_newline {
-                line.to_string()
+                line.strip_suffix('\n').unwrap_or(&line).to_string()
             } else {
-                format!("{}\n", line)
+                line
             }
         })
         .collect();
 
-    // Remove trailing empty entry that comes from final newline
-    let lines = if strip_newline {
-        let mut l = lines;
-        if l.last().map(|s| s.is_empty()).unwrap_or(false) {
-            l.pop();
-        }
-        l
-    } else {
-        lines
-    };
-
-    state.arrays.insert(array_name, lines);
+    state.arrays.insert(array_name, lines.into());
 
     BuiltinResult::Result(0)
 }
@@ -1709,8 +2633,15 @@ fn builtin_chmod(state: &ShellState, host: &dyn HostInterface, args: &[String])
     BuiltinResult::Result(code)
 }
 
-/// Parse symbolic mode string like "+x", "u+x", "go-w", "a+r"
+/// Parse a symbolic mode like "+x", "u+x", "go-w", "a+r", or a
+/// comma-separated list of them ("u+w,go-r"), applied left to right.
 fn parse_symbolic_mode(s: &str, current: u32) -> Option<u32> {
+    s.split(',')
+        .try_fold(current, |mode, clause| apply_symbolic_clause(clause, mode))
+}
+
+/// Apply one `[ugoa]*[+-=][rwx]*` clause of a symbolic mode.
+fn apply_symbolic_clause(s: &str, current: u32) -> Option<u32> {
     let mut mode = current;
     let chars: Vec<char> = s.chars().collect();
     let mut i = 0;
@@ -1790,6 +2721,218 @@ fn parse_symbolic_mode(s: &str, current: u32) -> Option<u32> {
     Some(mode)
 }
 
+// -- cp / mv ---------------------------------------------------------------
+//
+// These run in the shell rather than as WASI binaries because WASI cannot
+// read or set permission bits, so copies would lose their mode.
+
+/// Pair each source operand with its target: inside DEST when DEST is a
+/// directory (required when there are several sources), else DEST itself.
+fn copy_targets(
+    cmd: &str,
+    state: &ShellState,
+    host: &dyn HostInterface,
+    operands: &[String],
+) -> Result<Vec<(String, String)>, String> {
+    let Some((dest, sources)) = operands.split_last().filter(|(_, s)| !s.is_empty()) else {
+        return Err(match operands.first() {
+            Some(op) => format!("{cmd}: missing destination file operand after '{op}'\n"),
+            None => format!("{cmd}: missing file operand\n"),
+        });
+    };
+    let dest_path = state.resolve_path(dest);
+    let dest_is_dir = host.stat(&dest_path).is_ok_and(|s| s.is_dir);
+    if sources.len() > 1 && !dest_is_dir {
+        return Err(format!("{cmd}: target '{dest}' is not a directory\n"));
+    }
+    Ok(sources
+        .iter()
+        .map(|src| {
+            let target = if dest_is_dir {
+                let name = src.trim_end_matches('/').rsplit('/').next().unwrap_or(src);
+                format!("{}/{}", dest_path.trim_end_matches('/'), name)
+            } else {
+                dest_path.clone()
+            };
+            (src.clone(), target)
+        })
+        .collect())
+}
+
+/// Copy `src` (shown in messages as `shown`) to `dst`. New files take the
+/// source's mode bits; `preserve` (`-p`) also applies them to files that
+/// already existed. Directories need `recursive`.
+fn copy_path(
+    host: &dyn HostInterface,
+    shown: &str,
+    src: &str,
+    dst: &str,
+    recursive: bool,
+    preserve: bool,
+    stderr: &mut String,
+) {
+    let info = match host.stat(src) {
+        Ok(info) if info.exists => info,
+        _ => {
+            stderr.push_str(&format!(
+                "cp: cannot stat '{shown}': No such file or directory\n"
+            ));
+            return;
+        }
+    };
+    let existed = host.stat(dst).is_ok_and(|s| s.exists);
+    if info.is_dir {
+        if !recursive {
+            stderr.push_str(&format!(
+                "cp: omitting directory '{shown}' (use -r to copy recursively)\n"
+            ));
+            return;
+        }
+        // Copying into its own subtree would recurse without end.
+        if dst == src || dst.starts_with(&format!("{}/", src.trim_end_matches('/'))) {
+            stderr.push_str(&format!(
+                "cp: cannot copy a directory, '{shown}', into itself, '{dst}'\n"
+            ));
+            return;
+        }
+        if !existed {
+            if let Err(e) = host.mkdir(dst) {
+                stderr.push_str(&format!("cp: cannot create directory '{dst}': {e}\n"));
+                return;
+            }
+        }
+        match host.readdir(src) {
+            Ok(entries) => {
+                for name in entries {
+                    copy_path(
+                        host,
+                        &format!("{}/{name}", shown.trim_end_matches('/')),
+                        &format!("{src}/{name}"),
+                        &format!("{dst}/{name}"),
+                        recursive,
+                        preserve,
+                        stderr,
+                    );
+                }
+            }
+            Err(e) => stderr.push_str(&format!("cp: cannot read directory '{shown}': {e}\n")),
+        }
+    } else {
+        let copied = host
+            .read_file(src)
+            .and_then(|data| host.write_file(dst, &data, WriteMode::Truncate));
+        if let Err(e) = copied {
+            stderr.push_str(&format!("cp: cannot copy '{shown}' to '{dst}': {e}\n"));
+            return;
+        }
+    }
+    if !existed || preserve {
+        let _ = host.chmod(dst, info.mode & 0o7777);
+    }
+}
+
+fn builtin_cp(state: &ShellState, host: &dyn HostInterface, args: &[String]) -> BuiltinResult {
+    let mut recursive = false;
+    let mut preserve = false;
+    let mut operands = Vec::new();
+    let mut flags_done = false;
+    for arg in args {
+        if flags_done || !arg.starts_with('-') || arg.len() == 1 {
+            operands.push(arg.clone());
+            continue;
+        }
+        if arg == "--" {
+            flags_done = true;
+            continue;
+        }
+        for ch in arg[1..].chars() {
+            match ch {
+                'r' | 'R' => recursive = true,
+                'p' => preserve = true,
+                'a' => {
+                    recursive = true;
+                    preserve = true;
+                }
+                // Accepted for compatibility; no effect here.
+                'f' | 'v' | 'd' | 'n' | 'u' | 'l' => {}
+                _ => {
+                    shell_eprint!("cp: invalid option -- '{}'\n", ch);
+                    return BuiltinResult::Result(1);
+                }
+            }
+        }
+    }
+
+    let targets = match copy_targets("cp", state, host, &operands) {
+        Ok(targets) => targets,
+        Err(msg) => {
+            shell_eprint!("{}", msg);
+            return BuiltinResult::Result(1);
+        }
+    };
+    let mut stderr = String::new();
+    for (src, target) in targets {
+        let src_path = state.resolve_path(&src);
+        copy_path(
+            host,
+            &src,
+            &src_path,
+            &target,
+            recursive,
+            preserve,
+            &mut stderr,
+        );
+    }
+    if stderr.is_empty() {
+        BuiltinResult::Result(0)
+    } else {
+        shell_eprint!("{}", stderr);
+        BuiltinResult::Result(1)
+    }
+}
+
+/// `mv` renames through the host, so files and directories keep their
+/// mode bits.
+fn builtin_mv(state: &ShellState, host: &dyn HostInterface, args: &[String]) -> BuiltinResult {
+    let mut operands = Vec::new();
+    let mut flags_done = false;
+    for arg in args {
+        if flags_done || !arg.starts_with('-') || arg.len() == 1 {
+            operands.push(arg.clone());
+        } else if arg == "--" {
+            flags_done = true;
+        } else if let Some(ch) = arg[1..].chars().find(|&c| !matches!(c, 'f' | 'v')) {
+            shell_eprint!("mv: invalid option -- '{}'\n", ch);
+            return BuiltinResult::Result(1);
+        }
+    }
+
+    let targets = match copy_targets("mv", state, host, &operands) {
+        Ok(targets) => targets,
+        Err(msg) => {
+            shell_eprint!("{}", msg);
+            return BuiltinResult::Result(1);
+        }
+    };
+    let mut stderr = String::new();
+    for (src, target) in targets {
+        let src_path = state.resolve_path(&src);
+        if !host.stat(&src_path).is_ok_and(|s| s.exists) {
+            stderr.push_str(&format!(
+                "mv: cannot stat '{src}': No such file or directory\n"
+            ));
+        } else if let Err(e) = host.rename(&src_path, &target) {
+            stderr.push_str(&format!("mv: cannot move '{src}' to '{target}': {e}\n"));
+        }
+    }
+    if stderr.is_empty() {
+        BuiltinResult::Result(0)
+    } else {
+        shell_eprint!("{}", stderr);
+        BuiltinResult::Result(1)
+    }
+}
+
 // -- date -----------------------------------------------------------------
 
 fn builtin_date(host: &dyn HostInterface, args: &[String]) -> BuiltinResult {
@@ -2040,6 +3183,7 @@ fn builtin_readonly(state: &mut ShellState, args: &[String]) -> BuiltinResult {
                 return BuiltinResult::Result(1);
             }
             state.env.insert(name.to_string(), value.to_string());
+            state.var_changed(name);
             state.readonly_vars.insert(name.to_string());
         } else {
             // Mark existing var as readonly
@@ -2072,59 +3216,104 @@ fn builtin_wait(
     host: &dyn HostInterface,
     args: &[String],
 ) -> BuiltinResult {
+    if args.first().map(|a| a.as_str()) == Some("-n") {
+        return builtin_wait_next(state, host, &args[1..]);
+    }
     if args.is_empty() {
-        // Wait for all background jobs
+        // Wait for all background jobs; bash reports 0 and forgets them.
         for job in &mut state.jobs {
             if job.done.is_none() {
-                match host.waitpid(job.pid) {
-                    Ok(result) => {
-                        job.done = Some(result.exit_code);
-                    }
-                    Err(_) => {
-                        job.done = Some(-1);
-                    }
-                }
+                job.done = Some(reap_job(host, job.pid));
             }
         }
-        let last_code = state.jobs.last().and_then(|j| j.done).unwrap_or(0);
-        state.last_exit_code = last_code;
+        state.jobs.clear();
+        state.last_exit_code = 0;
     } else {
-        // Wait for specific PIDs
+        // Wait for specific PIDs or `%N` job specs. Like bash, an unknown
+        // job or a PID that is not our child reports 127.
         let mut last_code = 0;
         for arg in args {
-            if let Ok(pid) = arg.parse::<i32>() {
-                if let Some(job) = state.jobs.iter_mut().find(|j| j.pid == pid) {
-                    if job.done.is_none() {
-                        match host.waitpid(pid) {
-                            Ok(result) => {
-                                job.done = Some(result.exit_code);
-                                last_code = result.exit_code;
-                            }
-                            Err(_) => {
-                                job.done = Some(-1);
-                                last_code = -1;
-                            }
-                        }
-                    } else {
-                        last_code = job.done.unwrap_or(0);
-                    }
-                } else {
+            let idx = if let Some(spec) = arg.strip_prefix('%') {
+                let id = spec.parse::<usize>().ok();
+                let idx = state.jobs.iter().position(|j| Some(j.id) == id);
+                if idx.is_none() {
+                    shell_eprintln!("wait: %{}: no such job", spec);
+                }
+                idx
+            } else if let Ok(pid) = arg.parse::<i32>() {
+                let idx = state.jobs.iter().position(|j| j.pid == pid);
+                if idx.is_none() {
+                    // Not one of our jobs; the host may still know the PID.
                     match host.waitpid(pid) {
-                        Ok(result) => {
+                        Ok(result) if pid > 0 => {
                             last_code = result.exit_code;
+                            continue;
                         }
-                        Err(_) => {
-                            last_code = 127;
-                        }
+                        _ => shell_eprintln!("wait: pid {} is not a child of this shell", pid),
                     }
                 }
-            }
+                idx
+            } else {
+                shell_eprintln!("wait: `{}': not a pid or valid job spec", arg);
+                last_code = 2;
+                continue;
+            };
+            last_code = match idx {
+                Some(idx) => {
+                    let job = state.jobs.remove(idx);
+                    match job.done {
+                        Some(code) => code,
+                        None => reap_job(host, job.pid),
+                    }
+                }
+                None => 127,
+            };
         }
         state.last_exit_code = last_code;
     }
     BuiltinResult::Result(state.last_exit_code)
 }
 
+/// `wait -n [pid...]`: consume the next completed background job (restricted
+/// to the given PIDs, if any) and return its exit status. Jobs are taken in
+/// launch order; a still-running job is waited on. Returns 127 when there is
+/// nothing left to wait for.
+fn builtin_wait_next(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> BuiltinResult {
+    let pids: Vec<i32> = args.iter().filter_map(|a| a.parse().ok()).collect();
+    let eligible = |job: &crate::state::Job| pids.is_empty() || pids.contains(&job.pid);
+
+    // Prefer a job that has already finished; otherwise block on the oldest.
+    let idx = state
+        .jobs
+        .iter()
+        .position(|j| j.done.is_some() && eligible(j))
+        .or_else(|| state.jobs.iter().position(eligible));
+    let Some(idx) = idx else {
+        state.last_exit_code = 127;
+        return BuiltinResult::Result(127);
+    };
+    let job = state.jobs.remove(idx);
+    let code = match job.done {
+        Some(code) => code,
+        None => reap_job(host, job.pid),
+    };
+    state.last_exit_code = code;
+    BuiltinResult::Result(code)
+}
+
+/// Block until a background process exits, returning its exit code (-1 if
+/// the host could not wait on it).
+fn reap_job(host: &dyn HostInterface, pid: i32) -> i32 {
+    match host.waitpid(pid) {
+        Ok(result) => result.exit_code,
+        Err(_) => -1,
+    }
+}
+
 fn builtin_jobs(state: &mut ShellState, host: &dyn HostInterface) -> BuiltinResult {
     // Reap finished jobs first
     for job in &mut state.jobs {
@@ -2171,27 +3360,105 @@ fn builtin_ps(host: &dyn HostInterface) -> BuiltinResult {
 
 // -- kill -----------------------------------------------------------------
 
-/// Map a signal name to its conventional number.
-fn signal_number(name: &str) -> Option<i32> {
-    let name = name.strip_prefix("SIG").unwrap_or(name);
-    match name {
-        "HUP" => Some(1),
-        "INT" => Some(2),
-        "QUIT" => Some(3),
-        "KILL" => Some(9),
-        "USR1" => Some(10),
-        "USR2" => Some(12),
-        "TERM" => Some(15),
-        "STOP" => Some(17),
-        "CONT" => Some(19),
-        _ => None,
+/// Signal numbers and names (Linux numbering), shared by `kill` and `trap`.
+const SIGNALS: &[(i32, &str)] = &[
+    (1, "HUP"),
+    (2, "INT"),
+    (3, "QUIT"),
+    (4, "ILL"),
+    (5, "TRAP"),
+    (6, "ABRT"),
+    (7, "BUS"),
+    (8, "FPE"),
+    (9, "KILL"),
+    (10, "USR1"),
+    (11, "SEGV"),
+    (12, "USR2"),
+    (13, "PIPE"),
+    (14, "ALRM"),
+    (15, "TERM"),
+    (16, "STKFLT"),
+    (17, "CHLD"),
+    (18, "CONT"),
+    (19, "STOP"),
+    (20, "TSTP"),
+    (21, "TTIN"),
+    (22, "TTOU"),
+    (23, "URG"),
+    (24, "XCPU"),
+    (25, "XFSZ"),
+    (26, "VTALRM"),
+    (27, "PROF"),
+    (28, "WINCH"),
+    (29, "IO"),
+    (30, "PWR"),
+    (31, "SYS"),
+];
+
+/// Parse a signal given by name (`TERM`, `SIGTERM`, any case) or number.
+fn signal_number(spec: &str) -> Option<i32> {
+    if let Ok(n) = spec.parse::<i32>() {
+        return (n == 0 || signal_name(n).is_some()).then_some(n);
     }
+    let upper = spec.to_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS.iter().find(|(_, s)| *s == name).map(|(n, _)| *n)
 }
 
-/// All signal names we advertise, in numeric order.
-const SIGNAL_NAMES: &[&str] = &[
-    "HUP", "INT", "QUIT", "KILL", "USR1", "USR2", "TERM", "STOP", "CONT",
-];
+/// The name of signal `num`, without the `SIG` prefix.
+fn signal_name(num: i32) -> Option<&'static str> {
+    SIGNALS.iter().find(|(n, _)| *n == num).map(|(_, s)| *s)
+}
+
+/// The `kill -l` / `trap -l` table: ` 1) SIGHUP\t 2) SIGINT ...`, five to
+/// a line.
+fn signal_table() -> String {
+    let mut out = String::new();
+    for (i, (num, name)) in SIGNALS.iter().enumerate() {
+        out.push_str(&format!("{num:2}) SIG{name}"));
+        out.push(if i % 5 == 4 || i + 1 == SIGNALS.len() {
+            '\n'
+        } else {
+            '\t'
+        });
+    }
+    out
+}
+
+/// `kill -l [SIGSPEC...]`: with no arguments print the signal table (in
+/// POSIX mode just the names on one line); otherwise translate each number
+/// (or exit status above 128) to its name and each name to its number.
+fn list_signals(cmd: &str, specs: &[String], posix: bool) -> BuiltinResult {
+    if specs.is_empty() && posix {
+        let names: Vec<&str> = SIGNALS.iter().map(|(_, name)| *name).collect();
+        shell_println!("{}", names.join(" "));
+        return BuiltinResult::Result(0);
+    }
+    if specs.is_empty() {
+        shell_print!("{}", signal_table());
+        return BuiltinResult::Result(0);
+    }
+    let mut out = String::new();
+    let mut code = 0;
+    for spec in specs {
+        let found = match spec.parse::<i32>() {
+            Ok(n) => signal_name(if n > 128 { n - 128 } else { n }).map(str::to_string),
+            Err(_) => signal_number(spec).map(|n| n.to_string()),
+        };
+        match found {
+            Some(s) => {
+                out.push_str(&s);
+                out.push('\n');
+            }
+            None => {
+                shell_eprintln!("{}: {}: invalid signal specification", cmd, spec);
+                code = 1;
+            }
+        }
+    }
+    shell_print!("{}", out);
+    BuiltinResult::Result(code)
+}
 
 fn builtin_kill(
     state: &mut ShellState,
@@ -2205,44 +3472,26 @@ fn builtin_kill(
 
     while i < args.len() {
         let arg = &args[i];
-        if arg == "-l" {
-            // List signals
-            let mut out = String::new();
-            for (idx, name) in SIGNAL_NAMES.iter().enumerate() {
-                if idx > 0 {
-                    out.push(' ');
-                }
-                out.push_str(name);
-            }
-            out.push('\n');
-            shell_print!("{}", out);
-            return BuiltinResult::Result(0);
-        } else if arg == "-s" {
+        if pids.is_empty() && (arg == "-l" || arg == "-L") {
+            let posix = state.flags.contains(&ShellFlag::Posix);
+            return list_signals("kill", &args[i + 1..], posix);
+        } else if arg == "-s" || arg == "-n" {
             i += 1;
             if i >= args.len() {
-                shell_eprintln!("kill: -s requires a signal name");
+                shell_eprintln!("kill: {}: option requires an argument", arg);
                 return BuiltinResult::Result(1);
             }
-            let name = args[i].to_uppercase();
-            match signal_number(&name) {
+            match signal_number(&args[i]) {
                 Some(n) => signal = n,
                 None => {
-                    // Try numeric
-                    if let Ok(n) = name.parse::<i32>() {
-                        signal = n;
-                    } else {
-                        shell_eprintln!("kill: unknown signal: {}", args[i]);
-                        return BuiltinResult::Result(1);
-                    }
+                    shell_eprintln!("kill: unknown signal: {}", args[i]);
+                    return BuiltinResult::Result(1);
                 }
             }
         } else if let Some(signame) = arg.strip_prefix('-') {
             // -SIGNAL (e.g. -TERM, -9, -KILL)
             if pids.is_empty() {
-                let signame_upper = signame.to_uppercase();
-                if let Some(n) = signal_number(&signame_upper) {
-                    signal = n;
-                } else if let Ok(n) = signame.parse::<i32>() {
+                if let Some(n) = signal_number(signame) {
                     signal = n;
                 } else {
                     shell_eprintln!("kill: unknown signal: {}", signame);
@@ -2419,8 +3668,7 @@ fn builtin_nice(state: &mut ShellState, host: &dyn HostInterface, args: &[String
 
     let prog = args[i];
     let spawn_args: Vec<&str> = args[i + 1..].to_vec();
-    let env_pairs: Vec<(&str, &str)> =
-        state.env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let env_pairs = state.exported_env();
 
     match host.spawn(prog, None, &spawn_args, &env_pairs, &state.cwd, "", state.stdin_fd, state.stdout_fd, 2, nice) {
         Ok(pid) => match host.waitpid(pid) {
@@ -2434,91 +3682,1409 @@ fn builtin_nice(state: &mut ShellState, host: &dyn HostInterface, args: &[String
     }
 }
 
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
+// -- expr -----------------------------------------------------------------
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::test_support::mock::MockHost;
+/// `expr EXPRESSION`
+///
+/// Evaluates a POSIX `expr` expression given as separate arguments and prints
+/// the result. Exit status is 0 for a non-null, non-zero result, 1 for a null
+/// or zero result, and 2 for an invalid expression.
+fn builtin_expr(args: &[String]) -> BuiltinResult {
+    if args.is_empty() {
+        shell_eprintln!("expr: missing operand");
+        return BuiltinResult::Result(2);
+    }
+    let mut parser = ExprParser { args, pos: 0 };
+    let result = parser
+        .parse_or()
+        .and_then(|value| match args.get(parser.pos) {
+            Some(extra) => Err(format!("syntax error: unexpected argument '{extra}'")),
+            None => Ok(value),
+        });
+    match result {
+        Ok(value) => {
+            shell_println!("{value}");
+            let is_null = value.is_empty() || value.parse::<i64>() == Ok(0);
+            BuiltinResult::Result(if is_null { 1 } else { 0 })
+        }
+        Err(msg) => {
+            shell_eprintln!("expr: {msg}");
+            BuiltinResult::Result(2)
+        }
+    }
+}
 
-    fn make_args(args: &[&str]) -> Vec<String> {
-        args.iter().map(|s| s.to_string()).collect()
+/// Recursive-descent evaluator for `expr`, lowest precedence first:
+/// `|`, `&`, comparisons, `+ -`, `* / %`, `:`, then primaries.
+struct ExprParser<'a> {
+    args: &'a [String],
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.args.get(self.pos).map(|s| s.as_str())
     }
 
-    /// Run a builtin and return just the exit code.
-    fn run_builtin(
-        state: &mut ShellState,
-        host: &dyn HostInterface,
-        cmd: &str,
-        args: &[&str],
-    ) -> i32 {
-        let _lock = crate::test_support::mock::FD_MUTEX
-            .lock()
-            .unwrap_or_else(|e| e.into_inner());
-        let a = make_args(args);
-        match try_builtin(state, host, cmd, &a, "", None).expect("expected builtin") {
-            BuiltinResult::Result(c) | BuiltinResult::Exit(c) | BuiltinResult::Return(c) => c,
-        }
+    fn next_operand(&mut self) -> Result<String, String> {
+        let arg = self
+            .args
+            .get(self.pos)
+            .ok_or("syntax error: missing argument")?;
+        self.pos += 1;
+        Ok(arg.clone())
     }
 
-    fn run_builtin_stdin(
-        state: &mut ShellState,
-        host: &dyn HostInterface,
-        cmd: &str,
-        args: &[&str],
-        stdin: &str,
-    ) -> i32 {
-        let _lock = crate::test_support::mock::FD_MUTEX
-            .lock()
-            .unwrap_or_else(|e| e.into_inner());
-        let a = make_args(args);
+    fn parse_or(&mut self) -> Result<String, String> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some("|") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            if expr_is_null(&left) {
+                left = if expr_is_null(&right) {
+                    "0".into()
+                } else {
+                    right
+                };
+            }
+        }
+        Ok(left)
+    }
 
-        // Write stdin data to a pipe and dup2 onto fd 0, matching
-        // production flow where input redirects go through fd 0.
-        let saved_fd0 = if !stdin.is_empty() {
-            let (r, w) = host.pipe().expect("pipe failed");
-            unsafe {
-                libc::write(
-                    w as libc::c_int,
-                    stdin.as_ptr() as *const libc::c_void,
-                    stdin.len(),
-                );
+    fn parse_and(&mut self) -> Result<String, String> {
+        let mut left = self.parse_comparison()?;
+        while self.peek() == Some("&") {
+            self.pos += 1;
+            let right = self.parse_comparison()?;
+            if expr_is_null(&left) || expr_is_null(&right) {
+                left = "0".into();
             }
-            host.close_fd(w).ok();
-            let saved = host.dup(0).ok();
-            host.dup2(r, 0).ok();
-            host.close_fd(r).ok();
-            saved
-        } else {
-            None
-        };
+        }
+        Ok(left)
+    }
 
-        let result = match try_builtin(state, host, cmd, &a, "", None).expect("expected builtin") {
-            BuiltinResult::Result(c) | BuiltinResult::Exit(c) | BuiltinResult::Return(c) => c,
-        };
+    fn parse_comparison(&mut self) -> Result<String, String> {
+        let mut left = self.parse_additive()?;
+        while let Some(op) = self.peek() {
+            if !matches!(op, "=" | "==" | "!=" | "<" | "<=" | ">" | ">=") {
+                break;
+            }
+            let op = op.to_string();
+            self.pos += 1;
+            let right = self.parse_additive()?;
+            // Integers compare numerically, anything else as strings.
+            let ord = match (left.parse::<i64>(), right.parse::<i64>()) {
+                (Ok(l), Ok(r)) => l.cmp(&r),
+                _ => left.cmp(&right),
+            };
+            let holds = match op.as_str() {
+                "=" | "==" => ord.is_eq(),
+                "!=" => ord.is_ne(),
+                "<" => ord.is_lt(),
+                "<=" => ord.is_le(),
+                ">" => ord.is_gt(),
+                _ => ord.is_ge(),
+            };
+            left = if holds { "1" } else { "0" }.to_string();
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> Result<String, String> {
+        let mut left = self.parse_multiplicative()?;
+        while let Some(op @ ("+" | "-")) = self.peek() {
+            let op = op.to_string();
+            self.pos += 1;
+            let right = self.parse_multiplicative()?;
+            let (l, r) = (expr_int(&left)?, expr_int(&right)?);
+            let value = if op == "+" {
+                l.checked_add(r)
+            } else {
+                l.checked_sub(r)
+            };
+            left = value.ok_or("integer overflow")?.to_string();
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<String, String> {
+        let mut left = self.parse_match()?;
+        while let Some(op @ ("*" | "/" | "%")) = self.peek() {
+            let op = op.to_string();
+            self.pos += 1;
+            let right = self.parse_match()?;
+            let (l, r) = (expr_int(&left)?, expr_int(&right)?);
+            if op != "*" && r == 0 {
+                return Err("division by zero".into());
+            }
+            let value = match op.as_str() {
+                "*" => l.checked_mul(r),
+                "/" => l.checked_div(r),
+                _ => l.checked_rem(r),
+            };
+            left = value.ok_or("integer overflow")?.to_string();
+        }
+        Ok(left)
+    }
 
-        // Restore fd 0.
-        if let Some(fd) = saved_fd0 {
-            host.dup2(fd, 0).ok();
-            host.close_fd(fd).ok();
+    fn parse_match(&mut self) -> Result<String, String> {
+        let mut left = self.parse_primary()?;
+        while self.peek() == Some(":") {
+            self.pos += 1;
+            let pattern = self.parse_primary()?;
+            left = expr_regex_match(&left, &pattern)?;
         }
-        result
+        Ok(left)
     }
 
-    /// Capture stdout via real OS pipes. Uses dup2 to redirect fd 1
-    /// to a pipe, runs the builtin, then reads the pipe.
-    fn run_capture(
-        state: &mut ShellState,
-        host: &MockHost,
-        cmd: &str,
-        args: &[&str],
-    ) -> (i32, String, String) {
-        run_capture_stdin(state, host, cmd, args, "")
+    /// Whether at least `n` arguments follow the current one.
+    fn has_operands(&self, n: usize) -> bool {
+        self.pos + n < self.args.len()
     }
 
-    fn run_capture_stdin(
+    fn parse_primary(&mut self) -> Result<String, String> {
+        match self.peek() {
+            None => Err("syntax error: missing argument".into()),
+            Some("(") => {
+                self.pos += 1;
+                let value = self.parse_or()?;
+                if self.peek() != Some(")") {
+                    return Err("syntax error: expecting ')'".into());
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            // `+ TOKEN` treats TOKEN as a string even if it is a keyword.
+            Some("+") if self.has_operands(1) => {
+                self.pos += 1;
+                self.next_operand()
+            }
+            Some("length") if self.has_operands(1) => {
+                self.pos += 1;
+                let s = self.parse_primary()?;
+                Ok(s.chars().count().to_string())
+            }
+            Some("match") if self.has_operands(2) => {
+                self.pos += 1;
+                let s = self.parse_primary()?;
+                let pattern = self.parse_primary()?;
+                expr_regex_match(&s, &pattern)
+            }
+            Some("index") if self.has_operands(2) => {
+                self.pos += 1;
+                let s = self.parse_primary()?;
+                let chars = self.parse_primary()?;
+                let idx = s
+                    .chars()
+                    .position(|c| chars.contains(c))
+                    .map_or(0, |i| i + 1);
+                Ok(idx.to_string())
+            }
+            Some("substr") if self.has_operands(3) => {
+                self.pos += 1;
+                let s = self.parse_primary()?;
+                let start = self.parse_primary()?;
+                let len = self.parse_primary()?;
+                let (start, len) = (expr_int(&start)?, expr_int(&len)?);
+                if start < 1 || len < 1 {
+                    return Ok(String::new());
+                }
+                Ok(s.chars()
+                    .skip(start as usize - 1)
+                    .take(len as usize)
+                    .collect())
+            }
+            Some(_) => self.next_operand(),
+        }
+    }
+}
+
+/// `expr` treats the empty string and integer zero as false.
+fn expr_is_null(value: &str) -> bool {
+    value.is_empty() || value.parse::<i64>() == Ok(0)
+}
+
+fn expr_int(value: &str) -> Result<i64, String> {
+    value
+        .parse::<i64>()
+        .map_err(|_| "non-integer argument".to_string())
+}
+
+/// `STRING : REGEX` — an anchored basic-regex match. Returns the first
+/// `\(...\)` group when the pattern has one, otherwise the match length.
+fn expr_regex_match(s: &str, pattern: &str) -> Result<String, String> {
+    let re = regex::Regex::new(&format!("^(?:{})", bre_to_ere(pattern)))
+        .map_err(|e| format!("invalid regular expression: {e}"))?;
+    let caps = re.captures(s);
+    if re.captures_len() > 1 {
+        let group = caps.as_ref().and_then(|c| c.get(1));
+        Ok(group.map_or("", |m| m.as_str()).to_string())
+    } else {
+        let len = caps
+            .and_then(|c| c.get(0))
+            .map_or(0, |m| m.as_str().chars().count());
+        Ok(len.to_string())
+    }
+}
+
+/// Translate a POSIX basic regular expression into `regex` crate syntax:
+/// `\( \) \{ \} \+ \? \|` become operators and their bare forms literals.
+fn bre_to_ere(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(op @ ('(' | ')' | '{' | '}' | '+' | '?' | '|')) => out.push(op),
+                Some(other) => {
+                    out.push('\\');
+                    out.push(other);
+                }
+                None => out.push_str("\\\\"),
+            },
+            '(' | ')' | '{' | '}' | '+' | '?' | '|' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// -- ulimit ---------------------------------------------------------------
+
+/// Resources `ulimit` accepts: option letter, `-a` label, initial value.
+///
+/// The sandbox enforces none of these; they are recorded so scripts that
+/// raise or query a limit carry on.
+const ULIMIT_RESOURCES: &[(char, &str, &str)] = &[
+    ('c', "core file size          (blocks, -c)", "0"),
+    ('d', "data seg size           (kbytes, -d)", "unlimited"),
+    ('f', "file size               (blocks, -f)", "unlimited"),
+    ('l', "max locked memory       (kbytes, -l)", "unlimited"),
+    ('m', "max memory size         (kbytes, -m)", "unlimited"),
+    ('n', "open files                      (-n)", "1024"),
+    ('s', "stack size              (kbytes, -s)", "8192"),
+    ('t', "cpu time               (seconds, -t)", "unlimited"),
+    ('u', "max user processes              (-u)", "unlimited"),
+    ('v', "virtual memory          (kbytes, -v)", "unlimited"),
+];
+
+/// `ulimit [-SHa] [-cdflmnstuv] [limit]`
+///
+/// Prints or records resource limits. `-S`/`-H` are accepted and ignored:
+/// soft and hard limits share one value.
+fn builtin_ulimit(state: &mut ShellState, args: &[String]) -> BuiltinResult {
+    let mut resources: Vec<char> = Vec::new();
+    let mut show_all = false;
+    let mut value: Option<&str> = None;
+
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for c in flags.chars() {
+                    match c {
+                        'S' | 'H' => {}
+                        'a' => show_all = true,
+                        _ if ULIMIT_RESOURCES.iter().any(|r| r.0 == c) => resources.push(c),
+                        _ => {
+                            shell_eprintln!("ulimit: -{}: invalid option", c);
+                            return BuiltinResult::Result(2);
+                        }
+                    }
+                }
+            }
+            _ => value = Some(arg),
+        }
+    }
+
+    if show_all {
+        for &(c, label, _) in ULIMIT_RESOURCES {
+            shell_println!("{} {}", label, ulimit_value(state, c));
+        }
+        return BuiltinResult::Result(0);
+    }
+    if resources.is_empty() {
+        resources.push('f');
+    }
+
+    if let Some(value) = value {
+        if value != "unlimited" && value.parse::<u64>().is_err() {
+            shell_eprintln!("ulimit: {}: invalid number", value);
+            return BuiltinResult::Result(1);
+        }
+        for c in resources {
+            state.limits.insert(c, value.to_string());
+        }
+    } else if let [c] = resources[..] {
+        shell_println!("{}", ulimit_value(state, c));
+    } else {
+        for &(c, label, _) in ULIMIT_RESOURCES {
+            if resources.contains(&c) {
+                shell_println!("{} {}", label, ulimit_value(state, c));
+            }
+        }
+    }
+    BuiltinResult::Result(0)
+}
+
+/// Current value of a `ulimit` resource, falling back to its initial value.
+fn ulimit_value(state: &ShellState, resource: char) -> String {
+    if let Some(v) = state.limits.get(&resource) {
+        return v.clone();
+    }
+    ULIMIT_RESOURCES
+        .iter()
+        .find(|r| r.0 == resource)
+        .map(|r| r.2.to_string())
+        .unwrap_or_default()
+}
+
+// -- getconf --------------------------------------------------------------
+
+/// System configuration values reported by `getconf`, matching the wasm32
+/// sandbox (64 KiB pages, one processor).
+const GETCONF_VALUES: &[(&str, &str)] = &[
+    ("ARG_MAX", "2097152"),
+    ("CHILD_MAX", "1024"),
+    ("CLK_TCK", "100"),
+    ("HOST_NAME_MAX", "64"),
+    ("LINE_MAX", "2048"),
+    ("LONG_BIT", "32"),
+    ("NAME_MAX", "255"),
+    ("OPEN_MAX", "1024"),
+    ("PAGESIZE", "65536"),
+    ("PAGE_SIZE", "65536"),
+    ("PATH_MAX", "4096"),
+    ("PIPE_BUF", "4096"),
+    ("_NPROCESSORS_CONF", "1"),
+    ("_NPROCESSORS_ONLN", "1"),
+];
+
+/// `getconf -a` or `getconf NAME [path]`
+fn builtin_getconf(args: &[String]) -> BuiltinResult {
+    match args.first().map(|s| s.as_str()) {
+        Some("-a") => {
+            for (name, value) in GETCONF_VALUES {
+                shell_println!("{:<24}{}", name, value);
+            }
+            BuiltinResult::Result(0)
+        }
+        Some(name) => match GETCONF_VALUES.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => {
+                shell_println!("{}", value);
+                BuiltinResult::Result(0)
+            }
+            None => {
+                shell_eprintln!("getconf: Unrecognized variable `{}'", name);
+                BuiltinResult::Result(1)
+            }
+        },
+        None => {
+            shell_eprintln!("usage: getconf NAME [path]");
+            BuiltinResult::Result(2)
+        }
+    }
+}
+
+// -- yes ------------------------------------------------------------------
+
+/// Most output `yes` produces. Pipeline stages run one after another, so
+/// the consumer only starts reading once `yes` returns; one pipe's worth
+/// is as much as can be handed over without blocking.
+const YES_MAX_BYTES: usize = 64 * 1024;
+
+/// `yes [STRING...]` -- repeat STRING (default `y`) up to [`YES_MAX_BYTES`].
+fn builtin_yes(args: &[String]) -> BuiltinResult {
+    let line = if args.is_empty() {
+        "y\n".to_string()
+    } else {
+        format!("{}\n", args.join(" "))
+    };
+    let count = (YES_MAX_BYTES / line.len()).max(1);
+    shell_print!("{}", line.repeat(count));
+    shell_eprintln!("yes: stopped after {} lines", count);
+    BuiltinResult::Result(0)
+}
+
+// -- complete / compgen ---------------------------------------------------
+
+/// `complete` options that take an argument.
+const COMPLETE_ARG_OPTS: &str = "ACFGPSWXo";
+
+/// `complete [-pr] [OPTIONS] [NAME...]` -- record completion specs.
+///
+/// There is no interactive line editor, so specs are only stored (and
+/// listed by `-p`); this keeps sourced completion scripts from failing.
+fn builtin_complete(state: &mut ShellState, args: &[String]) -> BuiltinResult {
+    let mut opts = Vec::new();
+    let mut print = args.is_empty();
+    let mut remove = false;
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with('-') && args[i] != "-" {
+        let arg = &args[i];
+        i += 1;
+        if arg == "--" {
+            break;
+        }
+        match arg.as_str() {
+            "-p" => print = true,
+            "-r" => remove = true,
+            _ => {
+                opts.push(arg.clone());
+                let letter = arg.chars().last().unwrap_or('-');
+                if COMPLETE_ARG_OPTS.contains(letter) {
+                    let Some(value) = args.get(i) else {
+                        shell_eprintln!("complete: {}: option requires an argument", arg);
+                        return BuiltinResult::Result(2);
+                    };
+                    opts.push(value.clone());
+                    i += 1;
+                }
+            }
+        }
+    }
+    let names = &args[i..];
+
+    if remove {
+        if names.is_empty() {
+            state.completions.clear();
+        }
+        for name in names {
+            state.completions.remove(name);
+        }
+        return BuiltinResult::Result(0);
+    }
+
+    if print {
+        let mut code = 0;
+        let mut listed: Vec<&String> = if names.is_empty() {
+            state.completions.keys().collect()
+        } else {
+            names.iter().collect()
+        };
+        listed.sort();
+        for name in listed {
+            match state.completions.get(name) {
+                Some(spec) => {
+                    let mut line = String::from("complete");
+                    for word in spec {
+                        line.push(' ');
+                        if word.is_empty() || word.contains(char::is_whitespace) {
+                            line.push_str(&format!("'{word}'"));
+                        } else {
+                            line.push_str(word);
+                        }
+                    }
+                    shell_println!("{} {}", line, name);
+                }
+                None => {
+                    shell_eprintln!("complete: {}: no completion specification", name);
+                    code = 1;
+                }
+            }
+        }
+        return BuiltinResult::Result(code);
+    }
+
+    for name in names {
+        state.completions.insert(name.clone(), opts.clone());
+    }
+    BuiltinResult::Result(0)
+}
+
+/// `compgen [-W WORDLIST] [-f] [-d] [PREFIX]` -- print the candidates
+/// starting with PREFIX, one per line. Exits 1 when nothing matches.
+fn builtin_compgen(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> BuiltinResult {
+    let mut words: Vec<String> = Vec::new();
+    let mut files = false;
+    let mut dirs = false;
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with('-') && args[i] != "-" {
+        let arg = &args[i];
+        i += 1;
+        match arg.as_str() {
+            "--" => break,
+            "-W" => {
+                let Some(list) = args.get(i) else {
+                    shell_eprintln!("compgen: -W: option requires an argument");
+                    return BuiltinResult::Result(2);
+                };
+                words.extend(list.split_whitespace().map(str::to_string));
+                i += 1;
+            }
+            "-f" => files = true,
+            "-d" => dirs = true,
+            _ => {
+                shell_eprintln!("compgen: {}: invalid option", arg);
+                return BuiltinResult::Result(2);
+            }
+        }
+    }
+    let prefix = args.get(i).map(String::as_str).unwrap_or("");
+
+    let mut matches: Vec<String> = words
+        .into_iter()
+        .filter(|w| w.starts_with(prefix))
+        .collect();
+    if files || dirs {
+        matches.extend(compgen_paths(state, host, prefix, dirs && !files));
+    }
+
+    for m in &matches {
+        shell_println!("{}", m);
+    }
+    BuiltinResult::Result(if matches.is_empty() { 1 } else { 0 })
+}
+
+/// Directory entries completing the path PREFIX, keeping its directory part
+/// as typed. Dotfiles are only offered when the name prefix starts with `.`.
+fn compgen_paths(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    prefix: &str,
+    dirs_only: bool,
+) -> Vec<String> {
+    let (dir, name_prefix) = match prefix.rfind('/') {
+        Some(idx) => (&prefix[..=idx], &prefix[idx + 1..]),
+        None => ("", prefix),
+    };
+    let resolved = normalize_path(&state.resolve_path(if dir.is_empty() { "." } else { dir }));
+    let Ok(mut entries) = host.readdir(&resolved) else {
+        return Vec::new();
+    };
+    entries.sort();
+    entries
+        .into_iter()
+        .filter(|e| e.starts_with(name_prefix))
+        .filter(|e| name_prefix.starts_with('.') || !e.starts_with('.'))
+        .filter(|e| {
+            !dirs_only
+                || host
+                    .stat(&normalize_path(&format!("{resolved}/{e}")))
+                    .map(|s| s.is_dir)
+                    .unwrap_or(false)
+        })
+        .map(|e| format!("{dir}{e}"))
+        .collect()
+}
+
+// -- cmp / diff -----------------------------------------------------------
+
+/// Read a `cmp`/`diff` operand: `-` is stdin, anything else a VFS path.
+fn read_operand(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    name: &str,
+) -> Result<Vec<u8>, String> {
+    if name == "-" {
+        return Ok(FdLines::new(0).collect::<String>().into_bytes());
+    }
+    host.read_file(&state.resolve_path(name))
+        .map_err(|_| format!("{name}: No such file or directory"))
+}
+
+/// `cmp [-s] FILE1 [FILE2]` -- compare two files byte by byte, reporting
+/// the first difference. Exit 0 if identical, 1 if different, 2 on error.
+/// Other options fall through to the external `cmp`.
+fn builtin_cmp(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> Option<BuiltinResult> {
+    let mut silent = false;
+    let mut files: Vec<&str> = Vec::new();
+    let mut opts_done = false;
+    for arg in args {
+        match arg.as_str() {
+            "--" if !opts_done => opts_done = true,
+            "-s" | "--silent" | "--quiet" if !opts_done => silent = true,
+            a if !opts_done && a.starts_with('-') && a != "-" => return None,
+            a => files.push(a),
+        }
+    }
+    if files.is_empty() || files.len() > 2 {
+        shell_eprintln!("cmp: usage: cmp [-s] FILE1 [FILE2]");
+        return Some(BuiltinResult::Result(2));
+    }
+    let (name1, name2) = (files[0], files.get(1).copied().unwrap_or("-"));
+
+    let (data1, data2) = match (
+        read_operand(state, host, name1),
+        read_operand(state, host, name2),
+    ) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            if !silent {
+                shell_eprintln!("cmp: {}", e);
+            }
+            return Some(BuiltinResult::Result(2));
+        }
+    };
+
+    let mut line = 1;
+    for (i, (a, b)) in data1.iter().zip(&data2).enumerate() {
+        if a != b {
+            if !silent {
+                shell_println!("{} {} differ: byte {}, line {}", name1, name2, i + 1, line);
+            }
+            return Some(BuiltinResult::Result(1));
+        }
+        if *a == b'\n' {
+            line += 1;
+        }
+    }
+    if data1.len() == data2.len() {
+        return Some(BuiltinResult::Result(0));
+    }
+    if !silent {
+        let (shorter, len) = if data1.len() < data2.len() {
+            (name1, data1.len())
+        } else {
+            (name2, data2.len())
+        };
+        if len == 0 {
+            shell_eprintln!("cmp: EOF on {} which is empty", shorter);
+        } else {
+            shell_eprintln!("cmp: EOF on {} after byte {}, line {}", shorter, len, line);
+        }
+    }
+    Some(BuiltinResult::Result(1))
+}
+
+/// One step of a line edit script.
+#[derive(Clone, Copy, PartialEq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Longest-common-subsequence line diff: the edit script turning `a` into
+/// `b`, with deletions ordered before insertions within each change.
+fn diff_lines(a: &[&str], b: &[&str]) -> Vec<DiffOp> {
+    let (n, m) = (a.len(), b.len());
+    // lcs[i][j] = LCS length of a[i..] and b[j..].
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a[i] == b[j] {
+            ops.push(DiffOp::Equal);
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(DiffOp::Delete);
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert);
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// Split into lines, each keeping its trailing newline.
+fn split_lines_keep(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// Append a diff output line, flagging a missing final newline as diff does.
+fn push_diff_line(out: &mut String, marker: &str, line: &str) {
+    out.push_str(marker);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// A `START,END` line range for normal diff output (1-based, inclusive).
+fn normal_range(start: usize, count: usize) -> String {
+    if count == 1 {
+        format!("{start}")
+    } else {
+        format!("{},{}", start, start + count - 1)
+    }
+}
+
+/// A `START,COUNT` hunk range for unified output; an empty range names the
+/// line before it, and a count of one is left implicit.
+fn unified_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start - 1),
+        1 => format!("{start}"),
+        _ => format!("{start},{count}"),
+    }
+}
+
+/// Number of unchanged context lines around each unified hunk.
+const DIFF_CONTEXT: usize = 3;
+
+/// Render an edit script in classic `diff` format (`2c2`, `< old`, `> new`).
+fn format_normal_diff(ops: &[DiffOp], a: &[&str], b: &[&str]) -> String {
+    let mut out = String::new();
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    while k < ops.len() {
+        if ops[k] == DiffOp::Equal {
+            i += 1;
+            j += 1;
+            k += 1;
+            continue;
+        }
+        let (i0, j0) = (i, j);
+        while k < ops.len() && ops[k] != DiffOp::Equal {
+            if ops[k] == DiffOp::Delete {
+                i += 1;
+            } else {
+                j += 1;
+            }
+            k += 1;
+        }
+        let (dels, ins) = (i - i0, j - j0);
+        let header = match (dels, ins) {
+            (_, 0) => format!("{}d{}", normal_range(i0 + 1, dels), j0),
+            (0, _) => format!("{}a{}", i0, normal_range(j0 + 1, ins)),
+            _ => format!(
+                "{}c{}",
+                normal_range(i0 + 1, dels),
+                normal_range(j0 + 1, ins)
+            ),
+        };
+        out.push_str(&header);
+        out.push('\n');
+        for line in &a[i0..i] {
+            push_diff_line(&mut out, "< ", line);
+        }
+        if dels > 0 && ins > 0 {
+            out.push_str("---\n");
+        }
+        for line in &b[j0..j] {
+            push_diff_line(&mut out, "> ", line);
+        }
+    }
+    out
+}
+
+/// Render an edit script as unified diff hunks with [`DIFF_CONTEXT`] lines
+/// of context; changes separated by at most twice that share a hunk.
+fn format_unified_diff(ops: &[DiffOp], a: &[&str], b: &[&str]) -> String {
+    // (a index, b index) before each op.
+    let mut pos = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in ops {
+        pos.push((i, j));
+        match op {
+            DiffOp::Equal => {
+                i += 1;
+                j += 1;
+            }
+            DiffOp::Delete => i += 1,
+            DiffOp::Insert => j += 1,
+        }
+    }
+    pos.push((i, j));
+
+    let changed: Vec<usize> = (0..ops.len())
+        .filter(|&k| ops[k] != DiffOp::Equal)
+        .collect();
+    let mut out = String::new();
+    let mut c = 0;
+    while c < changed.len() {
+        let first = changed[c];
+        let mut last = first;
+        while c + 1 < changed.len() && changed[c + 1] - last <= 2 * DIFF_CONTEXT + 1 {
+            c += 1;
+            last = changed[c];
+        }
+        c += 1;
+
+        let start = first.saturating_sub(DIFF_CONTEXT);
+        let end = (last + 1 + DIFF_CONTEXT).min(ops.len());
+        let (a0, b0) = pos[start];
+        let (a1, b1) = pos[end];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            unified_range(a0 + 1, a1 - a0),
+            unified_range(b0 + 1, b1 - b0)
+        ));
+        for k in start..end {
+            let (ai, bi) = pos[k];
+            match ops[k] {
+                DiffOp::Equal => push_diff_line(&mut out, " ", a[ai]),
+                DiffOp::Delete => push_diff_line(&mut out, "-", a[ai]),
+                DiffOp::Insert => push_diff_line(&mut out, "+", b[bi]),
+            }
+        }
+    }
+    out
+}
+
+/// `diff [-u] FILE1 FILE2` -- line-by-line comparison of two files (either
+/// may be `-` for stdin). Exit 0 if identical, 1 if different, 2 on error.
+/// Other options fall through to the external `diff`.
+fn builtin_diff(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> Option<BuiltinResult> {
+    let mut unified = false;
+    let mut files: Vec<&str> = Vec::new();
+    let mut opts_done = false;
+    for arg in args {
+        match arg.as_str() {
+            "--" if !opts_done => opts_done = true,
+            "-u" | "--unified" if !opts_done => unified = true,
+            a if !opts_done && a.starts_with('-') && a != "-" => return None,
+            a => files.push(a),
+        }
+    }
+    if files.len() != 2 {
+        shell_eprintln!("diff: usage: diff [-u] FILE1 FILE2");
+        return Some(BuiltinResult::Result(2));
+    }
+
+    let mut texts = Vec::with_capacity(2);
+    for name in &files {
+        match read_operand(state, host, name) {
+            Ok(data) => texts.push(String::from_utf8_lossy(&data).into_owned()),
+            Err(e) => {
+                shell_eprintln!("diff: {}", e);
+                return Some(BuiltinResult::Result(2));
+            }
+        }
+    }
+    if texts[0] == texts[1] {
+        return Some(BuiltinResult::Result(0));
+    }
+
+    let (a, b) = (split_lines_keep(&texts[0]), split_lines_keep(&texts[1]));
+    let ops = diff_lines(&a, &b);
+    let out = if unified {
+        format!(
+            "--- {}\n+++ {}\n{}",
+            files[0],
+            files[1],
+            format_unified_diff(&ops, &a, &b)
+        )
+    } else {
+        format_normal_diff(&ops, &a, &b)
+    };
+    shell_print!("{}", out);
+    Some(BuiltinResult::Result(1))
+}
+
+// -- stat -----------------------------------------------------------------
+
+/// Human-readable file type, as printed by `%F`.
+fn stat_file_type(info: &StatInfo) -> &'static str {
+    if info.is_symlink {
+        "symbolic link"
+    } else if info.is_dir {
+        "directory"
+    } else if info.size == 0 {
+        "regular empty file"
+    } else {
+        "regular file"
+    }
+}
+
+/// Expand `stat -c` directives for one file. Unknown directives are kept
+/// verbatim; `\n` and `\t` are interpreted.
+fn apply_stat_format(format: &str, name: &str, info: &StatInfo) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => match chars.next() {
+                Some('s') => out.push_str(&info.size.to_string()),
+                Some('n') => out.push_str(name),
+                Some('F') => out.push_str(stat_file_type(info)),
+                Some('a') => out.push_str(&format!("{:o}", info.mode & 0o7777)),
+                Some('Y') => out.push_str(&(info.mtime_ms / 1000).to_string()),
+                Some('i') => out.push_str(&info.inode.to_string()),
+                Some('%') => out.push('%'),
+                Some(other) => {
+                    out.push('%');
+                    out.push(other);
+                }
+                None => out.push('%'),
+            },
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('\\') => out.push('\\'),
+                Some(other) => {
+                    out.push('\\');
+                    out.push(other);
+                }
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `stat [-c FORMAT] FILE...` -- show size, type, mode and mtime of each
+/// file. Exit 1 if any file is missing. Other options fall through to the
+/// external `stat`.
+fn builtin_stat(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> Option<BuiltinResult> {
+    let mut format: Option<String> = None;
+    let mut files: Vec<&str> = Vec::new();
+    let mut opts_done = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--" if !opts_done => opts_done = true,
+            "-c" | "--format" if !opts_done => match iter.next() {
+                Some(f) => format = Some(f.clone()),
+                None => {
+                    shell_eprintln!("stat: option requires an argument -- 'c'");
+                    return Some(BuiltinResult::Result(1));
+                }
+            },
+            a if !opts_done && a.starts_with("--format=") => {
+                format = Some(a["--format=".len()..].to_string());
+            }
+            a if !opts_done && a.starts_with("-c") && a.len() > 2 => {
+                format = Some(a[2..].to_string());
+            }
+            a if !opts_done && a.starts_with('-') && a != "-" => return None,
+            a => files.push(a),
+        }
+    }
+    if files.is_empty() {
+        shell_eprintln!("stat: missing operand");
+        return Some(BuiltinResult::Result(1));
+    }
+
+    let mut status = 0;
+    for name in files {
+        let info = match host.stat(&state.resolve_path(name)) {
+            Ok(info) if info.exists => info,
+            _ => {
+                shell_eprintln!("stat: cannot stat '{}': No such file or directory", name);
+                status = 1;
+                continue;
+            }
+        };
+        match &format {
+            Some(f) => shell_println!("{}", apply_stat_format(f, name, &info)),
+            None => {
+                shell_println!("  File: {}", name);
+                shell_println!("  Size: {:<10} Type: {}", info.size, stat_file_type(&info));
+                shell_println!("Access: ({:04o})", info.mode & 0o7777);
+                shell_println!("Modify: {}", format_timestamp(info.mtime_ms / 1000));
+            }
+        }
+    }
+    Some(BuiltinResult::Result(status))
+}
+
+// -- realpath / readlink --------------------------------------------------
+
+/// Which components of a path must exist for [`canonicalize`] to succeed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Canon {
+    /// `-e`: every component.
+    Existing,
+    /// Default / `readlink -f`: all but the last.
+    Parents,
+    /// `-m`: none.
+    Missing,
+}
+
+/// Most symlinks followed while canonicalizing one path.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Resolve `path` against the cwd to an absolute path with `.`, `..` and
+/// symlinks resolved. With `follow` false the result is purely lexical
+/// apart from the existence checks.
+fn canonicalize(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    path: &str,
+    mode: Canon,
+    follow: bool,
+) -> Result<String, String> {
+    // Components still to visit, in reverse so the next one is on top.
+    let mut pending = Vec::new();
+    push_components(&mut pending, &normalize_path(&state.resolve_path(path)));
+    let mut resolved = String::new();
+    let mut hops = 0;
+
+    while let Some(name) = pending.pop() {
+        match name.as_str() {
+            "." => continue,
+            ".." => {
+                let cut = resolved.rfind('/').unwrap_or(0);
+                resolved.truncate(cut);
+                continue;
+            }
+            _ => {}
+        }
+        let candidate = format!("{resolved}/{name}");
+        if follow && hops < MAX_SYMLINK_HOPS {
+            if let Ok(target) = host.readlink(&candidate) {
+                hops += 1;
+                if target.starts_with('/') {
+                    resolved.clear();
+                }
+                push_components(&mut pending, &target);
+                continue;
+            }
+        }
+        let exists = host.stat(&candidate).is_ok_and(|st| st.exists);
+        let required = match mode {
+            Canon::Existing => true,
+            Canon::Parents => !pending.is_empty(),
+            Canon::Missing => false,
+        };
+        if !exists && required {
+            return Err(format!("{path}: No such file or directory"));
+        }
+        resolved = candidate;
+    }
+
+    if resolved.is_empty() {
+        resolved.push('/');
+    }
+    Ok(resolved)
+}
+
+/// Push the components of `path` onto `stack` so they pop in order.
+fn push_components(stack: &mut Vec<String>, path: &str) {
+    let components = path.split('/').rev().filter(|c| !c.is_empty());
+    stack.extend(components.map(str::to_string));
+}
+
+/// `realpath [-e|-m] [-s] [-q] FILE...` -- print the absolute, canonical
+/// form of each path. Other options fall through to the external
+/// `realpath`.
+fn builtin_realpath(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> Option<BuiltinResult> {
+    let mut mode = Canon::Parents;
+    let mut follow = true;
+    let mut quiet = false;
+    let mut files: Vec<&str> = Vec::new();
+    let mut opts_done = false;
+    for arg in args {
+        match arg.as_str() {
+            "--" if !opts_done => opts_done = true,
+            "-e" | "--canonicalize-existing" if !opts_done => mode = Canon::Existing,
+            "-m" | "--canonicalize-missing" if !opts_done => mode = Canon::Missing,
+            "-s" | "--strip" | "--no-symlinks" if !opts_done => follow = false,
+            "-q" | "--quiet" if !opts_done => quiet = true,
+            a if !opts_done && a.starts_with('-') && a != "-" => return None,
+            a => files.push(a),
+        }
+    }
+    if files.is_empty() {
+        shell_eprintln!("realpath: missing operand");
+        return Some(BuiltinResult::Result(1));
+    }
+
+    let mut status = 0;
+    for file in files {
+        match canonicalize(state, host, file, mode, follow) {
+            Ok(path) => shell_println!("{}", path),
+            Err(e) => {
+                if !quiet {
+                    shell_eprintln!("realpath: {}", e);
+                }
+                status = 1;
+            }
+        }
+    }
+    Some(BuiltinResult::Result(status))
+}
+
+/// `readlink [-f|-e|-m] [-n] FILE...` -- print a symlink's target, or with
+/// `-f`/`-e`/`-m` the canonical path as `realpath` would. Exit 1 for
+/// anything that cannot be read or resolved. Other options fall through
+/// to the external `readlink`.
+fn builtin_readlink(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> Option<BuiltinResult> {
+    let mut mode: Option<Canon> = None;
+    let mut newline = true;
+    let mut files: Vec<&str> = Vec::new();
+    let mut opts_done = false;
+    for arg in args {
+        match arg.as_str() {
+            "--" if !opts_done => opts_done = true,
+            "--canonicalize" if !opts_done => mode = Some(Canon::Parents),
+            "--canonicalize-existing" if !opts_done => mode = Some(Canon::Existing),
+            "--canonicalize-missing" if !opts_done => mode = Some(Canon::Missing),
+            "--no-newline" if !opts_done => newline = false,
+            "--quiet" | "--silent" | "--verbose" if !opts_done => {}
+            a if !opts_done && a.starts_with('-') && a.len() > 1 => {
+                // Later flags win, as in GNU readlink.
+                for c in a[1..].chars() {
+                    match c {
+                        'f' => mode = Some(Canon::Parents),
+                        'e' => mode = Some(Canon::Existing),
+                        'm' => mode = Some(Canon::Missing),
+                        'n' => newline = false,
+                        'q' | 's' | 'v' => {}
+                        _ => return None,
+                    }
+                }
+            }
+            a => files.push(a),
+        }
+    }
+    if files.is_empty() {
+        shell_eprintln!("readlink: missing operand");
+        return Some(BuiltinResult::Result(1));
+    }
+
+    let mut status = 0;
+    for file in files {
+        let target = match mode {
+            Some(mode) => canonicalize(state, host, file, mode, true).ok(),
+            None => host.readlink(&state.resolve_path(file)).ok(),
+        };
+        match target {
+            Some(t) if newline => shell_println!("{}", t),
+            Some(t) => shell_print!("{}", t),
+            None => status = 1,
+        }
+    }
+    Some(BuiltinResult::Result(status))
+}
+
+// -- mktemp ---------------------------------------------------------------
+
+/// Characters substituted for the trailing `X`s of a `mktemp` template.
+const MKTEMP_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Names tried before `mktemp` gives up on a crowded template.
+const MKTEMP_ATTEMPTS: usize = 100;
+
+/// `mktemp [-d] [-u] [-q] [-t] [-p DIR] [TEMPLATE]` -- create a uniquely
+/// named empty file (or directory with `-d`) and print its path. The
+/// trailing `X`s of TEMPLATE are replaced from the `$RANDOM` generator.
+/// Without a template, `tmp.XXXXXXXXXX` is created under `$TMPDIR` or
+/// `/tmp`. Other options fall through to the external `mktemp`.
+fn builtin_mktemp(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    args: &[String],
+) -> Option<BuiltinResult> {
+    let mut make_dir = false;
+    let mut dry_run = false;
+    let mut quiet = false;
+    let mut in_tmpdir = false;
+    let mut tmpdir: Option<String> = None;
+    let mut template: Option<&str> = None;
+    let mut opts_done = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--" if !opts_done => opts_done = true,
+            "--directory" if !opts_done => make_dir = true,
+            "--dry-run" if !opts_done => dry_run = true,
+            "--quiet" if !opts_done => quiet = true,
+            "--tmpdir" if !opts_done => in_tmpdir = true,
+            "-p" if !opts_done => {
+                tmpdir = Some(iter.next()?.clone());
+                in_tmpdir = true;
+            }
+            a if !opts_done && a.starts_with("--tmpdir=") => {
+                tmpdir = Some(a["--tmpdir=".len()..].to_string());
+                in_tmpdir = true;
+            }
+            a if !opts_done && a.starts_with('-') && a.len() > 1 => {
+                for c in a[1..].chars() {
+                    match c {
+                        'd' => make_dir = true,
+                        'u' => dry_run = true,
+                        'q' => quiet = true,
+                        't' => in_tmpdir = true,
+                        _ => return None,
+                    }
+                }
+            }
+            a if template.is_none() => template = Some(a),
+            _ => {
+                shell_eprintln!("mktemp: too many templates");
+                return Some(BuiltinResult::Result(1));
+            }
+        }
+    }
+
+    let template = template.unwrap_or_else(|| {
+        in_tmpdir = true;
+        "tmp.XXXXXXXXXX"
+    });
+    let xs = template.len() - template.trim_end_matches('X').len();
+    if xs < 3 {
+        if !quiet {
+            shell_eprintln!("mktemp: too few X's in template '{}'", template);
+        }
+        return Some(BuiltinResult::Result(1));
+    }
+    let dir = if in_tmpdir {
+        let dir = tmpdir
+            .filter(|d| !d.is_empty())
+            .or_else(|| state.env.get("TMPDIR").filter(|d| !d.is_empty()).cloned())
+            .unwrap_or_else(|| "/tmp".to_string());
+        format!("{}/", dir.trim_end_matches('/'))
+    } else {
+        String::new()
+    };
+    let prefix = &template[..template.len() - xs];
+
+    for _ in 0..MKTEMP_ATTEMPTS {
+        let suffix: String = (0..xs)
+            .map(|_| MKTEMP_CHARS[random_u15(state) as usize % MKTEMP_CHARS.len()] as char)
+            .collect();
+        let path = format!("{dir}{prefix}{suffix}");
+        let resolved = state.resolve_path(&path);
+        if host.stat(&resolved).is_ok_and(|st| st.exists) {
+            continue;
+        }
+        if !dry_run {
+            let created = if make_dir {
+                host.mkdir(&resolved)
+            } else {
+                host.write_file(&resolved, b"", WriteMode::Truncate)
+            };
+            if let Err(e) = created {
+                if !quiet {
+                    let kind = if make_dir { "directory" } else { "file" };
+                    shell_eprintln!(
+                        "mktemp: failed to create {} via template '{}': {}",
+                        kind,
+                        template,
+                        e
+                    );
+                }
+                return Some(BuiltinResult::Result(1));
+            }
+        }
+        shell_println!("{}", path);
+        return Some(BuiltinResult::Result(0));
+    }
+
+    if !quiet {
+        shell_eprintln!(
+            "mktemp: cannot create a unique name from template '{}'",
+            template
+        );
+    }
+    Some(BuiltinResult::Result(1))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mock::MockHost;
+
+    fn make_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Run a builtin and return just the exit code.
+    fn run_builtin(
+        state: &mut ShellState,
+        host: &dyn HostInterface,
+        cmd: &str,
+        args: &[&str],
+    ) -> i32 {
+        let _lock = crate::test_support::mock::FD_MUTEX
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let a = make_args(args);
+        match try_builtin(state, host, cmd, &a, "", None).expect("expected builtin") {
+            BuiltinResult::Result(c) | BuiltinResult::Exit(c) | BuiltinResult::Return(c) => c,
+        }
+    }
+
+    fn run_builtin_stdin(
+        state: &mut ShellState,
+        host: &dyn HostInterface,
+        cmd: &str,
+        args: &[&str],
+        stdin: &str,
+    ) -> i32 {
+        let _lock = crate::test_support::mock::FD_MUTEX
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let a = make_args(args);
+
+        // Write stdin data to a pipe and dup2 onto fd 0, matching
+        // production flow where input redirects go through fd 0.
+        let saved_fd0 = if !stdin.is_empty() {
+            let (r, w) = host.pipe().expect("pipe failed");
+            unsafe {
+                libc::write(
+                    w as libc::c_int,
+                    stdin.as_ptr() as *const libc::c_void,
+                    stdin.len(),
+                );
+            }
+            host.close_fd(w).ok();
+            let saved = host.dup(0).ok();
+            host.dup2(r, 0).ok();
+            host.close_fd(r).ok();
+            saved
+        } else {
+            None
+        };
+
+        let result = match try_builtin(state, host, cmd, &a, "", None).expect("expected builtin") {
+            BuiltinResult::Result(c) | BuiltinResult::Exit(c) | BuiltinResult::Return(c) => c,
+        };
+
+        // Restore fd 0.
+        if let Some(fd) = saved_fd0 {
+            host.dup2(fd, 0).ok();
+            host.close_fd(fd).ok();
+        }
+        result
+    }
+
+    /// Capture stdout via real OS pipes. Uses dup2 to redirect fd 1
+    /// to a pipe, runs the builtin, then reads the pipe.
+    fn run_capture(
+        state: &mut ShellState,
+        host: &MockHost,
+        cmd: &str,
+        args: &[&str],
+    ) -> (i32, String, String) {
+        run_capture_stdin(state, host, cmd, args, "")
+    }
+
+    fn run_capture_stdin(
         state: &mut ShellState,
         host: &MockHost,
         cmd: &str,
@@ -2530,6 +5096,22 @@ mod tests {
             .lock()
             .unwrap_or_else(|e| e.into_inner());
 
+        // Feed stdin through fd 0 and collect stderr from fd 2, as the
+        // executor's redirects would.
+        let saved_fd0 = (!stdin.is_empty()).then(|| {
+            let (r, w) = host.pipe().unwrap();
+            host.write_fd(w, stdin.as_bytes()).unwrap();
+            host.close_fd(w).unwrap();
+            let saved = host.dup(0).unwrap();
+            host.dup2(r, 0).unwrap();
+            host.close_fd(r).unwrap();
+            saved
+        });
+        let (err_r, err_w) = host.pipe().unwrap();
+        let saved_fd2 = host.dup(2).unwrap();
+        host.dup2(err_w, 2).unwrap();
+        host.close_fd(err_w).unwrap();
+
         let (out_r, out_w) = host.pipe().unwrap();
         let saved_stdout = state.stdout_fd;
         state.stdout_fd = out_w;
@@ -2541,9 +5123,17 @@ mod tests {
 
         state.stdout_fd = saved_stdout;
         host.close_fd(out_w).unwrap();
+        host.dup2(saved_fd2, 2).unwrap();
+        host.close_fd(saved_fd2).unwrap();
+        if let Some(fd) = saved_fd0 {
+            host.dup2(fd, 0).unwrap();
+            host.close_fd(fd).unwrap();
+        }
 
         let stdout = String::from_utf8_lossy(&host.read_fd(out_r).unwrap()).to_string();
         host.close_fd(out_r).unwrap();
+        let stderr = String::from_utf8_lossy(&host.read_fd(err_r).unwrap()).to_string();
+        host.close_fd(err_r).unwrap();
 
         let exit_code = match result {
             Some(BuiltinResult::Result(c))
@@ -2551,7 +5141,7 @@ mod tests {
             | Some(BuiltinResult::Return(c)) => c,
             None => 127,
         };
-        (exit_code, stdout, String::new())
+        (exit_code, stdout, stderr)
     }
 
     // -- echo tests -------------------------------------------------------
@@ -2630,6 +5220,38 @@ mod tests {
         assert_eq!(stdout, "A\n"); // 0x41 = 65 = 'A'
     }
 
+    #[test]
+    fn echo_ne_keeps_only_the_escaped_newline() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (_, stdout, _) = run_capture(&mut state, &host, "echo", &["-ne", "a\\n"]);
+        assert_eq!(stdout, "a\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "echo", &["-n", "-e", "a\\n"]);
+        assert_eq!(stdout, "a\n");
+    }
+
+    #[test]
+    fn echo_escape_esc_and_unicode() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (_, stdout, _) = run_capture(&mut state, &host, "echo", &["-e", "\\e[0m\\u00e9\\x4g"]);
+        assert_eq!(stdout, "\x1b[0m\u{e9}\x04g\n");
+        // Octal needs the leading 0 in echo, and -E turns escapes back off.
+        let (_, stdout, _) = run_capture(&mut state, &host, "echo", &["-e", "\\101"]);
+        assert_eq!(stdout, "\\101\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "echo", &["-eE", "a\\tb"]);
+        assert_eq!(stdout, "a\\tb\n");
+    }
+
+    #[test]
+    fn echo_follows_xpg_echo() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        state.shopt.insert("xpg_echo".into(), true);
+        let (_, stdout, _) = run_capture(&mut state, &host, "echo", &["a\\tb"]);
+        assert_eq!(stdout, "a\tb\n");
+    }
+
     #[test]
     fn echo_no_args() {
         let mut state = ShellState::new_default();
@@ -2649,188 +5271,609 @@ mod tests {
     }
 
     #[test]
-    fn false_builtin() {
+    fn false_builtin() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let code = run_builtin(&mut state, &host, "false", &[]);
+        assert_eq!(code, 1);
+    }
+
+    // -- pwd tests --------------------------------------------------------
+
+    #[test]
+    fn pwd_prints_cwd() {
+        let mut state = ShellState::new_default();
+        state.cwd = "/tmp/mydir".to_string();
+        state.env.insert("PWD".into(), "/tmp/mydir".into());
+        let host = MockHost::new();
+        let (_, stdout, _) = run_capture(&mut state, &host, "pwd", &[]);
+        assert_eq!(stdout, "/tmp/mydir\n");
+    }
+
+    // -- cd tests ---------------------------------------------------------
+
+    #[test]
+    fn cd_basic() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new().with_dir("/tmp");
+        let code = run_builtin(&mut state, &host, "cd", &["/tmp"]);
+        assert_eq!(code, 0);
+        assert_eq!(state.cwd, "/tmp");
+    }
+
+    #[test]
+    fn cd_no_args_goes_home() {
+        let mut state = ShellState::new_default();
+        state.cwd = "/tmp".to_string();
+        let host = MockHost::new().with_dir("/home/user");
+        let code = run_builtin(&mut state, &host, "cd", &[]);
+        assert_eq!(code, 0);
+        assert_eq!(state.cwd, "/home/user");
+    }
+
+    #[test]
+    fn cd_dash_returns_to_oldpwd() {
+        let mut state = ShellState::new_default();
+        state.cwd = "/tmp".to_string();
+        state
+            .env
+            .insert("OLDPWD".to_string(), "/home/user".to_string());
+        let host = MockHost::new().with_dir("/home/user");
+        let code = run_builtin(&mut state, &host, "cd", &["-"]);
+        assert_eq!(code, 0);
+        assert_eq!(state.cwd, "/home/user");
+        assert_eq!(state.env.get("OLDPWD").unwrap(), "/tmp");
+    }
+
+    #[test]
+    fn cd_normalize_dotdot() {
+        let mut state = ShellState::new_default();
+        state.cwd = "/home/user/projects".to_string();
+        let host = MockHost::new().with_dir("/home/user");
+        let code = run_builtin(&mut state, &host, "cd", &[".."]);
+        assert_eq!(code, 0);
+        assert_eq!(state.cwd, "/home/user");
+    }
+
+    #[test]
+    fn cd_nonexistent() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (code, _, stderr) = run_capture(&mut state, &host, "cd", &["/nonexistent"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "cd: /nonexistent: No such file or directory\n");
+        assert_eq!(state.cwd, "/home/user");
+    }
+
+    #[test]
+    fn cd_into_file_is_not_a_directory() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new().with_file("/home/user/notes.txt", b"hi");
+        let (code, _, stderr) = run_capture(&mut state, &host, "cd", &["notes.txt"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "cd: notes.txt: Not a directory\n");
+        assert_eq!(state.cwd, "/home/user");
+        assert!(!state.env.contains_key("OLDPWD"));
+    }
+
+    // -- exit tests -------------------------------------------------------
+
+    #[test]
+    fn exit_with_code() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let a = make_args(&["42"]);
+        let result = try_builtin(&mut state, &host, "exit", &a, "", None).unwrap();
+        match result {
+            BuiltinResult::Exit(code) => assert_eq!(code, 42),
+            _ => panic!("expected Exit"),
+        }
+    }
+
+    #[test]
+    fn exit_uses_last_exit_code() {
+        let mut state = ShellState::new_default();
+        state.last_exit_code = 7;
+        let host = MockHost::new();
+        let a: Vec<String> = vec![];
+        let result = try_builtin(&mut state, &host, "exit", &a, "", None).unwrap();
+        match result {
+            BuiltinResult::Exit(code) => assert_eq!(code, 7),
+            _ => panic!("expected Exit"),
+        }
+    }
+
+    // -- export tests -----------------------------------------------------
+
+    #[test]
+    fn export_set_var() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let code = run_builtin(&mut state, &host, "export", &["FOO=bar"]);
+        assert_eq!(code, 0);
+        assert_eq!(state.env.get("FOO").unwrap(), "bar");
+    }
+
+    #[test]
+    fn export_print_vars() {
+        let mut state = ShellState::new_default();
+        state.env.clear();
+        state.env.insert("A".to_string(), "1".to_string());
+        state.env.insert("B".to_string(), "2".to_string());
+        let host = MockHost::new();
+        let (_, stdout, _) = run_capture(&mut state, &host, "export", &["-p"]);
+        assert!(stdout.contains("declare -x A=\"1\""));
+        assert!(stdout.contains("declare -x B=\"2\""));
+    }
+
+    #[test]
+    fn export_p_lists_only_exported_vars_quoted() {
+        let mut state = ShellState::new_default();
+        state.env.clear();
+        let host = MockHost::new();
+        run_builtin(&mut state, &host, "export", &["MSG=say \"hi\""]);
+        run_builtin(&mut state, &host, "declare", &["+x", "HIDDEN=1"]);
+        let (_, stdout, _) = run_capture(&mut state, &host, "export", &["-p"]);
+        assert_eq!(stdout, "declare -x MSG=\"say \\\"hi\\\"\"\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "declare", &["-x"]);
+        assert_eq!(stdout, "declare -x MSG=\"say \\\"hi\\\"\"\n");
+
+        run_builtin(&mut state, &host, "export", &["HIDDEN"]);
+        assert!(!state.unexported.contains("HIDDEN"));
+        run_builtin(&mut state, &host, "export", &["-n", "HIDDEN"]);
+        run_builtin(&mut state, &host, "unset", &["HIDDEN"]);
+        assert!(!state.unexported.contains("HIDDEN"));
+    }
+
+    // -- unset tests ------------------------------------------------------
+
+    #[test]
+    fn unset_removes_var() {
+        let mut state = ShellState::new_default();
+        state.env.insert("FOO".to_string(), "bar".to_string());
+        let host = MockHost::new();
+        let code = run_builtin(&mut state, &host, "unset", &["FOO"]);
+        assert_eq!(code, 0);
+        assert!(!state.env.contains_key("FOO"));
+    }
+
+    #[test]
+    fn unset_array_element() {
+        let mut state = ShellState::new_default();
+        state.arrays.insert(
+            "arr".to_string(),
+            vec!["a".into(), "b".into(), "c".into()].into(),
+        );
+        let host = MockHost::new();
+        let code = run_builtin(&mut state, &host, "unset", &["arr[1]"]);
+        assert_eq!(code, 0);
+        assert_eq!(state.arrays["arr"].to_vec(), ["a", "c"]);
+        assert_eq!(state.arrays["arr"].indices().collect::<Vec<_>>(), [0, 2]);
+        run_builtin(&mut state, &host, "unset", &["arr[-1]"]);
+        assert_eq!(state.arrays["arr"].to_vec(), ["a"]);
+        run_builtin(&mut state, &host, "unset", &["arr[5]"]);
+        assert_eq!(state.arrays["arr"].to_vec(), ["a"]);
+    }
+
+    #[test]
+    fn unset_assoc_element() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let mut map = HashMap::new();
+        map.insert("key".to_string(), "v".to_string());
+        map.insert("other".to_string(), "w".to_string());
+        state.assoc_arrays.insert("map".to_string(), map);
+        run_builtin(&mut state, &host, "unset", &["map[key]"]);
+        assert_eq!(state.assoc_arrays["map"].len(), 1);
+        assert_eq!(state.assoc_arrays["map"]["other"], "w");
+    }
+
+    // -- set tests --------------------------------------------------------
+
+    #[test]
+    fn set_errexit_flag() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        run_builtin(&mut state, &host, "set", &["-e"]);
+        assert!(state.flags.contains(&ShellFlag::Errexit));
+        run_builtin(&mut state, &host, "set", &["+e"]);
+        assert!(!state.flags.contains(&ShellFlag::Errexit));
+    }
+
+    #[test]
+    fn set_nounset_flag() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        run_builtin(&mut state, &host, "set", &["-u"]);
+        assert!(state.flags.contains(&ShellFlag::Nounset));
+    }
+
+    #[test]
+    fn set_posix_changes_kill_listing() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        run_builtin(&mut state, &host, "set", &["-o", "posix"]);
+        assert!(state.flags.contains(&ShellFlag::Posix));
+        let (_, out, _) = run_capture(&mut state, &host, "kill", &["-l"]);
+        assert!(out.starts_with("HUP INT QUIT ILL"));
+        assert_eq!(out.lines().count(), 1);
+
+        run_builtin(&mut state, &host, "set", &["+o", "posix"]);
+        assert!(!state.flags.contains(&ShellFlag::Posix));
+        let (_, out, _) = run_capture(&mut state, &host, "kill", &["-l"]);
+        assert!(out.starts_with(" 1) SIGHUP"));
+    }
+
+    #[test]
+    fn set_pipefail() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        run_builtin(&mut state, &host, "set", &["-o", "pipefail"]);
+        assert!(state.flags.contains(&ShellFlag::Pipefail));
+        run_builtin(&mut state, &host, "set", &["+o", "pipefail"]);
+        assert!(!state.flags.contains(&ShellFlag::Pipefail));
+    }
+
+    #[test]
+    fn set_positional_params() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        run_builtin(&mut state, &host, "set", &["--", "a", "b", "c"]);
+        assert_eq!(state.positional_args, vec!["a", "b", "c"]);
+    }
+
+    // -- shopt tests ------------------------------------------------------
+
+    #[test]
+    fn shopt_toggles_and_prints_options() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        assert!(!state.shopt_enabled("nullglob"));
+        assert_eq!(
+            run_builtin(&mut state, &host, "shopt", &["-s", "nullglob", "globstar"]),
+            0
+        );
+        assert!(state.shopt_enabled("nullglob") && state.shopt_enabled("globstar"));
+        let (code, stdout, _) =
+            run_capture(&mut state, &host, "shopt", &["-p", "nullglob", "dotglob"]);
+        assert_eq!(code, 1, "dotglob is off");
+        assert_eq!(stdout, "shopt -s nullglob\nshopt -u dotglob\n");
+
+        assert_eq!(
+            run_builtin(&mut state, &host, "shopt", &["-u", "globstar"]),
+            0
+        );
+        let (_, stdout, _) = run_capture(&mut state, &host, "shopt", &["globstar"]);
+        assert_eq!(stdout, "globstar       \toff\n");
+        let (code, stdout, _) = run_capture(&mut state, &host, "shopt", &["-q", "nullglob"]);
+        assert_eq!((code, stdout.as_str()), (0, ""));
+    }
+
+    #[test]
+    fn shopt_rejects_unknown_names() {
         let mut state = ShellState::new_default();
         let host = MockHost::new();
-        let code = run_builtin(&mut state, &host, "false", &[]);
-        assert_eq!(code, 1);
+        assert_eq!(
+            run_builtin(&mut state, &host, "shopt", &["-s", "nosuchopt"]),
+            1
+        );
+        assert!(!state.shopt.contains_key("nosuchopt"));
+        let (_, stdout, _) = run_capture(&mut state, &host, "shopt", &["-s"]);
+        assert_eq!(stdout, "expand_aliases \ton\n");
     }
 
-    // -- pwd tests --------------------------------------------------------
+    // -- complete / compgen tests -----------------------------------------
 
     #[test]
-    fn pwd_prints_cwd() {
+    fn compgen_word_list_filters_by_prefix() {
         let mut state = ShellState::new_default();
-        state.cwd = "/tmp/mydir".to_string();
-        state.env.insert("PWD".into(), "/tmp/mydir".into());
         let host = MockHost::new();
-        let (_, stdout, _) = run_capture(&mut state, &host, "pwd", &[]);
-        assert_eq!(stdout, "/tmp/mydir\n");
-    }
+        let (code, stdout, _) = run_capture(
+            &mut state,
+            &host,
+            "compgen",
+            &["-W", "apple apricot banana", "ap"],
+        );
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "apple\napricot\n");
 
-    // -- cd tests ---------------------------------------------------------
+        let (code, stdout, _) = run_capture(&mut state, &host, "compgen", &["-W", "apple", "z"]);
+        assert_eq!(code, 1);
+        assert_eq!(stdout, "");
+    }
 
     #[test]
-    fn cd_basic() {
+    fn compgen_files_and_dirs() {
         let mut state = ShellState::new_default();
-        let host = MockHost::new().with_dir("/tmp");
-        let code = run_builtin(&mut state, &host, "cd", &["/tmp"]);
-        assert_eq!(code, 0);
-        assert_eq!(state.cwd, "/tmp");
+        state.cwd = "/work".to_string();
+        let host = MockHost::new()
+            .with_dir("/work")
+            .with_dir("/work/src")
+            .with_file("/work/setup.py", b"")
+            .with_file("/work/.secret", b"")
+            .with_file("/work/src/main.rs", b"");
+        let (_, stdout, _) = run_capture(&mut state, &host, "compgen", &["-f", "s"]);
+        assert_eq!(stdout, "setup.py\nsrc\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "compgen", &["-d"]);
+        assert_eq!(stdout, "src\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "compgen", &["-f", "src/m"]);
+        assert_eq!(stdout, "src/main.rs\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "compgen", &["-f", "."]);
+        assert_eq!(stdout, ".secret\n");
     }
 
     #[test]
-    fn cd_no_args_goes_home() {
+    fn complete_records_specs() {
         let mut state = ShellState::new_default();
-        state.cwd = "/tmp".to_string();
-        let host = MockHost::new().with_dir("/home/user");
-        let code = run_builtin(&mut state, &host, "cd", &[]);
+        let host = MockHost::new();
+        let (code, _, _) = run_capture(
+            &mut state,
+            &host,
+            "complete",
+            &["-o", "default", "-F", "_git", "git", "gitk"],
+        );
         assert_eq!(code, 0);
-        assert_eq!(state.cwd, "/home/user");
+        let (_, stdout, _) = run_capture(&mut state, &host, "complete", &["-p", "git"]);
+        assert_eq!(stdout, "complete -o default -F _git git\n");
+
+        run_capture(&mut state, &host, "complete", &["-r", "gitk"]);
+        let (code, stdout, stderr) = run_capture(&mut state, &host, "complete", &["-p", "gitk"]);
+        assert_eq!(code, 1);
+        assert_eq!(stdout, "");
+        assert_eq!(stderr, "complete: gitk: no completion specification\n");
     }
 
-    #[test]
-    fn cd_dash_returns_to_oldpwd() {
-        let mut state = ShellState::new_default();
-        state.cwd = "/tmp".to_string();
-        state
-            .env
-            .insert("OLDPWD".to_string(), "/home/user".to_string());
-        let host = MockHost::new().with_dir("/home/user");
-        let code = run_builtin(&mut state, &host, "cd", &["-"]);
-        assert_eq!(code, 0);
-        assert_eq!(state.cwd, "/home/user");
-        assert_eq!(state.env.get("OLDPWD").unwrap(), "/tmp");
+    // -- cmp / diff tests -------------------------------------------------
+
+    fn compare_host() -> MockHost {
+        MockHost::new()
+            .with_file("/home/user/a.txt", b"a\nb\nc\n")
+            .with_file("/home/user/same.txt", b"a\nb\nc\n")
+            .with_file("/home/user/b.txt", b"a\nB\nc\nd\n")
+            .with_file("/home/user/short.txt", b"a\nb\n")
     }
 
     #[test]
-    fn cd_normalize_dotdot() {
+    fn cmp_reports_first_difference() {
         let mut state = ShellState::new_default();
-        state.cwd = "/home/user/projects".to_string();
-        let host = MockHost::new().with_dir("/home/user");
-        let code = run_builtin(&mut state, &host, "cd", &[".."]);
-        assert_eq!(code, 0);
-        assert_eq!(state.cwd, "/home/user");
+        let host = compare_host();
+        let (code, stdout, _) = run_capture(&mut state, &host, "cmp", &["a.txt", "same.txt"]);
+        assert_eq!((code, stdout.as_str()), (0, ""));
+
+        let (code, stdout, _) = run_capture(&mut state, &host, "cmp", &["a.txt", "b.txt"]);
+        assert_eq!(code, 1);
+        assert_eq!(stdout, "a.txt b.txt differ: byte 3, line 2\n");
+
+        let (code, stdout, _) = run_capture(&mut state, &host, "cmp", &["-s", "a.txt", "b.txt"]);
+        assert_eq!((code, stdout.as_str()), (1, ""));
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "cmp", &["a.txt", "short.txt"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "cmp: EOF on short.txt after byte 4, line 3\n");
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "cmp", &["a.txt", "nope"]);
+        assert_eq!(code, 2);
+        assert_eq!(stderr, "cmp: nope: No such file or directory\n");
     }
 
     #[test]
-    fn cd_nonexistent() {
+    fn diff_normal_and_unified_output() {
         let mut state = ShellState::new_default();
-        let host = MockHost::new();
-        let code = run_builtin(&mut state, &host, "cd", &["/nonexistent"]);
+        let host = compare_host();
+        let (code, stdout, _) = run_capture(&mut state, &host, "diff", &["a.txt", "same.txt"]);
+        assert_eq!((code, stdout.as_str()), (0, ""));
+
+        let (code, stdout, _) = run_capture(&mut state, &host, "diff", &["a.txt", "b.txt"]);
         assert_eq!(code, 1);
-    }
+        assert_eq!(stdout, "2c2\n< b\n---\n> B\n3a4\n> d\n");
 
-    // -- exit tests -------------------------------------------------------
+        let (code, stdout, _) = run_capture(&mut state, &host, "diff", &["-u", "a.txt", "b.txt"]);
+        assert_eq!(code, 1);
+        assert_eq!(
+            stdout,
+            "--- a.txt\n+++ b.txt\n@@ -1,3 +1,4 @@\n a\n-b\n+B\n c\n+d\n"
+        );
+
+        let (code, stdout, _) = run_capture(&mut state, &host, "diff", &["short.txt", "a.txt"]);
+        assert_eq!((code, stdout.as_str()), (1, "2a3\n> c\n"));
+
+        let (code, _, _) = run_capture(&mut state, &host, "diff", &["a.txt", "nope"]);
+        assert_eq!(code, 2);
+
+        // Options the builtin doesn't implement are left to the external tool.
+        let args = make_args(&["-q", "a.txt", "b.txt"]);
+        assert!(try_builtin(&mut state, &host, "diff", &args, "", None).is_none());
+        let args = make_args(&["-l", "a.txt", "b.txt"]);
+        assert!(try_builtin(&mut state, &host, "cmp", &args, "", None).is_none());
+    }
 
     #[test]
-    fn exit_with_code() {
+    fn diff_reads_stdin_and_flags_missing_newline() {
         let mut state = ShellState::new_default();
-        let host = MockHost::new();
-        let a = make_args(&["42"]);
-        let result = try_builtin(&mut state, &host, "exit", &a, "", None).unwrap();
-        match result {
-            BuiltinResult::Exit(code) => assert_eq!(code, 42),
-            _ => panic!("expected Exit"),
-        }
+        let host = compare_host();
+        let (code, stdout, _) =
+            run_capture_stdin(&mut state, &host, "diff", &["-", "a.txt"], "a\nb\nc");
+        assert_eq!(code, 1);
+        assert_eq!(stdout, "3c3\n< c\n\\ No newline at end of file\n---\n> c\n");
+
+        let (code, _, _) = run_capture_stdin(
+            &mut state,
+            &host,
+            "diff",
+            &["-u", "-", "a.txt"],
+            "a\nb\nc\n",
+        );
+        assert_eq!(code, 0);
     }
 
     #[test]
-    fn exit_uses_last_exit_code() {
-        let mut state = ShellState::new_default();
-        state.last_exit_code = 7;
-        let host = MockHost::new();
-        let a: Vec<String> = vec![];
-        let result = try_builtin(&mut state, &host, "exit", &a, "", None).unwrap();
-        match result {
-            BuiltinResult::Exit(code) => assert_eq!(code, 7),
-            _ => panic!("expected Exit"),
-        }
+    fn diff_unified_splits_distant_changes_into_hunks() {
+        let a: Vec<String> = (1..=20).map(|n| format!("{n}\n")).collect();
+        let mut b = a.clone();
+        b[1] = "two\n".to_string();
+        b[17] = "eighteen\n".to_string();
+        let a: Vec<&str> = a.iter().map(String::as_str).collect();
+        let b: Vec<&str> = b.iter().map(String::as_str).collect();
+        let out = format_unified_diff(&diff_lines(&a, &b), &a, &b);
+        let headers: Vec<&str> = out.lines().filter(|l| l.starts_with("@@")).collect();
+        assert_eq!(headers, vec!["@@ -1,5 +1,5 @@", "@@ -15,6 +15,6 @@"]);
     }
 
-    // -- export tests -----------------------------------------------------
+    // -- stat tests -------------------------------------------------------
 
     #[test]
-    fn export_set_var() {
+    fn stat_formats_size_mtime_and_mode() {
         let mut state = ShellState::new_default();
-        let host = MockHost::new();
-        let code = run_builtin(&mut state, &host, "export", &["FOO=bar"]);
+        let host = MockHost::new()
+            .with_file("/home/user/f.txt", b"hello\n")
+            .with_mtime("/home/user/f.txt", 1_700_000_000_123)
+            .with_mode("/home/user/f.txt", 0o100640);
+        let (code, stdout, _) = run_capture(&mut state, &host, "stat", &["-c", "%s %Y", "f.txt"]);
         assert_eq!(code, 0);
-        assert_eq!(state.env.get("FOO").unwrap(), "bar");
+        assert_eq!(stdout, "6 1700000000\n");
+
+        let (_, stdout, _) = run_capture(
+            &mut state,
+            &host,
+            "stat",
+            &["--format=%n: %F %a %%", "f.txt"],
+        );
+        assert_eq!(stdout, "f.txt: regular file 640 %\n");
+
+        let (code, stdout, _) = run_capture(&mut state, &host, "stat", &["f.txt"]);
+        assert_eq!(code, 0);
+        assert!(stdout.contains("  File: f.txt\n"));
+        assert!(stdout.contains("  Size: 6          Type: regular file\n"));
+        assert!(stdout.contains("Access: (0640)\n"));
+        assert!(stdout.contains("Modify: 2023-11-14T22:13:20Z\n"));
     }
 
     #[test]
-    fn export_print_vars() {
+    fn stat_reports_missing_files() {
         let mut state = ShellState::new_default();
-        state.env.clear();
-        state.env.insert("A".to_string(), "1".to_string());
-        state.env.insert("B".to_string(), "2".to_string());
-        let host = MockHost::new();
-        let (_, stdout, _) = run_capture(&mut state, &host, "export", &["-p"]);
-        assert!(stdout.contains("declare -x A=\"1\""));
-        assert!(stdout.contains("declare -x B=\"2\""));
+        let host = MockHost::new().with_dir("/home/user/d");
+        let (code, stdout, stderr) =
+            run_capture(&mut state, &host, "stat", &["-c", "%F", "d", "nope"]);
+        assert_eq!(code, 1);
+        assert_eq!(stdout, "directory\n");
+        assert_eq!(
+            stderr,
+            "stat: cannot stat 'nope': No such file or directory\n"
+        );
     }
 
-    // -- unset tests ------------------------------------------------------
+    // -- realpath / readlink tests ----------------------------------------
+
+    fn path_host() -> MockHost {
+        MockHost::new()
+            .with_dir("/home")
+            .with_dir("/home/user")
+            .with_dir("/home/user/b")
+            .with_file("/home/user/b/c", b"")
+            .with_symlink("/home/user/link", "b")
+            .with_symlink("/home/user/abs", "/home/user/b/c")
+    }
 
     #[test]
-    fn unset_removes_var() {
+    fn realpath_normalizes_relative_paths() {
         let mut state = ShellState::new_default();
-        state.env.insert("FOO".to_string(), "bar".to_string());
-        let host = MockHost::new();
-        let code = run_builtin(&mut state, &host, "unset", &["FOO"]);
+        let host = path_host();
+        let (code, stdout, _) =
+            run_capture(&mut state, &host, "realpath", &["a/../b/./c", "b", "."]);
         assert_eq!(code, 0);
-        assert!(!state.env.contains_key("FOO"));
+        assert_eq!(stdout, "/home/user/b/c\n/home/user/b\n/home/user\n");
+
+        // Symlinks are followed unless -s is given.
+        let (_, stdout, _) = run_capture(&mut state, &host, "realpath", &["link/c", "abs"]);
+        assert_eq!(stdout, "/home/user/b/c\n/home/user/b/c\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "realpath", &["-s", "link/c"]);
+        assert_eq!(stdout, "/home/user/link/c\n");
     }
 
     #[test]
-    fn unset_array_element() {
+    fn realpath_existence_modes() {
         let mut state = ShellState::new_default();
-        state
-            .arrays
-            .insert("arr".to_string(), vec!["a".into(), "b".into(), "c".into()]);
-        let host = MockHost::new();
-        let code = run_builtin(&mut state, &host, "unset", &["arr[1]"]);
-        assert_eq!(code, 0);
-        assert_eq!(state.arrays["arr"][1], "");
-    }
+        let host = path_host();
+        // By default only the last component may be missing.
+        let (code, stdout, _) = run_capture(&mut state, &host, "realpath", &["b/new"]);
+        assert_eq!((code, stdout.as_str()), (0, "/home/user/b/new\n"));
+        let (code, _, stderr) = run_capture(&mut state, &host, "realpath", &["x/y/z"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "realpath: x/y/z: No such file or directory\n");
 
-    // -- set tests --------------------------------------------------------
+        let (code, stdout, _) = run_capture(&mut state, &host, "realpath", &["-m", "x/../y/z"]);
+        assert_eq!((code, stdout.as_str()), (0, "/home/user/y/z\n"));
+        let (code, _, _) = run_capture(&mut state, &host, "realpath", &["-e", "b/new"]);
+        assert_eq!(code, 1);
+    }
 
     #[test]
-    fn set_errexit_flag() {
+    fn readlink_prints_targets_and_canonicalizes_with_f() {
         let mut state = ShellState::new_default();
-        let host = MockHost::new();
-        run_builtin(&mut state, &host, "set", &["-e"]);
-        assert!(state.flags.contains(&ShellFlag::Errexit));
-        run_builtin(&mut state, &host, "set", &["+e"]);
-        assert!(!state.flags.contains(&ShellFlag::Errexit));
+        let host = path_host();
+        let (code, stdout, _) = run_capture(&mut state, &host, "readlink", &["link"]);
+        assert_eq!((code, stdout.as_str()), (0, "b\n"));
+        let (code, stdout, _) = run_capture(&mut state, &host, "readlink", &["b/c"]);
+        assert_eq!((code, stdout.as_str()), (1, ""));
+
+        let (code, stdout, _) = run_capture(&mut state, &host, "readlink", &["-fn", "link/./c"]);
+        assert_eq!((code, stdout.as_str()), (0, "/home/user/b/c"));
+        let (code, _, _) = run_capture(&mut state, &host, "readlink", &["-e", "link/nope"]);
+        assert_eq!(code, 1);
     }
 
+    // -- mktemp tests -----------------------------------------------------
+
     #[test]
-    fn set_nounset_flag() {
+    fn mktemp_creates_distinct_files_and_dirs() {
         let mut state = ShellState::new_default();
-        let host = MockHost::new();
-        run_builtin(&mut state, &host, "set", &["-u"]);
-        assert!(state.flags.contains(&ShellFlag::Nounset));
+        let host = MockHost::new().with_dir("/tmp");
+        let (code, first, _) = run_capture(&mut state, &host, "mktemp", &[]);
+        assert_eq!(code, 0);
+        let (_, second, _) = run_capture(&mut state, &host, "mktemp", &[]);
+        let (first, second) = (first.trim_end(), second.trim_end());
+        assert_ne!(first, second);
+        for path in [first, second] {
+            assert!(path.starts_with("/tmp/tmp.") && path.len() == "/tmp/tmp.".len() + 10);
+            assert!(host.stat(path).unwrap().is_file);
+        }
+
+        state.env.insert("TMPDIR".into(), "/scratch/".into());
+        let (_, dir, _) = run_capture(&mut state, &host, "mktemp", &["-d", "-t", "job.XXXX"]);
+        let dir = dir.trim_end();
+        assert!(dir.starts_with("/scratch/job.") && dir.len() == "/scratch/job.".len() + 4);
+        assert!(host.stat(dir).unwrap().is_dir);
     }
 
     #[test]
-    fn set_pipefail() {
+    fn mktemp_dry_run_and_template_errors() {
         let mut state = ShellState::new_default();
         let host = MockHost::new();
-        run_builtin(&mut state, &host, "set", &["-o", "pipefail"]);
-        assert!(state.flags.contains(&ShellFlag::Pipefail));
-        run_builtin(&mut state, &host, "set", &["+o", "pipefail"]);
-        assert!(!state.flags.contains(&ShellFlag::Pipefail));
+        let (code, name, _) = run_capture(&mut state, &host, "mktemp", &["-u", "out.XXX"]);
+        assert_eq!(code, 0);
+        assert!(name.starts_with("out.") && name.len() == "out.XXX\n".len());
+        let path = format!("/home/user/{}", name.trim_end());
+        assert!(!host.stat(&path).unwrap().exists);
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "mktemp", &["fileXX"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "mktemp: too few X's in template 'fileXX'\n");
     }
 
+    // -- yes tests --------------------------------------------------------
+
     #[test]
-    fn set_positional_params() {
+    fn yes_stops_at_cap_with_diagnostic() {
         let mut state = ShellState::new_default();
         let host = MockHost::new();
-        run_builtin(&mut state, &host, "set", &["--", "a", "b", "c"]);
-        assert_eq!(state.positional_args, vec!["a", "b", "c"]);
+        let (code, stdout, stderr) = run_capture(&mut state, &host, "yes", &["a", "b"]);
+        assert_eq!(code, 0);
+        assert!(stdout.starts_with("a b\na b\n"));
+        assert_eq!(stdout.len(), YES_MAX_BYTES / 4 * 4);
+        assert_eq!(
+            stderr,
+            format!("yes: stopped after {} lines\n", YES_MAX_BYTES / 4)
+        );
     }
 
     // -- local tests ------------------------------------------------------
@@ -2847,7 +5890,10 @@ mod tests {
         assert_eq!(state.env.get("X").unwrap(), "local_val");
         // The local frame should have saved the old value
         let frame = state.local_var_stack.last().unwrap();
-        assert_eq!(frame.get("X"), Some(&Some("global".to_string())));
+        assert_eq!(
+            frame.get("X").and_then(|saved| saved.scalar.clone()),
+            Some("global".to_string())
+        );
     }
 
     #[test]
@@ -2867,8 +5913,8 @@ mod tests {
         let code = run_builtin(&mut state, &host, "declare", &["-a", "arr=(one two three)"]);
         assert_eq!(code, 0);
         assert_eq!(
-            state.arrays.get("arr").unwrap(),
-            &vec!["one".to_string(), "two".to_string(), "three".to_string()]
+            state.arrays["arr"].to_vec(),
+            vec!["one".to_string(), "two".to_string(), "three".to_string()]
         );
     }
 
@@ -2898,6 +5944,38 @@ mod tests {
         assert!(stdout.contains("declare -- X=\"42\""));
     }
 
+    #[test]
+    fn declare_print_quotes_values() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        state.env.insert("X".into(), "say \"hi\" $HOME".into());
+        run_builtin(&mut state, &host, "declare", &["-a", "arr=(a [2]=\"b c\")"]);
+        let (code, stdout, _) = run_capture(&mut state, &host, "declare", &["-p", "X", "arr"]);
+        assert_eq!(code, 0);
+        assert_eq!(
+            stdout,
+            "declare -- X=\"say \\\"hi\\\" \\$HOME\"\n\
+             declare -a arr=([0]=\"a\" [2]=\"b c\")\n"
+        );
+    }
+
+    #[test]
+    fn declare_f_prints_function_source() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        state.functions.insert(
+            "greet".into(),
+            codepod_shell::parser::try_parse("echo hi").unwrap(),
+        );
+        let (code, stdout, _) = run_capture(&mut state, &host, "declare", &["-f", "greet"]);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "greet () \n{\n    echo hi\n}\n");
+        let (code, stdout, _) = run_capture(&mut state, &host, "declare", &["-F"]);
+        assert_eq!((code, stdout.as_str()), (0, "declare -f greet\n"));
+        let code = run_builtin(&mut state, &host, "declare", &["-f", "nope"]);
+        assert_eq!(code, 1);
+    }
+
     // -- test / [ tests ---------------------------------------------------
 
     #[test]
@@ -2956,6 +6034,95 @@ mod tests {
         assert_eq!(code, 0);
     }
 
+    #[test]
+    fn mock_stat_reports_mtime_and_inode() {
+        let host = MockHost::new()
+            .with_file("/tmp/a", b"x")
+            .with_file("/tmp/b", b"y")
+            .with_dir("/tmp/d")
+            .with_mtime("/tmp/a", 1_500)
+            .with_mtime("/tmp/d", 42);
+        let a = host.stat("/tmp/a").unwrap();
+        let b = host.stat("/tmp/b").unwrap();
+        let d = host.stat("/tmp/d").unwrap();
+        assert_eq!(a.mtime_ms, 1_500);
+        assert_eq!(b.mtime_ms, 0);
+        assert_eq!(d.mtime_ms, 42);
+        assert_ne!(a.inode, 0);
+        assert_ne!(a.inode, b.inode);
+        assert_eq!(a.inode, host.stat("/tmp/a").unwrap().inode);
+        assert_eq!(host.stat("/tmp/missing").unwrap().inode, 0);
+    }
+
+    #[test]
+    fn test_nt_ot_compare_mtimes() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new()
+            .with_file("/tmp/old", b"")
+            .with_file("/tmp/new", b"")
+            .with_mtime("/tmp/old", 1_000)
+            .with_mtime("/tmp/new", 2_000);
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/new", "-nt", "/tmp/old"]),
+            0
+        );
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/old", "-nt", "/tmp/new"]),
+            1
+        );
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/old", "-ot", "/tmp/new"]),
+            0
+        );
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/new", "-ot", "/tmp/old"]),
+            1
+        );
+    }
+
+    #[test]
+    fn test_nt_ot_missing_file_is_older() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new().with_file("/tmp/a", b"");
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/a", "-nt", "/tmp/none"]),
+            0
+        );
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/none", "-nt", "/tmp/a"]),
+            1
+        );
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/none", "-ot", "/tmp/a"]),
+            0
+        );
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/none", "-ot", "/tmp/nah"]),
+            1
+        );
+    }
+
+    #[test]
+    fn test_ef_same_file() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new()
+            .with_file("/tmp/a", b"")
+            .with_file("/tmp/b", b"");
+        state.cwd = "/tmp".to_string();
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["a", "-ef", "/tmp/a"]),
+            0
+        );
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/a", "-ef", "/tmp/b"]),
+            1
+        );
+        assert_eq!(
+            run_builtin(&mut state, &host, "test", &["/tmp/x", "-ef", "/tmp/x"]),
+            1
+        );
+    }
+
     #[test]
     fn test_string_equality() {
         let mut state = ShellState::new_default();
@@ -3040,23 +6207,62 @@ mod tests {
         let host = MockHost::new();
         run_builtin_stdin(&mut state, &host, "read", &["-a", "arr"], "one two three\n");
         assert_eq!(
-            state.arrays.get("arr").unwrap(),
-            &vec!["one".to_string(), "two".to_string(), "three".to_string()]
+            state.arrays["arr"].to_vec(),
+            vec!["one".to_string(), "two".to_string(), "three".to_string()]
+        );
+    }
+
+    #[test]
+    fn read_custom_delimiter() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        run_builtin_stdin(
+            &mut state,
+            &host,
+            "read",
+            &["-d", ",", "VAR"],
+            "hello,world",
         );
+        assert_eq!(state.env.get("VAR").unwrap(), "hello");
+    }
+
+    #[test]
+    fn read_prompt_goes_to_stderr() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let args = ["-sp", "Name: ", "who"];
+        let (code, stdout, stderr) = run_capture_stdin(&mut state, &host, "read", &args, "bob\n");
+        assert_eq!((code, stdout.as_str()), (0, ""));
+        assert!(stderr.contains("Name: "));
+        assert_eq!(state.env.get("who").unwrap(), "bob");
+    }
+
+    #[test]
+    fn read_n_chars() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        run_builtin_stdin(&mut state, &host, "read", &["-n", "3", "VAR"], "abcdef\n");
+        assert_eq!(state.env.get("VAR").unwrap(), "abc");
+        assert_eq!(state.pipeline_stdin.as_deref(), Some("def\n"));
+
+        // -n stops early at the delimiter; -N reads straight through it
+        let mut state = ShellState::new_default();
+        run_builtin_stdin(&mut state, &host, "read", &["-n5", "VAR"], "ab\ncd\n");
+        assert_eq!(state.env.get("VAR").unwrap(), "ab");
+        let mut state = ShellState::new_default();
+        run_builtin_stdin(&mut state, &host, "read", &["-N", "4", "VAR"], "ab\ncd\n");
+        assert_eq!(state.env.get("VAR").unwrap(), "ab\nc");
     }
 
     #[test]
-    fn read_custom_delimiter() {
+    fn read_t_zero_polls_buffered_input() {
         let mut state = ShellState::new_default();
         let host = MockHost::new();
-        run_builtin_stdin(
-            &mut state,
-            &host,
-            "read",
-            &["-d", ",", "VAR"],
-            "hello,world",
-        );
-        assert_eq!(state.env.get("VAR").unwrap(), "hello");
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-t", "0"]), 1);
+        state.pipeline_stdin = Some("x\n".into());
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-t", "0"]), 0);
+        assert_eq!(state.pipeline_stdin.as_deref(), Some("x\n"));
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-t", "soon"]), 1);
     }
 
     // -- shift tests ------------------------------------------------------
@@ -3136,6 +6342,126 @@ mod tests {
         assert_eq!(code, 1);
     }
 
+    // -- hash tests -------------------------------------------------------
+
+    #[test]
+    fn hash_t_prints_resolution() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new().with_tool("git");
+        let (code, stdout, _) = run_capture(&mut state, &host, "hash", &["-t", "git"]);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "/usr/bin/git\n");
+        assert_eq!(state.command_hash.get("git").unwrap(), "/usr/bin/git");
+    }
+
+    #[test]
+    fn hash_name_records_and_lists() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new().with_tool("git");
+        assert_eq!(run_builtin(&mut state, &host, "hash", &["git"]), 0);
+        assert_eq!(run_builtin(&mut state, &host, "hash", &["echo"]), 0);
+        assert_eq!(run_builtin(&mut state, &host, "hash", &["nosuch"]), 1);
+        let (code, stdout, _) = run_capture(&mut state, &host, "hash", &[]);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "builtin hash -p /usr/bin/git git\n");
+    }
+
+    #[test]
+    fn hash_r_clears_table() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        state
+            .command_hash
+            .insert("ls".to_string(), "/usr/bin/ls".to_string());
+        assert_eq!(run_builtin(&mut state, &host, "hash", &["-r"]), 0);
+        assert!(state.command_hash.is_empty());
+        let (_, stdout, _) = run_capture(&mut state, &host, "hash", &[]);
+        assert_eq!(stdout, "hash: hash table empty\n");
+    }
+
+    // -- expr tests -------------------------------------------------------
+
+    #[test]
+    fn expr_arithmetic() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (code, stdout, _) = run_capture(&mut state, &host, "expr", &["1", "+", "2"]);
+        assert_eq!((code, stdout.as_str()), (0, "3\n"));
+        let (_, stdout, _) = run_capture(
+            &mut state,
+            &host,
+            "expr",
+            &["(", "2", "+", "3", ")", "*", "4"],
+        );
+        assert_eq!(stdout, "20\n");
+        let (code, stdout, _) = run_capture(&mut state, &host, "expr", &["5", "-", "5"]);
+        assert_eq!((code, stdout.as_str()), (1, "0\n"));
+        assert_eq!(run_builtin(&mut state, &host, "expr", &["1", "/", "0"]), 2);
+        assert_eq!(run_builtin(&mut state, &host, "expr", &["a", "+", "1"]), 2);
+    }
+
+    #[test]
+    fn expr_string_ops() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (code, stdout, _) = run_capture(&mut state, &host, "expr", &["length", "abc"]);
+        assert_eq!((code, stdout.as_str()), (0, "3\n"));
+        let (_, stdout, _) = run_capture(&mut state, &host, "expr", &["substr", "hello", "2", "3"]);
+        assert_eq!(stdout, "ell\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "expr", &["index", "hello", "lo"]);
+        assert_eq!(stdout, "3\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "expr", &["b", "<", "a"]);
+        assert_eq!(stdout, "0\n");
+    }
+
+    #[test]
+    fn expr_regex_match() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (_, stdout, _) = run_capture(&mut state, &host, "expr", &["abcdef", ":", "a.c"]);
+        assert_eq!(stdout, "3\n");
+        let (_, stdout, _) = run_capture(
+            &mut state,
+            &host,
+            "expr",
+            &["file.tar.gz", ":", "\\(.*\\)\\.gz"],
+        );
+        assert_eq!(stdout, "file.tar\n");
+        let (code, stdout, _) = run_capture(&mut state, &host, "expr", &["abc", ":", "x"]);
+        assert_eq!((code, stdout.as_str()), (1, "0\n"));
+    }
+
+    // -- ulimit / getconf tests -------------------------------------------
+
+    #[test]
+    fn ulimit_records_and_prints_limits() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (_, stdout, _) = run_capture(&mut state, &host, "ulimit", &["-n"]);
+        assert_eq!(stdout, "1024\n");
+        let code = run_builtin(&mut state, &host, "ulimit", &["-Sn", "4096"]);
+        assert_eq!(code, 0);
+        let (_, stdout, _) = run_capture(&mut state, &host, "ulimit", &["-n"]);
+        assert_eq!(stdout, "4096\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "ulimit", &[]);
+        assert_eq!(stdout, "unlimited\n");
+        let (_, stdout, _) = run_capture(&mut state, &host, "ulimit", &["-a"]);
+        assert!(stdout.contains("open files                      (-n) 4096\n"));
+        assert_eq!(run_builtin(&mut state, &host, "ulimit", &["-n", "lots"]), 1);
+    }
+
+    #[test]
+    fn getconf_reports_constants() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (code, stdout, _) = run_capture(&mut state, &host, "getconf", &["PAGE_SIZE"]);
+        assert_eq!((code, stdout.as_str()), (0, "65536\n"));
+        let (_, stdout, _) = run_capture(&mut state, &host, "getconf", &["_NPROCESSORS_ONLN"]);
+        assert_eq!(stdout, "1\n");
+        let (code, stdout, _) = run_capture(&mut state, &host, "getconf", &["NO_SUCH_KEY"]);
+        assert_eq!((code, stdout.as_str()), (1, ""));
+    }
+
     // -- let tests --------------------------------------------------------
 
     #[test]
@@ -3267,6 +6593,37 @@ mod tests {
         assert!(!state.traps.contains_key("EXIT"));
     }
 
+    #[test]
+    fn kill_and_trap_list_signals() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+
+        let (code, kill_out, _) = run_capture(&mut state, &host, "kill", &["-l"]);
+        assert_eq!(code, 0);
+        assert!(kill_out.starts_with(" 1) SIGHUP\t 2) SIGINT"));
+        assert!(kill_out.contains(" 9) SIGKILL"));
+        assert!(kill_out.contains("15) SIGTERM"));
+
+        let (code, trap_out, _) = run_capture(&mut state, &host, "trap", &["-l"]);
+        assert_eq!(code, 0);
+        assert_eq!(trap_out, kill_out);
+    }
+
+    #[test]
+    fn kill_l_translates_names_and_numbers() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+
+        let (code, out, _) =
+            run_capture(&mut state, &host, "kill", &["-l", "15", "SIGKILL", "130"]);
+        assert_eq!(code, 0);
+        assert_eq!(out, "TERM\n9\nINT\n");
+
+        let (code, _, err) = run_capture(&mut state, &host, "kill", &["-l", "BOGUS"]);
+        assert_eq!(code, 1);
+        assert!(err.contains("invalid signal specification"));
+    }
+
     // -- getopts tests ----------------------------------------------------
 
     #[test]
@@ -3292,6 +6649,50 @@ mod tests {
         assert_eq!(state.env.get("OPTARG").unwrap(), "val");
     }
 
+    #[test]
+    fn getopts_reports_errors() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+
+        let (code, _, err) = run_capture(&mut state, &host, "getopts", &["ab:", "opt", "-x"]);
+        assert_eq!(code, 0);
+        assert_eq!(state.env.get("opt").unwrap(), "?");
+        assert!(!state.env.contains_key("OPTARG"));
+        assert!(err.contains("illegal option -- x"));
+
+        state.env.insert("OPTIND".to_string(), "1".to_string());
+        let (code, _, err) = run_capture(&mut state, &host, "getopts", &["ab:", "opt", "-b"]);
+        assert_eq!(code, 0);
+        assert_eq!(state.env.get("opt").unwrap(), "?");
+        assert!(err.contains("option requires an argument -- b"));
+
+        // OPTERR=0 keeps the `?` result but suppresses the message.
+        state.env.insert("OPTIND".to_string(), "1".to_string());
+        state.env.insert("OPTERR".to_string(), "0".to_string());
+        let (_, _, err) = run_capture(&mut state, &host, "getopts", &["ab:", "opt", "-x"]);
+        assert_eq!(state.env.get("opt").unwrap(), "?");
+        assert!(err.is_empty());
+    }
+
+    #[test]
+    fn getopts_silent_mode() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+
+        let (code, _, err) = run_capture(&mut state, &host, "getopts", &[":ab:", "opt", "-x"]);
+        assert_eq!(code, 0);
+        assert_eq!(state.env.get("opt").unwrap(), "?");
+        assert_eq!(state.env.get("OPTARG").unwrap(), "x");
+        assert!(err.is_empty());
+
+        state.env.insert("OPTIND".to_string(), "1".to_string());
+        let (code, _, err) = run_capture(&mut state, &host, "getopts", &[":ab:", "opt", "-b"]);
+        assert_eq!(code, 0);
+        assert_eq!(state.env.get("opt").unwrap(), ":");
+        assert_eq!(state.env.get("OPTARG").unwrap(), "b");
+        assert!(err.is_empty());
+    }
+
     // -- mapfile tests ----------------------------------------------------
 
     #[test]
@@ -3307,8 +6708,8 @@ mod tests {
         );
         assert_eq!(code, 0);
         assert_eq!(
-            state.arrays.get("lines").unwrap(),
-            &vec![
+            state.arrays["lines"].to_vec(),
+            vec![
                 "line1".to_string(),
                 "line2".to_string(),
                 "line3".to_string()
@@ -3330,6 +6731,33 @@ mod tests {
         assert_eq!(state.arrays.get("lines").unwrap().len(), 2);
     }
 
+    #[test]
+    fn mapfile_max_lines_stops_reading_endless_input() {
+        let _lock = crate::test_support::mock::FD_MUTEX
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (r, w) = host.pipe().unwrap();
+        let writer = std::thread::spawn(move || {
+            while unsafe { libc::write(w, b"y\n".as_ptr() as *const libc::c_void, 2) } == 2 {}
+            unsafe { libc::close(w) };
+        });
+        let saved = host.dup(0).unwrap();
+        host.dup2(r, 0).unwrap();
+        host.close_fd(r).unwrap();
+
+        let args = make_args(&["-t", "-n", "1"]);
+        let result = try_builtin(&mut state, &host, "mapfile", &args, "", None);
+
+        // Dropping the last read end lets the writer see EPIPE and stop.
+        host.dup2(saved, 0).unwrap();
+        host.close_fd(saved).unwrap();
+        writer.join().unwrap();
+        assert!(matches!(result, Some(BuiltinResult::Result(0))));
+        assert_eq!(state.arrays["MAPFILE"].to_vec(), ["y"]);
+    }
+
     // -- which tests ------------------------------------------------------
 
     #[test]
@@ -3437,6 +6865,128 @@ mod tests {
         assert_eq!(code, 0);
     }
 
+    #[test]
+    fn printf_escapes_match_bash() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        // Format strings take \NNN octal and keep \c literal.
+        let (_, stdout, _) =
+            run_capture(&mut state, &host, "printf", &["\\101\\e\\u00e9\\c|\\\"\\n"]);
+        assert_eq!(stdout, "A\x1b\u{e9}\\c|\"\n");
+        // %b takes echo-style escapes, and \c there ends all output.
+        let (_, stdout, _) = run_capture(
+            &mut state,
+            &host,
+            "printf",
+            &["%b|%b|", "\\0101\\101\\t", "x\\cy", "z"],
+        );
+        assert_eq!(stdout, "AA\t|x");
+    }
+
+    #[test]
+    fn printf_time_conversion_and_flags() {
+        let mut state = ShellState::new_default();
+        // MockHost time() returns 1700000000.0 (2023-11-14T22:13:20Z).
+        let host = MockHost::new();
+        let mut printf = |args: &[&str]| run_capture(&mut state, &host, "printf", args).1;
+
+        assert_eq!(printf(&["%(%Y)T\n", "0"]), "1970\n");
+        assert_eq!(printf(&["[%(%F %T)T]", "-1"]), "[2023-11-14 22:13:20]");
+        assert_eq!(printf(&["%(%Y)T"]), "2023");
+        assert_eq!(printf(&["%b", "a\\nb"]), "a\nb");
+        assert_eq!(
+            printf(&["%'d|%'.2f|%'d", "1234567", "9876.5", "-1000"]),
+            "1,234,567|9,876.50|-1,000"
+        );
+        assert_eq!(
+            printf(&["[%5s|%-5s|%.2s]", "ab", "cd", "xyz"]),
+            "[   ab|cd   |xy]"
+        );
+        assert_eq!(
+            printf(&["[%05d|%+d|%#x]", "-42", "7", "255"]),
+            "[-0042|+7|0xff]"
+        );
+    }
+
+    #[test]
+    fn printf_reports_invalid_conversion() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (code, stdout, stderr) =
+            run_capture(&mut state, &host, "printf", &["%s %z %d|%-3y\n", "a", "7"]);
+        assert_eq!(code, 2);
+        assert_eq!(stdout, "a %z 7|%-3y\n");
+        assert_eq!(
+            stderr,
+            "printf: %z: invalid conversion specification\n\
+             printf: %-3y: invalid conversion specification\n"
+        );
+
+        // Valid conversions never take the error path or shift arguments.
+        let args = [
+            "%5.2f|%-4d|%04d|%x|%o|%e\n",
+            "3.14159",
+            "7",
+            "42",
+            "255",
+            "8",
+            "1234.5",
+        ];
+        let (code, stdout, stderr) = run_capture(&mut state, &host, "printf", &args);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, " 3.14|7   |0042|ff|10|1.234500e+03\n");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn printf_q_quotes_for_reuse_as_input() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let args = ["%q|", "x", "a b", "", "it's", "a\nb", "$HOME/*"];
+        let (code, stdout, _) = run_capture(&mut state, &host, "printf", &args);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "x|a\\ b|''|it\\'s|$'a\\nb'|\\$HOME/\\*|");
+    }
+
+    #[test]
+    fn printf_recycles_format_while_arguments_remain() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let mut printf = |args: &[&str]| run_capture(&mut state, &host, "printf", args).1;
+
+        assert_eq!(printf(&["%s\n", "a", "b", "c"]), "a\nb\nc\n");
+        assert_eq!(printf(&["%s-%s\n", "a"]), "a-\n");
+        assert_eq!(printf(&["%s=%d;", "a", "1", "b"]), "a=1;b=0;");
+        assert_eq!(printf(&["[%s|%d]"]), "[|0]");
+        // A format without conversions is printed once.
+        assert_eq!(printf(&["hi\n", "x", "y"]), "hi\n");
+    }
+
+    #[test]
+    fn printf_exponent_and_general_conversions() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let mut printf = |args: &[&str]| run_capture(&mut state, &host, "printf", args);
+
+        let (code, stdout, _) = printf(&["%e|%E|%.2e|%10.1e", "1234.5", "0.00012", "-98765", "5"]);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "1.234500e+03|1.200000E-04|-9.88e+04|   5.0e+00");
+
+        let (code, stdout, _) = printf(&["%g|%g|%G|%.3g", "1234.5", "1.234e-5", "1e20", "3.14"]);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "1234.5|1.234e-05|1E+20|3.14");
+        assert_eq!(printf(&["%#g|%g", "2", "0"]).1, "2.00000|0");
+
+        // `*` takes the width or precision from the next argument.
+        let (code, stdout, _) = printf(&["[%*d|%-*s]", "5", "42", "4", "ab"]);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "[   42|ab  ]");
+        assert_eq!(
+            printf(&["[%.*f|%*d]", "2", "3.14159", "-4", "7"]).1,
+            "[3.14|7   ]"
+        );
+    }
+
     // -- date tests -------------------------------------------------------
 
     #[test]
@@ -3454,9 +7004,130 @@ mod tests {
     #[test]
     fn chmod_basic() {
         let mut state = ShellState::new_default();
-        let host = MockHost::new();
+        let host = MockHost::new().with_file("/tmp/script.sh", b"");
         let code = run_builtin(&mut state, &host, "chmod", &["755", "/tmp/script.sh"]);
         assert_eq!(code, 0);
+        assert_eq!(host.stat("/tmp/script.sh").unwrap().mode, 0o755);
+    }
+
+    #[test]
+    fn chmod_symbolic_modes() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new().with_file("/home/user/f", b"");
+        let mut chmod = |spec: &str| {
+            let code = run_builtin(&mut state, &host, "chmod", &[spec, "f"]);
+            (code, host.stat("/home/user/f").unwrap().mode)
+        };
+        assert_eq!(chmod("+x"), (0, 0o755));
+        assert_eq!(chmod("go-r"), (0, 0o711));
+        assert_eq!(chmod("u-w,g+w"), (0, 0o531));
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "chmod", &["u+q", "f"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "chmod: invalid mode: 'u+q'\n");
+        let (code, _, stderr) = run_capture(&mut state, &host, "chmod", &["644", "nope"]);
+        assert_eq!(code, 1);
+        assert!(stderr.starts_with("chmod: cannot access 'nope'"));
+    }
+
+    // -- cp / mv tests ----------------------------------------------------
+
+    #[test]
+    fn cp_copies_mode_bits() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new()
+            .with_file("/home/user/run.sh", b"echo hi\n")
+            .with_mode("/home/user/run.sh", 0o755)
+            .with_file("/home/user/kept", b"old")
+            .with_mode("/home/user/kept", 0o600);
+        let code = run_builtin(&mut state, &host, "cp", &["run.sh", "copy.sh"]);
+        assert_eq!(code, 0);
+        assert_eq!(host.read_file("/home/user/copy.sh").unwrap(), b"echo hi\n");
+        assert_eq!(host.stat("/home/user/copy.sh").unwrap().mode, 0o755);
+
+        // An existing destination keeps its mode unless -p is given.
+        run_builtin(&mut state, &host, "cp", &["run.sh", "kept"]);
+        assert_eq!(host.stat("/home/user/kept").unwrap().mode, 0o600);
+        run_builtin(&mut state, &host, "cp", &["-p", "run.sh", "kept"]);
+        assert_eq!(host.stat("/home/user/kept").unwrap().mode, 0o755);
+    }
+
+    #[test]
+    fn cp_recursive_copies_tree() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new()
+            .with_dir("/src")
+            .with_dir("/src/bin")
+            .with_file("/src/bin/tool", b"#!/bin/sh\n")
+            .with_mode("/src/bin/tool", 0o700)
+            .with_file("/src/readme", b"hi")
+            .with_dir("/dst");
+        let (code, _, stderr) = run_capture(&mut state, &host, "cp", &["/src", "/dst"]);
+        assert_eq!(code, 1);
+        assert_eq!(
+            stderr,
+            "cp: omitting directory '/src' (use -r to copy recursively)\n"
+        );
+
+        let code = run_builtin(&mut state, &host, "cp", &["-r", "/src", "/dst"]);
+        assert_eq!(code, 0);
+        assert!(host.stat("/dst/src/bin").unwrap().is_dir);
+        assert_eq!(host.read_file("/dst/src/readme").unwrap(), b"hi");
+        assert_eq!(host.stat("/dst/src/bin/tool").unwrap().mode, 0o700);
+    }
+
+    #[test]
+    fn cp_refuses_to_copy_a_directory_into_itself() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new().with_dir("/t/a").with_file("/t/a/f", b"x");
+        for dest in ["/t/a/sub", "/t/a"] {
+            let (code, _, stderr) = run_capture(&mut state, &host, "cp", &["-r", "/t/a", dest]);
+            assert_eq!(code, 1);
+            assert!(
+                stderr.starts_with("cp: cannot copy a directory, '/t/a', into itself"),
+                "{stderr}"
+            );
+        }
+        assert!(!host.stat("/t/a/sub").unwrap().exists);
+    }
+
+    #[test]
+    fn cp_reports_missing_sources_and_continues() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new()
+            .with_file("/home/user/one", b"1")
+            .with_dir("/home/user/out");
+        let (code, _, stderr) = run_capture(&mut state, &host, "cp", &["one", "nope", "out"]);
+        assert_eq!(code, 1);
+        assert_eq!(
+            stderr,
+            "cp: cannot stat 'nope': No such file or directory\n"
+        );
+        assert_eq!(host.read_file("/home/user/out/one").unwrap(), b"1");
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "cp", &["one", "nope", "x"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "cp: target 'x' is not a directory\n");
+    }
+
+    #[test]
+    fn mv_keeps_mode_bits() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new()
+            .with_file("/home/user/run.sh", b"x")
+            .with_mode("/home/user/run.sh", 0o755)
+            .with_dir("/home/user/bin");
+        let code = run_builtin(&mut state, &host, "mv", &["run.sh", "bin"]);
+        assert_eq!(code, 0);
+        assert!(!host.stat("/home/user/run.sh").unwrap().exists);
+        assert_eq!(host.stat("/home/user/bin/run.sh").unwrap().mode, 0o755);
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "mv", &["run.sh", "bin"]);
+        assert_eq!(code, 1);
+        assert_eq!(
+            stderr,
+            "mv: cannot stat 'run.sh': No such file or directory\n"
+        );
     }
 
     // -- exec tests -------------------------------------------------------
@@ -3520,6 +7191,29 @@ mod tests {
         assert_eq!(state.env.get("X").unwrap(), "42");
     }
 
+    #[test]
+    fn readonly_rejects_unset_and_redeclare() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        run_builtin(&mut state, &host, "declare", &["-r", "X=42"]);
+        assert!(state.readonly_vars.contains("X"));
+
+        let (code, _, err) = run_capture(&mut state, &host, "unset", &["X"]);
+        assert_eq!(code, 1);
+        assert_eq!(err, "unset: X: cannot unset: readonly variable\n");
+
+        let (code, _, err) = run_capture(&mut state, &host, "declare", &["X=1"]);
+        assert_eq!(code, 1);
+        assert_eq!(err, "declare: X: readonly variable\n");
+
+        let (code, _, _) = run_capture(&mut state, &host, "export", &["X=2"]);
+        assert_eq!(code, 1);
+        assert_eq!(state.env.get("X").unwrap(), "42");
+
+        let (_, out, _) = run_capture(&mut state, &host, "declare", &["-p", "X"]);
+        assert_eq!(out, "declare -r X=\"42\"\n");
+    }
+
     // -- alias tests ------------------------------------------------------
 
     #[test]
@@ -3603,4 +7297,88 @@ mod tests {
         let code = run_builtin(&mut state, &host, "unalias", &["nope"]);
         assert_eq!(code, 1);
     }
+
+    #[test]
+    fn wait_reports_unknown_jobs_with_127() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+
+        // Empty job table: a bare `wait` returns at once with 0.
+        assert_eq!(run_builtin(&mut state, &host, "wait", &[]), 0);
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "wait", &["%3"]);
+        assert_eq!((code, stderr.as_str()), (127, "wait: %3: no such job\n"));
+
+        // `$!` with no background job expands to 0, which is nobody's pid.
+        let (code, _, stderr) = run_capture(&mut state, &host, "wait", &["0"]);
+        assert_eq!(code, 127);
+        assert_eq!(stderr, "wait: pid 0 is not a child of this shell\n");
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "wait", &["x"]);
+        assert_eq!(code, 2);
+        assert_eq!(stderr, "wait: `x': not a pid or valid job spec\n");
+
+        state.jobs.push(crate::state::Job {
+            id: 1,
+            pid: 0,
+            command: "false".to_string(),
+            done: Some(1),
+        });
+        assert_eq!(run_builtin(&mut state, &host, "wait", &["%1"]), 1);
+        assert!(state.jobs.is_empty());
+    }
+
+    #[test]
+    fn read_u_reads_successive_lines_from_fd() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (r, w) = host.pipe().unwrap();
+        host.write_fd(w, b"first line\nsecond\n").unwrap();
+        host.close_fd(w).unwrap();
+        state.pipeline_stdin = Some("from stdin\n".to_string());
+        let fd = r.to_string();
+
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-u", &fd, "a"]), 0);
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-u", &fd, "b"]), 0);
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-u", &fd, "c"]), 1);
+        host.close_fd(r).unwrap();
+        assert_eq!(state.env["a"], "first line");
+        assert_eq!(state.env["b"], "second");
+        // Stdin's own buffered input is left untouched.
+        assert_eq!(state.pipeline_stdin.as_deref(), Some("from stdin\n"));
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "read", &["-u", "x"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "read: x: invalid file descriptor specification\n");
+
+        let (code, _, stderr) = run_capture(&mut state, &host, "read", &["-u", "1000", "a"]);
+        assert_eq!(code, 1);
+        assert_eq!(stderr, "read: 1000: invalid file descriptor\n");
+    }
+
+    #[test]
+    fn read_u_returns_a_line_without_waiting_for_eof() {
+        let mut state = ShellState::new_default();
+        let host = MockHost::new();
+        let (r, w) = host.pipe().unwrap();
+        let fd = r.to_string();
+
+        // The writer stays open, so draining the fd would block forever.
+        host.write_fd(w, b"one\ntwo\n").unwrap();
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-u", &fd, "a"]), 0);
+        assert_eq!(state.env["a"], "one");
+        assert_eq!(state.fd_buffers[&r], "two\n");
+
+        host.write_fd(w, b"x,y\nz\n").unwrap();
+        host.close_fd(w).unwrap();
+        let read = ["-u", &fd, "-d", ",", "b"];
+        assert_eq!(run_builtin(&mut state, &host, "read", &read), 0);
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-u", &fd, "c"]), 0);
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-u", &fd, "d"]), 0);
+        assert_eq!(run_builtin(&mut state, &host, "read", &["-u", &fd, "e"]), 1);
+        host.close_fd(r).unwrap();
+        assert_eq!(state.env["b"], "two\nx");
+        assert_eq!(state.env["c"], "y");
+        assert_eq!(state.env["d"], "z");
+    }
 }
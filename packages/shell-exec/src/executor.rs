@@ -1,10 +1,11 @@
-use codepod_shell::ast::{Command, ListOp, Word, WordPart};
+use codepod_shell::ast::{CaseTerminator, Command, ListOp, Word, WordPart};
 use codepod_shell::lexer::parse_string_expansion;
 use codepod_shell::token::RedirectType;
 
 use crate::control::{ControlFlow, RunResult, ShellError};
 use crate::expand::{
-    expand_braces, expand_globs, expand_word, expand_words_with_splitting, glob_matches,
+    expand_braces, expand_globs, expand_pattern, expand_word, expand_words_with_splitting,
+    extglob_matches, glob_matches, protect_special_chars, restore_all_sentinels,
     restore_brace_sentinels, restore_glob_sentinels, ExecFn,
 };
 use crate::host::{HostInterface, WriteMode};
@@ -54,6 +55,53 @@ fn expand_alias(state: &ShellState, words: Vec<String>) -> Vec<String> {
     result
 }
 
+/// The `PATH` searched by `command -p`.
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Strip a leading `command [-p] [--]` or `builtin` so the named command
+/// runs as a builtin or external program even when a function of that name
+/// exists.
+///
+/// Returns the remaining words and whether function lookup must be skipped.
+/// `command -v`/`-V`, a bare `command`/`builtin`, and `builtin` with a name
+/// that is not a builtin are left for the builtins to handle. With `-p` the
+/// name is resolved against [`DEFAULT_PATH`] instead of `$PATH`.
+fn strip_dispatch_prefix(host: &dyn HostInterface, words: Vec<String>) -> (Vec<String>, bool) {
+    if words[0] == "builtin" {
+        return match words.get(1) {
+            Some(name) if crate::builtins::is_builtin(name) => (words[1..].to_vec(), true),
+            _ => (words, false),
+        };
+    }
+    if words[0] != "command" {
+        return (words, false);
+    }
+    let mut default_path = false;
+    let mut i = 1;
+    while let Some(arg) = words.get(i) {
+        match arg.as_str() {
+            "-p" => default_path = true,
+            "--" => {
+                i += 1;
+                break;
+            }
+            a if a.starts_with('-') => return (words, false),
+            _ => break,
+        }
+        i += 1;
+    }
+    if i >= words.len() {
+        return (words, false);
+    }
+    let mut rest = words[i..].to_vec();
+    if default_path && !rest[0].contains('/') && !crate::builtins::is_builtin(&rest[0]) {
+        if let Some((path, _)) = find_in_dirs(DEFAULT_PATH, host, &rest[0]) {
+            rest[0] = path;
+        }
+    }
+    (rest, true)
+}
+
 // ---------------------------------------------------------------------------
 // Heredoc / herestring expansion helper
 // ---------------------------------------------------------------------------
@@ -91,6 +139,12 @@ const CREATION_COMMANDS: &[&str] = &["mkdir", "touch", "cp", "mv", "tee"];
 /// Python interpreter names (for shebang dispatch).
 const PYTHON_INTERPRETERS: &[&str] = &["python", "python3"];
 
+/// Permission flag marking a VFS file as a wasm tool stub.
+const S_TOOL: u32 = 0o100000;
+
+/// Any of the owner/group/other execute bits.
+const X_BITS: u32 = 0o111;
+
 /// Returns true if the command defaults to "." and no non-flag args were given.
 fn needs_default_dir(cmd: &str, args: &[&str]) -> bool {
     IMPLICIT_CWD_COMMANDS.contains(&cmd) && args.iter().all(|a| a.starts_with('-'))
@@ -112,6 +166,8 @@ fn resolve_arg_if_path(
     if PASSTHROUGH_ARGS.contains(&cmd_name) {
         return arg.to_string();
     }
+    // Options — including `--key=value` forms and `-abc` clusters — and
+    // absolute paths are never rewritten.
     if arg.starts_with('-') || arg.starts_with('/') {
         return arg.to_string();
     }
@@ -171,25 +227,25 @@ fn looks_like_filename(arg: &str) -> bool {
 }
 
 /// Resolve command args. For PATTERN_COMMANDS, skip resolving the first
-/// positional (non-flag) arg (the regex/pattern).
+/// positional (non-flag) arg (the regex/pattern). Nothing after a `--`
+/// end-of-options marker is resolved: those args are deliberate literals.
 fn resolve_command_args(
     state: &ShellState,
     host: &dyn HostInterface,
     cmd_name: &str,
     args: &[&str],
 ) -> Vec<String> {
-    if !PATTERN_COMMANDS.contains(&cmd_name) {
-        return args
-            .iter()
-            .map(|a| resolve_arg_if_path(state, host, cmd_name, a))
-            .collect();
-    }
+    let end_of_opts = args.iter().position(|a| *a == "--").unwrap_or(args.len());
     // Find the first positional arg (not a flag) — that's the pattern.
-    let pat_idx = args.iter().position(|a| !a.starts_with('-'));
+    let pat_idx = if PATTERN_COMMANDS.contains(&cmd_name) {
+        args[..end_of_opts].iter().position(|a| !a.starts_with('-'))
+    } else {
+        None
+    };
     args.iter()
         .enumerate()
         .map(|(i, a)| {
-            if Some(i) == pat_idx {
+            if i >= end_of_opts || Some(i) == pat_idx {
                 a.to_string()
             } else {
                 resolve_arg_if_path(state, host, cmd_name, a)
@@ -291,7 +347,6 @@ fn exec_path(
     // `argv[0]` is always the basename of the path the user typed, so multicall
     // dispatch (BusyBox) sees the applet name even though the tool registry
     // lookup targets the multicall binary.
-    const S_TOOL: u32 = 0o100000;
     if let Ok(stat) = host.stat(&resolved) {
         if stat.mode & S_TOOL != 0 {
             // Derive the registered tool name from the path.  Tool stubs live in
@@ -310,11 +365,7 @@ fn exec_path(
             // argv[0] is the basename of the path the user invoked (not of the
             // symlink target), so multicall dispatch can see the applet name.
             let argv0 = cmd_path.rsplit('/').next().unwrap_or(cmd_path);
-            let env_pairs: Vec<(&str, &str)> = state
-                .env
-                .iter()
-                .map(|(k, v)| (k.as_str(), v.as_str()))
-                .collect();
+            let env_pairs = state.exported_env();
             let pid = host
                 .spawn(
                     &tool_name_owned,
@@ -351,11 +402,7 @@ fn exec_path(
             // Python script: spawn python with the resolved script path + args
             let mut python_args: Vec<&str> = vec![resolved.as_str()];
             python_args.extend(args);
-            let env_pairs: Vec<(&str, &str)> = state
-                .env
-                .iter()
-                .map(|(k, v)| (k.as_str(), v.as_str()))
-                .collect();
+            let env_pairs = state.exported_env();
             let pid = host
                 .spawn(
                     "python3",
@@ -400,18 +447,10 @@ fn exec_shell_script(
         script_text
     };
 
-    // Set positional parameters
-    let saved_positionals = state.positional_args.clone();
-    state.positional_args = args.iter().map(|s| s.to_string()).collect();
-
-    // Run the entire script as a single parsed command
-    let parsed = codepod_shell::parser::parse(script);
-    let result = exec_command(state, host, &parsed);
-
-    // Restore positional parameters
-    state.positional_args = saved_positionals;
-
-    result
+    // Run the entire script as a single parsed command with its own
+    // positional parameters
+    let args = args.iter().map(|s| s.to_string()).collect();
+    state.with_positional_args(args, |state| exec_str(state, host, script))
 }
 
 /// Handle `sh`/`bash` command dispatch.
@@ -424,11 +463,31 @@ fn exec_shell_command(
     args: &[&str],
     stdin_data: &str,
 ) -> Result<ControlFlow, ShellError> {
-    // sh -c 'command string'
+    // sh -c 'command string' — runs as a child shell, so `set` flags and
+    // traps it changes must not leak back into the caller.
     if args.len() >= 2 && args[0] == "-c" {
         let cmd_str = args[1];
-        let parsed = codepod_shell::parser::parse(cmd_str);
-        return exec_command(state, host, &parsed);
+        let saved_flags = state.flags.clone();
+        let saved_traps = std::mem::take(&mut state.traps);
+
+        let result = exec_str(state, host, cmd_str);
+        let result = match result {
+            Ok(ControlFlow::Exit(code)) | Ok(ControlFlow::Return(code)) => {
+                Ok(ControlFlow::Normal(RunResult::exit(code)))
+            }
+            other => other,
+        };
+        let result = result.map(|flow| match flow {
+            ControlFlow::Normal(mut r) => {
+                r.exit_code = run_exit_trap(state, host, r.exit_code);
+                ControlFlow::Normal(r)
+            }
+            other => other,
+        });
+
+        state.flags = saved_flags;
+        state.traps = saved_traps;
+        return result;
     }
     // sh script.sh — read and execute as shell script
     if !args.is_empty() && !args[0].starts_with('-') {
@@ -500,7 +559,10 @@ fn dispatch_external_command(
     // 6. POSIX PATH lookup for bare commands.  Walk `$PATH` and stat each
     //    `<dir>/<cmd_name>`; the first match that is a regular file with the
     //    executable bit set (S_TOOL for wasm tool stubs, or 0o111 for shell
-    //    scripts / host-mounted binaries) is the resolved command.
+    //    scripts / host-mounted binaries) is the resolved command, and is
+    //    remembered in the `hash` table.  Later runs stat only the hashed
+    //    path instead of walking `$PATH` again; a stale entry is dropped
+    //    and the walk is redone.
     //
     //    For S_TOOL hits, resolve the tool-registry key + argv[0] inline and
     //    return via Ok so the caller's existing redirect-aware / pipeline-
@@ -516,40 +578,25 @@ fn dispatch_external_command(
     //    the command name is dispatched verbatim to host.spawn, where the
     //    tool registry produces the familiar "command not found" for unknown
     //    names.
-    if let Some(path_env) = state.env.get("PATH").cloned() {
-        const S_TOOL: u32 = 0o100000;
-        const X_BITS: u32 = 0o111;
-        for dir in path_env.split(':') {
-            if dir.is_empty() {
-                continue;
-            }
-            let candidate = if dir.ends_with('/') {
-                format!("{dir}{cmd_name}")
-            } else {
-                format!("{dir}/{cmd_name}")
-            };
-            let st = match host.stat(&candidate) {
-                Ok(s) => s,
-                Err(_) => continue,
-            };
-            if !st.exists || !st.is_file {
-                continue;
-            }
-            let is_tool = st.mode & S_TOOL != 0;
-            let is_exec = st.mode & X_BITS != 0;
-            if !is_tool && !is_exec {
-                continue;
-            }
-
-            if is_tool {
-                // Resolve the tool-registry key.  Stubs directly in /usr/bin
-                // or /bin are registered under their basename.  Symlinks
-                // elsewhere follow one level so multicall applets (e.g.
-                // /tmp/bb-bin/grep -> /usr/bin/busybox) keep the applet
-                // name as argv[0] while dispatching the multicall binary.
-                let tool_name: String = if candidate.starts_with("/usr/bin/")
-                    || candidate.starts_with("/bin/")
-                {
+    let hashed = state.command_hash.get(cmd_name).and_then(|path| {
+        let st = host.stat(path).ok().filter(is_executable_file)?;
+        Some((path.clone(), st))
+    });
+    if hashed.is_none() {
+        state.command_hash.remove(cmd_name);
+    }
+    if let Some((candidate, st)) = hashed.or_else(|| find_in_path(state, host, cmd_name)) {
+        state
+            .command_hash
+            .insert(cmd_name.to_string(), candidate.clone());
+        if st.mode & S_TOOL != 0 {
+            // Resolve the tool-registry key.  Stubs directly in /usr/bin
+            // or /bin are registered under their basename.  Symlinks
+            // elsewhere follow one level so multicall applets (e.g.
+            // /tmp/bb-bin/grep -> /usr/bin/busybox) keep the applet
+            // name as argv[0] while dispatching the multicall binary.
+            let tool_name: String =
+                if candidate.starts_with("/usr/bin/") || candidate.starts_with("/bin/") {
                     cmd_name.to_string()
                 } else if let Ok(target) = host.readlink(&candidate) {
                     target
@@ -560,31 +607,72 @@ fn dispatch_external_command(
                 } else {
                     cmd_name.to_string()
                 };
-                let argv0 = if tool_name == cmd_name {
-                    None
-                } else {
-                    Some(cmd_name.to_string())
-                };
-                return Ok((tool_name, argv0, resolved_args));
-            }
-
-            // Non-S_TOOL executable: a host-mounted binary or a shell
-            // script.  Delegate to exec_path so shebang / shell-script
-            // semantics apply.
-            let resolved_refs: Vec<&str> = resolved_args.iter().map(|s| s.as_str()).collect();
-            return match exec_path(state, host, &candidate, &resolved_refs, stdin_data) {
-                Ok(flow) => Err(flow),
-                Err(e) => {
-                    crate::shell_eprintln!("{}", e);
-                    Err(ControlFlow::Normal(RunResult::exit(127)))
-                }
+            let argv0 = if tool_name == cmd_name {
+                None
+            } else {
+                Some(cmd_name.to_string())
             };
+            return Ok((tool_name, argv0, resolved_args));
         }
+
+        // Non-S_TOOL executable: a host-mounted binary or a shell
+        // script.  Delegate to exec_path so shebang / shell-script
+        // semantics apply.
+        let resolved_refs: Vec<&str> = resolved_args.iter().map(|s| s.as_str()).collect();
+        return match exec_path(state, host, &candidate, &resolved_refs, stdin_data) {
+            Ok(flow) => Err(flow),
+            Err(e) => {
+                crate::shell_eprintln!("{}", e);
+                Err(ControlFlow::Normal(RunResult::exit(127)))
+            }
+        };
     }
 
     Ok((cmd_name.to_string(), None, resolved_args))
 }
 
+/// Walk `$PATH` for `cmd_name` and return the first candidate that is a
+/// regular file with the S_TOOL flag or an executable bit set.
+pub(crate) fn find_in_path(
+    state: &ShellState,
+    host: &dyn HostInterface,
+    cmd_name: &str,
+) -> Option<(String, crate::host::StatInfo)> {
+    find_in_dirs(state.env.get("PATH")?, host, cmd_name)
+}
+
+/// Search the colon-separated directory list `path_env` for an executable
+/// `cmd_name`.
+fn find_in_dirs(
+    path_env: &str,
+    host: &dyn HostInterface,
+    cmd_name: &str,
+) -> Option<(String, crate::host::StatInfo)> {
+    for dir in path_env.split(':') {
+        if dir.is_empty() {
+            continue;
+        }
+        let candidate = if dir.ends_with('/') {
+            format!("{dir}{cmd_name}")
+        } else {
+            format!("{dir}/{cmd_name}")
+        };
+        let st = match host.stat(&candidate) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        if is_executable_file(&st) {
+            return Some((candidate, st));
+        }
+    }
+    None
+}
+
+/// A regular file with the S_TOOL flag or an executable bit set.
+fn is_executable_file(st: &crate::host::StatInfo) -> bool {
+    st.exists && st.is_file && st.mode & (S_TOOL | X_BITS) != 0
+}
+
 /// Apply output redirects (stdout/stderr overwrite, append, merge, etc.)
 /// to the given stdout/stderr buffers. This is called after a command
 /// finishes execution to process any `>`, `>>`, `2>`, `2>>`, `2>&1`, `&>`
@@ -593,8 +681,8 @@ fn apply_output_redirects(
     state: &ShellState,
     host: &dyn HostInterface,
     redirects: &[codepod_shell::ast::Redirect],
-    stdout: &mut String,
-    stderr: &mut String,
+    stdout: &mut Vec<u8>,
+    stderr: &mut Vec<u8>,
 ) -> Result<(), ShellError> {
     let mut last_stdout_redirect_path: Option<String> = None;
     for redir in redirects {
@@ -605,62 +693,61 @@ fn apply_output_redirects(
                     match path.as_str() {
                         "&2" => {
                             // >&2: merge stdout into stderr
-                            stderr.push_str(stdout);
-                            *stdout = String::new();
+                            stderr.append(stdout);
                         }
                         "&1" => {
                             // >&1: no-op
                         }
                         _ => {
                             // >&N for unsupported N — discard output (like /dev/null)
-                            *stdout = String::new();
+                            stdout.clear();
                         }
                     }
                 } else {
                     let resolved = state.resolve_path(path);
-                    host.write_file(&resolved, stdout.as_bytes(), WriteMode::Truncate)
+                    host.write_file(&resolved, stdout, WriteMode::Truncate)
                         .map_err(|e| ShellError::HostError(e.to_string()))?;
-                    *stdout = String::new();
+                    stdout.clear();
                     last_stdout_redirect_path = Some(resolved);
                 }
             }
             RedirectType::StdoutAppend(path) => {
                 let resolved = state.resolve_path(path);
-                host.write_file(&resolved, stdout.as_bytes(), WriteMode::Append)
+                host.write_file(&resolved, stdout, WriteMode::Append)
                     .map_err(|e| ShellError::HostError(e.to_string()))?;
-                *stdout = String::new();
+                stdout.clear();
                 last_stdout_redirect_path = Some(resolved);
             }
             RedirectType::StderrOverwrite(path) => {
                 let resolved = state.resolve_path(path);
-                host.write_file(&resolved, stderr.as_bytes(), WriteMode::Truncate)
+                host.write_file(&resolved, stderr, WriteMode::Truncate)
                     .map_err(|e| ShellError::HostError(e.to_string()))?;
-                *stderr = String::new();
+                stderr.clear();
             }
             RedirectType::StderrAppend(path) => {
                 let resolved = state.resolve_path(path);
-                host.write_file(&resolved, stderr.as_bytes(), WriteMode::Append)
+                host.write_file(&resolved, stderr, WriteMode::Append)
                     .map_err(|e| ShellError::HostError(e.to_string()))?;
-                *stderr = String::new();
+                stderr.clear();
             }
             RedirectType::StderrToStdout => {
                 if let Some(ref file_path) = last_stdout_redirect_path {
                     if !stderr.is_empty() {
-                        host.write_file(file_path, stderr.as_bytes(), WriteMode::Append)
+                        host.write_file(file_path, stderr, WriteMode::Append)
                             .map_err(|e| ShellError::HostError(e.to_string()))?;
                     }
                 } else {
-                    stdout.push_str(stderr);
+                    stdout.append(stderr);
                 }
-                *stderr = String::new();
+                stderr.clear();
             }
             RedirectType::BothOverwrite(path) => {
                 let resolved = state.resolve_path(path);
-                let combined = format!("{stdout}{stderr}");
-                host.write_file(&resolved, combined.as_bytes(), WriteMode::Truncate)
+                let combined = [stdout.as_slice(), stderr.as_slice()].concat();
+                host.write_file(&resolved, &combined, WriteMode::Truncate)
                     .map_err(|e| ShellError::HostError(e.to_string()))?;
-                *stdout = String::new();
-                *stderr = String::new();
+                stdout.clear();
+                stderr.clear();
             }
             // Input redirects are handled separately; skip them here.
             _ => {}
@@ -745,51 +832,258 @@ fn run_deferred_output_subs(
     for (path, cmd_str) in deferred {
         if let Ok(content) = host.read_file_str(path) {
             state.pipeline_stdin = Some(content);
-            let inner_cmd = codepod_shell::parser::parse(cmd_str);
-            let _ = exec_command(state, host, &inner_cmd);
+            let _ = exec_str(state, host, cmd_str);
             state.pipeline_stdin = None;
         }
         let _ = host.remove(path, false);
     }
 }
 
+/// Collect the stdin data supplied by input redirects (`< file`, heredocs,
+/// herestrings). The last input redirect wins, as in bash.
+fn collect_stdin_redirects(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    redirects: &[codepod_shell::ast::Redirect],
+    exec_fn: ExecFn,
+) -> Result<String, ShellError> {
+    let mut stdin_data = String::new();
+    for redir in redirects {
+        match &redir.redirect_type {
+            RedirectType::StdinFrom(path) => {
+                let resolved = state.resolve_path(path);
+                stdin_data = host
+                    .read_file_str(&resolved)
+                    .map_err(|e| ShellError::HostError(e.to_string()))?;
+            }
+            RedirectType::Heredoc(content) => {
+                stdin_data = expand_raw_string(state, content, Some(exec_fn));
+            }
+            RedirectType::HeredocQuoted(content) => {
+                stdin_data = content.clone();
+            }
+            RedirectType::HeredocStrip(content) => {
+                let expanded = expand_raw_string(state, content, Some(exec_fn));
+                stdin_data = expanded
+                    .lines()
+                    .map(|l| l.trim_start_matches('\t'))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if expanded.ends_with('\n') {
+                    stdin_data.push('\n');
+                }
+            }
+            RedirectType::HeredocStripQuoted(content) => {
+                stdin_data = content
+                    .lines()
+                    .map(|l| l.trim_start_matches('\t'))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if content.ends_with('\n') {
+                    stdin_data.push('\n');
+                }
+            }
+            RedirectType::HereString(word) => {
+                stdin_data = format!("{}\n", expand_raw_string(state, word, Some(exec_fn)));
+            }
+            _ => {}
+        }
+    }
+    Ok(stdin_data)
+}
+
+/// Open the numbered-fd input redirects of a bare `exec` (`exec 3< file`)
+/// for the rest of the shell. The file is read up front into the fd's
+/// buffer, which `read -u` consumes line by line.
+fn open_fd_redirects(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    redirects: &[codepod_shell::ast::Redirect],
+) -> Result<(), ShellError> {
+    for redir in redirects {
+        if let RedirectType::FdInput(fd, path) = &redir.redirect_type {
+            let resolved = state.resolve_path(path);
+            let data = host
+                .read_file_str(&resolved)
+                .map_err(|e| ShellError::HostError(e.to_string()))?;
+            state.fd_buffers.insert(*fd, data);
+            state.fd_files.insert(*fd);
+        }
+    }
+    Ok(())
+}
+
+/// Fd state installed by `apply_compound_redirects` for the duration of a
+/// compound command's body.
+struct CompoundRedirects {
+    /// Original targets of fds we dup2'd over: (fd, saved copy).
+    saved_fds: Vec<(i32, Option<i32>)>,
+    /// `state.stdout_fd` before any stdout sink was installed.
+    saved_stdout_fd: i32,
+    /// Pipe collecting the body's stdout for `>`, `>>`, `&>`.
+    stdout_sink: Option<(i32, i32)>,
+    /// Pipe collecting the body's stderr for `2>`, `2>>`.
+    stderr_sink: Option<(i32, i32)>,
+    /// Extra fds (e.g. from `>&2`) to close once the body finishes.
+    extra_fds: Vec<i32>,
+}
+
 /// Apply redirects on compound commands (Subshell, BraceGroup).
-/// Returns saved fd state for restoration.
+///
+/// File redirects are honored by sinking the body's stdout/stderr into pipes;
+/// `restore_compound_redirects` then writes the aggregate output to the
+/// target files. Input redirects are fed through a pipe dup2'd onto fd 0.
 fn apply_compound_redirects(
     state: &mut ShellState,
     host: &dyn HostInterface,
     redirects: &[codepod_shell::ast::Redirect],
-) -> Vec<(i32, Option<i32>)> {
-    use codepod_shell::token::RedirectType;
-    let mut saved = Vec::new();
+    stdin_data: &str,
+) -> CompoundRedirects {
+    let mut applied = CompoundRedirects {
+        saved_fds: Vec::new(),
+        saved_stdout_fd: state.stdout_fd,
+        stdout_sink: None,
+        stderr_sink: None,
+        extra_fds: Vec::new(),
+    };
+
+    if !stdin_data.is_empty() {
+        if let Ok((r, w)) = host.pipe() {
+            let _ = host.write_fd(w, stdin_data.as_bytes());
+            let _ = host.close_fd(w);
+            applied.saved_fds.push((0, host.dup(0).ok()));
+            let _ = host.dup2(r, 0);
+            let _ = host.close_fd(r);
+        }
+    }
+
     for redir in redirects {
         match &redir.redirect_type {
+            RedirectType::StdoutOverwrite(path) if path == "&2" => {
+                // >&2: point the body's stdout at the current stderr target
+                if let Ok(fd) = host.dup(2) {
+                    state.stdout_fd = fd;
+                    applied.extra_fds.push(fd);
+                }
+            }
+            RedirectType::StdoutOverwrite(path) if path.starts_with('&') => {}
+            RedirectType::StdoutOverwrite(_) | RedirectType::StdoutAppend(_)
+                if applied.stdout_sink.is_none() =>
+            {
+                if let Ok((r, w)) = host.pipe() {
+                    state.stdout_fd = w;
+                    applied.stdout_sink = Some((r, w));
+                }
+            }
+            RedirectType::StderrOverwrite(_) | RedirectType::StderrAppend(_)
+                if applied.stderr_sink.is_none() =>
+            {
+                if let Ok((r, w)) = host.pipe() {
+                    applied.saved_fds.push((2, host.dup(2).ok()));
+                    let _ = host.dup2(w, 2);
+                    applied.stderr_sink = Some((r, w));
+                }
+            }
             RedirectType::StderrToStdout => {
                 // 2>&1: dup stderr to wherever stdout currently points
-                let saved_fd2 = host.dup(2).ok();
-                saved.push((2, saved_fd2));
+                applied.saved_fds.push((2, host.dup(2).ok()));
                 let _ = host.dup2(state.stdout_fd, 2);
             }
             RedirectType::BothOverwrite(_) => {
-                // &>file: redirect both stdout and stderr — dup stderr to stdout
-                let saved_fd2 = host.dup(2).ok();
-                saved.push((2, saved_fd2));
+                // &>file: sink stdout, then send stderr to the same place
+                if applied.stdout_sink.is_none() {
+                    if let Ok((r, w)) = host.pipe() {
+                        state.stdout_fd = w;
+                        applied.stdout_sink = Some((r, w));
+                    }
+                }
+                applied.saved_fds.push((2, host.dup(2).ok()));
                 let _ = host.dup2(state.stdout_fd, 2);
             }
-            _ => {} // Other redirect types not yet handled on compound commands
+            _ => {} // Input redirects were folded into stdin_data
         }
     }
-    saved
+    applied
 }
 
-/// Restore fds saved by apply_compound_redirects.
-fn restore_compound_redirects(host: &dyn HostInterface, saved: &[(i32, Option<i32>)]) {
-    for (fd, saved_fd) in saved.iter().rev() {
+/// Restore fds saved by apply_compound_redirects and deliver any captured
+/// output to its redirect targets.
+fn restore_compound_redirects(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    redirects: &[codepod_shell::ast::Redirect],
+    applied: CompoundRedirects,
+) -> Result<(), ShellError> {
+    for (fd, saved_fd) in applied.saved_fds.iter().rev() {
         if let Some(orig) = saved_fd {
             let _ = host.dup2(*orig, *fd);
             let _ = host.close_fd(*orig);
         }
     }
+    state.stdout_fd = applied.saved_stdout_fd;
+    for fd in applied.extra_fds {
+        let _ = host.close_fd(fd);
+    }
+
+    let drain = |sink: Option<(i32, i32)>| -> Option<Vec<u8>> {
+        let (r, w) = sink?;
+        let _ = host.close_fd(w);
+        let data = host.read_fd(r).unwrap_or_default();
+        let _ = host.close_fd(r);
+        Some(data)
+    };
+    let captured_stdout = drain(applied.stdout_sink);
+    let captured_stderr = drain(applied.stderr_sink);
+    if captured_stdout.is_none() && captured_stderr.is_none() {
+        return Ok(());
+    }
+
+    let mut stdout = captured_stdout.unwrap_or_default();
+    let mut stderr = captured_stderr.unwrap_or_default();
+    apply_output_redirects(state, host, redirects, &mut stdout, &mut stderr)?;
+    if !stdout.is_empty() {
+        let _ = host.write_fd(state.stdout_fd, &stdout);
+    }
+    if !stderr.is_empty() {
+        let _ = host.write_fd(2, &stderr);
+    }
+    Ok(())
+}
+
+/// Run a compound command body with its trailing redirects in effect.
+fn exec_with_compound_redirects(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    redirects: &[codepod_shell::ast::Redirect],
+    exec_fn: ExecFn,
+    body: impl FnOnce(&mut ShellState) -> Result<ControlFlow, ShellError>,
+) -> Result<ControlFlow, ShellError> {
+    if redirects.is_empty() {
+        return body(state);
+    }
+    let stdin_data = collect_stdin_redirects(state, host, redirects, exec_fn)?;
+    let applied = apply_compound_redirects(state, host, redirects, &stdin_data);
+    let result = body(state);
+    restore_compound_redirects(state, host, redirects, applied)?;
+    result
+}
+
+/// Run the `DEBUG` trap, if one is set, before a simple command executes.
+/// `$BASH_COMMAND` holds the command's source text and `$?` is preserved
+/// across the trap. Commands inside the trap body do not re-fire it.
+fn run_debug_trap(state: &mut ShellState, host: &dyn HostInterface, cmd: &Command) {
+    if state.in_debug_trap {
+        return;
+    }
+    let Some(action) = state.traps.get("DEBUG").cloned() else {
+        return;
+    };
+    state.bash_command = codepod_shell::printer::format_command(cmd);
+    let saved_exit = state.last_exit_code;
+    state.in_debug_trap = true;
+    let _ = exec_str(state, host, &action);
+    state.in_debug_trap = false;
+    state.last_exit_code = saved_exit;
 }
 
 /// Execute a parsed `Command` AST node.
@@ -816,10 +1110,138 @@ fn format_command(cmd: &Command) -> String {
     }
 }
 
+/// Report a `${var:?msg}` error raised by the preceding expansion, if any.
+///
+/// Returns the failing result the command ends with instead of running.
+fn take_param_error(state: &mut ShellState) -> Option<RunResult> {
+    let msg = state.param_error.take()?;
+    crate::shell_eprintln!("{}", msg);
+    state.last_exit_code = 1;
+    Some(RunResult::exit(1))
+}
+
+/// Invoke the shell function named by `words[0]`, if one is defined, with
+/// the remaining words as its positional parameters.
+///
+/// Returns `None` when no such function exists. A `return` inside the body
+/// ends the call and becomes its exit status.
+fn call_function(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    words: &[String],
+) -> Option<Result<ControlFlow, ShellError>> {
+    let func_body = state.functions.get(&words[0]).cloned()?;
+    if state.function_depth >= crate::state::MAX_FUNCTION_DEPTH {
+        crate::shell_eprintln!("{}: maximum function nesting depth exceeded", words[0]);
+        return Some(Ok(ControlFlow::Normal(RunResult::exit(1))));
+    }
+    let result = state.with_function_frame(words[1..].to_vec(), |state| {
+        exec_command(state, host, &func_body)
+    });
+
+    Some(result.map(|flow| match flow {
+        ControlFlow::Return(code) => {
+            state.last_exit_code = code;
+            ControlFlow::Normal(RunResult::exit(code))
+        }
+        other => other,
+    }))
+}
+
+/// Run the `EXIT` trap, if one is set, for a shell finishing with status
+/// `code`, and return the shell's final status.
+///
+/// The trap sees `code` as `$?`. Its own status is discarded unless it calls
+/// `exit`, whose code replaces `code`. The result is masked to 0–255.
+pub fn run_exit_trap(state: &mut ShellState, host: &dyn HostInterface, code: i32) -> i32 {
+    let mut code = code;
+    if let Some(trap_cmd) = state.traps.remove("EXIT") {
+        state.last_exit_code = code;
+        if let Ok(ControlFlow::Exit(trap_code)) = exec_str(state, host, &trap_cmd) {
+            code = trap_code;
+        }
+    }
+    code & 0xff
+}
+
+/// Parse and run a command string, as for scripts, `sh -c`, `eval` and
+/// command substitution.
+///
+/// Malformed input is not run: the syntax error is reported on stderr and
+/// the result is exit status 2, as in bash.
+pub fn exec_str(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    input: &str,
+) -> Result<ControlFlow, ShellError> {
+    let options = codepod_shell::parser::ParseOptions {
+        posix: state.flags.contains(&crate::state::ShellFlag::Posix),
+    };
+    match codepod_shell::parser::try_parse_with(input, options) {
+        Ok(cmd) => exec_command(state, host, &cmd),
+        Err(e) => {
+            crate::shell_eprintln!("bash: {}", e);
+            state.last_exit_code = 2;
+            Ok(ControlFlow::Normal(RunResult::exit(2)))
+        }
+    }
+}
+
+/// Execute a parsed `Command` AST node, then run the `ERR` trap if it was
+/// a simple command or pipeline that failed outside a condition.
 pub fn exec_command(
     state: &mut ShellState,
     host: &dyn HostInterface,
     cmd: &Command,
+) -> Result<ControlFlow, ShellError> {
+    let result = exec_node(state, host, cmd);
+    if let Ok(ControlFlow::Normal(run)) = &result {
+        let traps_failure = match cmd {
+            Command::Simple { words, .. } => !words.is_empty(),
+            Command::Pipeline { commands } => commands.len() > 1,
+            _ => false,
+        };
+        if traps_failure && run.exit_code != 0 {
+            run_err_trap(state, host, run.exit_code);
+        }
+    }
+    result
+}
+
+/// Run the `ERR` trap, if one is set, for a command that exited with
+/// `code`. The trap sees `$?` as that code. Functions do not inherit the
+/// trap (no `set -E`), and failures inside the trap body do not re-fire it.
+fn run_err_trap(state: &mut ShellState, host: &dyn HostInterface, code: i32) {
+    if state.condition_depth > 0 || state.function_depth > 0 {
+        return;
+    }
+    let Some(action) = state.traps.get("ERR").cloned() else {
+        return;
+    };
+    state.last_exit_code = code;
+    state.condition_depth += 1;
+    let _ = exec_str(state, host, &action);
+    state.condition_depth -= 1;
+    state.last_exit_code = code;
+}
+
+/// Run `cmd` in a condition context, where a non-zero status is a
+/// result to test rather than an error for the ERR trap.
+fn exec_condition(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    cmd: &Command,
+) -> Result<ControlFlow, ShellError> {
+    state.condition_depth += 1;
+    let result = exec_command(state, host, cmd);
+    state.condition_depth -= 1;
+    result
+}
+
+fn exec_node(
+    state: &mut ShellState,
+    host: &dyn HostInterface,
+    cmd: &Command,
 ) -> Result<ControlFlow, ShellError> {
     // Create executor callback for command substitution.
     // When word expansion encounters `$(...)`, it calls this closure to
@@ -833,8 +1255,9 @@ pub fn exec_command(
         };
         let saved_stdout_fd = state.stdout_fd;
         state.stdout_fd = write_fd;
-        let inner_cmd = codepod_shell::parser::parse(cmd_str);
-        match exec_command(state, host, &inner_cmd) {
+        let saved_bashpid = state.bashpid;
+        state.bashpid = crate::state::alloc_shell_pid();
+        match exec_str(state, host, cmd_str) {
             Ok(ControlFlow::Normal(r)) => {
                 state.last_exit_code = r.exit_code;
             }
@@ -844,6 +1267,7 @@ pub fn exec_command(
             _ => {}
         }
         state.stdout_fd = saved_stdout_fd;
+        state.bashpid = saved_bashpid;
         let _ = host.close_fd(write_fd);
         let captured = host.read_fd(read_fd).unwrap_or_default();
         let _ = host.close_fd(read_fd);
@@ -863,6 +1287,9 @@ pub fn exec_command(
                 // Assignment-only command; nothing to spawn.
                 // In bash, $? reflects the exit code of the last command
                 // substitution that ran during the assignment (e.g. x=$(false) → $?=1).
+                if let Some(r) = take_param_error(state) {
+                    return Ok(ControlFlow::Normal(r));
+                }
                 if let Some(err) = assign_err {
                     state.last_exit_code = 1;
                     crate::shell_eprint!("{}", err);
@@ -871,6 +1298,13 @@ pub fn exec_command(
                 let code = state.last_exit_code;
                 return Ok(ControlFlow::Normal(RunResult::exit(code)));
             }
+            // A failed prefix assignment (e.g. to a readonly variable)
+            // stops the command from running.
+            if let Some(err) = assign_err {
+                state.last_exit_code = 1;
+                crate::shell_eprint!("{}", err);
+                return Ok(ControlFlow::Normal(RunResult::exit(1)));
+            }
 
             // Resolve process substitutions <(cmd) and >(cmd) before word expansion.
             // Each ProcessSub part is replaced with a Literal containing a
@@ -882,10 +1316,8 @@ pub fn exec_command(
                 expand_words_with_splitting(state, &proc_sub_result.words, Some(&exec_fn));
 
             // Check for ${var:?msg} error during expansion
-            if let Some(err_msg) = state.param_error.take() {
-                state.last_exit_code = 1;
-                crate::shell_eprintln!("{}", err_msg);
-                return Ok(ControlFlow::Normal(RunResult::exit(1)));
+            if let Some(r) = take_param_error(state) {
+                return Ok(ControlFlow::Normal(r));
             }
 
             if expanded.is_empty() {
@@ -895,104 +1327,40 @@ pub fn exec_command(
             // Brace expansion → sentinel restoration → glob expansion → glob sentinel restoration
             let braced = expand_braces(&expanded);
             let restored = restore_brace_sentinels(&braced);
-            let globbed = expand_globs(host, &restored, &state.cwd);
+            let globbed = expand_globs(host, &restored, state);
             let globbed = restore_glob_sentinels(&globbed);
 
             if globbed.is_empty() {
                 return Ok(ControlFlow::Normal(RunResult::empty()));
             }
             let globbed = expand_alias(state, globbed);
+            let (globbed, skip_functions) = strip_dispatch_prefix(host, globbed);
+            run_debug_trap(state, host, cmd);
+            state.last_arg = globbed[globbed.len() - 1].clone();
             let cmd_name = &globbed[0];
             let args: Vec<&str> = globbed[1..].iter().map(|s| s.as_str()).collect();
 
             // ── Check for function invocation ────────────────────────────
-            if let Some(func_body) = state.functions.get(cmd_name).cloned() {
-                if state.function_depth >= crate::state::MAX_FUNCTION_DEPTH {
-                    crate::shell_eprintln!("{cmd_name}: maximum function nesting depth exceeded");
-                    return Ok(ControlFlow::Normal(RunResult::exit(1)));
-                }
-                let func_args: Vec<String> = globbed[1..].iter().map(|s| s.to_string()).collect();
-                let saved_positionals = state.positional_args.clone();
-                state.positional_args = func_args;
-                state.function_depth += 1;
-                let local_frame = std::collections::HashMap::new();
-                state.local_var_stack.push(local_frame);
-
-                let result = exec_command(state, host, &func_body);
-
-                // Restore local variables from the popped frame
-                if let Some(frame) = state.local_var_stack.pop() {
-                    for (name, prev_value) in frame {
-                        match prev_value {
-                            Some(v) => {
-                                state.env.insert(name, v);
-                            }
-                            None => {
-                                state.env.remove(&name);
-                            }
-                        }
-                    }
-                }
-                state.function_depth -= 1;
-                state.positional_args = saved_positionals;
-
+            let function_result = if skip_functions {
+                None
+            } else {
+                call_function(state, host, &globbed)
+            };
+            if let Some(result) = function_result {
                 run_deferred_output_subs(state, host, &proc_sub_result.deferred_output_subs);
-                return match result? {
-                    ControlFlow::Return(code) => {
-                        state.last_exit_code = code;
-                        Ok(ControlFlow::Normal(RunResult::exit(code)))
-                    }
-                    other => Ok(other),
-                };
+                return result;
+            }
+
+            // `exec N< file` with no command opens the fds for the shell.
+            if cmd_name == "exec" && args.is_empty() {
+                open_fd_redirects(state, host, redirects)?;
             }
 
             // ── Phase 1: Extract stdin from input redirects ──────────────
             // Collect stdin data from redirects, then write to a pipe and
             // dup2 onto fd 0 so all consumers (builtins, spawned commands)
             // read from standard input.
-            let mut stdin_data = String::new();
-            for redir in redirects {
-                match &redir.redirect_type {
-                    RedirectType::StdinFrom(path) => {
-                        let resolved = state.resolve_path(path);
-                        stdin_data = host
-                            .read_file_str(&resolved)
-                            .map_err(|e| ShellError::HostError(e.to_string()))?;
-                    }
-                    RedirectType::Heredoc(content) => {
-                        stdin_data = expand_raw_string(state, content, Some(&exec_fn));
-                    }
-                    RedirectType::HeredocQuoted(content) => {
-                        stdin_data = content.clone();
-                    }
-                    RedirectType::HeredocStrip(content) => {
-                        let expanded = expand_raw_string(state, content, Some(&exec_fn));
-                        stdin_data = expanded
-                            .lines()
-                            .map(|l| l.trim_start_matches('\t'))
-                            .collect::<Vec<_>>()
-                            .join("\n");
-                        if expanded.ends_with('\n') {
-                            stdin_data.push('\n');
-                        }
-                    }
-                    RedirectType::HeredocStripQuoted(content) => {
-                        stdin_data = content
-                            .lines()
-                            .map(|l| l.trim_start_matches('\t'))
-                            .collect::<Vec<_>>()
-                            .join("\n");
-                        if content.ends_with('\n') {
-                            stdin_data.push('\n');
-                        }
-                    }
-                    RedirectType::HereString(word) => {
-                        stdin_data =
-                            format!("{}\n", expand_raw_string(state, word, Some(&exec_fn)));
-                    }
-                    _ => {}
-                }
-            }
+            let stdin_data = collect_stdin_redirects(state, host, redirects, &exec_fn)?;
 
             // If we have stdin data from redirects, write it to a pipe and
             // dup2 onto fd 0 so builtins can read from standard input.
@@ -1015,10 +1383,11 @@ pub fn exec_command(
             // ── Check for builtin commands ────────────────────────────
             let func_args: Vec<String> = globbed[1..].iter().map(|s| s.to_string()).collect();
             let run_fn = |state: &mut ShellState, cmd_str: &str| -> RunResult {
-                let inner_cmd = codepod_shell::parser::parse(cmd_str);
-                match exec_command(state, host, &inner_cmd) {
+                match exec_str(state, host, cmd_str) {
                     Ok(ControlFlow::Normal(r)) => r,
-                    Ok(ControlFlow::Exit(code)) => RunResult::exit(code),
+                    Ok(ControlFlow::Exit(code)) | Ok(ControlFlow::Return(code)) => {
+                        RunResult::exit(code)
+                    }
                     _ => RunResult::empty(),
                 }
             };
@@ -1050,7 +1419,7 @@ pub fn exec_command(
                 )
             });
             let saved_redir_stdout = state.stdout_fd;
-            let redir_sink = if has_stdout_redir && state.stdout_fd == 1 {
+            let redir_sink = if has_stdout_redir {
                 if let Ok((r, w)) = host.pipe() {
                     state.stdout_fd = w;
                     Some((r, w))
@@ -1089,9 +1458,9 @@ pub fn exec_command(
                     let _ = host.close_fd(w);
                     let data = host.read_fd(r).unwrap_or_default();
                     let _ = host.close_fd(r);
-                    String::from_utf8_lossy(&data).to_string()
+                    data
                 } else {
-                    String::new()
+                    Vec::new()
                 };
 
                 let exit_code = match builtin_result {
@@ -1108,12 +1477,12 @@ pub fn exec_command(
                 };
 
                 let mut stdout = captured_stdout;
-                let mut stderr = String::new();
+                let mut stderr = Vec::new();
                 apply_output_redirects(state, host, redirects, &mut stdout, &mut stderr)?;
 
                 // Write remaining stderr to fd 2 (handles >&2 redirect)
                 if !stderr.is_empty() {
-                    let _ = host.write_fd(2, stderr.as_bytes());
+                    let _ = host.write_fd(2, &stderr);
                 }
 
                 // Restore fd 0 if we redirected stdin.
@@ -1150,8 +1519,8 @@ pub fn exec_command(
                 &stdin_data,
             ) {
                 state.last_exit_code = result.exit_code;
-                let mut stdout = String::new();
-                let mut stderr = String::new();
+                let mut stdout = Vec::new();
+                let mut stderr = Vec::new();
                 apply_output_redirects(state, host, redirects, &mut stdout, &mut stderr)?;
                 run_deferred_output_subs(state, host, &proc_sub_result.deferred_output_subs);
                 return Ok(ControlFlow::Normal(RunResult::exit(result.exit_code)));
@@ -1172,8 +1541,8 @@ pub fn exec_command(
                         state.last_exit_code = run.exit_code;
 
                         // Process output redirects for dispatched commands too
-                        let mut stdout = String::new();
-                        let mut stderr = String::new();
+                        let mut stdout = Vec::new();
+                        let mut stderr = Vec::new();
                         apply_output_redirects(state, host, redirects, &mut stdout, &mut stderr)?;
 
                         run_deferred_output_subs(
@@ -1185,13 +1554,6 @@ pub fn exec_command(
                     }
                 };
 
-            // Convert env HashMap to the slice format expected by spawn.
-            let env_pairs: Vec<(&str, &str)> = state
-                .env
-                .iter()
-                .map(|(k, v)| (k.as_str(), v.as_str()))
-                .collect();
-
             let spawn_args_refs: Vec<&str> = spawn_args.iter().map(|s| s.as_str()).collect();
             // Use pipeline stdin if no explicit stdin redirect
             let effective_stdin = if stdin_data.is_empty() {
@@ -1199,6 +1561,8 @@ pub fn exec_command(
             } else {
                 stdin_data.clone()
             };
+            // Only exported variables are passed to the child.
+            let env_pairs = state.exported_env();
             // If there are stderr redirects that require capture, pipe-sink stderr.
             // Note: StderrToStdout is handled by routing stderr_fd directly to
             // spawn_stdout_fd (computed below), so it doesn't need a pipe.
@@ -1282,18 +1646,18 @@ pub fn exec_command(
                 let _ = host.close_fd(w);
                 let data = host.read_fd(r).unwrap_or_default();
                 let _ = host.close_fd(r);
-                String::from_utf8_lossy(&data).to_string()
+                data
             } else {
-                String::new()
+                Vec::new()
             };
             // Capture stderr from pipe sink if used.
             let mut stderr = if let Some((r, w)) = stderr_sink {
                 let _ = host.close_fd(w);
                 let data = host.read_fd(r).unwrap_or_default();
                 let _ = host.close_fd(r);
-                String::from_utf8_lossy(&data).to_string()
+                data
             } else {
-                String::new()
+                Vec::new()
             };
             apply_output_redirects(state, host, redirects, &mut stdout, &mut stderr)?;
 
@@ -1372,6 +1736,14 @@ pub fn exec_command(
 
                             let expanded =
                                 expand_words_with_splitting(state, words, Some(&exec_fn));
+                            if let Some(r) = take_param_error(state) {
+                                last_result = r;
+                                stdin_data = String::new();
+                                if pipefail && last_result.exit_code != 0 {
+                                    pipefail_code = last_result.exit_code;
+                                }
+                                continue;
+                            }
                             if expanded.is_empty() {
                                 last_result = RunResult::empty();
                                 stdin_data = String::new();
@@ -1383,7 +1755,7 @@ pub fn exec_command(
 
                             let braced = expand_braces(&expanded);
                             let restored = restore_brace_sentinels(&braced);
-                            let globbed = expand_globs(host, &restored, &state.cwd);
+                            let globbed = expand_globs(host, &restored, state);
                             let globbed = restore_glob_sentinels(&globbed);
 
                             if globbed.is_empty() {
@@ -1396,6 +1768,7 @@ pub fn exec_command(
                             }
 
                             let globbed = expand_alias(state, globbed);
+                            let (globbed, skip_functions) = strip_dispatch_prefix(host, globbed);
                             let cmd_name = &globbed[0];
                             let args: Vec<&str> = globbed[1..].iter().map(|s| s.as_str()).collect();
 
@@ -1448,30 +1821,60 @@ pub fn exec_command(
                                 }
                             }
 
-                            // Check for builtin in pipeline
-                            let pipe_func_args: Vec<String> =
-                                globbed[1..].iter().map(|s| s.to_string()).collect();
-                            let pipe_run_fn =
-                                |state: &mut ShellState, cmd_str: &str| -> RunResult {
-                                    let inner_cmd = codepod_shell::parser::parse(cmd_str);
-                                    match exec_command(state, host, &inner_cmd) {
-                                        Ok(ControlFlow::Normal(r)) => r,
-                                        Ok(ControlFlow::Exit(code)) => RunResult::exit(code),
-                                        _ => RunResult::empty(),
-                                    }
+                            // Shell function: the stage's stdin reaches the
+                            // body's `read`s through pipeline_stdin.
+                            if !skip_functions && state.functions.contains_key(cmd_name) {
+                                let prev_stdin =
+                                    state.pipeline_stdin.replace(effective_stdin.clone());
+                                let result = call_function(state, host, &globbed);
+                                state.pipeline_stdin = prev_stdin;
+                                let code = match result {
+                                    Some(Ok(ControlFlow::Normal(r))) => r.exit_code,
+                                    Some(Ok(ControlFlow::Exit(code))) => code,
+                                    Some(Err(e)) => return Err(e),
+                                    _ => state.last_exit_code,
                                 };
-                            if let Some(builtin_result) = crate::builtins::try_builtin(
-                                state,
-                                host,
-                                cmd_name,
+                                let mut fstdout = Vec::new();
+                                let mut fstderr = Vec::new();
+                                apply_output_redirects(
+                                    state,
+                                    host,
+                                    redirects,
+                                    &mut fstdout,
+                                    &mut fstderr,
+                                )?;
+                                state.last_exit_code = code;
+                                last_result = RunResult::exit(code);
+                                if pipefail && last_result.exit_code != 0 {
+                                    pipefail_code = last_result.exit_code;
+                                }
+                                stdin_data = String::new();
+                                continue;
+                            }
+
+                            // Check for builtin in pipeline
+                            let pipe_func_args: Vec<String> =
+                                globbed[1..].iter().map(|s| s.to_string()).collect();
+                            let pipe_run_fn =
+                                |state: &mut ShellState, cmd_str: &str| -> RunResult {
+                                    match exec_str(state, host, cmd_str) {
+                                        Ok(ControlFlow::Normal(r)) => r,
+                                        Ok(ControlFlow::Exit(code)) => RunResult::exit(code),
+                                        _ => RunResult::empty(),
+                                    }
+                                };
+                            if let Some(builtin_result) = crate::builtins::try_builtin(
+                                state,
+                                host,
+                                cmd_name,
                                 &pipe_func_args,
                                 &effective_stdin,
                                 Some(&pipe_run_fn),
                             ) {
                                 match builtin_result {
                                     crate::builtins::BuiltinResult::Result(code) => {
-                                        let mut bstdout = String::new();
-                                        let mut bstderr = String::new();
+                                        let mut bstdout = Vec::new();
+                                        let mut bstderr = Vec::new();
 
                                         // Handle output redirects
                                         apply_output_redirects(
@@ -1509,8 +1912,8 @@ pub fn exec_command(
                                 &effective_stdin,
                             ) {
                                 state.last_exit_code = result.exit_code;
-                                let mut bstdout = String::new();
-                                let mut bstderr = String::new();
+                                let mut bstdout = Vec::new();
+                                let mut bstderr = Vec::new();
                                 apply_output_redirects(
                                     state,
                                     host,
@@ -1556,11 +1959,7 @@ pub fn exec_command(
                                     continue;
                                 }
                                 Ok((prog, argv0, resolved_args)) => {
-                                    let env_pairs: Vec<(&str, &str)> = state
-                                        .env
-                                        .iter()
-                                        .map(|(k, v)| (k.as_str(), v.as_str()))
-                                        .collect();
+                                    let env_pairs = state.exported_env();
 
                                     let spawn_args_refs: Vec<&str> =
                                         resolved_args.iter().map(|s| s.as_str()).collect();
@@ -1581,8 +1980,8 @@ pub fn exec_command(
                                         .and_then(|pid| host.waitpid(pid))
                                     {
                                         Ok(spawn_result) => {
-                                            let mut stdout = String::new();
-                                            let mut stderr = String::new();
+                                            let mut stdout = Vec::new();
+                                            let mut stderr = Vec::new();
 
                                             // Handle output redirects in pipeline stages
                                             apply_output_redirects(
@@ -1791,13 +2190,16 @@ pub fn exec_command(
                         } else {
                             expand_words_with_splitting(state, words, Some(&exec_fn))
                         };
-                        if expanded_words.is_empty() {
+                        if let Some(r) = take_param_error(state) {
+                            last_result = r;
+                            last_stage_was_spawned = false;
+                        } else if expanded_words.is_empty() {
                             last_result = RunResult::empty();
                             last_stage_was_spawned = false;
                         } else {
                             let braced = expand_braces(&expanded_words);
                             let restored = restore_brace_sentinels(&braced);
-                            let globbed = expand_globs(host, &restored, &state.cwd);
+                            let globbed = expand_globs(host, &restored, state);
                             let globbed = restore_glob_sentinels(&globbed);
 
                             if globbed.is_empty() {
@@ -1805,24 +2207,58 @@ pub fn exec_command(
                                 last_stage_was_spawned = false;
                             } else {
                                 let globbed = expand_alias(state, globbed);
+                                let (globbed, skip_functions) =
+                                    strip_dispatch_prefix(host, globbed);
                                 let cmd_name = &globbed[0];
                                 let pipe_func_args: Vec<String> =
                                     globbed[1..].iter().map(|s| s.to_string()).collect();
 
-                                // ── Builtin check ──
-                                // Builtins run inline. Task 6 ensures they also
-                                // write to stdout_fd via write_to_fd, so the pipe
-                                // gets data even in streaming mode.
                                 let pipe_run_fn =
                                     |state: &mut ShellState, cmd_str: &str| -> RunResult {
-                                        let inner_cmd = codepod_shell::parser::parse(cmd_str);
-                                        match exec_command(state, host, &inner_cmd) {
+                                        match exec_str(state, host, cmd_str) {
                                             Ok(ControlFlow::Normal(r)) => r,
                                             Ok(ControlFlow::Exit(code)) => RunResult::exit(code),
                                             _ => RunResult::empty(),
                                         }
                                     };
-                                if let Some(builtin_result) = crate::builtins::try_builtin(
+
+                                // ── Shell function ──
+                                // Runs inline with fd 0/1 already wired to
+                                // this stage's pipes. Like any pipeline stage
+                                // it is a subshell, so `exit` only ends the
+                                // stage.
+                                let function_result = if skip_functions {
+                                    None
+                                } else {
+                                    call_function(state, host, &globbed)
+                                };
+                                if let Some(result) = function_result {
+                                    let code = match result? {
+                                        ControlFlow::Normal(r) => r.exit_code,
+                                        ControlFlow::Exit(code) => code,
+                                        _ => state.last_exit_code,
+                                    };
+                                    let mut fstdout = Vec::new();
+                                    let mut fstderr = Vec::new();
+                                    apply_output_redirects(
+                                        state,
+                                        host,
+                                        redirects,
+                                        &mut fstdout,
+                                        &mut fstderr,
+                                    )?;
+                                    state.last_exit_code = code;
+                                    last_result = RunResult::exit(code);
+                                    if pipefail && last_result.exit_code != 0 {
+                                        pipefail_code = last_result.exit_code;
+                                    }
+                                    last_stage_was_spawned = false;
+                                }
+                                // ── Builtin check ──
+                                // Builtins run inline. Task 6 ensures they also
+                                // write to stdout_fd via write_to_fd, so the pipe
+                                // gets data even in streaming mode.
+                                else if let Some(builtin_result) = crate::builtins::try_builtin(
                                     state,
                                     host,
                                     cmd_name,
@@ -1832,8 +2268,8 @@ pub fn exec_command(
                                 ) {
                                     match builtin_result {
                                         crate::builtins::BuiltinResult::Result(code) => {
-                                            let mut bstdout = String::new();
-                                            let mut bstderr = String::new();
+                                            let mut bstdout = Vec::new();
+                                            let mut bstderr = Vec::new();
                                             apply_output_redirects(
                                                 state,
                                                 host,
@@ -1881,8 +2317,8 @@ pub fn exec_command(
                                     )
                                 {
                                     state.last_exit_code = result.exit_code;
-                                    let mut bstdout = String::new();
-                                    let mut bstderr = String::new();
+                                    let mut bstdout = Vec::new();
+                                    let mut bstderr = Vec::new();
                                     apply_output_redirects(
                                         state,
                                         host,
@@ -1939,11 +2375,7 @@ pub fn exec_command(
                                             last_stage_was_spawned = false;
                                         }
                                         Ok((prog, argv0, resolved_args)) => {
-                                            let env_pairs: Vec<(&str, &str)> = state
-                                                .env
-                                                .iter()
-                                                .map(|(k, v)| (k.as_str(), v.as_str()))
-                                                .collect();
+                                            let env_pairs = state.exported_env();
                                             let spawn_args_refs: Vec<&str> =
                                                 resolved_args.iter().map(|s| s.as_str()).collect();
 
@@ -2122,11 +2554,11 @@ pub fn exec_command(
                 let _ = host.close_fd(w);
                 let data = host.read_fd(r).unwrap_or_default();
                 let _ = host.close_fd(r);
-                let mut sink_stdout = String::from_utf8_lossy(&data).to_string();
-                let mut sink_stderr = String::new();
+                let mut sink_stdout = data;
+                let mut sink_stderr = Vec::new();
                 apply_output_redirects(state, host, &sink_redirects, &mut sink_stdout, &mut sink_stderr)?;
                 if !sink_stderr.is_empty() {
-                    let _ = host.write_fd(2, sink_stderr.as_bytes());
+                    let _ = host.write_fd(2, &sink_stderr);
                 }
             }
 
@@ -2159,98 +2591,88 @@ pub fn exec_command(
         }
 
         // ── List: ;, &&, || ────────────────────────────────────────────
-        Command::List { left, op, right } => {
-            // For && and ||, suppress errexit during evaluation (bash spec)
-            let suppress_errexit = matches!(op, ListOp::And | ListOp::Or);
-            let had_errexit = suppress_errexit
-                && state.flags.contains(&crate::state::ShellFlag::Errexit);
-            if suppress_errexit && had_errexit {
-                state.flags.remove(&crate::state::ShellFlag::Errexit);
-            }
-
-            let left_result = exec_command(state, host, left)?;
-            let left_run = match left_result {
-                ControlFlow::Normal(r) => r,
-                other => {
-                    if had_errexit {
-                        state.flags.insert(crate::state::ShellFlag::Errexit);
-                    }
-                    return Ok(other);
-                }
-            };
-            state.last_exit_code = left_run.exit_code;
-
-            // Restore errexit before executing the right side
-            if had_errexit {
-                state.flags.insert(crate::state::ShellFlag::Errexit);
-            }
+        // `a; b; c` and `a & b & c` nest to the right, so walk the chain in
+        // a loop instead of recursing once per command: long scripts would
+        // otherwise grow the stack with every `;`.
+        Command::List { .. } => {
+            let mut current = cmd;
+            loop {
+                let Command::List { left, op, right } = current else {
+                    return exec_command(state, host, current);
+                };
+                // The left side of && and || is a condition: errexit is
+                // suppressed while it runs (bash spec), though `$-` still
+                // shows it.
+                let fg_exit_code = state.last_exit_code;
+                let left_result = if matches!(op, ListOp::And | ListOp::Or) {
+                    exec_condition(state, host, left)?
+                } else {
+                    exec_command(state, host, left)?
+                };
+                let left_run = match left_result {
+                    ControlFlow::Normal(r) => r,
+                    other => return Ok(other),
+                };
+                state.last_exit_code = left_run.exit_code;
 
-            match op {
-                ListOp::And => {
-                    if left_run.exit_code == 0 {
-                        let right_result = exec_command(state, host, right)?;
-                        match right_result {
-                            ControlFlow::Normal(r) => Ok(ControlFlow::Normal(r)),
-                            other => Ok(other),
+                match op {
+                    ListOp::And => {
+                        if left_run.exit_code == 0 {
+                            return exec_command(state, host, right);
                         }
-                    } else {
-                        Ok(ControlFlow::Normal(left_run))
+                        return Ok(ControlFlow::Normal(left_run));
                     }
-                }
-                ListOp::Or => {
-                    if left_run.exit_code != 0 {
-                        let right_result = exec_command(state, host, right)?;
-                        match right_result {
-                            ControlFlow::Normal(r) => Ok(ControlFlow::Normal(r)),
-                            other => Ok(other),
+                    ListOp::Or => {
+                        if left_run.exit_code != 0 {
+                            return exec_command(state, host, right);
                         }
-                    } else {
-                        Ok(ControlFlow::Normal(left_run))
-                    }
-                }
-                ListOp::Seq => {
-                    // set -e (errexit): if the left side of a sequence failed,
-                    // stop executing and return the failing exit code.
-                    // Exception: suppress if left side was &&/|| (bash spec).
-                    let left_is_andor = matches!(
-                        left.as_ref(),
-                        Command::List { op: ListOp::And | ListOp::Or, .. }
-                    );
-                    if state.flags.contains(&crate::state::ShellFlag::Errexit)
-                        && left_run.exit_code != 0
-                        && !left_is_andor
-                    {
                         return Ok(ControlFlow::Normal(left_run));
                     }
-                    exec_command(state, host, right)
-                }
-                ListOp::Background => {
-                    // Record background job
-                    let job_id = state.next_job_id;
-                    state.next_job_id += 1;
-                    state.jobs.push(crate::state::Job {
-                        id: job_id,
-                        pid: 0,
-                        command: format_command(left),
-                        done: Some(left_run.exit_code),
-                    });
-                    state.last_bg_pid = 0;
-                    state.last_exit_code = 0; // & always returns 0
-
-                    // If right side is empty (trailing &), return
-                    if let Command::Simple { words, .. } = right.as_ref() {
-                        if words.is_empty() {
-                            return Ok(ControlFlow::Normal(RunResult::exit(0)));
+                    ListOp::Seq => {
+                        // set -e (errexit): if the left side of a sequence
+                        // failed, stop executing and return the failing exit
+                        // code. Exception: suppress if left side was &&/||
+                        // (bash spec).
+                        let left_is_andor = matches!(
+                            left.as_ref(),
+                            Command::List {
+                                op: ListOp::And | ListOp::Or,
+                                ..
+                            }
+                        );
+                        if state.flags.contains(&crate::state::ShellFlag::Errexit)
+                            && state.condition_depth == 0
+                            && left_run.exit_code != 0
+                            && !left_is_andor
+                        {
+                            return Ok(ControlFlow::Normal(left_run));
                         }
                     }
-
-                    // Execute the right side
-                    let right_result = exec_command(state, host, right)?;
-                    match right_result {
-                        ControlFlow::Normal(r) => Ok(ControlFlow::Normal(r)),
-                        other => Ok(other),
+                    ListOp::Background => {
+                        // Record background job
+                        let job_id = state.next_job_id;
+                        state.next_job_id += 1;
+                        state.jobs.push(crate::state::Job {
+                            id: job_id,
+                            pid: 0,
+                            command: format_command(left),
+                            done: Some(left_run.exit_code),
+                        });
+                        state.last_bg_pid = 0;
+                        // The job's status is only reported by an explicit
+                        // `wait`; the foreground `$?` keeps the previous
+                        // command's status.
+                        state.last_exit_code = fg_exit_code;
+
+                        // If right side is empty (trailing &), return
+                        if let Command::Simple { words, .. } = right.as_ref() {
+                            if words.is_empty() {
+                                return Ok(ControlFlow::Normal(RunResult::exit(fg_exit_code)));
+                            }
+                        }
                     }
                 }
+                current = right;
             }
         }
 
@@ -2259,8 +2681,9 @@ pub fn exec_command(
             condition,
             then_body,
             else_body,
-        } => {
-            let cond_result = exec_command(state, host, condition)?;
+            redirects,
+        } => exec_with_compound_redirects(state, host, redirects, &exec_fn, |state| {
+            let cond_result = exec_condition(state, host, condition)?;
             let cond_run = match cond_result {
                 ControlFlow::Normal(r) => r,
                 other => return Ok(other),
@@ -2270,22 +2693,33 @@ pub fn exec_command(
             } else if let Some(else_cmd) = else_body {
                 exec_command(state, host, else_cmd)
             } else {
+                // No branch ran: the status is 0, not the condition's.
+                state.last_exit_code = 0;
                 Ok(ControlFlow::Normal(RunResult::empty()))
             }
-        }
+        }),
 
         // ── For loop ─────────────────────────────────────────────────────
-        Command::For { var, words, body } => {
+        Command::For {
+            var,
+            words,
+            body,
+            redirects,
+        } => exec_with_compound_redirects(state, host, redirects, &exec_fn, |state| {
             let expanded = expand_words_with_splitting(state, words, Some(&exec_fn));
+            if let Some(r) = take_param_error(state) {
+                return Ok(ControlFlow::Normal(r));
+            }
             let braced = expand_braces(&expanded);
             let restored = restore_brace_sentinels(&braced);
-            let final_words = expand_globs(host, &restored, &state.cwd);
+            let final_words = expand_globs(host, &restored, state);
             let final_words = restore_glob_sentinels(&final_words);
 
             let mut last_exit_code = 0;
 
             for word in &final_words {
                 state.env.insert(var.clone(), word.clone());
+                state.var_changed(var);
                 match exec_command(state, host, body)? {
                     ControlFlow::Normal(r) => {
                         last_exit_code = r.exit_code;
@@ -2297,15 +2731,19 @@ pub fn exec_command(
             }
             state.last_exit_code = last_exit_code;
             Ok(ControlFlow::Normal(RunResult::exit(last_exit_code)))
-        }
+        }),
 
         // ── While loop ──────────────────────────────────────────────────
-        Command::While { condition, body } => {
+        Command::While {
+            condition,
+            body,
+            redirects,
+        } => exec_with_compound_redirects(state, host, redirects, &exec_fn, |state| {
             let mut last_exit_code = 0;
             let max_iterations = 100_000;
 
             for _ in 0..max_iterations {
-                let cond_result = exec_command(state, host, condition)?;
+                let cond_result = exec_condition(state, host, condition)?;
                 let cond_run = match cond_result {
                     ControlFlow::Normal(r) => r,
                     other => return Ok(other),
@@ -2325,7 +2763,7 @@ pub fn exec_command(
             }
             state.last_exit_code = last_exit_code;
             Ok(ControlFlow::Normal(RunResult::exit(last_exit_code)))
-        }
+        }),
 
         // ── C-style for loop ────────────────────────────────────────────
         Command::CFor {
@@ -2333,7 +2771,8 @@ pub fn exec_command(
             cond,
             step,
             body,
-        } => {
+            redirects,
+        } => exec_with_compound_redirects(state, host, redirects, &exec_fn, |state| {
             use crate::arithmetic::eval_arithmetic;
             if !init.is_empty() {
                 eval_arithmetic(state, init);
@@ -2369,21 +2808,55 @@ pub fn exec_command(
             }
             state.last_exit_code = last_exit_code;
             Ok(ControlFlow::Normal(RunResult::exit(last_exit_code)))
-        }
+        }),
 
         // ── Case ────────────────────────────────────────────────────────
-        Command::Case { word, items } => {
-            let value = expand_word(state, word, Some(&exec_fn));
+        Command::Case {
+            word,
+            items,
+            redirects,
+        } => exec_with_compound_redirects(state, host, redirects, &exec_fn, |state| {
+            let value = restore_all_sentinels(&expand_word(state, word, Some(&exec_fn)));
+            if let Some(r) = take_param_error(state) {
+                return Ok(ControlFlow::Normal(r));
+            }
+            let extglob = state.shopt_enabled("extglob");
+            let mut last = RunResult::empty();
+            let mut fall_through = false;
             for item in items {
-                for pattern in &item.patterns {
-                    let pat_str = expand_word(state, pattern, Some(&exec_fn));
-                    if glob_matches(&pat_str, &value) {
-                        return exec_command(state, host, &item.body);
+                if !fall_through {
+                    let mut matched = false;
+                    for pattern in &item.patterns {
+                        let pat_str = expand_pattern(state, pattern, Some(&exec_fn));
+                        if let Some(r) = take_param_error(state) {
+                            return Ok(ControlFlow::Normal(r));
+                        }
+                        matched = if extglob {
+                            extglob_matches(&pat_str, &value)
+                        } else {
+                            glob_matches(&pat_str, &value)
+                        };
+                        if matched {
+                            break;
+                        }
                     }
+                    if !matched {
+                        continue;
+                    }
+                }
+                let flow = exec_command(state, host, &item.body)?;
+                let ControlFlow::Normal(run) = flow else {
+                    return Ok(flow);
+                };
+                match item.terminator {
+                    CaseTerminator::Break => return Ok(ControlFlow::Normal(run)),
+                    CaseTerminator::FallThrough => fall_through = true,
+                    CaseTerminator::Continue => fall_through = false,
                 }
+                last = run;
             }
-            Ok(ControlFlow::Normal(RunResult::empty()))
-        }
+            Ok(ControlFlow::Normal(last))
+        }),
 
         // ── Subshell ────────────────────────────────────────────────────
         Command::Subshell { body, redirects } => {
@@ -2394,12 +2867,15 @@ pub fn exec_command(
             let saved_assoc_arrays = state.assoc_arrays.clone();
             let saved_flags = state.flags.clone();
             let saved_traps = state.traps.clone();
+            let saved_unexported = state.unexported.clone();
+            let saved_integer_vars = state.integer_vars.clone();
             let saved_last_exit_code = state.last_exit_code;
+            let saved_bashpid = state.bashpid;
+            state.bashpid = crate::state::alloc_shell_pid();
 
-            // Apply redirects (e.g. 2>&1) via fd dup
-            let saved_fds = apply_compound_redirects(state, host, redirects);
-            let result = exec_command(state, host, body);
-            restore_compound_redirects(host, &saved_fds);
+            let result = exec_with_compound_redirects(state, host, redirects, &exec_fn, |state| {
+                exec_command(state, host, body)
+            });
 
             state.env = saved_env;
             state.cwd = saved_cwd;
@@ -2408,20 +2884,22 @@ pub fn exec_command(
             state.assoc_arrays = saved_assoc_arrays;
             state.flags = saved_flags;
             state.traps = saved_traps;
+            state.unexported = saved_unexported;
+            state.integer_vars = saved_integer_vars;
             state.last_exit_code = saved_last_exit_code;
+            state.bashpid = saved_bashpid;
             result
         }
 
         // ── Brace group ─────────────────────────────────────────────────
         Command::BraceGroup { body, redirects } => {
-            let saved_fds = apply_compound_redirects(state, host, redirects);
-            let result = exec_command(state, host, body);
-            restore_compound_redirects(host, &saved_fds);
-            result
+            exec_with_compound_redirects(state, host, redirects, &exec_fn, |state| {
+                exec_command(state, host, body)
+            })
         }
 
         // ── Negate ──────────────────────────────────────────────────────
-        Command::Negate { body } => match exec_command(state, host, body)? {
+        Command::Negate { body } => match exec_condition(state, host, body)? {
             ControlFlow::Normal(mut r) => {
                 r.exit_code = if r.exit_code == 0 { 1 } else { 0 };
                 state.last_exit_code = r.exit_code;
@@ -2443,6 +2921,9 @@ pub fn exec_command(
         // ── DoubleBracket [[ ... ]] ─────────────────────────────────────
         Command::DoubleBracket { expr } => {
             let result = eval_double_bracket(state, host, expr, Some(&exec_fn));
+            if let Some(r) = take_param_error(state) {
+                return Ok(ControlFlow::Normal(r));
+            }
             let exit_code = if result { 0 } else { 1 };
             state.last_exit_code = exit_code;
             Ok(ControlFlow::Normal(RunResult::exit(exit_code)))
@@ -2488,27 +2969,38 @@ fn process_assignments(
     let mut errors = Vec::new();
     for assignment in assignments {
         // Parse the raw assignment value into a Word with proper parts
-        // (the parser stores values as raw strings, so $(...) etc. need re-parsing)
-        let word = crate::expand::parse_assignment_value(&assignment.value);
-        let value = expand_word(state, &word, exec);
+        // (the parser stores values as raw strings, so $(...) etc. need re-parsing).
+        // Array literals are split into elements before expansion so quoted
+        // elements survive word splitting intact.
+        let raw = &assignment.value;
+        let array_literal =
+            (raw.len() >= 2 && raw.starts_with('(') && raw.ends_with(')')).then(|| {
+                let words = crate::expand::parse_array_elements(&raw[1..raw.len() - 1]);
+                expand_words_with_splitting(state, &words, exec)
+            });
+        let value = match array_literal {
+            Some(_) => String::new(),
+            None => expand_word(state, &crate::expand::parse_assignment_value(raw), exec),
+        };
 
         // Append assignment: name ends with '+'
         if let Some(real_name) = assignment.name.strip_suffix('+') {
-            if value.starts_with('(') && value.ends_with(')') {
+            if let Some(elements) = array_literal {
                 // Array append: arr+=(elem1 elem2)
-                let inner = value[1..value.len() - 1].trim();
-                let elements: Vec<String> = if inner.is_empty() {
-                    vec![]
-                } else {
-                    inner.split_whitespace().map(|s| s.to_string()).collect()
-                };
                 let arr = state.arrays.entry(real_name.to_string()).or_default();
                 arr.extend(elements);
+            } else if state.integer_vars.contains(real_name) {
+                // Integer append: n+=expr adds numerically
+                let prev = state.env.get(real_name).cloned().unwrap_or_default();
+                let sum = crate::arithmetic::eval_arithmetic(state, &prev)
+                    .wrapping_add(crate::arithmetic::eval_arithmetic(state, &value));
+                state.env.insert(real_name.to_string(), sum.to_string());
             } else {
                 // String append
                 let prev = state.env.get(real_name).cloned().unwrap_or_default();
                 state.env.insert(real_name.to_string(), prev + &value);
             }
+            state.var_changed(real_name);
             continue;
         }
 
@@ -2516,25 +3008,28 @@ fn process_assignments(
         if let Some((arr_name, subscript)) = parse_array_subscript(&assignment.name) {
             if let Some(assoc) = state.assoc_arrays.get_mut(&arr_name) {
                 assoc.insert(subscript.to_string(), value.to_string());
-            } else if let Ok(idx) = subscript.parse::<usize>() {
-                let arr = state.arrays.entry(arr_name).or_default();
-                while arr.len() <= idx {
-                    arr.push(String::new());
-                }
-                arr[idx] = value.to_string();
+                continue;
+            }
+            // Indexed subscripts are arithmetic: `arr[i+1]`, `arr[i++]`,
+            // `arr[$((n-1))]`. Negative indices count back from the highest
+            // index.
+            let subscript_word = crate::expand::parse_assignment_value(&subscript);
+            let expr = expand_word(state, &subscript_word, exec);
+            let idx = crate::arithmetic::eval_arithmetic(state, &expr);
+            let arr = state.arrays.entry(arr_name.clone()).or_default();
+            let idx = if idx < 0 { idx + arr.end() as i64 } else { idx };
+            match usize::try_from(idx) {
+                Ok(idx) => arr.set(idx, value.to_string()),
+                Err(_) => errors.push(format!("{arr_name}[{subscript}]: bad array subscript\n")),
             }
             continue;
         }
 
         // Array literal: var=(elem1 elem2)
-        if value.starts_with('(') && value.ends_with(')') {
-            let inner = value[1..value.len() - 1].trim();
-            let elements: Vec<String> = if inner.is_empty() {
-                vec![]
-            } else {
-                inner.split_whitespace().map(|s| s.to_string()).collect()
-            };
-            state.arrays.insert(assignment.name.clone(), elements);
+        if let Some(elements) = array_literal {
+            state
+                .arrays
+                .insert(assignment.name.clone(), elements.into());
             continue;
         }
 
@@ -2543,7 +3038,13 @@ fn process_assignments(
             errors.push(format!("{}: readonly variable\n", assignment.name));
             continue;
         }
-        state.env.insert(assignment.name.clone(), value.to_string());
+        let value = if state.integer_vars.contains(&assignment.name) {
+            crate::arithmetic::eval_arithmetic(state, &value).to_string()
+        } else {
+            value
+        };
+        state.env.insert(assignment.name.clone(), value);
+        state.var_changed(&assignment.name);
     }
     if errors.is_empty() {
         None
@@ -2656,9 +3157,18 @@ fn tokenize_bracket_expr(
                 i += 1; // skip closing quote
             }
             // Expand variables in double-quoted strings
-            if quote == '"' && word.contains('$') {
-                let expanded = expand_bracket_word(state, &word, exec);
-                tokens.push(BracketToken::Word(expanded));
+            let word = if quote == '"' && word.contains('$') {
+                expand_bracket_word(state, &word, exec)
+            } else {
+                word
+            };
+            // A quoted pattern after `==`/`!=` matches literally, so protect
+            // its glob characters the way QuotedLiteral expansion does.
+            let is_pattern = tokens
+                .last()
+                .is_some_and(|t| matches!(t, BracketToken::Word(w) if w == "==" || w == "!="));
+            if is_pattern {
+                tokens.push(BracketToken::Word(protect_special_chars(&word)));
             } else {
                 tokens.push(BracketToken::Word(word));
             }
@@ -2800,24 +3310,28 @@ fn parse_primary(
                     if op == "=~" {
                         match regex::Regex::new(&right) {
                             Ok(re) => {
-                                if let Some(caps) = re.captures(&left) {
-                                    state.bash_rematch.clear();
-                                    for i in 0..caps.len() {
-                                        state.bash_rematch.push(
-                                            caps.get(i)
-                                                .map(|m| m.as_str().to_string())
-                                                .unwrap_or_default(),
-                                        );
-                                    }
-                                    return true;
-                                } else {
-                                    state.bash_rematch.clear();
-                                    return false;
-                                }
+                                // BASH_REMATCH holds the whole match, then each
+                                // group (empty when it did not participate); a
+                                // failed match leaves it empty.
+                                let groups: Vec<String> = re
+                                    .captures(&left)
+                                    .map(|caps| {
+                                        caps.iter()
+                                            .map(|m| m.map_or("", |m| m.as_str()).to_string())
+                                            .collect()
+                                    })
+                                    .unwrap_or_default();
+                                let matched = !groups.is_empty();
+                                let groups = groups.into();
+                                state.arrays.insert("BASH_REMATCH".to_string(), groups);
+                                return matched;
                             }
                             Err(_) => return false,
                         }
                     }
+                    if matches!(op.as_str(), "-nt" | "-ot" | "-ef") {
+                        return crate::builtins::compare_files(state, host, &left, &op, &right);
+                    }
                     return eval_binary_test(&left, &op, &right);
                 }
             }
@@ -2847,28 +3361,23 @@ fn expand_bracket_word(state: &mut ShellState, word: &str, exec: Option<ExecFn>)
             }
             i += 1;
             if i < chars.len() && chars[i] == '{' {
-                // ${VAR}
+                // ${VAR}, ${VAR:-default}, ${VAR:?msg}, ...
                 i += 1;
-                let mut var_name = String::new();
+                let mut inner = String::new();
                 while i < chars.len() && chars[i] != '}' {
-                    var_name.push(chars[i]);
+                    inner.push(chars[i]);
                     i += 1;
                 }
                 if i < chars.len() {
                     i += 1; // skip '}'
                 }
-                parts.push(codepod_shell::ast::WordPart::Variable(var_name));
+                parts.push(crate::expand::parse_param_expansion_inner(&inner));
             } else if i < chars.len()
-                && (chars[i].is_alphanumeric()
-                    || chars[i] == '_'
-                    || chars[i] == '?'
-                    || chars[i] == '#'
-                    || chars[i] == '$'
-                    || chars[i] == '!')
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || "?#$!@*-".contains(chars[i]))
             {
                 // $VAR or $? etc.
                 let mut var_name = String::new();
-                if chars[i] == '?' || chars[i] == '#' || chars[i] == '$' || chars[i] == '!' {
+                if "?#$!@*-".contains(chars[i]) {
                     var_name.push(chars[i]);
                     i += 1;
                 } else {
@@ -2904,7 +3413,19 @@ fn is_unary_test(op: &str) -> bool {
 fn is_binary_op(op: &str) -> bool {
     matches!(
         op,
-        "==" | "!=" | "=~" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge"
+        "==" | "!="
+            | "=~"
+            | "<"
+            | ">"
+            | "-eq"
+            | "-ne"
+            | "-lt"
+            | "-le"
+            | "-gt"
+            | "-ge"
+            | "-nt"
+            | "-ot"
+            | "-ef"
     )
 }
 
@@ -2941,6 +3462,13 @@ fn eval_unary_test(
     }
 }
 
+/// Evaluate a binary `[[ ]]` operator.
+///
+/// `<` and `>` always compare as strings, even when both operands look
+/// numeric (`[[ 10 < 9 ]]` is true); `-lt` and friends compare as integers.
+/// String order is byte order, which is what bash gives in the C locale
+/// and matches other locales for plain ASCII. The sandbox has no locale
+/// data to collate by.
 fn eval_binary_test(left: &str, op: &str, right: &str) -> bool {
     match op {
         "==" => glob_matches(right, left),
@@ -2952,6 +3480,7 @@ fn eval_binary_test(left: &str, op: &str, right: &str) -> bool {
                 Err(_) => false,
             }
         }
+        // Byte-wise `str` ordering, never numeric.
         "<" => left < right,
         ">" => left > right,
         "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
@@ -2976,6 +3505,11 @@ mod tests {
     use super::*;
     use crate::test_support::mock::{MockHost, MockSpawnOutput};
 
+    /// Parse a script the test expects to be well-formed.
+    fn parse(input: &str) -> Command {
+        codepod_shell::parser::try_parse(input).unwrap()
+    }
+
     /// Helper: execute a shell command string, capturing stdout via a pipe.
     /// Returns (exit_code, captured_stdout).
     fn exec_capture(
@@ -2983,7 +3517,7 @@ mod tests {
         host: &dyn HostInterface,
         cmd_str: &str,
     ) -> (i32, String) {
-        let cmd = codepod_shell::parser::parse(cmd_str);
+        let cmd = parse(cmd_str);
         exec_capture_cmd(state, host, &cmd)
     }
 
@@ -3027,11 +3561,143 @@ mod tests {
         assert_eq!(stdout, "file.txt\n");
     }
 
+    #[test]
+    fn path_lookup_is_memoized_in_hash_table() {
+        let host = MockHost::new()
+            .with_file("/usr/bin/mytool", b"")
+            .with_mode("/usr/bin/mytool", S_TOOL | 0o755)
+            .with_spawn_result(
+                "mytool",
+                MockSpawnOutput {
+                    exit_code: 0,
+                    stdout: "ran\n".into(),
+                    stderr: String::new(),
+                },
+            );
+        let mut state = ShellState::new_default();
+        state
+            .env
+            .insert("PATH".into(), "/usr/local/bin:/usr/bin".into());
+        let walked = |host: &MockHost| {
+            host.get_stat_calls()
+                .iter()
+                .filter(|p| *p == "/usr/local/bin/mytool")
+                .count()
+        };
+
+        let (code, stdout) = exec_capture(&mut state, &host, "mytool; mytool; mytool");
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "ran\nran\nran\n");
+        assert_eq!(walked(&host), 1, "PATH is walked only on the first run");
+        assert_eq!(state.command_hash.get("mytool").unwrap(), "/usr/bin/mytool");
+
+        // A stale entry is dropped and the walk is redone.
+        state
+            .command_hash
+            .insert("mytool".into(), "/gone/mytool".into());
+        let (_, stdout) = exec_capture(&mut state, &host, "mytool");
+        assert_eq!(stdout, "ran\n");
+        assert_eq!(walked(&host), 2);
+        assert_eq!(state.command_hash.get("mytool").unwrap(), "/usr/bin/mytool");
+    }
+
+    #[test]
+    fn path_lookup_runs_executable_script_by_bare_name() {
+        let host = MockHost::new()
+            .with_file("/opt/bin/greet", b"#!/bin/sh\necho hello $1\n")
+            .with_mode("/opt/bin/greet", 0o755)
+            .with_file("/usr/local/bin/greet", b"echo shadowed\n")
+            .with_mode("/usr/local/bin/greet", 0o755);
+        let mut state = ShellState::new_default();
+        state
+            .env
+            .insert("PATH".into(), "/opt/bin:/usr/local/bin".into());
+
+        let (code, stdout) = exec_capture(&mut state, &host, "greet world");
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "hello world\n", "first PATH entry wins");
+        assert_eq!(state.command_hash.get("greet").unwrap(), "/opt/bin/greet");
+    }
+
+    #[test]
+    fn assigning_path_empties_the_command_hash() {
+        let host = MockHost::new()
+            .with_file("/a/greet", b"echo from-a\n")
+            .with_mode("/a/greet", 0o755)
+            .with_file("/b/greet", b"echo from-b\n")
+            .with_mode("/b/greet", 0o755);
+        let mut state = ShellState::new_default();
+
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "PATH=/a; greet; PATH=/b; greet; export PATH=/a; greet",
+        );
+        assert_eq!(stdout, "from-a\nfrom-b\nfrom-a\n");
+
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "f() { local PATH=/b; greet; }; f; greet; unset PATH; hash",
+        );
+        assert_eq!(stdout, "from-b\nfrom-a\nhash: hash table empty\n");
+    }
+
+    #[test]
+    fn path_lookup_skips_files_without_exec_bit() {
+        let host = MockHost::new()
+            .with_file("/opt/bin/notes", b"echo should-not-run\n")
+            .with_mode("/opt/bin/notes", 0o644)
+            .with_file("/usr/local/bin/notes", b"echo from-local\n")
+            .with_mode("/usr/local/bin/notes", 0o755);
+        let mut state = ShellState::new_default();
+        state
+            .env
+            .insert("PATH".into(), "/opt/bin:/usr/local/bin".into());
+
+        let (_, stdout) = exec_capture(&mut state, &host, "notes");
+        assert_eq!(stdout, "from-local\n");
+
+        let (code, stdout) = exec_capture(&mut state, &host, "PATH=/opt/bin; notes");
+        assert_eq!(code, 127);
+        assert_eq!(stdout, "");
+    }
+
+    #[test]
+    fn export_n_keeps_value_but_hides_it_from_children() {
+        let host = MockHost::new().with_tool("tool").with_spawn_result(
+            "tool",
+            MockSpawnOutput {
+                exit_code: 0,
+                stdout: String::new(),
+                stderr: String::new(),
+            },
+        );
+        let mut state = ShellState::new_default();
+
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "FOO=1; export FOO; export -n FOO; tool; echo $FOO",
+        );
+        assert_eq!(stdout, "1\n");
+        let calls = host.get_spawn_calls();
+        assert!(!calls[0].env.contains_key("FOO"));
+        assert_eq!(calls[0].env.get("HOME").unwrap(), "/home/user");
+
+        exec_capture(&mut state, &host, "declare -x FOO; tool");
+        assert_eq!(host.get_spawn_calls()[1].env.get("FOO").unwrap(), "1");
+
+        exec_capture(&mut state, &host, "declare +x FOO; FOO=2; tool");
+        assert!(!host.get_spawn_calls()[2].env.contains_key("FOO"));
+        assert_eq!(state.env.get("FOO").unwrap(), "2");
+    }
+
     #[test]
     fn unknown_command_returns_127() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("nonexistent");
+        let cmd = parse("nonexistent");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -3066,7 +3732,7 @@ mod tests {
             },
         );
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("fail");
+        let cmd = parse("fail");
         let _ = exec_command(&mut state, &host, &cmd);
         assert_eq!(state.last_exit_code, 42);
     }
@@ -3087,7 +3753,7 @@ mod tests {
             },
         );
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("echo $(echo hello)");
+        let cmd = parse("echo $(echo hello)");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(exit_code, 0);
         // The outer "echo" also returns "hello\n" from MockHost since
@@ -3119,7 +3785,7 @@ mod tests {
             };
             let saved = state.stdout_fd;
             state.stdout_fd = write_fd;
-            let inner_cmd = codepod_shell::parser::parse(cmd_str);
+            let inner_cmd = parse(cmd_str);
             let _ = exec_command(state, &host, &inner_cmd);
             state.stdout_fd = saved;
             let _ = host.close_fd(write_fd);
@@ -3158,7 +3824,7 @@ mod tests {
             };
             let saved = state.stdout_fd;
             state.stdout_fd = write_fd;
-            let inner_cmd = codepod_shell::parser::parse(cmd_str);
+            let inner_cmd = parse(cmd_str);
             let _ = exec_command(state, &host, &inner_cmd);
             state.stdout_fd = saved;
             let _ = host.close_fd(write_fd);
@@ -3215,7 +3881,7 @@ mod tests {
             };
             let saved = state.stdout_fd;
             state.stdout_fd = write_fd;
-            let inner_cmd = codepod_shell::parser::parse(cmd_str);
+            let inner_cmd = parse(cmd_str);
             let _ = exec_command(state, &host, &inner_cmd);
             state.stdout_fd = saved;
             let _ = host.close_fd(write_fd);
@@ -3253,7 +3919,7 @@ mod tests {
             };
             let saved = state.stdout_fd;
             state.stdout_fd = write_fd;
-            let inner_cmd = codepod_shell::parser::parse(cmd_str);
+            let inner_cmd = parse(cmd_str);
             let _ = exec_command(state, &host, &inner_cmd);
             state.stdout_fd = saved;
             let _ = host.close_fd(write_fd);
@@ -3292,7 +3958,7 @@ mod tests {
             };
             let saved = state.stdout_fd;
             state.stdout_fd = write_fd;
-            let inner_cmd = codepod_shell::parser::parse(cmd_str);
+            let inner_cmd = parse(cmd_str);
             let _ = exec_command(state, &host, &inner_cmd);
             state.stdout_fd = saved;
             let _ = host.close_fd(write_fd);
@@ -3323,7 +3989,7 @@ mod tests {
             };
             let saved = state.stdout_fd;
             state.stdout_fd = write_fd;
-            let inner_cmd = codepod_shell::parser::parse(cmd_str);
+            let inner_cmd = parse(cmd_str);
             let _ = exec_command(state, &host, &inner_cmd);
             state.stdout_fd = saved;
             let _ = host.close_fd(write_fd);
@@ -3337,6 +4003,22 @@ mod tests {
         assert_eq!(result, "");
     }
 
+    // ---- Arithmetic expansion tests ----
+
+    #[test]
+    fn arithmetic_expansion_in_words() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(&mut state, &host, "echo $((2*3))");
+        assert_eq!(stdout, "6\n");
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "i=4; echo x$((i+1))y file_$((i)).txt \"q$(( $i * (i+1) ))\"",
+        );
+        assert_eq!(stdout, "x5y file_4.txt q20\n");
+    }
+
     // ---- Redirect tests ----
 
     /// Helper: build a `Command::Simple` with the given command name and redirects.
@@ -3483,6 +4165,20 @@ mod tests {
         assert_eq!(stdout, "relative content\n");
     }
 
+    #[test]
+    fn exec_opens_a_file_on_a_numbered_fd_for_read_u() {
+        let host = MockHost::new().with_file("/home/user/lines.txt", b"first\nsecond\n");
+        let mut state = ShellState::new_default();
+        let (code, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "exec 3< lines.txt; read -u 3 a; read -u 3 b; echo \"$a/$b\"; read -u 3 c",
+        );
+        assert_eq!(stdout, "first/second\n");
+        // The third read hits EOF on fd 3.
+        assert_eq!(code, 1);
+    }
+
     #[test]
     fn redirect_stderr_overwrite() {
         // `cmd 2> /tmp/err.txt`
@@ -3644,6 +4340,24 @@ mod tests {
         assert_eq!(stdout, "stripped content\n");
     }
 
+    #[test]
+    fn heredoc_expands_unless_delimiter_is_quoted() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let script = "x=val\n\
+                      cat <<EOF\n$x $(echo sub)\nEOF\n\
+                      cat <<'EOF'\n$x $(echo sub)\nEOF\n\
+                      cat <<\"EOF\"\n$x\nEOF\n\
+                      cat <<\\EOF\n$x\nEOF";
+        exec_capture(&mut state, &host, script);
+        let stdins: Vec<String> = host
+            .get_spawn_calls()
+            .into_iter()
+            .map(|c| c.stdin)
+            .collect();
+        assert_eq!(stdins, ["val sub\n", "$x $(echo sub)\n", "$x\n", "$x\n"]);
+    }
+
     #[test]
     fn redirect_here_string() {
         // `cat <<< "hello"` — stdin becomes "hello\n"
@@ -3724,7 +4438,7 @@ mod tests {
             },
         );
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("echo hi");
+        let cmd = parse("echo hi");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "hi\n");
     }
@@ -3861,7 +4575,7 @@ mod tests {
             },
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("echo hello | cat");
+        let cmd = parse("echo hello | cat");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(exit_code, 0);
         assert_eq!(stdout, "hello\n");
@@ -3873,6 +4587,56 @@ mod tests {
         assert_eq!(calls[0].stdin, "hello\n"); // cat gets echo's stdout
     }
 
+    #[test]
+    fn pipeline_yes_into_head() {
+        let host = MockHost::new().with_spawn_handler(|program, args, stdin| {
+            let n: usize = args.last().and_then(|a| a.parse().ok()).unwrap_or(10);
+            MockSpawnOutput {
+                exit_code: if program == "head" { 0 } else { 127 },
+                stdout: stdin.lines().take(n).map(|l| format!("{l}\n")).collect(),
+                stderr: String::new(),
+            }
+        });
+        let mut state = ShellState::new_default();
+        let (code, stdout) = exec_capture(&mut state, &host, "yes hi | head -n 2");
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "hi\nhi\n");
+    }
+
+    #[test]
+    fn pipeline_stage_param_error_fails_that_stage() {
+        let host = MockHost::new()
+            .with_spawn_handler(|_, _, stdin| MockSpawnOutput {
+                exit_code: 0,
+                stdout: format!("got [{stdin}]\n"),
+                stderr: String::new(),
+            })
+            .with_tool("cat");
+        let mut state = ShellState::new_default();
+        let (code, stdout) = exec_capture(&mut state, &host, "echo ${missing:?required} | cat");
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "got []\n");
+
+        let (code, _) = exec_capture(&mut state, &host, "echo hi | echo ${missing:?required}");
+        assert_eq!(code, 1);
+        assert!(state.param_error.is_none());
+    }
+
+    #[test]
+    fn case_and_double_bracket_param_errors() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let script = r#"case ${missing:?required} in *) echo matched;; esac"#;
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 1);
+        assert_eq!(stdout, "");
+
+        let script = r#"[[ -z ${missing:?required} ]] && echo empty"#;
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 1);
+        assert_eq!(stdout, "");
+    }
+
     #[test]
     fn pipeline_three_stage() {
         // `echo hello | cat | cat` — chaining works through 3 stages
@@ -3890,7 +4654,7 @@ mod tests {
             },
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("echo hello | cat | cat");
+        let cmd = parse("echo hello | cat | cat");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(exit_code, 0);
         assert_eq!(stdout, "hello\n");
@@ -3925,7 +4689,7 @@ mod tests {
             },
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("false | true");
+        let cmd = parse("false | true");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -3957,7 +4721,7 @@ mod tests {
         });
         let mut state = ShellState::new_default();
         state.flags.insert(ShellFlag::Pipefail);
-        let cmd = codepod_shell::parser::parse("false | true");
+        let cmd = parse("false | true");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -3994,7 +4758,7 @@ mod tests {
         });
         let mut state = ShellState::new_default();
         state.flags.insert(ShellFlag::Pipefail);
-        let cmd = codepod_shell::parser::parse("cmd-exit-2 | cmd-exit-3 | true");
+        let cmd = parse("cmd-exit-2 | cmd-exit-3 | true");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -4024,7 +4788,7 @@ mod tests {
             },
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("cmd-with-stderr | cat");
+        let cmd = parse("cmd-with-stderr | cat");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         // cat only sees stdout, not stderr
         assert_eq!(stdout, "out\n");
@@ -4054,7 +4818,7 @@ mod tests {
             },
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("cmd 2>&1 | cat");
+        let cmd = parse("cmd 2>&1 | cat");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         // In pipe-based model, stderr merge happens at fd level;
         // cat only receives what was written to stdout_fd.
@@ -4107,16 +4871,123 @@ mod tests {
         assert_eq!(calls[0].stdin, "hello from curl");
     }
 
+    fn pkg_host() -> MockHost {
+        MockHost::new().with_file("/etc/codepod/pkg-policy.json", br#"{"enabled": true}"#)
+    }
+
+    fn fetch_ok(body: &str, body_base64: Option<String>) -> crate::host::FetchResult {
+        crate::host::FetchResult {
+            ok: true,
+            status: 200,
+            headers: Default::default(),
+            body: body.to_string(),
+            body_base64,
+            error: None,
+        }
+    }
+
     #[test]
-    fn pipeline_single_command_delegates() {
-        // A Pipeline with a single command should behave identically to
-        // executing that command directly.
-        use codepod_shell::ast::{Command as AstCommand, Word};
+    fn pkg_add_list_remove_round_trip() {
+        let host = pkg_host().with_fetch_result(
+            "https://example.com/tools/hello.wasm",
+            fetch_ok("\0asm", None),
+        );
+        let mut state = ShellState::new_default();
 
-        let host = MockHost::new().with_spawn_result(
-            "echo",
-            MockSpawnOutput {
-                exit_code: 0,
+        let (code, out) = exec_capture(
+            &mut state,
+            &host,
+            "pkg add https://example.com/tools/hello.wasm",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(out, "Installed hello\n");
+        assert!(host.read_file("/usr/share/pkg/bin/hello.wasm").is_ok());
+        assert_eq!(host.get_registered_tools()[0].0, "hello");
+
+        let (code, out) = exec_capture(&mut state, &host, "pkg list");
+        assert_eq!(code, 0);
+        assert_eq!(out, "hello\thttps://example.com/tools/hello.wasm\t4\n");
+
+        let (code, out) = exec_capture(&mut state, &host, "pkg info hello");
+        assert_eq!(code, 0);
+        assert!(out.contains("Files:\n  /usr/share/pkg/bin/hello.wasm\n"));
+
+        let (code, out) = exec_capture(&mut state, &host, "pkg remove hello");
+        assert_eq!(code, 0);
+        assert_eq!(out, "Removed hello\n");
+        assert!(host.read_file("/usr/share/pkg/bin/hello.wasm").is_err());
+
+        let (_, out) = exec_capture(&mut state, &host, "pkg list");
+        assert_eq!(out, "");
+        let (code, _) = exec_capture(&mut state, &host, "pkg info hello");
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn pkg_add_unpacks_archive_under_prefix() {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("bin/tool.sh", options).unwrap();
+        zip.write_all(b"echo hi\n").unwrap();
+        zip.start_file("share/README", options).unwrap();
+        zip.write_all(b"docs\n").unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+        let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &bytes);
+
+        let host = pkg_host()
+            .with_fetch_result("https://example.com/kit.zip", fetch_ok("", Some(encoded)));
+        let mut state = ShellState::new_default();
+
+        let (code, out) = exec_capture(
+            &mut state,
+            &host,
+            "pkg add --prefix /opt/kit https://example.com/kit.zip",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(out, "Installed kit (2 files in /opt/kit)\n");
+        assert_eq!(
+            host.read_file("/opt/kit/bin/tool.sh").unwrap(),
+            b"echo hi\n"
+        );
+        assert_eq!(host.read_file("/opt/kit/share/README").unwrap(), b"docs\n");
+        assert!(host.get_registered_tools().is_empty());
+
+        let (code, _) = exec_capture(&mut state, &host, "pkg remove kit");
+        assert_eq!(code, 0);
+        assert!(host.read_file("/opt/kit/bin/tool.sh").is_err());
+        assert!(host.read_file("/opt/kit/share/README").is_err());
+    }
+
+    #[test]
+    fn pkg_rejects_unknown_subcommands_and_bad_prefix() {
+        let host = pkg_host();
+        let mut state = ShellState::new_default();
+        let (code, _) = exec_capture(&mut state, &host, "pkg frobnicate");
+        assert_eq!(code, 1);
+        let (code, _) = exec_capture(&mut state, &host, "pkg");
+        assert_eq!(code, 1);
+        let (code, _) = exec_capture(
+            &mut state,
+            &host,
+            "pkg add --prefix /opt https://example.com/a.wasm",
+        );
+        assert_eq!(code, 1);
+        let (code, _) = exec_capture(&mut state, &host, "pkg remove missing");
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn pipeline_single_command_delegates() {
+        // A Pipeline with a single command should behave identically to
+        // executing that command directly.
+        use codepod_shell::ast::{Command as AstCommand, Word};
+
+        let host = MockHost::new().with_spawn_result(
+            "echo",
+            MockSpawnOutput {
+                exit_code: 0,
                 stdout: "hello\n".into(),
                 stderr: String::new(),
             },
@@ -4155,7 +5026,7 @@ mod tests {
             },
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("true | exit42");
+        let cmd = parse("true | exit42");
         let _ = exec_command(&mut state, &host, &cmd);
         assert_eq!(state.last_exit_code, 42);
     }
@@ -4186,7 +5057,7 @@ mod tests {
             },
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("fail1 | fail2 | succeed");
+        let cmd = parse("fail1 | fail2 | succeed");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -4194,6 +5065,26 @@ mod tests {
         assert_eq!(run.exit_code, 0);
     }
 
+    #[test]
+    fn pipeline_stage_calls_shell_function_with_stage_stdin() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (code, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "wrap() { while read l; do echo \"$1$l\"; done; return 3; }\n\
+             printf 'a\\nb\\n' | wrap '> '",
+        );
+        assert_eq!(code, 3);
+        assert_eq!(stdout, "> a\n> b\n");
+
+        // A function in the middle of a pipeline feeds the next stage.
+        let (code, stdout) = exec_capture(&mut state, &host, "echo x | wrap - | wrap +");
+        assert_eq!(code, 3);
+        assert_eq!(stdout, "+-x\n");
+        assert!(state.positional_args.is_empty());
+    }
+
     // ====================================================================
     // List operator tests (&&, ||, ;)
     // ====================================================================
@@ -4350,6 +5241,40 @@ mod tests {
         assert_eq!(stdout, "a\n");
     }
 
+    #[test]
+    fn long_sequence_runs_without_deep_recursion() {
+        // Long enough to overflow the test thread's stack if each `;` or
+        // `&` cost a level of recursion.
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let n = 5000;
+        let script: Vec<String> = (0..n).map(|i| format!("echo {i}")).collect();
+        let (code, stdout) = exec_capture(&mut state, &host, &script.join("; "));
+        assert_eq!(code, 0);
+        let expected: String = (0..n).map(|i| format!("{i}\n")).collect();
+        assert_eq!(stdout, expected);
+
+        // Background jobs chain the same way.
+        let (_, stdout) = exec_capture(&mut state, &host, &script.join(" & "));
+        assert_eq!(stdout, expected);
+        assert_eq!(state.jobs.len(), n - 1);
+    }
+
+    #[test]
+    fn long_sequence_with_large_output() {
+        // Appended to a file: the capture pipe only holds 64 KiB.
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let line = "x".repeat(1024);
+        let script = vec![format!("echo {line} >> /tmp/big"); 2000].join("; ");
+        let (code, _) = exec_capture(&mut state, &host, &script);
+        assert_eq!(code, 0);
+        let out = host.read_file("/tmp/big").unwrap();
+        assert_eq!(out.len(), 2000 * 1025);
+        let lines: Vec<&[u8]> = out.split(|&b| b == b'\n').take(2000).collect();
+        assert!(lines.iter().all(|l| *l == line.as_bytes()));
+    }
+
     #[test]
     fn list_nested_and_or() {
         // `false && echo-a || echo-b` — false fails, so && short-circuits,
@@ -4383,6 +5308,7 @@ mod tests {
             condition: Box::new(simple_cmd("true")),
             then_body: Box::new(simple_cmd("echo-a")),
             else_body: None,
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "a\n");
@@ -4396,6 +5322,7 @@ mod tests {
             condition: Box::new(simple_cmd("false")),
             then_body: Box::new(simple_cmd("echo-a")),
             else_body: Some(Box::new(simple_cmd("echo-b"))),
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "b\n");
@@ -4409,6 +5336,7 @@ mod tests {
             condition: Box::new(simple_cmd("false")),
             then_body: Box::new(simple_cmd("echo-a")),
             else_body: None,
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4429,7 +5357,9 @@ mod tests {
                 condition: Box::new(simple_cmd("true")),
                 then_body: Box::new(simple_cmd("echo-b")),
                 else_body: None,
+                redirects: vec![],
             })),
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "b\n");
@@ -4466,6 +5396,7 @@ mod tests {
                 redirects: vec![],
                 assignments: vec![],
             }),
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "a\nb\nc\n");
@@ -4481,6 +5412,7 @@ mod tests {
             var: "i".to_string(),
             words: vec![],
             body: Box::new(simple_cmd("echo")),
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4491,6 +5423,17 @@ mod tests {
         assert_eq!(host.get_spawn_calls().len(), 0);
     }
 
+    #[test]
+    fn for_word_list_param_error_skips_body() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let script = r#"for i in a ${missing:?required}; do echo $i; done"#;
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 1);
+        assert_eq!(stdout, "");
+        assert!(state.param_error.is_none());
+    }
+
     #[test]
     fn for_break_exits_loop() {
         use codepod_shell::ast::Word;
@@ -4515,6 +5458,7 @@ mod tests {
             var: "i".to_string(),
             words: vec![Word::literal("a"), Word::literal("b"), Word::literal("c")],
             body: Box::new(Command::Break),
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4559,6 +5503,7 @@ mod tests {
                     assignments: vec![],
                 }),
             }),
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4608,6 +5553,7 @@ mod tests {
                     expr: "i++".to_string(),
                 }),
             }),
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "0\n1\n2\n");
@@ -4623,6 +5569,7 @@ mod tests {
         let cmd = Command::While {
             condition: Box::new(simple_cmd("true")),
             body: Box::new(Command::Break),
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4670,6 +5617,7 @@ mod tests {
                     assignments: vec![],
                 }),
             }),
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4710,6 +5658,7 @@ mod tests {
                 redirects: vec![],
                 assignments: vec![],
             }),
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "0\n1\n2\n");
@@ -4727,6 +5676,7 @@ mod tests {
             cond: String::new(),
             step: String::new(),
             body: Box::new(Command::Break),
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4767,6 +5717,7 @@ mod tests {
                     assignments: vec![],
                 }),
             }),
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4776,13 +5727,27 @@ mod tests {
         assert_eq!(state.env.get("i").unwrap(), "5");
     }
 
+    #[test]
+    fn cfor_comma_sequenced_init_and_step() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "for ((i=0, j=10; i<j; i++, j--)); do n=$((n+1)); done; echo $i $j $n",
+        );
+        assert_eq!(stdout, "5 5 5\n");
+        assert_eq!(state.env.get("i").unwrap(), "5");
+        assert_eq!(state.env.get("j").unwrap(), "5");
+    }
+
     // ====================================================================
     // Case tests
     // ====================================================================
 
     #[test]
     fn case_exact_match() {
-        use codepod_shell::ast::{CaseItem, Word};
+        use codepod_shell::ast::{CaseItem, CaseTerminator, Word};
 
         let host = MockHost::new().with_spawn_handler(make_handler());
         let mut state = ShellState::new_default();
@@ -4793,12 +5758,15 @@ mod tests {
                 CaseItem {
                     patterns: vec![Word::literal("world")],
                     body: Box::new(simple_cmd("echo-a")),
+                    terminator: CaseTerminator::Break,
                 },
                 CaseItem {
                     patterns: vec![Word::literal("hello")],
                     body: Box::new(simple_cmd("echo-b")),
+                    terminator: CaseTerminator::Break,
                 },
             ],
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "b\n");
@@ -4806,7 +5774,7 @@ mod tests {
 
     #[test]
     fn case_glob_wildcard() {
-        use codepod_shell::ast::{CaseItem, Word};
+        use codepod_shell::ast::{CaseItem, CaseTerminator, Word};
 
         let host = MockHost::new().with_spawn_handler(make_handler());
         let mut state = ShellState::new_default();
@@ -4817,12 +5785,15 @@ mod tests {
                 CaseItem {
                     patterns: vec![Word::literal("specific")],
                     body: Box::new(simple_cmd("echo-a")),
+                    terminator: CaseTerminator::Break,
                 },
                 CaseItem {
                     patterns: vec![Word::literal("*")],
                     body: Box::new(simple_cmd("echo-b")),
+                    terminator: CaseTerminator::Break,
                 },
             ],
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "b\n");
@@ -4830,7 +5801,7 @@ mod tests {
 
     #[test]
     fn case_first_match_wins() {
-        use codepod_shell::ast::{CaseItem, Word};
+        use codepod_shell::ast::{CaseItem, CaseTerminator, Word};
 
         let host = MockHost::new().with_spawn_handler(make_handler());
         let mut state = ShellState::new_default();
@@ -4841,12 +5812,15 @@ mod tests {
                 CaseItem {
                     patterns: vec![Word::literal("hello")],
                     body: Box::new(simple_cmd("echo-a")),
+                    terminator: CaseTerminator::Break,
                 },
                 CaseItem {
                     patterns: vec![Word::literal("*")],
                     body: Box::new(simple_cmd("echo-b")),
+                    terminator: CaseTerminator::Break,
                 },
             ],
+            redirects: vec![],
         };
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "a\n");
@@ -4854,7 +5828,7 @@ mod tests {
 
     #[test]
     fn case_no_match_returns_empty() {
-        use codepod_shell::ast::{CaseItem, Word};
+        use codepod_shell::ast::{CaseItem, CaseTerminator, Word};
 
         let host = MockHost::new().with_spawn_handler(make_handler());
         let mut state = ShellState::new_default();
@@ -4864,7 +5838,9 @@ mod tests {
             items: vec![CaseItem {
                 patterns: vec![Word::literal("world")],
                 body: Box::new(simple_cmd("echo-a")),
+                terminator: CaseTerminator::Break,
             }],
+            redirects: vec![],
         };
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
@@ -4873,6 +5849,37 @@ mod tests {
         assert_eq!(run.exit_code, 0);
     }
 
+    #[test]
+    fn case_patterns_get_tilde_and_parameter_expansion() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "case $HOME in ~) echo home;; *) echo other;; esac\n\
+             case $HOME/src in ~/*) echo under-home;; esac\n\
+             pre=ab; case abc in ${pre}?) echo param;; esac\n\
+             case 3 in $((1 + 2))|$(echo x)) echo arith;; esac",
+        );
+        assert_eq!(stdout, "home\nunder-home\nparam\narith\n");
+    }
+
+    #[test]
+    fn case_quoted_pattern_parts_match_literally() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "v='*'\n\
+             case zzz in $v) echo bare-glob;; esac\n\
+             case zzz in \"$v\") echo wrong;; *) echo quoted-literal;; esac\n\
+             x='[a]'; case \"$x\" in '[a]') echo brackets;; esac\n\
+             case 'a*b' in \"a*\"?) echo mixed;; esac",
+        );
+        assert_eq!(stdout, "bare-glob\nquoted-literal\nbrackets\nmixed\n");
+    }
+
     // ====================================================================
     // Subshell tests
     // ====================================================================
@@ -4928,6 +5935,99 @@ mod tests {
         assert_eq!(stdout, "a\n");
     }
 
+    #[test]
+    fn brace_group_stdout_redirect_writes_all_output() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (exit_code, stdout) = exec_capture(&mut state, &host, "{ echo a; echo b; } > out.txt");
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "");
+        assert_eq!(host.get_file("/home/user/out.txt").unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn brace_group_append_redirect() {
+        let host = MockHost::new().with_file("/home/user/log.txt", b"first\n");
+        let mut state = ShellState::new_default();
+
+        let (exit_code, _) = exec_capture(&mut state, &host, "{ echo second; } >> log.txt");
+        assert_eq!(exit_code, 0);
+        assert_eq!(
+            host.get_file("/home/user/log.txt").unwrap(),
+            "first\nsecond\n"
+        );
+    }
+
+    #[test]
+    fn brace_group_redirect_captures_spawned_output() {
+        let host = MockHost::new().with_spawn_handler(make_handler());
+        let mut state = ShellState::new_default();
+
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "{ echo-a; echo-b; } > ab.txt; echo finished",
+        );
+        assert_eq!(stdout, "finished\n");
+        assert_eq!(host.get_file("/home/user/ab.txt").unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn subshell_stdout_redirect_writes_file() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (exit_code, stdout) = exec_capture(&mut state, &host, "(echo x; echo y) > sub.txt");
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "");
+        assert_eq!(host.get_file("/home/user/sub.txt").unwrap(), "x\ny\n");
+    }
+
+    #[test]
+    fn for_loop_stdout_redirect_writes_file() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (exit_code, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "for i in a b c; do echo $i; done > loop.txt",
+        );
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "");
+        assert_eq!(host.get_file("/home/user/loop.txt").unwrap(), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn while_and_cfor_redirects_write_file() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let script = "i=0; while (( i < 2 )); do echo w$i; i=$((i+1)); done > w.txt; \
+                      for ((j=0; j<2; j++)); do echo c$j; done >> w.txt";
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "");
+        assert_eq!(
+            host.get_file("/home/user/w.txt").unwrap(),
+            "w0\nw1\nc0\nc1\n"
+        );
+    }
+
+    #[test]
+    fn if_and_case_redirects_write_file() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let script = "if true; then echo yes; fi > if.txt; case a in a) echo hit;; esac > case.txt";
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "");
+        assert_eq!(host.get_file("/home/user/if.txt").unwrap(), "yes\n");
+        assert_eq!(host.get_file("/home/user/case.txt").unwrap(), "hit\n");
+    }
+
     // ====================================================================
     // Negate tests
     // ====================================================================
@@ -5078,6 +6178,90 @@ mod tests {
         assert_eq!(state.positional_args, vec!["original"]);
     }
 
+    #[test]
+    fn command_bypasses_shadowing_function() {
+        let host = MockHost::new().with_spawn_handler(|program, args, _stdin| match program {
+            "ls" => MockSpawnOutput {
+                exit_code: 0,
+                stdout: format!("real ls {}\n", args.join(" ")),
+                stderr: String::new(),
+            },
+            _ => MockSpawnOutput {
+                exit_code: 127,
+                stdout: String::new(),
+                stderr: format!("{program}: command not found\n"),
+            },
+        });
+        let mut state = ShellState::new_default();
+
+        let (code, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "ls() { echo wrapped; command ls \"$@\"; }; ls -a; command -- ls -l",
+        );
+        assert_eq!(code, 0);
+        // (`ls` without a path argument is given the cwd.)
+        assert_eq!(
+            stdout,
+            "wrapped\nreal ls -a /home/user\nreal ls -l /home/user\n"
+        );
+
+        // Builtins are still reachable, and `command -v` still reports.
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "echo() { :; }; command echo hi; command -v echo",
+        );
+        assert_eq!(stdout, "hi\necho\n");
+    }
+
+    #[test]
+    fn builtin_reaches_builtin_shadowed_by_function() {
+        let host = MockHost::new().with_dir("/tmp");
+        let mut state = ShellState::new_default();
+
+        let (code, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "cd() { echo \"cd to $1\"; builtin cd \"$@\"; }; cd /tmp; pwd",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "cd to /tmp\n/tmp\n");
+        assert_eq!(state.cwd, "/tmp");
+
+        let (code, _) = exec_capture(&mut state, &host, "builtin nosuchthing");
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn function_in_pipeline_matches_standalone_call() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        state.positional_args = vec!["p".to_string(), "q".to_string()];
+        exec_capture(
+            &mut state,
+            &host,
+            "show() { local v=in; echo \"$# [$1] [$2] $v\"; }",
+        );
+
+        let (_, standalone) = exec_capture(&mut state, &host, "show a");
+        let (_, piped) = exec_capture(&mut state, &host, "echo | show a");
+        assert_eq!(standalone, "1 [a] [] in\n");
+        assert_eq!(piped, standalone);
+
+        // Each stage gets its own arguments; none leak into the next stage
+        // or back to the caller, and the local is gone afterwards.
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "show a b | show c | { read l; echo \"$l\"; show; }; echo \"$# $1 [$v]\"",
+        );
+        assert_eq!(stdout, "1 [c] [] in\n0 [] [] in\n2 p []\n");
+        assert_eq!(state.positional_args, vec!["p", "q"]);
+        assert!(state.local_var_stack.is_empty());
+        assert!(!state.env.contains_key("v"));
+    }
+
     #[test]
     fn function_return_from_function() {
         use codepod_shell::ast::Word;
@@ -5163,6 +6347,113 @@ mod tests {
         assert_eq!(stdout.trim(), "world");
     }
 
+    #[test]
+    fn local_ifs_restored_after_function() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        state.env.insert("IFS".to_string(), " ".to_string());
+
+        let script = r#"f() { local IFS=:; x="a:b"; for w in $x; do echo $w; done; }; f"#;
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "a\nb\n");
+        assert_eq!(state.env.get("IFS").map(String::as_str), Some(" "));
+    }
+
+    #[test]
+    fn local_ifs_unset_when_previously_unset() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (exit_code, _) = exec_capture(&mut state, &host, "f() { local IFS=,; }; f");
+        assert_eq!(exit_code, 0);
+        assert!(!state.env.contains_key("IFS"));
+    }
+
+    #[test]
+    fn quoted_star_joins_on_first_ifs_char() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let script = r#"set -- a b c; IFS=-; echo "$*"; IFS=:,; echo "$*"; IFS=; echo "$*""#;
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "a-b-c\na:b:c\nabc\n");
+    }
+
+    #[test]
+    fn quoted_at_keeps_positional_params_separate() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let script = r#"set -- 'a 1' b c; for w in "$@"; do echo "[$w]"; done"#;
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "[a 1]\n[b]\n[c]\n");
+
+        let script = r#"set -- 'a 1' b c; for w in x"$@"y; do echo "[$w]"; done"#;
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(stdout, "[xa 1]\n[b]\n[cy]\n");
+    }
+
+    #[test]
+    fn for_without_in_iterates_positional_params() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (_, stdout) = exec_capture(&mut state, &host, "set -- a b; for x; do echo $x; done");
+        assert_eq!(stdout, "a\nb\n");
+
+        let script = "f() { for arg do echo \"[$arg]\"; done; }; f 'one two' three; set --; for x; do echo never; done";
+        let (code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "[one two]\n[three]\n");
+    }
+
+    #[test]
+    fn positional_expansion_field_counts() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let script = r#"f() { echo $#; }; set -- 'a 1' b; f "$@"; f "$*"; f $@; f $*"#;
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(stdout, "2\n1\n3\n3\n");
+
+        // No positional parameters: "$@" vanishes, "$*" is one empty word.
+        let script = r#"f() { echo $#; }; set --; f "$@"; f "$*""#;
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(stdout, "0\n1\n");
+
+        // Unquoted, each parameter is split on IFS on its own; an empty
+        // IFS still keeps them as separate words.
+        let script = r#"f() { echo $#; }; set -- a-b c; IFS=-; f $*; IFS=; f $@ $*"#;
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(stdout, "3\n4\n");
+    }
+
+    #[test]
+    fn local_arrays_restored_after_function() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let script = r#"arr=(x y); declare -A m=([k]=v); f() { declare -a arr=(p q r); declare -A m=([other]=1); }; f; echo ${arr[@]} ${m[k]}"#;
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "x y v\n");
+        assert!(!state.assoc_arrays["m"].contains_key("other"));
+    }
+
+    #[test]
+    fn local_scalar_shadowing_array_restores_array() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let script = r#"arr=(x y); f() { local arr=flat; echo $arr; }; f; echo ${arr[@]}"#;
+        let (exit_code, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "flat\nx y\n");
+    }
+
     // ====================================================================
     // DoubleBracket tests
     // ====================================================================
@@ -5182,6 +6473,81 @@ mod tests {
         assert_eq!(run.exit_code, 0);
     }
 
+    #[test]
+    fn special_params_in_every_context() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (_, stdout) = exec_capture(&mut state, &host, "echo $$");
+        assert_eq!(stdout, format!("{}\n", state.pid));
+
+        let (_, stdout) = exec_capture(&mut state, &host, "set -- a b c; echo $# ${#} \"$#\"");
+        assert_eq!(stdout, "3 3 3\n");
+
+        let script =
+            "set -e; echo \"[$-]\"; f=$-; echo $f; [[ $- == e ]] && echo cond; echo $(( $# + 1 ))";
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(stdout, "[e]\ne\ncond\n4\n");
+    }
+
+    #[test]
+    fn underscore_is_last_argument_of_previous_command() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (_, stdout) = exec_capture(&mut state, &host, "echo $_");
+        assert_eq!(stdout, "codepod-shell\n");
+
+        let (_, stdout) = exec_capture(&mut state, &host, "echo a b c; echo $_");
+        assert_eq!(stdout, "a b c\nc\n");
+
+        let script = "set -- x \"y z\"; echo \"$@\" >/dev/null; echo \"${_}\"";
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(stdout, "y z\n");
+    }
+
+    #[test]
+    fn pid_is_stable_and_bashpid_changes_in_subshell() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (_, first) = exec_capture(&mut state, &host, "echo $$");
+        let (_, second) = exec_capture(&mut state, &host, "echo $$; echo $BASHPID");
+        assert_eq!(second, format!("{first}{first}"));
+
+        let (_, stdout) = exec_capture(&mut state, &host, "(echo $$; echo $BASHPID)");
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines[0], first.trim_end());
+        assert_ne!(lines[1], first.trim_end());
+
+        let (_, after) = exec_capture(&mut state, &host, "echo $BASHPID");
+        assert_eq!(after, first);
+        assert_ne!(ShellState::new_default().pid, state.pid);
+    }
+
+    #[test]
+    fn double_bracket_file_comparisons() {
+        let host = MockHost::new()
+            .with_file("/home/user/old.txt", b"")
+            .with_file("/home/user/new.txt", b"")
+            .with_mtime("/home/user/old.txt", 5)
+            .with_mtime("/home/user/new.txt", 10);
+        let mut state = ShellState::new_default();
+
+        let script = "[[ new.txt -nt old.txt ]] && echo nt; \
+                      [[ old.txt -ot new.txt ]] && echo ot; \
+                      [[ old.txt -ef ./old.txt ]] && echo ef-dot; \
+                      [[ old.txt -ef /home/user/old.txt ]] && echo ef; \
+                      [[ ../user/old.txt -ef old.txt ]] && echo ef-up; \
+                      [[ old.txt -ef new.txt ]] || echo ef-other; \
+                      [[ missing -nt old.txt ]] || echo missing-older";
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(
+            stdout,
+            "nt\not\nef-dot\nef\nef-up\nef-other\nmissing-older\n"
+        );
+    }
+
     #[test]
     fn double_bracket_string_ne() {
         let host = MockHost::new();
@@ -5302,6 +6668,63 @@ mod tests {
         assert_eq!(run.exit_code, 0);
     }
 
+    #[test]
+    fn double_bracket_angle_operators_compare_strings_not_numbers() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "[[ 10 < 9 ]] && echo lexical-lt\n\
+             [[ 10 -lt 9 ]] || echo numeric-ge\n\
+             [[ 2 > 10 ]] && echo lexical-gt\n\
+             [[ B < a ]] && echo byte-order\n\
+             [[ abc > abc ]] || echo not-gt",
+        );
+        assert_eq!(
+            out,
+            "lexical-lt\nnumeric-ge\nlexical-gt\nbyte-order\nnot-gt\n"
+        );
+        // `<` and `>` inside `[[ ]]` are operators, not redirections.
+        assert!(!host.stat("/home/user/9").unwrap().exists);
+        assert!(!host.stat("/home/user/10").unwrap().exists);
+    }
+
+    #[test]
+    fn double_bracket_regex_fills_bash_rematch() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "[[ abc123 =~ ([a-z]+)([0-9]+) ]]; echo ${BASH_REMATCH[1]}\n\
+             echo ${BASH_REMATCH[0]} ${BASH_REMATCH[2]} ${#BASH_REMATCH[@]}\n\
+             [[ xyz =~ ^[0-9]+$ ]] || echo no-match ${#BASH_REMATCH[@]}",
+        );
+        assert_eq!(out, "abc\nabc123 123 3\nno-match 0\n");
+    }
+
+    #[test]
+    fn double_bracket_quoted_pattern_compares_literally() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "[[ axb == \"a*b\" ]] || echo quoted-literal\n\
+             [[ axb == a*b ]] && echo unquoted-glob\n\
+             [[ 'a*b' == 'a*b' ]] && echo same-text\n\
+             [[ axb != \"a*b\" ]] && echo quoted-ne\n\
+             p='a?'\n\
+             [[ ab == \"$p\" ]] || echo quoted-var\n\
+             [[ ab == $p ]] && echo unquoted-var",
+        );
+        assert_eq!(
+            out,
+            "quoted-literal\nunquoted-glob\nsame-text\nquoted-ne\nquoted-var\nunquoted-var\n"
+        );
+    }
+
     #[test]
     fn double_bracket_unary_z_empty() {
         let host = MockHost::new();
@@ -5579,6 +7002,18 @@ mod tests {
         assert_eq!(run.exit_code, 1);
     }
 
+    #[test]
+    fn arithmetic_cmd_exponentiation() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "(( x = 2 ** 10 )); (( y = 2 ** 3 ** 2 )); echo $x $y $(( 2 ** -1 ))",
+        );
+        assert_eq!(stdout, "1024 512 0\n");
+    }
+
     // ====================================================================
     // Assignment handling tests
     // ====================================================================
@@ -5611,76 +7046,294 @@ mod tests {
     }
 
     #[test]
-    fn assignment_with_command() {
-        // FOO=bar echo test — env set before command execution
-        let host = MockHost::new().with_spawn_result(
-            "echo",
-            MockSpawnOutput {
-                exit_code: 0,
-                stdout: "test\n".into(),
-                stderr: String::new(),
-            },
+    fn assignment_with_command() {
+        // FOO=bar echo test — env set before command execution
+        let host = MockHost::new().with_spawn_result(
+            "echo",
+            MockSpawnOutput {
+                exit_code: 0,
+                stdout: "test\n".into(),
+                stderr: String::new(),
+            },
+        );
+        let mut state = ShellState::new_default();
+        let cmd = Command::Simple {
+            words: vec![Word::literal("echo"), Word::literal("test")],
+            redirects: vec![],
+            assignments: vec![assignment("FOO", "bar")],
+        };
+        let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "test\n");
+        // Assignment should be set
+        assert_eq!(state.env.get("FOO"), Some(&"bar".to_string()));
+    }
+
+    #[test]
+    fn assignment_append_string() {
+        // FOO=hello; FOO+=world
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        state.env.insert("FOO".to_string(), "hello".to_string());
+        let cmd = Command::Simple {
+            words: vec![],
+            redirects: vec![],
+            assignments: vec![assignment("FOO+", "world")],
+        };
+        let result = exec_command(&mut state, &host, &cmd);
+        assert!(result.is_ok());
+        assert_eq!(state.env.get("FOO"), Some(&"helloworld".to_string()));
+    }
+
+    #[test]
+    fn assignment_append_string_from_empty() {
+        // FOO+=bar when FOO is unset
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let cmd = Command::Simple {
+            words: vec![],
+            redirects: vec![],
+            assignments: vec![assignment("FOO+", "bar")],
+        };
+        let result = exec_command(&mut state, &host, &cmd);
+        assert!(result.is_ok());
+        assert_eq!(state.env.get("FOO"), Some(&"bar".to_string()));
+    }
+
+    #[test]
+    fn assignment_array_literal() {
+        // arr=(a b c)
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let cmd = Command::Simple {
+            words: vec![],
+            redirects: vec![],
+            assignments: vec![assignment("arr", "(a b c)")],
+        };
+        let result = exec_command(&mut state, &host, &cmd);
+        assert!(result.is_ok());
+        assert_eq!(
+            state.arrays["arr"].to_vec(),
+            vec!["a".to_string(), "b".to_string(), "c".to_string()]
+        );
+    }
+
+    #[test]
+    fn quoted_command_substitution_is_one_word() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "for x in \"$(printf 'a b')\"; do echo [$x]; done\n\
+             for x in $(printf 'c d'); do echo [$x]; done\n\
+             for x in \"`printf 'e\\nf'`\"; do echo \"[$x]\"; done",
+        );
+        assert_eq!(stdout, "[a b]\n[c]\n[d]\n[e\nf]\n");
+
+        exec_capture(
+            &mut state,
+            &host,
+            "v='x y'; arr=(\"$(printf 'a b')\" $(printf 'c d') 'e f' \"$v\" $v); \
+             arr+=(\"$(printf 'g h')\")",
+        );
+        assert_eq!(
+            state.arrays["arr"].to_vec(),
+            ["a b", "c", "d", "e f", "x y", "x", "y", "g h"]
+        );
+    }
+
+    #[test]
+    fn param_operators_apply_to_array_elements() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "declare -A m; m[x]=hello; a=(prefix-one two)\n\
+             echo ${m[x]^^} ${m[x]:1:2} ${m[x]#he} ${m[x]%lo} ${m[x]/l/L} ${m[x]^}\n\
+             echo ${a[0]#prefix-} ${a[1]:1} ${a[-1],,} ${a[1]@Q} [${m[nope]:-unset}]",
+        );
+        assert_eq!(
+            out,
+            "HELLO el llo hel heLlo Hello\none wo two \"two\" [unset]\n"
+        );
+    }
+
+    #[test]
+    fn assoc_element_assignment_needs_declare() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (code, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "declare -A mymap; mymap[greeting]=hi; mymap[1]=one; echo ${mymap[greeting]} ${mymap[1]}",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "hi one\n");
+
+        // Without `declare -A` the subscript is arithmetic: `greeting` is 0.
+        let (code, _) = exec_capture(&mut state, &host, "other[greeting]=hi");
+        assert_eq!(code, 0);
+        assert_eq!(state.arrays["other"].to_vec(), ["hi"]);
+        assert!(!state.assoc_arrays.contains_key("other"));
+    }
+
+    #[test]
+    fn unset_array_element_leaves_a_hole() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "arr=(a b c d); unset 'arr[2]'; echo ${#arr[@]} ${arr[@]} ${!arr[@]} [${arr[2]}] ${arr[3]}\n\
+             arr+=(e); echo ${!arr[@]} ${arr[-1]}\n\
+             declare -A map; map[k]=v; map[j]=w; unset 'map[k]'; echo ${#map[@]} ${map[j]}\n\
+             x=5; unset 'x[0]'; echo ${x:-unset}; y=5; unset 'y[1]'; echo $y",
+        );
+        assert_eq!(stdout, "3 a b d 0 1 3 [] d\n0 1 3 4 e\n1 w\nunset\n5\n");
+    }
+
+    #[test]
+    fn integer_attribute_makes_append_numeric() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "declare -i n=5; n+=3; echo $n\n\
+             s=5; s+=3; echo $s\n\
+             n=2*4; n+=n; echo $n",
+        );
+        assert_eq!(stdout, "8\n53\n16\n");
+    }
+
+    #[test]
+    fn copied_and_moved_executables_stay_executable() {
+        let host = MockHost::new()
+            .with_file("/home/user/s.sh", b"echo hi\n")
+            .with_mode("/home/user/s.sh", 0o755);
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "cp s.sh c.sh && [[ -x c.sh ]] && echo copied; \
+             mv c.sh m.sh && [[ -x m.sh ]] && [[ ! -e c.sh ]] && echo moved",
+        );
+        assert_eq!(out, "copied\nmoved\n");
+    }
+
+    #[test]
+    fn chmod_plus_x_makes_file_executable() {
+        let host = MockHost::new().with_file("/home/user/s.sh", b"echo hi\n");
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "[[ -x s.sh ]] || [ -x s.sh ] || echo no\n\
+             chmod +x s.sh && [[ -x s.sh ]] && [ -x s.sh ] && echo yes",
         );
+        assert_eq!(stdout, "no\nyes\n");
+    }
+
+    #[test]
+    fn bang_expansions_are_distinct() {
+        let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = Command::Simple {
-            words: vec![Word::literal("echo"), Word::literal("test")],
-            redirects: vec![],
-            assignments: vec![assignment("FOO", "bar")],
-        };
-        let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
-        assert_eq!(exit_code, 0);
-        assert_eq!(stdout, "test\n");
-        // Assignment should be set
-        assert_eq!(state.env.get("FOO"), Some(&"bar".to_string()));
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "ref=target; target=hit; echo ${!ref}\n\
+             arr=(a '' c); echo ${!arr[@]}\n\
+             declare -A m=([k1]=x [k2]=y); echo ${!m[*]}\n\
+             my_a=1; my_b=2; my_list=(z); echo ${!my_*}\n\
+             echo \"${!my_@}\"",
+        );
+        assert_eq!(
+            stdout,
+            "hit\n0 1 2\nk1 k2\nmy_a my_b my_list\nmy_a my_b my_list\n"
+        );
     }
 
     #[test]
-    fn assignment_append_string() {
-        // FOO=hello; FOO+=world
+    fn case_extglob_and_fallthrough_terminators() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        state.env.insert("FOO".to_string(), "hello".to_string());
-        let cmd = Command::Simple {
-            words: vec![],
-            redirects: vec![],
-            assignments: vec![assignment("FOO+", "world")],
-        };
-        let result = exec_command(&mut state, &host, &cmd);
-        assert!(result.is_ok());
-        assert_eq!(state.env.get("FOO"), Some(&"helloworld".to_string()));
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "case foobar in @(foo)*) echo no-extglob;; *) echo plain;; esac\n\
+             shopt -s extglob\n\
+             case foobar in @(foo)*) echo hit;; esac\n\
+             case ab in +(a|b)) echo one;;& a*) echo two;;& x) echo three;; esac\n\
+             case x in x) echo first;& y) echo second;; z) echo third;; esac",
+        );
+        assert_eq!(stdout, "plain\nhit\none\ntwo\nfirst\nsecond\n");
     }
 
     #[test]
-    fn assignment_append_string_from_empty() {
-        // FOO+=bar when FOO is unset
+    fn printf_writes_nul_and_raw_bytes_to_files() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = Command::Simple {
-            words: vec![],
-            redirects: vec![],
-            assignments: vec![assignment("FOO+", "bar")],
-        };
-        let result = exec_command(&mut state, &host, &cmd);
-        assert!(result.is_ok());
-        assert_eq!(state.env.get("FOO"), Some(&"bar".to_string()));
+        let (code, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "printf 'a\\x00b\\0c' > nul.bin; printf '\\xff\\376' >> nul.bin\n\
+             echo -e 'x\\0y\\x80' > echo.bin; printf '%b' '\\0001\\u00e9' > b.bin",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "");
+        assert_eq!(
+            host.read_file("/home/user/nul.bin").unwrap(),
+            b"a\0b\0c\xff\xfe"
+        );
+        assert_eq!(
+            host.read_file("/home/user/echo.bin").unwrap(),
+            b"x\0y\x80\n"
+        );
+        assert_eq!(
+            host.read_file("/home/user/b.bin").unwrap(),
+            "\u{1}é".as_bytes()
+        );
     }
 
     #[test]
-    fn assignment_array_literal() {
-        // arr=(a b c)
+    fn printf_keeps_private_use_text_intact() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = Command::Simple {
-            words: vec![],
-            redirects: vec![],
-            assignments: vec![assignment("arr", "(a b c)")],
-        };
-        let result = exec_command(&mut state, &host, &cmd);
-        assert!(result.is_ok());
+        let (code, _) = exec_capture(
+            &mut state,
+            &host,
+            "printf '\\uef80' > u.bin; x='\\uefff'; printf '%b' \"$x\" > b.bin\n\
+             echo -e '\\uef90\\x80' > e.bin",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(
+            host.read_file("/home/user/u.bin").unwrap(),
+            "\u{ef80}".as_bytes()
+        );
+        assert_eq!(
+            host.read_file("/home/user/b.bin").unwrap(),
+            "\u{efff}".as_bytes()
+        );
         assert_eq!(
-            state.arrays.get("arr"),
-            Some(&vec!["a".to_string(), "b".to_string(), "c".to_string()])
+            host.read_file("/home/user/e.bin").unwrap(),
+            b"\xee\xbe\x90\x80\n"
+        );
+    }
+
+    #[test]
+    fn array_literal_from_command_output() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, stdout) = exec_capture(
+            &mut state,
+            &host,
+            "arr=( $(printf 'a b c') ); echo ${#arr[@]}\n\
+             IFS=:; arr=( $(printf 'x:y') ); echo ${#arr[@]} ${arr[1]}\n\
+             unset IFS; arr=(\n  $(printf 'p q')\n  r\n); echo ${#arr[@]}",
         );
+        assert_eq!(stdout, "3\n2 y\n3\n");
     }
 
     #[test]
@@ -5695,12 +7348,12 @@ mod tests {
         };
         let result = exec_command(&mut state, &host, &cmd);
         assert!(result.is_ok());
-        assert_eq!(state.arrays.get("arr"), Some(&vec![]));
+        assert!(state.arrays["arr"].is_empty());
     }
 
     #[test]
     fn assignment_array_element() {
-        // arr[2]=x — sets arr[2], extending with empty strings
+        // arr[2]=x — sets arr[2] only; indices 0 and 1 stay unset
         let host = MockHost::new();
         let mut state = ShellState::new_default();
         let cmd = Command::Simple {
@@ -5710,11 +7363,11 @@ mod tests {
         };
         let result = exec_command(&mut state, &host, &cmd);
         assert!(result.is_ok());
-        let arr = state.arrays.get("arr").unwrap();
-        assert_eq!(arr.len(), 3);
-        assert_eq!(arr[0], "");
-        assert_eq!(arr[1], "");
-        assert_eq!(arr[2], "x");
+        let arr = &state.arrays["arr"];
+        assert_eq!(arr.len(), 1);
+        assert_eq!(arr.get(0), None);
+        assert_eq!(arr.get(1), None);
+        assert_eq!(arr.get(2).unwrap(), "x");
     }
 
     #[test]
@@ -5724,7 +7377,7 @@ mod tests {
         let mut state = ShellState::new_default();
         state.arrays.insert(
             "arr".to_string(),
-            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            vec!["a".to_string(), "b".to_string(), "c".to_string()].into(),
         );
         let cmd = Command::Simple {
             words: vec![],
@@ -5733,11 +7386,7 @@ mod tests {
         };
         let result = exec_command(&mut state, &host, &cmd);
         assert!(result.is_ok());
-        let arr = state.arrays.get("arr").unwrap();
-        assert_eq!(
-            arr,
-            &vec!["a".to_string(), "X".to_string(), "c".to_string()]
-        );
+        assert_eq!(state.arrays["arr"].to_vec(), ["a", "X", "c"]);
     }
 
     #[test]
@@ -5764,9 +7413,10 @@ mod tests {
         // arr=(a b); arr+=(c d)
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        state
-            .arrays
-            .insert("arr".to_string(), vec!["a".to_string(), "b".to_string()]);
+        state.arrays.insert(
+            "arr".to_string(),
+            vec!["a".to_string(), "b".to_string()].into(),
+        );
         let cmd = Command::Simple {
             words: vec![],
             redirects: vec![],
@@ -5774,15 +7424,7 @@ mod tests {
         };
         let result = exec_command(&mut state, &host, &cmd);
         assert!(result.is_ok());
-        assert_eq!(
-            state.arrays.get("arr"),
-            Some(&vec![
-                "a".to_string(),
-                "b".to_string(),
-                "c".to_string(),
-                "d".to_string()
-            ])
-        );
+        assert_eq!(state.arrays["arr"].to_vec(), ["a", "b", "c", "d"]);
     }
 
     #[test]
@@ -5798,8 +7440,8 @@ mod tests {
         let result = exec_command(&mut state, &host, &cmd);
         assert!(result.is_ok());
         assert_eq!(
-            state.arrays.get("arr"),
-            Some(&vec!["x".to_string(), "y".to_string()])
+            state.arrays["arr"].to_vec(),
+            vec!["x".to_string(), "y".to_string()]
         );
     }
 
@@ -6150,6 +7792,31 @@ mod tests {
         assert_eq!(resolved[1], "/home/user/file.txt"); // file resolved
     }
 
+    #[test]
+    fn resolve_command_args_stops_after_double_dash() {
+        let state = ShellState::new_default();
+        let host = MockHost::new().with_file("/home/user/file.txt", b"content");
+        let args = vec!["--", "-pattern", "file.txt"];
+        let resolved = resolve_command_args(&state, &host, "grep", &args);
+        assert_eq!(resolved, vec!["--", "-pattern", "file.txt"]);
+
+        let args = vec!["file.txt", "--", "file.txt"];
+        let resolved = resolve_command_args(&state, &host, "cat", &args);
+        assert_eq!(resolved, vec!["/home/user/file.txt", "--", "file.txt"]);
+    }
+
+    #[test]
+    fn resolve_command_args_leaves_option_values_alone() {
+        let state = ShellState::new_default();
+        let host = MockHost::new().with_file("/home/user/foo.txt", b"content");
+        let args = vec!["--out=foo.txt", "-xvf", "foo.txt"];
+        let resolved = resolve_command_args(&state, &host, "cmd", &args);
+        assert_eq!(
+            resolved,
+            vec!["--out=foo.txt", "-xvf", "/home/user/foo.txt"]
+        );
+    }
+
     // ---- implicit cwd commands (integration) ----
 
     #[test]
@@ -6160,7 +7827,7 @@ mod tests {
             stderr: String::new(),
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("ls -la");
+        let cmd = parse("ls -la");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(_run) = result.unwrap() else {
             panic!("expected Normal")
@@ -6181,7 +7848,7 @@ mod tests {
             stderr: String::new(),
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("ls /tmp");
+        let cmd = parse("ls /tmp");
         let _ = exec_command(&mut state, &host, &cmd);
         let calls = host.get_spawn_calls();
         assert_eq!(calls.len(), 1);
@@ -6214,7 +7881,7 @@ mod tests {
                 }
             });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("./script.py");
+        let cmd = parse("./script.py");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -6237,7 +7904,7 @@ mod tests {
                 stderr: String::new(),
             });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("./test.sh");
+        let cmd = parse("./test.sh");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         // Shell script is executed by parsing and running through our executor
         // echo is a builtin, so it should produce output
@@ -6248,7 +7915,7 @@ mod tests {
     fn exec_path_script_no_shebang() {
         let host = MockHost::new().with_file("/home/user/run.sh", b"echo no_shebang\n");
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("./run.sh");
+        let cmd = parse("./run.sh");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         // Scripts without shebang default to shell execution
         assert_eq!(stdout, "no_shebang\n");
@@ -6258,7 +7925,7 @@ mod tests {
     fn exec_path_nonexistent_file() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("./nonexistent.sh");
+        let cmd = parse("./nonexistent.sh");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -6286,7 +7953,7 @@ mod tests {
                 }
             });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("/tmp/run.py arg1 arg2");
+        let cmd = parse("/tmp/run.py arg1 arg2");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -6305,7 +7972,7 @@ mod tests {
     fn sh_minus_c_executes_command() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("sh -c 'echo hello'");
+        let cmd = parse("sh -c 'echo hello'");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "hello\n");
     }
@@ -6314,7 +7981,7 @@ mod tests {
     fn bash_minus_c_executes_command() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("bash -c 'echo world'");
+        let cmd = parse("bash -c 'echo world'");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "world\n");
     }
@@ -6324,7 +7991,7 @@ mod tests {
         let host =
             MockHost::new().with_file("/home/user/test.sh", b"#!/bin/sh\necho from_script\n");
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("sh test.sh");
+        let cmd = parse("sh test.sh");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "from_script\n");
     }
@@ -6333,7 +8000,7 @@ mod tests {
     fn bare_sh_succeeds() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("sh");
+        let cmd = parse("sh");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -6345,7 +8012,7 @@ mod tests {
     fn bare_bash_succeeds() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("bash");
+        let cmd = parse("bash");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -6359,7 +8026,7 @@ mod tests {
     fn shell_script_positional_params() {
         let host = MockHost::new().with_file("/home/user/params.sh", b"echo $1 $2\n");
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("sh params.sh hello world");
+        let cmd = parse("sh params.sh hello world");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "hello world\n");
     }
@@ -6374,7 +8041,7 @@ mod tests {
             stderr: String::new(),
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("echo input | sh -c 'echo piped'");
+        let cmd = parse("echo input | sh -c 'echo piped'");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "piped\n");
     }
@@ -6391,7 +8058,7 @@ mod tests {
         let mut state = ShellState::new_default();
         // cat data.txt | grep pattern
         // In the pipeline, cat's arg "data.txt" should be resolved
-        let cmd = codepod_shell::parser::parse("cat data.txt | grep pattern");
+        let cmd = parse("cat data.txt | grep pattern");
         let _ = exec_command(&mut state, &host, &cmd);
         let calls = host.get_spawn_calls();
         // cat should have its arg resolved
@@ -6409,7 +8076,7 @@ mod tests {
             stderr: String::new(),
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("mkdir -p newdir");
+        let cmd = parse("mkdir -p newdir");
         let _ = exec_command(&mut state, &host, &cmd);
         let calls = host.get_spawn_calls();
         assert_eq!(calls.len(), 1);
@@ -6426,7 +8093,7 @@ mod tests {
             stderr: String::new(),
         });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("touch newfile.txt");
+        let cmd = parse("touch newfile.txt");
         let _ = exec_command(&mut state, &host, &cmd);
         let calls = host.get_spawn_calls();
         assert!(calls[0]
@@ -6456,7 +8123,7 @@ mod tests {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
         state.env.insert("FOO".into(), "bar".into());
-        let cmd = codepod_shell::parser::parse("sh -c 'echo hello; echo world'");
+        let cmd = parse("sh -c 'echo hello; echo world'");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "hello\nworld\n");
     }
@@ -6466,18 +8133,69 @@ mod tests {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
         state.env.insert("NAME".into(), "world".into());
-        let cmd = codepod_shell::parser::parse("sh -c 'echo $NAME'");
+        let cmd = parse("sh -c 'echo $NAME'");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "world\n");
     }
 
+    // ---- syntax errors ----
+
+    #[test]
+    fn unterminated_if_is_a_syntax_error() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let result = exec_str(&mut state, &host, "if true; then echo x");
+        assert!(matches!(result, Ok(ControlFlow::Normal(ref r)) if r.exit_code == 2));
+        assert_eq!(state.last_exit_code, 2);
+    }
+
+    #[test]
+    fn malformed_sh_c_and_eval_run_nothing() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (exit_code, stdout) = exec_capture(&mut state, &host, "sh -c 'echo x; fi'");
+        assert_eq!(exit_code, 2);
+        assert_eq!(stdout, "");
+
+        let (_, stdout) = exec_capture(&mut state, &host, r#"eval 'echo "x'; echo $?"#);
+        assert_eq!(stdout, "2\n");
+    }
+
+    #[test]
+    fn reserved_words_in_argument_position_are_plain_words() {
+        let host = MockHost::new()
+            .with_tool("grep")
+            .with_spawn_handler(|program, args, _stdin| MockSpawnOutput {
+                exit_code: 0,
+                stdout: format!("{program} {}\n", args.join(" ")),
+                stderr: String::new(),
+            });
+        let mut state = ShellState::new_default();
+        for (script, expected) in [
+            ("echo start; echo done", "start\ndone\n"),
+            ("echo in", "in\n"),
+            ("echo then", "then\n"),
+            (
+                "grep -w if file.txt; echo rc=$?",
+                "grep -w if /home/user/file.txt\nrc=0\n",
+            ),
+            ("x=1; echo ok; echo esac", "ok\nesac\n"),
+            ("for w in if then do; do echo $w; done", "if\nthen\ndo\n"),
+            ("case in in in) echo case;; esac", "case\n"),
+            ("for i in 1; do if true; then echo $i; fi done", "1\n"),
+        ] {
+            let (code, stdout) = exec_capture(&mut state, &host, script);
+            assert_eq!((code, stdout.as_str()), (0, expected), "{script}");
+        }
+    }
+
     // ---- exec_path with arguments ----
 
     #[test]
     fn exec_path_shell_script_with_args() {
         let host = MockHost::new().with_file("/home/user/greet.sh", b"#!/bin/sh\necho Hello $1\n");
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("./greet.sh World");
+        let cmd = parse("./greet.sh World");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "Hello World\n");
     }
@@ -6497,7 +8215,7 @@ mod tests {
                 stderr: String::new(),
             });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("./script.py");
+        let cmd = parse("./script.py");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(_run) = result.unwrap() else {
             panic!("expected Normal")
@@ -6518,7 +8236,7 @@ mod tests {
         });
         let mut state = ShellState::new_default();
         // find with no args should append cwd
-        let cmd = codepod_shell::parser::parse("find");
+        let cmd = parse("find");
         let _ = exec_command(&mut state, &host, &cmd);
         let calls = host.get_spawn_calls();
         assert_eq!(calls[0].program, "find");
@@ -6539,7 +8257,7 @@ mod tests {
                 stderr: String::new(),
             });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("grep error log.txt");
+        let cmd = parse("grep error log.txt");
         let _ = exec_command(&mut state, &host, &cmd);
         let calls = host.get_spawn_calls();
         assert_eq!(calls[0].program, "grep");
@@ -6555,7 +8273,7 @@ mod tests {
     fn sh_c_with_output_redirect() {
         let host = MockHost::new();
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("sh -c 'echo redirected' > /tmp/out.txt");
+        let cmd = parse("sh -c 'echo redirected' > /tmp/out.txt");
         let result = exec_command(&mut state, &host, &cmd);
         let ControlFlow::Normal(run) = result.unwrap() else {
             panic!("expected Normal")
@@ -6595,7 +8313,7 @@ mod tests {
                 stderr: String::new(),
             });
         let mut state = ShellState::new_default();
-        let cmd = codepod_shell::parser::parse("echo input | ./count.sh");
+        let cmd = parse("echo input | ./count.sh");
         let (exit_code, stdout) = exec_capture_cmd(&mut state, &host, &cmd);
         assert_eq!(stdout, "counted\n");
     }
@@ -6636,6 +8354,254 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn sh_c_flag_changes_do_not_leak_to_parent() {
+        use crate::state::ShellFlag;
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (code, _) = exec_capture(&mut state, &host, "sh -c 'set -e; false'");
+        assert_eq!(code, 1);
+        assert!(state.flags.is_empty());
+        let (code, out) = exec_capture(&mut state, &host, "false; echo after");
+        assert_eq!(code, 0);
+        assert_eq!(out, "after\n");
+
+        exec_capture(&mut state, &host, "set -o pipefail");
+        exec_capture(&mut state, &host, "sh -c 'set -u; set +o pipefail'");
+        assert!(state.flags.contains(&ShellFlag::Pipefail));
+        assert!(!state.flags.contains(&ShellFlag::Nounset));
+    }
+
+    #[test]
+    fn sh_c_runs_its_own_exit_trap_and_keeps_parent_traps() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        exec_capture(&mut state, &host, "trap 'echo parent' EXIT");
+
+        let (code, out) = exec_capture(
+            &mut state,
+            &host,
+            "sh -c 'trap \"echo child\" EXIT; exit 3'; echo $?",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(out, "child\n3\n");
+        assert_eq!(state.traps.get("EXIT").unwrap(), "echo parent");
+    }
+
+    #[test]
+    fn getopts_reparses_after_optind_reset() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (code, out) = exec_capture(
+            &mut state,
+            &host,
+            "parse() { while getopts ab: opt; do echo \"$opt $OPTARG\"; done; echo \"ind $OPTIND\"; }\n\
+             echo \"start $OPTIND\"\n\
+             parse -a -b x rest\n\
+             OPTIND=1\n\
+             parse -b y -- -a\n\
+             parse -a",
+        );
+        assert_eq!(code, 0);
+        // Without a reset the last call finds nothing left to parse.
+        assert_eq!(out, "start 1\na \nb x\nind 4\nb y\nind 4\nind 4\n");
+    }
+
+    #[test]
+    fn posix_mode_rejects_function_keyword() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "set -o posix; eval 'function f { echo hi; }'; echo $?; eval 'g() { echo ok; }'; g",
+        );
+        assert_eq!(out, "2\nok\n");
+        assert!(!state.functions.contains_key("f"));
+    }
+
+    #[test]
+    fn array_assignment_subscript_is_arithmetic() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "i=2; arr[i+1]=x; arr[i++]=y; arr[$((i*2))]=z; arr[-1]=w; \
+             echo \"${arr[2]} ${arr[3]} ${arr[6]} $i\"",
+        );
+        assert_eq!(out, "y x w 3\n");
+        assert_eq!(state.arrays["arr"].to_vec(), ["y", "x", "w"]);
+        assert_eq!(state.arrays["arr"].indices().collect::<Vec<_>>(), [2, 3, 6]);
+    }
+
+    #[test]
+    fn if_status_comes_from_branch_not_and_or_condition() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "f3() { return 3; }; \
+             if true && false; then echo t; else echo f; f3; fi; echo $?; \
+             if false || true; then echo t; fi; echo $?; \
+             if ! true || false; then :; else f3; fi; echo $?; \
+             if false; then :; elif true && f3; then :; else echo e; fi; echo $?",
+        );
+        assert_eq!(out, "f\n3\nt\n0\n3\ne\n0\n");
+
+        // A failing && condition neither trips errexit nor leaks its status.
+        let (code, out) = exec_capture(
+            &mut state,
+            &host,
+            "set -e; if true && false; then echo t; fi; echo ok",
+        );
+        assert_eq!((code, out.as_str()), (0, "ok\n"));
+        let (code, _) = exec_capture(&mut state, &host, "set +e; if true && false; then :; fi");
+        assert_eq!((code, state.last_exit_code), (0, 0));
+    }
+
+    #[test]
+    fn function_bodies_may_be_any_compound_command() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "function a { echo a; }\n\
+             function b() { echo b; }\n\
+             c() (x=c; echo $x)\n\
+             d() if [ \"$1\" = y ]; then echo d; fi\n\
+             e() for i in 1 2; do echo e$i; done\n\
+             a; b; c; d y; d n; e; echo \"[$x]\"",
+        );
+        assert_eq!(out, "a\nb\nc\nd\ne1\ne2\n[]\n");
+    }
+
+    #[test]
+    fn exit_status_survives_exit_trap_unless_trap_exits() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "sh -c 'trap \"echo bye \\$?\" EXIT; exit 3'; echo $?",
+        );
+        assert_eq!(out, "bye 3\n3\n");
+
+        let script = "sh -c 'trap \"exit 9\" EXIT; exit 3'; echo $?";
+        let (_, out) = exec_capture(&mut state, &host, script);
+        assert_eq!(out, "9\n");
+
+        let script = "sh -c 'exit 258'; echo $?; sh -c 'exit -1'; echo $?";
+        let (_, out) = exec_capture(&mut state, &host, script);
+        assert_eq!(out, "2\n255\n");
+    }
+
+    #[test]
+    fn debug_trap_runs_before_each_simple_command() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (code, out) = exec_capture(
+            &mut state,
+            &host,
+            "trap 'log=\"$log|$BASH_COMMAND\"' DEBUG\n\
+             x=1\n\
+             echo a $x\n\
+             false\n\
+             echo status $?\n\
+             trap - DEBUG\n\
+             echo after",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(out, "a 1\nstatus 1\nafter\n");
+        assert_eq!(
+            state.env.get("log").unwrap(),
+            "|echo a ${x}|false|echo status ${?}|trap - DEBUG"
+        );
+        assert!(!state.in_debug_trap);
+    }
+
+    #[test]
+    fn err_trap_runs_on_failures_outside_conditions() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "trap 'echo \"failed: $?\"' ERR\n\
+             true\n\
+             echo ok\n\
+             if false; then :; fi\n\
+             while false; do :; done\n\
+             false || true\n\
+             false && true\n\
+             ! false\n\
+             echo conditions\n\
+             true && false\n\
+             echo pipe | false\n\
+             f() { false; return 3; }\n\
+             f\n\
+             echo last $?",
+        );
+        assert_eq!(
+            out,
+            "ok\nconditions\nfailed: 1\nfailed: 1\nfailed: 3\nlast 3\n"
+        );
+        assert_eq!(state.condition_depth, 0);
+    }
+
+    #[test]
+    fn source_searches_path_and_propagates_return() {
+        let host = MockHost::new()
+            .with_file("/opt/lib/helpers.sh", b"greeting=hello\nfalse\n")
+            .with_file("/home/user/early.sh", b"echo before\nreturn 3\n");
+        let mut state = ShellState::new_default();
+        state.env.insert("PATH".into(), "/usr/bin:/opt/lib".into());
+
+        let (code, out) = exec_capture(&mut state, &host, "source helpers.sh; echo $? $greeting");
+        assert_eq!(code, 0);
+        assert_eq!(out, "1 hello\n");
+
+        let (_, out) = exec_capture(&mut state, &host, ". ./early.sh; echo status $?");
+        assert_eq!(out, "before\nstatus 3\n");
+
+        let (_, out) = exec_capture(&mut state, &host, "source missing.sh; echo status $?");
+        assert_eq!(out, "status 1\n");
+    }
+
+    #[test]
+    fn source_and_function_calls_scope_positionals_and_locals() {
+        let host = MockHost::new().with_file(
+            "/home/user/lib.sh",
+            b"echo \"lib $# $1 $v\"\nv=sourced\nshow() { local w=inner; echo \"show $# $1 $w\"; }\n",
+        );
+        let mut state = ShellState::new_default();
+
+        // Sourcing with arguments inside a function: the script sees its own
+        // arguments and the function's locals, and both are undone in turn.
+        let (_, out) = exec_capture(
+            &mut state,
+            &host,
+            "set -- p q\n\
+             f() { local v=loc; source ./lib.sh x y z; echo \"f $# $1 $v\"; show s; echo \"f $# $1 [$w]\"; }\n\
+             f a; echo \"top $# $1 [$v]\"",
+        );
+        assert_eq!(
+            out,
+            "lib 3 x loc\nf 1 a sourced\nshow 1 s inner\nf 1 a []\ntop 2 p []\n"
+        );
+
+        // Without arguments the script shares the caller's positionals.
+        let (_, out) = exec_capture(&mut state, &host, "source ./lib.sh; echo \"top $# $1 $v\"");
+        assert_eq!(out, "lib 2 p \ntop 2 p sourced\n");
+        assert_eq!(state.positional_args, vec!["p", "q"]);
+        assert!(state.local_var_stack.is_empty());
+    }
+
     #[test]
     fn dispatch_external_normal_command_returns_resolved_args() {
         let host = MockHost::new().with_file("/home/user/file.txt", b"data");
@@ -6684,6 +8650,37 @@ mod tests {
         assert_eq!(state.jobs.len(), 1);
     }
 
+    #[test]
+    fn background_job_status_only_reported_by_wait() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let script = "true; false & echo $?; wait %1; echo $?; false; true & echo $?";
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(stdout, "0\n1\n1\n");
+        assert_eq!(state.jobs.len(), 1);
+    }
+
+    #[test]
+    fn wait_n_consumes_jobs_in_launch_order() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let script = "f() { return 3; }; g() { return 5; }; f & g & \
+                      wait -n; echo $?; wait -n; echo $?; wait -n; echo $?";
+        let (_, stdout) = exec_capture(&mut state, &host, script);
+        assert_eq!(stdout, "3\n5\n127\n");
+        assert!(state.jobs.is_empty());
+    }
+
+    #[test]
+    fn wait_without_args_returns_zero_and_clears_jobs() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (code, stdout) = exec_capture(&mut state, &host, "false & false & wait; echo $?");
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "0\n");
+        assert!(state.jobs.is_empty());
+    }
+
     // -- alias expansion tests --------------------------------------------
 
     #[test]
@@ -6792,10 +8789,41 @@ mod tests {
         // "world" in second position should NOT be expanded
         assert_eq!(stdout, "world\n");
     }
-}
-
-
-
-
 
+    #[test]
+    fn readonly_variable_rejects_reassignment() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let (code, out) = exec_capture(
+            &mut state,
+            &host,
+            "readonly X=1; X=2; echo $? $X; unset X; echo $? $X; X=3 true; echo $? $X",
+        );
+        assert_eq!(code, 0);
+        assert_eq!(out, "1 1\n1 1\n1 1\n");
+    }
 
+    #[test]
+    fn declare_p_output_reparses() {
+        let host = MockHost::new();
+        let mut state = ShellState::new_default();
+        let setup = r#"s='a "b" $c \d'
+declare -a arr=(x "y z" '$q')
+declare -A map=([k]="v 1" ["two words"]=2)
+f() { if [ -n "$1" ]; then echo "$1"; fi; }"#;
+        exec_capture(&mut state, &host, setup);
+        let dump = "declare -p s arr map; declare -f f";
+        let (_, first) = exec_capture(&mut state, &host, dump);
+        assert!(first.starts_with("declare -- s=\"a \\\"b\\\" \\$c \\\\d\"\n"));
+
+        let mut fresh = ShellState::new_default();
+        exec_capture(&mut fresh, &host, &first);
+        let (_, second) = exec_capture(&mut fresh, &host, dump);
+        assert_eq!(second, first);
+        assert_eq!(exec_capture(&mut fresh, &host, "f hello").1, "hello\n");
+        assert_eq!(
+            exec_capture(&mut fresh, &host, "echo \"${s@Q}\"").1,
+            "\"a \\\"b\\\" \\$c \\\\d\"\n"
+        );
+    }
+}
@@ -26,6 +26,30 @@ pub fn expand_word(state: &mut ShellState, word: &Word, exec: Option<ExecFn>) ->
         .collect()
 }
 
+/// Expand a `case` pattern word.
+///
+/// Glob metacharacters written bare in the pattern stay active. Quoted
+/// text is protected by sentinels so it matches literally, and so are
+/// expansions in a quoted word (`"$v"`), judged the same way as
+/// [`word_needs_splitting`] does.
+pub fn expand_pattern(state: &mut ShellState, word: &Word, exec: Option<ExecFn>) -> String {
+    let quoted = word
+        .parts
+        .iter()
+        .any(|p| matches!(p, WordPart::QuotedLiteral(_)));
+    word.parts
+        .iter()
+        .map(|part| {
+            let text = expand_word_part(state, part, exec);
+            match part {
+                WordPart::Literal(_) | WordPart::QuotedLiteral(_) => text,
+                _ if quoted => protect_special_chars(&text),
+                _ => text,
+            }
+        })
+        .collect()
+}
+
 /// Determine whether a word's expansion should be subject to word splitting.
 ///
 /// A word needs splitting when it contains a substitution (Variable or
@@ -44,6 +68,9 @@ pub fn word_needs_splitting(word: &Word) -> bool {
 
 /// Expand a list of words, applying word splitting to unquoted substitutions.
 ///
+/// `$@` (and an unquoted `$*`) expands to one field per positional
+/// parameter; see [`expand_positional_fields`].
+///
 /// `exec` is an optional callback for command substitution — see [`expand_word`].
 pub fn expand_words_with_splitting(
     state: &mut ShellState,
@@ -52,43 +79,14 @@ pub fn expand_words_with_splitting(
 ) -> Vec<String> {
     let mut result = Vec::new();
     for w in words {
+        if let Some(fields) = expand_positional_fields(state, w, exec) {
+            result.extend(fields);
+            continue;
+        }
         let expanded = expand_word(state, w, exec);
         if word_needs_splitting(w) {
             let ifs = state.env.get("IFS").cloned();
-            let split: Vec<String> = match &ifs {
-                Some(ifs_val) if ifs_val.is_empty() => {
-                    // Empty IFS: no splitting
-                    vec![expanded.clone()]
-                }
-                Some(ifs_val) => {
-                    // Split on IFS characters
-                    let ifs_chars: Vec<char> = ifs_val.chars().collect();
-                    let mut parts = Vec::new();
-                    let mut current = String::new();
-                    for ch in expanded.chars() {
-                        if ifs_chars.contains(&ch) {
-                            if !current.is_empty() {
-                                parts.push(std::mem::take(&mut current));
-                            }
-                        } else {
-                            current.push(ch);
-                        }
-                    }
-                    if !current.is_empty() {
-                        parts.push(current);
-                    }
-                    parts
-                }
-                None => {
-                    // Default IFS: split on whitespace
-                    expanded.split_whitespace().map(|s| s.to_string()).collect()
-                }
-            };
-            for s in split {
-                if !s.is_empty() {
-                    result.push(s);
-                }
-            }
+            result.extend(split_on_ifs(&expanded, ifs.as_deref()));
         } else {
             result.push(expanded);
         }
@@ -96,6 +94,79 @@ pub fn expand_words_with_splitting(
     result
 }
 
+/// Split `s` into fields on the characters of `ifs`, dropping empty fields.
+///
+/// An unset IFS splits on whitespace; an empty IFS does not split at all.
+fn split_on_ifs(s: &str, ifs: Option<&str>) -> Vec<String> {
+    match ifs {
+        // Empty IFS: no splitting
+        Some("") if s.is_empty() => Vec::new(),
+        Some("") => vec![s.to_string()],
+        Some(ifs_val) => s
+            .split(|c| ifs_val.contains(c))
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect(),
+        // Default IFS: split on whitespace
+        None => s.split_whitespace().map(str::to_string).collect(),
+    }
+}
+
+/// Expand a word containing `$@`, or an unquoted `$*`, into separate fields.
+///
+/// Each positional parameter becomes its own field, with any text around
+/// the expansion joined onto the first and last fields (`x"$@"y`). Quoted,
+/// the fields are kept as-is; unquoted, each is further split on IFS. With
+/// no positional parameters a bare `"$@"` expands to no fields at all.
+///
+/// Returns `None` when the word holds no such expansion.
+fn expand_positional_fields(
+    state: &mut ShellState,
+    word: &Word,
+    exec: Option<ExecFn>,
+) -> Option<Vec<String>> {
+    let quoted = word
+        .parts
+        .iter()
+        .any(|p| matches!(p, WordPart::QuotedLiteral(_)));
+    let is_list =
+        |p: &WordPart| matches!(p, WordPart::Variable(n) if n == "@" || (!quoted && n == "*"));
+    if !word.parts.iter().any(is_list) {
+        return None;
+    }
+
+    let mut fields = vec![String::new()];
+    let mut has_text = false;
+    for part in &word.parts {
+        if is_list(part) {
+            for (i, arg) in state.positional_args.iter().enumerate() {
+                if i > 0 {
+                    fields.push(String::new());
+                }
+                fields.last_mut().unwrap().push_str(arg);
+            }
+        } else {
+            let s = expand_word_part(state, part, exec);
+            has_text |= !s.is_empty();
+            fields.last_mut().unwrap().push_str(&s);
+        }
+    }
+
+    if quoted {
+        if state.positional_args.is_empty() && !has_text {
+            return Some(Vec::new());
+        }
+        return Some(fields);
+    }
+    let ifs = state.env.get("IFS").cloned();
+    Some(
+        fields
+            .iter()
+            .flat_map(|f| split_on_ifs(f, ifs.as_deref()))
+            .collect(),
+    )
+}
+
 /// Parse a raw assignment value string into a `Word` with proper parts.
 ///
 /// The lexer stores assignment values as raw strings (e.g. `$(echo hi)` is
@@ -189,7 +260,7 @@ pub fn parse_assignment_value(raw: &str) -> Word {
                 // $VAR -- simple variable
                 let start = i + 1;
                 let mut j = start;
-                // Special single-char variables: $?, $#, $@, $*, $0-$9, $$, $!
+                // Special single-char variables: $?, $#, $@, $*, $0-$9, $$, $!, $-
                 if j < len
                     && (chars[j] == '?'
                         || chars[j] == '#'
@@ -197,6 +268,7 @@ pub fn parse_assignment_value(raw: &str) -> Word {
                         || chars[j] == '*'
                         || chars[j] == '$'
                         || chars[j] == '!'
+                        || chars[j] == '-'
                         || chars[j].is_ascii_digit())
                 {
                     j += 1;
@@ -231,13 +303,92 @@ pub fn parse_assignment_value(raw: &str) -> Word {
     Word { parts }
 }
 
+/// Split the raw inside of an array literal (`arr=(...)`) into one `Word`
+/// per element, honouring quotes the way [`parse_assignment_value`] does not.
+///
+/// Single-quoted text becomes a `QuotedLiteral`; double-quoted text is
+/// parsed like a heredoc body behind an empty `QuotedLiteral` marker, so
+/// `"$(cmd)"` or `"$v"` stays one element through
+/// [`expand_words_with_splitting`] while a bare `$(cmd)` is still split.
+pub fn parse_array_elements(inner: &str) -> Vec<Word> {
+    fn flush(raw: &mut String, parts: &mut Vec<WordPart>) {
+        if !raw.is_empty() {
+            parts.extend(parse_assignment_value(&std::mem::take(raw)).parts);
+        }
+    }
+
+    let chars: Vec<char> = inner.chars().collect();
+    let len = chars.len();
+    let mut words = Vec::new();
+    let mut parts = Vec::new();
+    let mut raw = String::new();
+    let mut in_word = false;
+    // Nesting of `$(...)` / `${...}` in unquoted text, where quotes and
+    // blanks belong to the substitution rather than delimiting elements.
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < len {
+        let c = chars[i];
+        if depth == 0 && c.is_whitespace() {
+            flush(&mut raw, &mut parts);
+            if in_word {
+                words.push(Word {
+                    parts: std::mem::take(&mut parts),
+                });
+                in_word = false;
+            }
+            i += 1;
+            continue;
+        }
+        in_word = true;
+        match c {
+            '\\' if i + 1 < len => {
+                raw.push(c);
+                raw.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '\'' | '"' if depth == 0 => {
+                flush(&mut raw, &mut parts);
+                let start = i + 1;
+                let mut j = start;
+                while j < len && chars[j] != c {
+                    if c == '"' && chars[j] == '\\' {
+                        j += 1;
+                    }
+                    j += 1;
+                }
+                let content: String = chars[start..j.min(len)].iter().collect();
+                if c == '\'' {
+                    parts.push(WordPart::QuotedLiteral(content));
+                } else {
+                    parts.push(WordPart::QuotedLiteral(String::new()));
+                    parts.extend(codepod_shell::lexer::parse_string_expansion(&content));
+                }
+                i = j + 1;
+                continue;
+            }
+            '(' | '{' if depth > 0 || raw.ends_with('$') => depth += 1,
+            ')' | '}' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+        raw.push(c);
+        i += 1;
+    }
+    flush(&mut raw, &mut parts);
+    if in_word {
+        words.push(Word { parts });
+    }
+    words
+}
+
 /// Parse the inner content of `${...}` into a `WordPart`.
 ///
 /// Handles: `${var}`, `${var:-default}`, `${var:=default}`, `${var:+alt}`,
 /// `${var:?err}`, `${#var}`, `${var%pat}`, `${var%%pat}`, `${var#pat}`,
 /// `${var##pat}`, `${var^^}`, `${var,,}`, `${var^}`, `${var/pat/repl}`,
-/// `${var:off:len}`, etc.
-fn parse_param_expansion_inner(inner: &str) -> WordPart {
+/// `${var:off:len}`, `${var@Q}`, etc.
+pub(crate) fn parse_param_expansion_inner(inner: &str) -> WordPart {
     // ${#var} -- length
     if inner.starts_with('#') && inner.len() > 1 && !inner.contains(':') {
         return WordPart::ParamExpansion {
@@ -250,7 +401,7 @@ fn parse_param_expansion_inner(inner: &str) -> WordPart {
     // Find the operator position: look for :-, :=, :+, :?, %, %%, #, ##, ^^, ,,, ^, /, :
     // Try longer operators first
     let ops = [
-        "%%", "##", "^^", ",,", ":-", ":=", ":+", ":?", "^", "/", "%", "#", ":",
+        "@Q", "%%", "##", "^^", ",,", ":-", ":=", ":+", ":?", "^", "/", "%", "#", ":",
     ];
     for op in ops {
         if let Some(pos) = inner.find(op) {
@@ -447,6 +598,24 @@ pub fn restore_brace_sentinels(words: &[String]) -> Vec<String> {
         .collect()
 }
 
+/// Protect brace and glob characters so they survive brace expansion and
+/// glob expansion as literal text.  Uses Private Use Area chars.
+pub(crate) fn protect_special_chars(s: &str) -> String {
+    s.replace('{', "\u{E000}")
+        .replace('}', "\u{E001}")
+        .replace('*', "\u{E002}")
+        .replace('?', "\u{E003}")
+        .replace('[', "\u{E004}")
+        .replace(']', "\u{E005}")
+}
+
+/// Turn every quoting sentinel in `word` back into the character it
+/// protects, for text that is used as-is rather than expanded further.
+pub fn restore_all_sentinels(word: &str) -> String {
+    let braced = restore_brace_sentinels(&[word.to_string()]);
+    restore_glob_sentinels(&braced).remove(0)
+}
+
 /// Restore glob sentinels that were inserted by QuotedLiteral expansion.
 ///
 /// Must be called AFTER glob expansion so that quoted glob characters
@@ -467,19 +636,24 @@ pub fn restore_glob_sentinels(words: &[String]) -> Vec<String> {
 // Glob expansion (filesystem)
 // ---------------------------------------------------------------------------
 
-/// Expand glob patterns (`*`, `?`) in a word list using the host's glob
-/// implementation.
+/// Expand glob patterns (`*`, `?`, `[...]`) in a word list against the VFS.
 ///
-/// For each word containing `*` or `?`, calls `host.glob(pattern)`.  If the
-/// host returns matches they are used (sorted); otherwise the literal pattern
-/// is preserved (POSIX behavior).
+/// Each pattern is walked one path component at a time: the literal prefix
+/// names the starting directory, and every later component is matched
+/// against the entries `host.readdir` reports at that level.  Matches are
+/// returned sorted; a word that matches nothing is kept literally (POSIX
+/// behavior), or dropped under `shopt -s nullglob`.  Hidden entries only
+/// match a wildcard with `dotglob`, `nocaseglob` makes letters match either
+/// case, and `extglob` enables the `@(a|b)`-style groups.
 ///
-/// Relative patterns are resolved against `cwd` and results are returned
+/// Relative patterns are resolved against the cwd and results are returned
 /// as relative paths (stripping the cwd prefix).
-pub fn expand_globs(host: &dyn HostInterface, words: &[String], cwd: &str) -> Vec<String> {
+pub fn expand_globs(host: &dyn HostInterface, words: &[String], state: &ShellState) -> Vec<String> {
+    let cwd = state.cwd.as_str();
+    let extglob = state.shopt_enabled("extglob");
     let mut result = Vec::new();
     for word in words {
-        if word.contains('*') || word.contains('?') || word.contains('[') {
+        if is_glob_pattern(word, extglob) {
             // Resolve relative patterns against cwd
             let (pattern, is_relative) = if word.starts_with('/') {
                 (word.clone(), false)
@@ -491,26 +665,29 @@ pub fn expand_globs(host: &dyn HostInterface, words: &[String], cwd: &str) -> Ve
                 };
                 (abs, true)
             };
-            match host.glob(&pattern) {
-                Ok(mut matches) if !matches.is_empty() => {
-                    matches.sort();
-                    if is_relative {
-                        // Strip cwd prefix to return relative paths
-                        let prefix = if cwd.ends_with('/') {
-                            cwd.to_string()
-                        } else {
-                            format!("{cwd}/")
-                        };
-                        result.extend(
-                            matches
-                                .into_iter()
-                                .map(|m| m.strip_prefix(&prefix).unwrap_or(&m).to_string()),
-                        );
-                    } else {
-                        result.extend(matches);
-                    }
+            let pattern = collapse_globstar(&pattern, state.shopt_enabled("globstar"));
+            let mut matches = glob_walk(host, &pattern, state);
+            if matches.is_empty() {
+                if !state.shopt_enabled("nullglob") {
+                    result.push(word.clone());
                 }
-                _ => result.push(word.clone()),
+                continue;
+            }
+            matches.sort();
+            if is_relative {
+                // Strip cwd prefix to return relative paths
+                let prefix = if cwd.ends_with('/') {
+                    cwd.to_string()
+                } else {
+                    format!("{cwd}/")
+                };
+                result.extend(
+                    matches
+                        .into_iter()
+                        .map(|m| m.strip_prefix(&prefix).unwrap_or(&m).to_string()),
+                );
+            } else {
+                result.extend(matches);
             }
         } else {
             result.push(word.clone());
@@ -519,6 +696,173 @@ pub fn expand_globs(host: &dyn HostInterface, words: &[String], cwd: &str) -> Ve
     result
 }
 
+/// Walk an absolute glob `pattern` through the VFS, returning every path
+/// whose components match the pattern's components level by level.
+fn glob_walk(host: &dyn HostInterface, pattern: &str, state: &ShellState) -> Vec<String> {
+    let extglob = state.shopt_enabled("extglob");
+    let components: Vec<&str> = pattern.split('/').collect();
+    let Some(first_glob) = components.iter().position(|c| is_glob_pattern(c, extglob)) else {
+        return Vec::new();
+    };
+    let dotglob = state.shopt_enabled("dotglob");
+    let nocase = state.shopt_enabled("nocaseglob");
+
+    // The components before the first wildcard are taken literally; quoted
+    // glob characters in them name real files.
+    let base = restore_all_sentinels(&components[..first_glob].join("/"));
+    let mut paths = vec![base];
+    let last = components.len() - 1;
+    for (i, &component) in components.iter().enumerate().skip(first_glob) {
+        if component.is_empty() {
+            // A trailing slash keeps only directories; `a//b` is just `a/b`.
+            if i == last {
+                paths.retain(|p| is_dir(host, p));
+                for path in &mut paths {
+                    path.push('/');
+                }
+            }
+            continue;
+        }
+        if component == "**" {
+            // collapse_globstar only leaves `**` behind under globstar.
+            let mut expanded = Vec::new();
+            for path in &paths {
+                if i < last {
+                    expanded.push(path.clone());
+                }
+                collect_descendants(host, path, i == last, dotglob, &mut expanded);
+            }
+            paths = expanded;
+            continue;
+        }
+        let matcher = if nocase {
+            caseless_pattern(component)
+        } else {
+            component.to_string()
+        };
+        let mut next = Vec::new();
+        for path in &paths {
+            let Ok(entries) = host.readdir(dir_or_root(path)) else {
+                continue;
+            };
+            for name in entries {
+                if name.starts_with('.') && !component.starts_with('.') && !dotglob {
+                    continue;
+                }
+                let matched = if extglob {
+                    extglob_matches(&matcher, &name)
+                } else {
+                    glob_matches(&matcher, &name)
+                };
+                if matched {
+                    next.push(format!("{path}/{name}"));
+                }
+            }
+        }
+        paths = next;
+        if paths.is_empty() {
+            break;
+        }
+    }
+    paths
+}
+
+/// Push every path below `dir` for a globstar component: directories only
+/// when more components follow, files as well when `**` ends the pattern.
+fn collect_descendants(
+    host: &dyn HostInterface,
+    dir: &str,
+    include_files: bool,
+    dotglob: bool,
+    out: &mut Vec<String>,
+) {
+    let Ok(entries) = host.readdir(dir_or_root(dir)) else {
+        return;
+    };
+    for name in entries {
+        if name.starts_with('.') && !dotglob {
+            continue;
+        }
+        let path = format!("{dir}/{name}");
+        if is_dir(host, &path) {
+            out.push(path.clone());
+            collect_descendants(host, &path, include_files, dotglob, out);
+        } else if include_files {
+            out.push(path);
+        }
+    }
+}
+
+/// Whether `s` contains anything filename expansion would treat as a pattern.
+fn is_glob_pattern(s: &str, extglob: bool) -> bool {
+    s.contains(['*', '?', '[']) || (extglob && find_extglob_group(s.as_bytes()).is_some())
+}
+
+/// The directory to list for a walk path; the empty path is the root.
+fn dir_or_root(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+fn is_dir(host: &dyn HostInterface, path: &str) -> bool {
+    host.stat(dir_or_root(path)).is_ok_and(|s| s.is_dir)
+}
+
+/// Reduce `**` to `*` unless globstar is on and it forms a whole path
+/// component; only then does the host let it cross directories.
+fn collapse_globstar(pattern: &str, globstar: bool) -> String {
+    pattern
+        .split('/')
+        .map(|component| {
+            if globstar && component == "**" {
+                return component.to_string();
+            }
+            let mut out = String::with_capacity(component.len());
+            for c in component.chars() {
+                if !(c == '*' && out.ends_with('*')) {
+                    out.push(c);
+                }
+            }
+            out
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Rewrite letters outside bracket expressions as `[xX]` classes so a
+/// case-sensitive glob matches either case. Any literal directory prefix
+/// is left alone.
+fn caseless_pattern(pattern: &str) -> String {
+    let first_glob = pattern.find(['*', '?', '[']).unwrap_or(pattern.len());
+    let split = pattern[..first_glob].rfind('/').map_or(0, |i| i + 1);
+    let mut out = String::with_capacity(pattern.len() * 2);
+    out.push_str(&pattern[..split]);
+    let mut in_class = false;
+    for c in pattern[split..].chars() {
+        match c {
+            '[' if !in_class => {
+                in_class = true;
+                out.push(c);
+            }
+            ']' if in_class => {
+                in_class = false;
+                out.push(c);
+            }
+            c if !in_class && c.is_alphabetic() && c.to_lowercase().ne(c.to_uppercase()) => {
+                out.push('[');
+                out.extend(c.to_lowercase());
+                out.extend(c.to_uppercase());
+                out.push(']');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
 // ---------------------------------------------------------------------------
 // Word expansion internals
 // ---------------------------------------------------------------------------
@@ -533,16 +877,7 @@ pub fn expand_word_part(state: &mut ShellState, part: &WordPart, exec: Option<Ex
     match part {
         WordPart::Literal(s) => expand_literal(s, state),
 
-        WordPart::QuotedLiteral(s) => {
-            // Protect special characters inside quoted literals so they survive
-            // brace expansion and glob expansion.  Uses Private Use Area chars.
-            s.replace('{', "\u{E000}")
-                .replace('}', "\u{E001}")
-                .replace('*', "\u{E002}")
-                .replace('?', "\u{E003}")
-                .replace('[', "\u{E004}")
-                .replace(']', "\u{E005}")
-        }
+        WordPart::QuotedLiteral(s) => protect_special_chars(s),
 
         WordPart::Variable(name) => expand_variable(state, name),
 
@@ -608,29 +943,104 @@ fn expand_literal(s: &str, state: &ShellState) -> String {
 // Variable expansion ($VAR, $?, $#, $@, $*, $0–$9, specials)
 // ---------------------------------------------------------------------------
 
-fn expand_variable(state: &mut ShellState, name: &str) -> String {
-    // Special variables
-    match name {
-        "?" => return state.last_exit_code.to_string(),
-        "!" => return state.last_bg_pid.to_string(),
-        "@" | "*" => return state.positional_args.join(" "),
-        "#" => return state.positional_args.len().to_string(),
-        "RANDOM" => return random_u15(state).to_string(),
-        "SECONDS" => return "0".to_string(), // placeholder — no start_time yet
-        "LINENO" => return "1".to_string(),  // minimum value for shell conformance
-        _ => {}
-    }
-
-    // Positional parameters ($0–$9 and beyond)
-    if let Ok(idx) = name.parse::<usize>() {
-        if idx == 0 {
-            return "codepod-shell".to_string();
+/// Resolve a special parameter (`$?`, `$$`, `$-`, `$#`, `$@`, `$0`…) or a
+/// dynamic variable. Returns `None` for ordinary variable names.
+///
+/// `$RANDOM` advances the generator and is handled by the callers that hold
+/// mutable state.
+pub(crate) fn special_param(state: &ShellState, name: &str) -> Option<String> {
+    let value = match name {
+        "?" => state.last_exit_code.to_string(),
+        "!" => state.last_bg_pid.to_string(),
+        "$" => state.pid.to_string(),
+        "PPID" => state.ppid.to_string(),
+        "BASHPID" => state.bashpid.to_string(),
+        "BASH_COMMAND" => state.bash_command.clone(),
+        "_" => state.last_arg.clone(),
+        "-" => flag_letters(state),
+        "@" => state.positional_args.join(" "),
+        // `$*` joins on the first character of IFS (a space when unset,
+        // nothing when empty).
+        "*" => {
+            let sep = match state.env.get("IFS") {
+                Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                None => " ".to_string(),
+            };
+            state.positional_args.join(&sep)
         }
-        return state
-            .positional_args
-            .get(idx - 1)
+        "#" => state.positional_args.len().to_string(),
+        "0" => "codepod-shell".to_string(),
+        "SECONDS" => "0".to_string(), // placeholder — no start_time yet
+        "LINENO" => "1".to_string(),  // minimum value for shell conformance
+        "PWD" => state
+            .env
+            .get("PWD")
             .cloned()
-            .unwrap_or_default();
+            .unwrap_or_else(|| state.cwd.clone()),
+        _ => {
+            let idx = name.parse::<usize>().ok()?;
+            return state.positional_args.get(idx.checked_sub(1)?).cloned();
+        }
+    };
+    Some(value)
+}
+
+/// The single-letter form of the active `set` options, as reported by `$-`.
+fn flag_letters(state: &ShellState) -> String {
+    let mut letters = String::new();
+    if state.flags.contains(&ShellFlag::Errexit) {
+        letters.push('e');
+    }
+    if state.flags.contains(&ShellFlag::Nounset) {
+        letters.push('u');
+    }
+    letters
+}
+
+/// Look up a parameter by name for `${…}` operators: special parameters
+/// first, then ordinary variables. `None` means unset.
+fn lookup_param(state: &mut ShellState, name: &str) -> Option<String> {
+    if name == "RANDOM" {
+        return Some(random_u15(state).to_string());
+    }
+    if let Some((arr_name, index)) = parse_array_access(name) {
+        return lookup_element(state, &arr_name, &index);
+    }
+    special_param(state, name).or_else(|| state.env.get(name).cloned())
+}
+
+/// The value of `name[index]` in an associative or indexed array (negative
+/// indices count from the end), or all values joined for `@`/`*`. `None`
+/// means unset.
+fn lookup_element(state: &ShellState, name: &str, index: &str) -> Option<String> {
+    let all = index == "@" || index == "*";
+    if let Some(assoc) = state.assoc_arrays.get(name) {
+        if all {
+            return Some(assoc.values().cloned().collect::<Vec<_>>().join(" "));
+        }
+        return assoc.get(index).cloned();
+    }
+    let arr = state.arrays.get(name)?;
+    if all {
+        return Some(arr.to_vec().join(" "));
+    }
+    let mut idx = index.parse::<isize>().ok()?;
+    if idx < 0 {
+        idx += arr.end() as isize;
+    }
+    arr.get(usize::try_from(idx).ok()?).cloned()
+}
+
+fn expand_variable(state: &mut ShellState, name: &str) -> String {
+    if name == "RANDOM" {
+        return random_u15(state).to_string();
+    }
+    if let Some(value) = special_param(state, name) {
+        return value;
+    }
+    // Unset positional parameters expand to nothing.
+    if name.parse::<usize>().is_ok() {
+        return String::new();
     }
 
     // Array access with slicing: arr[@]:offset:length  (encoded by parser)
@@ -638,7 +1048,7 @@ fn expand_variable(state: &mut ShellState, name: &str) -> String {
         let (arr_name, subscript, slice_spec) = caps;
         if subscript == "@" || subscript == "*" {
             if let Some(arr) = state.arrays.get(&arr_name) {
-                return apply_array_slice(arr, &slice_spec);
+                return apply_array_slice(&arr.to_vec(), &slice_spec);
             }
         }
         return String::new();
@@ -646,47 +1056,7 @@ fn expand_variable(state: &mut ShellState, name: &str) -> String {
 
     // Array access: arr[n], arr[@], arr[*]
     if let Some((arr_name, index)) = parse_array_access(name) {
-        // BASH_REMATCH special array — check dedicated field first, then
-        // fall back to state.arrays (manual assignment: BASH_REMATCH=(a b))
-        if arr_name == "BASH_REMATCH" {
-            let source = if !state.bash_rematch.is_empty() {
-                &state.bash_rematch
-            } else if let Some(arr) = state.arrays.get("BASH_REMATCH") {
-                arr
-            } else {
-                return String::new();
-            };
-            if index == "@" || index == "*" {
-                return source.join(" ");
-            }
-            if let Ok(idx) = index.parse::<usize>() {
-                return source.get(idx).cloned().unwrap_or_default();
-            }
-            return String::new();
-        }
-        // Check associative arrays first
-        if let Some(assoc) = state.assoc_arrays.get(&arr_name) {
-            if index == "@" || index == "*" {
-                return assoc.values().cloned().collect::<Vec<_>>().join(" ");
-            }
-            return assoc.get(&index).cloned().unwrap_or_default();
-        }
-        // Then indexed arrays
-        if let Some(arr) = state.arrays.get(&arr_name) {
-            if index == "@" || index == "*" {
-                return arr.join(" ");
-            }
-            if let Ok(mut idx) = index.parse::<isize>() {
-                if idx < 0 {
-                    idx += arr.len() as isize;
-                }
-                if idx >= 0 && (idx as usize) < arr.len() {
-                    return arr[idx as usize].clone();
-                }
-            }
-            return String::new();
-        }
-        return String::new();
+        return lookup_element(state, &arr_name, &index).unwrap_or_default();
     }
 
     // Regular variable lookup
@@ -709,7 +1079,7 @@ fn expand_variable(state: &mut ShellState, name: &str) -> String {
 }
 
 /// Generate a pseudo-random number in [0, 32768) using xorshift on state's seed.
-fn random_u15(state: &mut ShellState) -> u32 {
+pub(crate) fn random_u15(state: &mut ShellState) -> u32 {
     // xorshift64 — simple, fast, and produces different values each call.
     let mut s = state.rng_seed;
     s ^= s << 13;
@@ -723,8 +1093,25 @@ fn random_u15(state: &mut ShellState) -> u32 {
 // Parameter expansion  ${var op default}
 // ---------------------------------------------------------------------------
 
+/// Quote `value` so that it reads back as the same single word.
+///
+/// Used by `${var@Q}` and `declare -p`, which must print values the shell
+/// can re-parse.
+pub(crate) fn quote_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
 fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> String {
-    let val = state.env.get(var).cloned();
+    let val = lookup_param(state, var);
 
     match op {
         ":-" => match &val {
@@ -736,6 +1123,7 @@ fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> S
             Some(v) if !v.is_empty() => v.clone(),
             _ => {
                 state.env.insert(var.to_string(), operand.to_string());
+                state.var_changed(var);
                 operand.to_string()
             }
         },
@@ -764,6 +1152,10 @@ fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> S
         "#" => {
             // ${#VAR} — string length
             // When var is empty and operand is set, it's ${#operand}
+            if var.is_empty() && (operand.is_empty() || operand == "@" || operand == "*") {
+                // ${#}, ${#@}, ${#*} — number of positional parameters
+                return state.positional_args.len().to_string();
+            }
             if var.is_empty() && !operand.is_empty() {
                 // Array length: ${#arr[@]} or element length: ${#arr[N]}
                 if let Some((arr_name, sub)) = parse_array_access(operand) {
@@ -780,7 +1172,7 @@ fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> S
                     if let Ok(idx) = sub.parse::<i64>() {
                         if let Some(arr) = state.arrays.get(&arr_name) {
                             let real_idx = if idx < 0 {
-                                (arr.len() as i64 + idx) as usize
+                                (arr.end() as i64 + idx) as usize
                             } else {
                                 idx as usize
                             };
@@ -799,10 +1191,7 @@ fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> S
                     }
                 }
                 // String length of variable value: ${#VAR}
-                let v = state.env.get(operand)
-                    .or_else(|| state.env.get(operand))
-                    .cloned()
-                    .unwrap_or_default();
+                let v = lookup_param(state, operand).unwrap_or_default();
                 return v.chars().count().to_string();
             }
             // ${VAR#pattern} — trim shortest prefix
@@ -847,6 +1236,8 @@ fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> S
             Some(v) => replace_pattern_anchored(v, operand, false),
         },
 
+        "@Q" => val.map(|v| quote_value(&v)).unwrap_or_default(),
+
         "^^" => val.unwrap_or_default().to_uppercase(),
 
         ",," => val.unwrap_or_default().to_lowercase(),
@@ -881,7 +1272,7 @@ fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> S
             if let Some((arr_name, sub)) = parse_array_access(var) {
                 if sub == "@" || sub == "*" {
                     if let Some(arr) = state.arrays.get(&arr_name) {
-                        return apply_array_slice(arr, operand);
+                        return apply_array_slice(&arr.to_vec(), operand);
                     }
                     return String::new();
                 }
@@ -890,17 +1281,14 @@ fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> S
             apply_substring(&s, operand)
         }
 
-        // Indirect expansion: ${!var} — the value of var names another variable
-        // or ${!arr[@]} — array indices
+        // Indirect expansion: ${!var} — the value of var names another variable,
+        // ${!arr[@]} — array indices, or ${!prefix*} — variable names by prefix
         "!" => {
             // Check if operand is an array subscript like arr[@] or arr[*]
             if let Some((arr_name, sub)) = parse_array_access(operand) {
                 if sub == "@" || sub == "*" {
                     if let Some(arr) = state.arrays.get(&arr_name) {
-                        let indices: Vec<String> = (0..arr.len())
-                            .filter(|i| !arr[*i].is_empty() || *i == 0)
-                            .map(|i| i.to_string())
-                            .collect();
+                        let indices: Vec<String> = arr.indices().map(|i| i.to_string()).collect();
                         return indices.join(" ");
                     }
                     if let Some(assoc) = state.assoc_arrays.get(&arr_name) {
@@ -911,17 +1299,31 @@ fn expand_param(state: &mut ShellState, var: &str, op: &str, operand: &str) -> S
                     return String::new();
                 }
             }
+            // Prefix listing: ${!prefix*} / ${!prefix@}
+            if let Some(prefix) = operand.strip_suffix(['*', '@']) {
+                let mut names: Vec<&str> = state
+                    .env
+                    .keys()
+                    .chain(state.arrays.keys())
+                    .chain(state.assoc_arrays.keys())
+                    .map(String::as_str)
+                    .filter(|name| name.starts_with(prefix))
+                    .collect();
+                names.sort();
+                names.dedup();
+                return names.join(" ");
+            }
             // Simple indirect: ${!var} — operand holds the variable name
             let indirect_name = if operand.is_empty() {
                 val.unwrap_or_default()
             } else {
                 // Look up operand as a variable to get the indirect name
-                state.env.get(operand).cloned().unwrap_or_default()
+                lookup_param(state, operand).unwrap_or_default()
             };
             if indirect_name.is_empty() {
                 String::new()
             } else {
-                state.env.get(&indirect_name).cloned().unwrap_or_default()
+                lookup_param(state, &indirect_name).unwrap_or_default()
             }
         }
 
@@ -995,40 +1397,40 @@ fn char_boundaries(s: &str) -> Vec<usize> {
         .collect()
 }
 
+/// `${var:offset}` / `${var:offset:length}`, counted in characters.
+///
+/// A negative offset counts back from the end; one reaching before the start
+/// yields nothing. A negative length is an end position counted back from
+/// the end, so `${v:1:-1}` drops the first and last characters. Any range
+/// that ends up empty or past the end expands to the empty string.
 fn apply_substring(s: &str, operand: &str) -> String {
     let parts: Vec<&str> = operand.splitn(2, ':').collect();
     let char_count = s.chars().count() as isize;
-    let mut offset = parts[0].parse::<isize>().unwrap_or(0);
-
+    let mut offset = parts[0].trim().parse::<isize>().unwrap_or(0);
     if offset < 0 {
-        offset = (char_count + offset).max(0);
+        offset += char_count;
+        if offset < 0 {
+            return String::new();
+        }
     }
-    let offset = offset as usize;
-
-    let boundaries = char_boundaries(s);
 
-    if parts.len() > 1 {
-        let length = parts[1].trim().parse::<isize>().unwrap_or(0);
-        if length < 0 {
-            let end_char = (char_count + length).max(0) as usize;
-            if offset < boundaries.len() && end_char < boundaries.len() && offset <= end_char {
-                return s[boundaries[offset]..boundaries[end_char]].to_string();
+    let end = match parts.get(1) {
+        Some(len) => {
+            let length = len.trim().parse::<isize>().unwrap_or(0);
+            if length < 0 {
+                char_count + length
+            } else {
+                offset.saturating_add(length).min(char_count)
             }
-            return String::new();
-        }
-        let length = length as usize;
-        let end_char = (offset + length).min(boundaries.len() - 1);
-        if offset < boundaries.len() {
-            return s[boundaries[offset]..boundaries[end_char]].to_string();
         }
+        None => char_count,
+    };
+    if offset >= end {
         return String::new();
     }
 
-    if offset < boundaries.len() {
-        s[boundaries[offset]..].to_string()
-    } else {
-        String::new()
-    }
+    let boundaries = char_boundaries(s);
+    s[boundaries[offset as usize]..boundaries[end as usize]].to_string()
 }
 
 // ---------------------------------------------------------------------------
@@ -1129,6 +1531,87 @@ pub fn glob_matches(pattern: &str, text: &str) -> bool {
     glob_match_inner(pattern.as_bytes(), text.as_bytes())
 }
 
+/// Match a string against a pattern that may also contain the extglob
+/// groups `?(..)`, `*(..)`, `+(..)`, `@(..)` and `!(..)`, each holding
+/// `|`-separated alternatives (`shopt -s extglob`).
+pub fn extglob_matches(pattern: &str, text: &str) -> bool {
+    extglob_match_inner(pattern.as_bytes(), text.as_bytes())
+}
+
+fn extglob_match_inner(pat: &[u8], txt: &[u8]) -> bool {
+    let Some((start, end)) = find_extglob_group(pat) else {
+        return glob_match_inner(pat, txt);
+    };
+    let op = pat[start];
+    let alts = split_alternatives(&pat[start + 2..end]);
+    let rest = &pat[end + 1..];
+    (0..=txt.len()).any(|i| {
+        glob_match_inner(&pat[..start], &txt[..i]) && match_group(op, &alts, rest, &txt[i..])
+    })
+}
+
+/// Match `txt` against one extglob group followed by the rest of the pattern.
+fn match_group(op: u8, alts: &[&[u8]], rest: &[u8], txt: &[u8]) -> bool {
+    let any_alt = |t: &[u8]| alts.iter().any(|a| extglob_match_inner(a, t));
+    // An alternative takes txt[..k] and the rest of the pattern the remainder.
+    let once = |k: usize| any_alt(&txt[..k]) && extglob_match_inner(rest, &txt[k..]);
+    // An alternative takes txt[..k] and the group may repeat after it.
+    let repeat = |k: usize| any_alt(&txt[..k]) && match_group(b'*', alts, rest, &txt[k..]);
+    match op {
+        b'@' => (0..=txt.len()).any(once),
+        b'?' => extglob_match_inner(rest, txt) || (0..=txt.len()).any(once),
+        b'*' => extglob_match_inner(rest, txt) || (1..=txt.len()).any(repeat),
+        b'+' => (0..=txt.len()).any(repeat),
+        _ => (0..=txt.len()).any(|k| !any_alt(&txt[..k]) && extglob_match_inner(rest, &txt[k..])),
+    }
+}
+
+/// Locate the first `X(...)` group, returning the index of its operator
+/// and of its closing paren.
+fn find_extglob_group(pat: &[u8]) -> Option<(usize, usize)> {
+    let mut i = 0;
+    while i + 1 < pat.len() {
+        if b"?*+@!".contains(&pat[i]) && pat[i + 1] == b'(' {
+            let mut depth = 0;
+            for (j, &b) in pat.iter().enumerate().skip(i + 1) {
+                match b {
+                    b'(' => depth += 1,
+                    b')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some((i, j));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            return None;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Split the inside of an extglob group on its top-level `|`s.
+fn split_alternatives(inner: &[u8]) -> Vec<&[u8]> {
+    let mut alts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, &b) in inner.iter().enumerate() {
+        match b {
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            b'|' if depth == 0 => {
+                alts.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    alts.push(&inner[start..]);
+    alts
+}
+
 fn glob_match_inner(pat: &[u8], txt: &[u8]) -> bool {
     let mut pi = 0;
     let mut ti = 0;
@@ -1136,7 +1619,23 @@ fn glob_match_inner(pat: &[u8], txt: &[u8]) -> bool {
     let mut star_ti = usize::MAX; // text index at last '*'
 
     while ti < txt.len() {
-        if pi < pat.len() && pat[pi] == b'?' {
+        if let Some(lit) = quoted_literal_at(&pat[pi..]) {
+            // Quoted character: matches itself literally (or the same
+            // sentinel, when the text was not restored).
+            if txt[ti] == lit {
+                pi += 3;
+                ti += 1;
+            } else if txt[ti..].starts_with(&pat[pi..pi + 3]) {
+                pi += 3;
+                ti += 3;
+            } else if star_pi != usize::MAX {
+                star_ti += 1;
+                pi = star_pi;
+                ti = star_ti;
+            } else {
+                return false;
+            }
+        } else if pi < pat.len() && pat[pi] == b'?' {
             // '?' matches any single character
             pi += 1;
             ti += 1;
@@ -1192,6 +1691,15 @@ fn glob_match_inner(pat: &[u8], txt: &[u8]) -> bool {
     pi == pat.len()
 }
 
+/// The character a quoting sentinel (U+E000..=U+E005, see
+/// [`protect_special_chars`]) at the start of `pat` stands for.
+fn quoted_literal_at(pat: &[u8]) -> Option<u8> {
+    match pat {
+        [0xEE, 0x80, b @ 0x80..=0x85, ..] => Some(b"{}*?[]"[(b - 0x80) as usize]),
+        _ => None,
+    }
+}
+
 /// Try to match a character class at the start of `pat` against `ch`.
 /// Returns Some((matched, bytes_consumed)) or None if the class is malformed.
 fn match_char_class(pat: &[u8], ch: u8) -> Option<(bool, usize)> {
@@ -1441,6 +1949,33 @@ mod tests {
         assert_eq!(expand_word_part(&mut state, &part, None), "arg1 arg2 arg3");
     }
 
+    #[test]
+    fn special_var_flags() {
+        let mut state = test_state();
+        let part = WordPart::Variable("-".into());
+        assert_eq!(expand_word_part(&mut state, &part, None), "");
+        state.flags.insert(ShellFlag::Errexit);
+        state.flags.insert(ShellFlag::Nounset);
+        assert_eq!(expand_word_part(&mut state, &part, None), "eu");
+    }
+
+    #[test]
+    fn special_var_in_param_expansion() {
+        let mut state = test_state();
+        let part = WordPart::ParamExpansion {
+            var: "?".into(),
+            op: ":-".into(),
+            default: "unset".into(),
+        };
+        assert_eq!(expand_word_part(&mut state, &part, None), "42");
+        let part = WordPart::ParamExpansion {
+            var: "".into(),
+            op: "#".into(),
+            default: "".into(),
+        };
+        assert_eq!(expand_word_part(&mut state, &part, None), "3");
+    }
+
     #[test]
     fn special_var_random() {
         let mut state = test_state();
@@ -1810,14 +2345,53 @@ mod tests {
         assert_eq!(expand_word_part(&mut state, &part, None), "ell");
     }
 
+    #[test]
+    fn substring_bounds_matrix() {
+        let mut state = test_state();
+        state.env.insert("v".into(), "abcdef".into());
+        let cases = [
+            ("0", "abcdef"),
+            ("5", "f"),
+            ("6", ""),
+            ("10", ""),
+            ("6:2", ""),
+            ("10:2", ""),
+            ("2:0", ""),
+            ("2:100", "cdef"),
+            (" 1: -1", "bcde"),
+            ("1:-1", "bcde"),
+            ("0:-6", ""),
+            ("3:-4", ""),
+            ("1:-10", ""),
+            (" -2", "ef"),
+            (" -6", "abcdef"),
+            (" -7", ""),
+            (" -3:2", "de"),
+            (" -3:-1", "de"),
+        ];
+        for (operand, expected) in cases {
+            let part = WordPart::ParamExpansion {
+                var: "v".into(),
+                op: ":".into(),
+                default: operand.into(),
+            };
+            assert_eq!(
+                expand_word_part(&mut state, &part, None),
+                expected,
+                "${{v:{operand}}}"
+            );
+        }
+    }
+
     // ---- Array access ----
 
     #[test]
     fn array_element_access() {
         let mut state = test_state();
-        state
-            .arrays
-            .insert("arr".into(), vec!["a".into(), "b".into(), "c".into()]);
+        state.arrays.insert(
+            "arr".into(),
+            vec!["a".into(), "b".into(), "c".into()].into(),
+        );
         let part = WordPart::Variable("arr[1]".into());
         assert_eq!(expand_word_part(&mut state, &part, None), "b");
     }
@@ -1825,9 +2399,10 @@ mod tests {
     #[test]
     fn array_all_elements() {
         let mut state = test_state();
-        state
-            .arrays
-            .insert("arr".into(), vec!["a".into(), "b".into(), "c".into()]);
+        state.arrays.insert(
+            "arr".into(),
+            vec!["a".into(), "b".into(), "c".into()].into(),
+        );
         let part = WordPart::Variable("arr[@]".into());
         assert_eq!(expand_word_part(&mut state, &part, None), "a b c");
     }
@@ -1837,7 +2412,7 @@ mod tests {
         let mut state = test_state();
         state
             .arrays
-            .insert("arr".into(), vec!["x".into(), "y".into()]);
+            .insert("arr".into(), vec!["x".into(), "y".into()].into());
         let part = WordPart::Variable("arr[*]".into());
         assert_eq!(expand_word_part(&mut state, &part, None), "x y");
     }
@@ -1845,9 +2420,10 @@ mod tests {
     #[test]
     fn array_negative_index() {
         let mut state = test_state();
-        state
-            .arrays
-            .insert("arr".into(), vec!["a".into(), "b".into(), "c".into()]);
+        state.arrays.insert(
+            "arr".into(),
+            vec!["a".into(), "b".into(), "c".into()].into(),
+        );
         let part = WordPart::Variable("arr[-1]".into());
         assert_eq!(expand_word_part(&mut state, &part, None), "c");
     }
@@ -1855,9 +2431,10 @@ mod tests {
     #[test]
     fn array_length() {
         let mut state = test_state();
-        state
-            .arrays
-            .insert("arr".into(), vec!["a".into(), "b".into(), "c".into()]);
+        state.arrays.insert(
+            "arr".into(),
+            vec!["a".into(), "b".into(), "c".into()].into(),
+        );
         let part = WordPart::ParamExpansion {
             var: "".into(),
             op: "#".into(),
@@ -1871,7 +2448,7 @@ mod tests {
         let mut state = test_state();
         state.arrays.insert(
             "arr".into(),
-            vec!["a".into(), "b".into(), "c".into(), "d".into(), "e".into()],
+            vec!["a".into(), "b".into(), "c".into(), "d".into(), "e".into()].into(),
         );
         // ${arr[@]:1:3} → "b c d"
         let part = WordPart::ParamExpansion {
@@ -1887,7 +2464,7 @@ mod tests {
         let mut state = test_state();
         state.arrays.insert(
             "arr".into(),
-            vec!["a".into(), "b".into(), "c".into(), "d".into(), "e".into()],
+            vec!["a".into(), "b".into(), "c".into(), "d".into(), "e".into()].into(),
         );
         // arr[@]:2:2 encoded in Variable name (parser format for $arr[@]:offset:length)
         let part = WordPart::Variable("arr[@]:2:2".into());
@@ -2100,6 +2677,32 @@ mod tests {
         assert!(glob_matches("[!abc]", "d"));
     }
 
+    #[test]
+    fn extglob_groups() {
+        assert!(extglob_matches("@(foo|bar).txt", "bar.txt"));
+        assert!(!extglob_matches("@(foo|bar).txt", "baz.txt"));
+        assert!(extglob_matches("a?(b)c", "ac"));
+        assert!(extglob_matches("a?(b)c", "abc"));
+        assert!(!extglob_matches("a?(b)c", "abbc"));
+        assert!(extglob_matches("a*(b)c", "abbbc"));
+        assert!(extglob_matches("+(ab|c)", "abcab"));
+        assert!(!extglob_matches("+(ab)", ""));
+        assert!(extglob_matches("!(*.rs)", "main.py"));
+        assert!(!extglob_matches("!(*.rs)", "main.rs"));
+        assert!(extglob_matches("file[0-9]*", "file10"));
+    }
+
+    #[test]
+    fn extglob_negation() {
+        assert!(extglob_matches("!(foo)", "bar"));
+        assert!(extglob_matches("!(foo)", ""));
+        assert!(extglob_matches("!(foo)", "foobar"));
+        assert!(!extglob_matches("!(foo)", "foo"));
+        assert!(!extglob_matches("!(foo|bar)", "bar"));
+        assert!(extglob_matches("x!(a*)", "xbc"));
+        assert!(!extglob_matches("x!(a*)", "xabc"));
+    }
+
     // ---- Param expansion on unset var ----
 
     #[test]
@@ -2316,73 +2919,181 @@ mod tests {
 
     // ---- Glob expansion ----
 
+    fn home_state() -> ShellState {
+        let mut state = ShellState::new_default();
+        state.cwd = "/home/user".to_string();
+        state
+    }
+
+    /// A small tree under the test cwd for glob walks.
+    fn glob_tree() -> crate::test_support::mock::MockHost {
+        crate::test_support::mock::MockHost::new()
+            .with_dir("/home/user")
+            .with_dir("/home/user/abc")
+            .with_dir("/home/user/axc")
+            .with_dir("/home/user/abc/deep")
+            .with_file("/home/user/a.txt", b"")
+            .with_file("/home/user/b.txt", b"")
+            .with_file("/home/user/file1.txt", b"")
+            .with_file("/home/user/file2.txt", b"")
+            .with_file("/home/user/file10.txt", b"")
+            .with_file("/home/user/main.rs", b"")
+            .with_file("/home/user/lib.rs", b"")
+            .with_file("/home/user/README", b"")
+            .with_file("/home/user/.env", b"")
+            .with_file("/home/user/abc/x.txt", b"")
+            .with_file("/home/user/abc/foo", b"")
+            .with_file("/home/user/abc/deep/y.txt", b"")
+            .with_file("/home/user/axc/z.txt", b"")
+            .with_file("/home/user/axc/foo", b"")
+    }
+
     #[test]
     fn glob_expansion_with_matches() {
-        use crate::test_support::mock::MockHost;
-
-        // expand_globs resolves relative patterns against cwd, so register the absolute pattern.
-        let host = MockHost::new().with_glob_result(
-            "/home/user/*.txt",
-            vec![
-                "/home/user/b.txt".to_string(),
-                "/home/user/a.txt".to_string(),
-            ],
-        );
         let input = vec!["*.txt".to_string()];
-        let result = expand_globs(&host, &input, "/home/user");
+        let result = expand_globs(&glob_tree(), &input, &home_state());
         // Should be sorted and stripped back to relative paths
-        assert_eq!(result, vec!["a.txt", "b.txt"]);
+        assert_eq!(
+            result,
+            vec!["a.txt", "b.txt", "file1.txt", "file10.txt", "file2.txt"]
+        );
     }
 
     #[test]
     fn glob_expansion_no_matches_keeps_literal() {
-        use crate::test_support::mock::MockHost;
-
-        let host = MockHost::new();
-        let input = vec!["*.xyz".to_string()];
-        let result = expand_globs(&host, &input, "/home/user");
-        assert_eq!(result, vec!["*.xyz"]);
+        let input = vec!["*.xyz".to_string(), "nodir/*.txt".to_string()];
+        let result = expand_globs(&glob_tree(), &input, &home_state());
+        assert_eq!(result, vec!["*.xyz", "nodir/*.txt"]);
     }
 
     #[test]
     fn glob_expansion_question_mark() {
-        use crate::test_support::mock::MockHost;
+        let input = vec!["file?.txt".to_string()];
+        let result = expand_globs(&glob_tree(), &input, &home_state());
+        assert_eq!(result, vec!["file1.txt", "file2.txt"]);
+    }
 
-        let host = MockHost::new().with_glob_result(
-            "/home/user/file?.txt",
+    #[test]
+    fn glob_expansion_multi_segment_patterns() {
+        let host = glob_tree();
+        let state = home_state();
+        let input = vec!["*/foo".to_string()];
+        assert_eq!(
+            expand_globs(&host, &input, &state),
+            vec!["abc/foo", "axc/foo"]
+        );
+        let input = vec!["a?c/*.txt".to_string()];
+        assert_eq!(
+            expand_globs(&host, &input, &state),
+            vec!["abc/x.txt", "axc/z.txt"]
+        );
+        let input = vec!["/home/user/a[b]c/*".to_string()];
+        assert_eq!(
+            expand_globs(&host, &input, &state),
             vec![
-                "/home/user/file1.txt".to_string(),
-                "/home/user/file2.txt".to_string(),
-            ],
+                "/home/user/abc/deep",
+                "/home/user/abc/foo",
+                "/home/user/abc/x.txt"
+            ]
         );
-        let input = vec!["file?.txt".to_string()];
-        let result = expand_globs(&host, &input, "/home/user");
-        assert_eq!(result, vec!["file1.txt", "file2.txt"]);
+        let input = vec!["*/".to_string()];
+        assert_eq!(expand_globs(&host, &input, &state), vec!["abc/", "axc/"]);
     }
 
     #[test]
     fn glob_expansion_no_glob_chars_passthrough() {
-        use crate::test_support::mock::MockHost;
-
-        let host = MockHost::new();
         let input = vec!["plain.txt".to_string()];
-        let result = expand_globs(&host, &input, "/home/user");
+        let result = expand_globs(&glob_tree(), &input, &home_state());
         assert_eq!(result, vec!["plain.txt"]);
     }
 
     #[test]
     fn glob_expansion_mixed_words() {
+        let input = vec!["echo".to_string(), "*.rs".to_string(), "done".to_string()];
+        let result = expand_globs(&glob_tree(), &input, &home_state());
+        assert_eq!(result, vec!["echo", "lib.rs", "main.rs", "done"]);
+    }
+
+    #[test]
+    fn glob_expansion_nullglob_drops_unmatched() {
+        let mut state = home_state();
+        state.shopt.insert("nullglob".into(), true);
+        let input = vec!["ls".to_string(), "*.xyz".to_string()];
+        assert_eq!(expand_globs(&glob_tree(), &input, &state), vec!["ls"]);
+    }
+
+    #[test]
+    fn glob_expansion_hides_dotfiles_without_dotglob() {
         use crate::test_support::mock::MockHost;
 
-        let host = MockHost::new().with_glob_result(
-            "/home/user/*.rs",
+        let host = MockHost::new()
+            .with_file("/home/user/.env", b"")
+            .with_file("/home/user/a", b"");
+        let mut state = home_state();
+        let star = vec!["*".to_string()];
+        assert_eq!(expand_globs(&host, &star, &state), vec!["a"]);
+        let dot_star = vec![".*".to_string()];
+        assert_eq!(expand_globs(&host, &dot_star, &state), vec![".env"]);
+        state.shopt.insert("dotglob".into(), true);
+        assert_eq!(expand_globs(&host, &star, &state), vec![".env", "a"]);
+    }
+
+    #[test]
+    fn glob_expansion_nocaseglob() {
+        let host = glob_tree();
+        let mut state = home_state();
+        let input = vec!["read*".to_string()];
+        assert_eq!(expand_globs(&host, &input, &state), vec!["read*"]);
+        state.shopt.insert("nocaseglob".into(), true);
+        assert_eq!(expand_globs(&host, &input, &state), vec!["README"]);
+    }
+
+    #[test]
+    fn glob_expansion_globstar_gates_recursion() {
+        let host = glob_tree();
+        let mut state = home_state();
+        let input = vec!["**/*.txt".to_string()];
+        assert_eq!(
+            expand_globs(&host, &input, &state),
+            vec!["abc/x.txt", "axc/z.txt"]
+        );
+        state.shopt.insert("globstar".into(), true);
+        assert_eq!(
+            expand_globs(&host, &input, &state),
             vec![
-                "/home/user/main.rs".to_string(),
-                "/home/user/lib.rs".to_string(),
-            ],
+                "a.txt",
+                "abc/deep/y.txt",
+                "abc/x.txt",
+                "axc/z.txt",
+                "b.txt",
+                "file1.txt",
+                "file10.txt",
+                "file2.txt"
+            ]
         );
-        let input = vec!["echo".to_string(), "*.rs".to_string(), "done".to_string()];
-        let result = expand_globs(&host, &input, "/home/user");
-        assert_eq!(result, vec!["echo", "lib.rs", "main.rs", "done"]);
+    }
+
+    #[test]
+    fn glob_expansion_extglob_groups() {
+        let host = glob_tree();
+        let mut state = home_state();
+        let input = vec!["@(a|b).txt".to_string(), "!(*.txt|*.rs)".to_string()];
+        assert_eq!(expand_globs(&host, &input, &state), input);
+        state.shopt.insert("extglob".into(), true);
+        assert_eq!(
+            expand_globs(&host, &input, &state),
+            vec!["a.txt", "b.txt", "README", "abc", "axc"]
+        );
+        let input = vec!["file+([0-9]).txt".to_string(), "a@(b|x)c/foo".to_string()];
+        assert_eq!(
+            expand_globs(&host, &input, &state),
+            vec!["file1.txt", "file10.txt", "file2.txt", "abc/foo", "axc/foo"]
+        );
+    }
+
+    #[test]
+    fn collapse_globstar_keeps_only_whole_components() {
+        assert_eq!(collapse_globstar("/a/**/b**c", true), "/a/**/b*c");
+        assert_eq!(collapse_globstar("/a/**/b**c", false), "/a/*/b*c");
     }
 }
@@ -54,6 +54,9 @@ pub struct StatInfo {
     pub size: u64,
     pub mode: u32,
     pub mtime_ms: u64,
+    /// Stable file identity within the VFS; 0 when the host cannot supply one.
+    #[serde(default)]
+    pub inode: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -145,6 +148,8 @@ pub trait HostInterface {
 
     fn remove(&self, path: &str, recursive: bool) -> Result<(), HostError>;
 
+    /// Set the permission bits of `path`; later `stat` calls report them
+    /// in `StatInfo::mode`.
     fn chmod(&self, path: &str, mode: u32) -> Result<(), HostError>;
 
     fn glob(&self, pattern: &str) -> Result<Vec<String>, HostError>;
@@ -414,6 +419,17 @@ const DEFAULT_OUTBUF_CAP: usize = 4096;
 /// path or operation name).
 #[cfg(target_arch = "wasm32")]
 fn call_with_outbuf<F>(context: &str, f: F) -> Result<String, HostError>
+where
+    F: Fn(*mut u8, u32) -> i32,
+{
+    let buf = call_with_outbuf_bytes(context, f)?;
+    String::from_utf8(buf).map_err(|e| HostError::Other(format!("invalid UTF-8 from host: {e}")))
+}
+
+/// Like [`call_with_outbuf`], but returns the raw bytes so binary file
+/// contents and pipe data survive.
+#[cfg(target_arch = "wasm32")]
+fn call_with_outbuf_bytes<F>(context: &str, f: F) -> Result<Vec<u8>, HostError>
 where
     F: Fn(*mut u8, u32) -> i32,
 {
@@ -434,7 +450,7 @@ where
     } else {
         buf.truncate(n);
     }
-    String::from_utf8(buf).map_err(|e| HostError::Other(format!("invalid UTF-8 from host: {e}")))
+    Ok(buf)
 }
 
 // ---------------------------------------------------------------------------
@@ -502,10 +518,9 @@ impl HostInterface for WasmHost {
     }
 
     fn read_file(&self, path: &str) -> Result<Vec<u8>, HostError> {
-        let s = call_with_outbuf(path, |out_ptr, out_cap| unsafe {
+        call_with_outbuf_bytes(path, |out_ptr, out_cap| unsafe {
             host_read_file(path.as_ptr(), path.len() as u32, out_ptr, out_cap)
-        })?;
-        Ok(s.into_bytes())
+        })
     }
 
     fn write_file(&self, path: &str, data: &[u8], mode: WriteMode) -> Result<(), HostError> {
@@ -741,10 +756,9 @@ impl HostInterface for WasmHost {
     }
 
     fn read_fd(&self, fd: i32) -> Result<Vec<u8>, HostError> {
-        let result_str = call_with_outbuf("read_fd", |out_ptr, out_cap| unsafe {
+        call_with_outbuf_bytes("read_fd", |out_ptr, out_cap| unsafe {
             host_read_fd(fd, out_ptr, out_cap)
-        })?;
-        Ok(result_str.into_bytes())
+        })
     }
 
     fn write_fd(&self, fd: i32, data: &[u8]) -> Result<(), HostError> {
@@ -904,3 +918,40 @@ pub fn read_from_fd(fd: i32, buf: &mut [u8]) -> Result<usize, HostError> {
     Ok(nread as usize)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mock::MockHost;
+
+    #[test]
+    fn write_file_round_trips_non_utf8_bytes() {
+        let host = MockHost::new();
+        host.write_file("/tmp/bin", &[0xff, 0x00, 0xfe], WriteMode::Truncate)
+            .unwrap();
+        host.write_file("/tmp/bin", &[0x80, b'\n'], WriteMode::Append)
+            .unwrap();
+        assert_eq!(
+            host.read_file("/tmp/bin").unwrap(),
+            vec![0xff, 0x00, 0xfe, 0x80, b'\n']
+        );
+        host.write_file_str("/tmp/txt", "héllo", WriteMode::Truncate)
+            .unwrap();
+        assert_eq!(host.read_file("/tmp/txt").unwrap(), "héllo".as_bytes());
+    }
+
+    #[test]
+    fn chmod_updates_reported_mode() {
+        let host = MockHost::new().with_file("/tmp/f", b"").with_dir("/tmp/d");
+        assert_eq!(host.stat("/tmp/f").unwrap().mode, 0o644);
+        assert_eq!(host.stat("/tmp/d").unwrap().mode, 0o755);
+
+        host.chmod("/tmp/f", 0o700).unwrap();
+        host.chmod("/tmp/d", 0o500).unwrap();
+        assert_eq!(host.stat("/tmp/f").unwrap().mode, 0o700);
+        assert_eq!(host.stat("/tmp/d").unwrap().mode, 0o500);
+        assert!(matches!(
+            host.chmod("/tmp/missing", 0o755),
+            Err(HostError::NotFound(_))
+        ));
+    }
+}
@@ -1,13 +1,20 @@
+use std::fs::File;
+use std::io::Read;
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+
 /// Write bytes to fd 1.
 ///
-/// On wasm32: uses `print!()` which goes through WASI `fd_write(1)` → kernel.
+/// On wasm32: writes through Rust's stdout, i.e. WASI `fd_write(1)` → kernel.
 /// On native: writes directly to OS fd 1 via `libc::write`, bypassing Rust's
 /// stdout wrapper (which intercepts output during `cargo test`).
 pub fn write_stdout(data: &[u8]) {
     #[cfg(target_arch = "wasm32")]
     {
         // WASI fd_write(1) routes through kernel fd table → correct target.
-        print!("{}", String::from_utf8_lossy(data));
+        // Written as bytes so binary output (e.g. `printf '\xff'`) survives.
+        use std::io::Write;
+        let _ = std::io::stdout().lock().write_all(data);
     }
 
     #[cfg(not(target_arch = "wasm32"))]
@@ -55,6 +62,64 @@ pub fn write_stderr(data: &[u8]) {
     }
 }
 
+/// Lines read lazily from a file descriptor, each keeping its trailing
+/// newline (the last line may lack one).
+///
+/// Input is pulled in small chunks as lines are requested, so a consumer
+/// that stops early leaves the rest of the stream unread rather than
+/// draining the pipe to EOF. At most one chunk past the last line
+/// returned is consumed. The fd is borrowed and not closed on drop.
+pub struct FdLines {
+    file: ManuallyDrop<File>,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl FdLines {
+    pub fn new(fd: i32) -> Self {
+        // SAFETY: the File is never dropped, so the caller keeps ownership of `fd`.
+        let file = unsafe { File::from_raw_fd(fd) };
+        Self {
+            file: ManuallyDrop::new(file),
+            buf: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Stop reading and hand back the input pulled in past the last line
+    /// returned, so the caller can keep it for later.
+    pub fn into_remainder(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Iterator for FdLines {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=pos).collect();
+                return Some(String::from_utf8_lossy(&line).into_owned());
+            }
+            if self.eof {
+                if self.buf.is_empty() {
+                    return None;
+                }
+                let line = std::mem::take(&mut self.buf);
+                return Some(String::from_utf8_lossy(&line).into_owned());
+            }
+            let mut chunk = [0u8; 4096];
+            match self.file.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => self.eof = true,
+            }
+        }
+    }
+}
+
 /// Convenience macros for writing formatted output to stdout/stderr via fd.
 #[macro_export]
 macro_rules! shell_print {
@@ -87,3 +152,44 @@ macro_rules! shell_eprintln {
         $crate::io::write_stderr(s.as_bytes());
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fd_lines_stops_reading_an_endless_writer() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [read_fd, write_fd] = fds;
+        // Writes "y\n" until the reader goes away; the write then fails
+        // with EPIPE (SIGPIPE is ignored by the Rust runtime).
+        let writer = std::thread::spawn(move || {
+            let mut written = 0usize;
+            while unsafe { libc::write(write_fd, b"y\n".as_ptr() as *const libc::c_void, 2) } == 2 {
+                written += 2;
+            }
+            unsafe { libc::close(write_fd) };
+            written
+        });
+
+        let first: Vec<String> = FdLines::new(read_fd).take(3).collect();
+        assert_eq!(first, vec!["y\n", "y\n", "y\n"]);
+        unsafe { libc::close(read_fd) };
+        // The writer only finishes because the reader stopped early.
+        assert!(writer.join().unwrap() > 0);
+    }
+
+    #[test]
+    fn fd_lines_keeps_unterminated_last_line() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        unsafe {
+            libc::write(fds[1], b"a\nb".as_ptr() as *const libc::c_void, 3);
+            libc::close(fds[1]);
+        }
+        let lines: Vec<String> = FdLines::new(fds[0]).collect();
+        unsafe { libc::close(fds[0]) };
+        assert_eq!(lines, vec!["a\n", "b"]);
+    }
+}
@@ -14,7 +14,7 @@ mod wasm_entry {
     use std::sync::OnceLock;
 
     use codepod_shell_exec::control::{ControlFlow, RunResult};
-    use codepod_shell_exec::executor::exec_command;
+    use codepod_shell_exec::executor::{exec_str, run_exit_trap};
     use codepod_shell_exec::host::WasmHost;
     use codepod_shell_exec::shell_eprintln;
     use codepod_shell_exec::state::ShellState;
@@ -52,8 +52,7 @@ mod wasm_entry {
         // Track command in history
         state.history.push(cmd_str.to_string());
 
-        let ast = codepod_shell::parser::parse(cmd_str);
-        let result = match exec_command(&mut state, &host, &ast) {
+        let mut result = match exec_str(&mut state, &host, cmd_str) {
             Ok(ControlFlow::Normal(r)) => r,
             Ok(ControlFlow::Exit(code)) => RunResult::exit(code),
             Ok(_) => RunResult::empty(),
@@ -64,10 +63,7 @@ mod wasm_entry {
         };
 
         // Fire EXIT trap if one is registered
-        if let Some(trap_cmd) = state.traps.remove("EXIT") {
-            let trap_ast = codepod_shell::parser::parse(&trap_cmd);
-            let _ = exec_command(&mut state, &host, &trap_ast);
-        }
+        result.exit_code = run_exit_trap(&mut state, &host, result.exit_code);
 
         // Include env state in result for host sync
         #[derive(serde::Serialize)]
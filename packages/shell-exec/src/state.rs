@@ -1,15 +1,39 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicI32, Ordering};
 
 use codepod_shell::ast::Command;
 
 pub const MAX_SUBSTITUTION_DEPTH: u32 = 50;
 pub const MAX_FUNCTION_DEPTH: u32 = 100;
 
+/// Source of pseudo-PIDs for shell instances and their subshells.
+static NEXT_SHELL_PID: AtomicI32 = AtomicI32::new(1);
+
+/// Allocate a fresh pseudo-PID, unique within this process.
+pub fn alloc_shell_pid() -> i32 {
+    NEXT_SHELL_PID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Options `shopt` recognises, with their initial values.
+pub const SHOPT_OPTIONS: &[(&str, bool)] = &[
+    ("dotglob", false),
+    // Aliases are always expanded; the option is accepted for scripts
+    // that turn it on.
+    ("expand_aliases", true),
+    ("extglob", false),
+    ("globstar", false),
+    ("nocaseglob", false),
+    ("nullglob", false),
+    ("xpg_echo", false),
+];
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ShellFlag {
     Errexit,
     Nounset,
     Pipefail,
+    /// `set -o posix`: stricter POSIX grammar and output formats.
+    Posix,
 }
 
 #[derive(Debug, Clone)]
@@ -20,20 +44,129 @@ pub struct Job {
     pub done: Option<i32>, // exit code once reaped, None if running
 }
 
+/// An indexed array. Indices are stable as in bash: `unset 'arr[i]'` leaves
+/// a hole rather than shifting later elements down, and assigning past the
+/// end does not create the elements in between. Holes are skipped by
+/// `${arr[@]}`, `${#arr[@]}` and `${!arr[@]}`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexedArray {
+    /// Element `i` lives in slot `i`; never ends in a hole.
+    slots: Vec<Option<String>>,
+}
+
+impl IndexedArray {
+    /// The set elements, in index order.
+    pub fn values(&self) -> impl Iterator<Item = &String> {
+        self.slots.iter().flatten()
+    }
+
+    /// The indices of the set elements, in order.
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.as_ref().map(|_| i))
+    }
+
+    /// The set elements, in index order, as an owned list.
+    pub fn to_vec(&self) -> Vec<String> {
+        self.values().cloned().collect()
+    }
+
+    /// The number of set elements.
+    pub fn len(&self) -> usize {
+        self.values().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// One past the highest set index; negative subscripts count back
+    /// from here.
+    pub fn end(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&String> {
+        self.slots.get(idx)?.as_ref()
+    }
+
+    pub fn set(&mut self, idx: usize, value: String) {
+        if self.slots.len() <= idx {
+            self.slots.resize(idx + 1, None);
+        }
+        self.slots[idx] = Some(value);
+    }
+
+    pub fn unset(&mut self, idx: usize) {
+        if let Some(slot) = self.slots.get_mut(idx) {
+            *slot = None;
+        }
+        while self.slots.last() == Some(&None) {
+            self.slots.pop();
+        }
+    }
+
+    /// Append after the highest set index, as `arr+=(...)` does.
+    pub fn push(&mut self, value: String) {
+        self.slots.push(Some(value));
+    }
+}
+
+impl From<Vec<String>> for IndexedArray {
+    fn from(values: Vec<String>) -> Self {
+        values.into_iter().collect()
+    }
+}
+
+impl FromIterator<String> for IndexedArray {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        IndexedArray {
+            slots: iter.into_iter().map(Some).collect(),
+        }
+    }
+}
+
+impl Extend<String> for IndexedArray {
+    fn extend<I: IntoIterator<Item = String>>(&mut self, iter: I) {
+        self.slots.extend(iter.into_iter().map(Some));
+    }
+}
+
+/// A variable's value as it was before a `local` (or in-function `declare`)
+/// shadowed it. All three namespaces are captured so that leaving the
+/// function restores scalars, indexed arrays and associative arrays alike.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SavedVar {
+    pub scalar: Option<String>,
+    pub array: Option<IndexedArray>,
+    pub assoc: Option<HashMap<String, String>>,
+}
+
 pub struct ShellState {
     pub env: HashMap<String, String>,
-    pub arrays: HashMap<String, Vec<String>>,
+    pub arrays: HashMap<String, IndexedArray>,
     pub assoc_arrays: HashMap<String, HashMap<String, String>>,
     pub functions: HashMap<String, Command>,
     /// Alias table: name → replacement text.
     pub aliases: HashMap<String, String>,
+    /// Command hash table (`hash`): name → resolved path.
+    pub command_hash: HashMap<String, String>,
+    /// Completion specs recorded by `complete`: name → option arguments.
+    /// Never acted on; kept so `complete -p` can list them.
+    pub completions: HashMap<String, Vec<String>>,
+    /// Resource limits set with `ulimit`, keyed by option letter.
+    pub limits: HashMap<char, String>,
+    /// `shopt` options, pre-populated from [`SHOPT_OPTIONS`].
+    pub shopt: HashMap<String, bool>,
     pub flags: HashSet<ShellFlag>,
     pub positional_args: Vec<String>,
     pub last_exit_code: i32,
     pub function_depth: u32,
     pub substitution_depth: u32,
     pub traps: HashMap<String, String>,
-    pub local_var_stack: Vec<HashMap<String, Option<String>>>,
+    pub local_var_stack: Vec<HashMap<String, SavedVar>>,
     pub history: Vec<String>,
     pub cwd: String,
     /// Seed for $RANDOM pseudo-random number generator.
@@ -42,12 +175,33 @@ pub struct ShellState {
     pub param_error: Option<String>,
     /// Stdin data for compound commands in a pipeline.
     pub pipeline_stdin: Option<String>,
+    /// Unread input left over by `read -u FD`, keyed by descriptor.
+    pub fd_buffers: HashMap<i32, String>,
+    /// Descriptors opened by `exec N< file`. Their whole input lives in
+    /// `fd_buffers`, so `read -u` never reads the host fd behind them.
+    pub fd_files: HashSet<i32>,
     /// Set of variable names marked as readonly.
     pub readonly_vars: HashSet<String>,
+    /// Variables kept in `env` but withheld from child processes
+    /// (`export -n`, `declare +x`). Everything else in `env` is exported.
+    pub unexported: HashSet<String>,
+    /// Variables with the integer attribute (`declare -i`); assignments to
+    /// them are evaluated arithmetically.
+    pub integer_vars: HashSet<String>,
     /// Directory stack for pushd/popd.
     pub dir_stack: Vec<String>,
-    /// Captured groups from last `[[ ... =~ ... ]]` regex match.
-    pub bash_rematch: Vec<String>,
+    /// Source text of the command the DEBUG trap is running for
+    /// (`$BASH_COMMAND`).
+    pub bash_command: String,
+    /// Final expanded word of the previous simple command (`$_`); the
+    /// shell's name until the first command runs.
+    pub last_arg: String,
+    /// Set while the DEBUG trap runs so its own commands do not re-fire it.
+    pub in_debug_trap: bool,
+    /// Nesting depth of contexts where failure is tested rather than an
+    /// error: `if`/`while` conditions, the left side of `&&`/`||`, and `!`.
+    /// The ERR trap does not fire inside them.
+    pub condition_depth: u32,
     /// Counter for generating unique process substitution temp file paths.
     pub proc_sub_counter: u32,
     /// Current stdout fd for the executing context (default: 1).
@@ -61,6 +215,12 @@ pub struct ShellState {
     pub next_job_id: usize,
     /// PID of most recently backgrounded process ($!).
     pub last_bg_pid: i32,
+    /// Pseudo-PID of this shell instance ($$), fixed at construction.
+    pub pid: i32,
+    /// Parent PID ($PPID); 0 unless the host supplies one.
+    pub ppid: i32,
+    /// PID of the current (sub)shell ($BASHPID); differs from `pid` in subshells.
+    pub bashpid: i32,
 }
 
 impl ShellState {
@@ -72,6 +232,9 @@ impl ShellState {
         env.insert("PATH".into(), "/bin:/usr/bin".into());
         env.insert("PYTHONPATH".into(), "/usr/lib/python".into());
         env.insert("SHELL".into(), "/bin/sh".into());
+        // Shell variable, not exported: getopts starts from the first argument.
+        env.insert("OPTIND".into(), "1".into());
+        let pid = alloc_shell_pid();
 
         Self {
             env,
@@ -79,6 +242,13 @@ impl ShellState {
             assoc_arrays: HashMap::new(),
             functions: HashMap::new(),
             aliases: HashMap::new(),
+            command_hash: HashMap::new(),
+            completions: HashMap::new(),
+            limits: HashMap::new(),
+            shopt: SHOPT_OPTIONS
+                .iter()
+                .map(|&(name, on)| (name.to_string(), on))
+                .collect(),
             flags: HashSet::new(),
             positional_args: Vec::new(),
             last_exit_code: 0,
@@ -91,18 +261,132 @@ impl ShellState {
             rng_seed: 12345, // deterministic default; host can override
             param_error: None,
             pipeline_stdin: None,
+            fd_buffers: HashMap::new(),
+            fd_files: HashSet::new(),
             readonly_vars: HashSet::new(),
+            unexported: HashSet::from(["OPTIND".to_string()]),
+            integer_vars: HashSet::new(),
             dir_stack: Vec::new(),
-            bash_rematch: Vec::new(),
+            bash_command: String::new(),
+            last_arg: "codepod-shell".into(),
+            in_debug_trap: false,
+            condition_depth: 0,
             proc_sub_counter: 0,
             stdout_fd: 1,
             stdin_fd: 0,
             jobs: Vec::new(),
             next_job_id: 1,
             last_bg_pid: 0,
+            pid,
+            ppid: 0,
+            bashpid: pid,
+        }
+    }
+
+    /// Whether the `shopt` option `name` is enabled.
+    pub fn shopt_enabled(&self, name: &str) -> bool {
+        self.shopt.get(name).copied().unwrap_or(false)
+    }
+
+    /// The environment handed to spawned processes: every variable in
+    /// `env` except those marked unexported.
+    pub fn exported_env(&self) -> Vec<(&str, &str)> {
+        self.env
+            .iter()
+            .filter(|(k, _)| !self.unexported.contains(*k))
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    /// Note that `name` was assigned or unset. Any change to `PATH` empties
+    /// the command hash table, as bash does.
+    pub fn var_changed(&mut self, name: &str) {
+        if name == "PATH" {
+            self.command_hash.clear();
+        }
+    }
+
+    /// Record the current value of `name` in the innermost local frame,
+    /// unless it was already saved there. No-op outside a function.
+    pub fn save_local(&mut self, name: &str) {
+        if self.local_var_stack.is_empty() {
+            return;
+        }
+        let saved = SavedVar {
+            scalar: self.env.get(name).cloned(),
+            array: self.arrays.get(name).cloned(),
+            assoc: self.assoc_arrays.get(name).cloned(),
+        };
+        if let Some(frame) = self.local_var_stack.last_mut() {
+            frame.entry(name.to_string()).or_insert(saved);
         }
     }
 
+    /// Pop the innermost local frame and put every saved variable back.
+    pub fn pop_local_frame(&mut self) {
+        let Some(frame) = self.local_var_stack.pop() else {
+            return;
+        };
+        for (name, saved) in frame {
+            self.var_changed(&name);
+            match saved.scalar {
+                Some(v) => {
+                    self.env.insert(name.clone(), v);
+                }
+                None => {
+                    self.env.remove(&name);
+                }
+            }
+            match saved.array {
+                Some(v) => {
+                    self.arrays.insert(name.clone(), v);
+                }
+                None => {
+                    self.arrays.remove(&name);
+                }
+            }
+            match saved.assoc {
+                Some(v) => {
+                    self.assoc_arrays.insert(name, v);
+                }
+                None => {
+                    self.assoc_arrays.remove(&name);
+                }
+            }
+        }
+    }
+
+    /// Run `f` with `args` as the positional parameters, restoring the
+    /// caller's afterwards. Shared by function calls, `source file args...`
+    /// and script execution so every path scopes `$@` the same way.
+    pub fn with_positional_args<R>(
+        &mut self,
+        args: Vec<String>,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let saved = std::mem::replace(&mut self.positional_args, args);
+        let result = f(self);
+        self.positional_args = saved;
+        result
+    }
+
+    /// Run `f` as a function body: fresh positional parameters plus a new
+    /// frame for `local` variables, both undone when `f` returns.
+    pub fn with_function_frame<R>(
+        &mut self,
+        args: Vec<String>,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        self.with_positional_args(args, |state| {
+            state.function_depth += 1;
+            state.local_var_stack.push(HashMap::new());
+            let result = f(state);
+            state.pop_local_frame();
+            state.function_depth -= 1;
+            result
+        })
+    }
+
     pub fn resolve_path(&self, path: &str) -> String {
         if path.starts_with('/') {
             return path.to_string();
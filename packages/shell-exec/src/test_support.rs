@@ -16,6 +16,7 @@ pub mod mock {
         pub argv0: Option<String>,
         pub args: Vec<String>,
         pub stdin: String,
+        pub env: HashMap<String, String>,
     }
 
     /// Mock-only spawn output that carries stdout/stderr data for piping,
@@ -33,10 +34,17 @@ pub mod mock {
     /// through a `&self` reference (as required by the `HostInterface` trait).
     pub struct MockHost {
         files: RefCell<HashMap<String, Vec<u8>>>,
-        dirs: HashSet<String>,
+        dirs: RefCell<HashSet<String>>,
+        /// Modification times (ms since epoch) reported by `stat`; default 0.
+        mtimes: HashMap<String, u64>,
+        /// File modes reported by `stat`; default 0o644.
+        modes: RefCell<HashMap<String, u32>>,
+        /// Symlink targets keyed by link path, as returned by `readlink`.
+        symlinks: HashMap<String, String>,
+        /// Records every path passed to `stat`, for test assertions.
+        stat_calls: RefCell<Vec<String>>,
         tools: HashSet<String>,
         spawn_results: HashMap<String, MockSpawnOutput>,
-        glob_results: HashMap<String, Vec<String>>,
         /// Records every spawn invocation for later assertion.
         spawn_calls: RefCell<Vec<SpawnCall>>,
         /// Optional dynamic spawn handler: receives (program, args, stdin) and
@@ -63,10 +71,13 @@ pub mod mock {
         pub fn new() -> Self {
             Self {
                 files: RefCell::new(HashMap::new()),
-                dirs: HashSet::new(),
+                dirs: RefCell::new(HashSet::new()),
+                mtimes: HashMap::new(),
+                modes: RefCell::new(HashMap::new()),
+                symlinks: HashMap::new(),
+                stat_calls: RefCell::new(Vec::new()),
                 tools: HashSet::new(),
                 spawn_results: HashMap::new(),
-                glob_results: HashMap::new(),
                 spawn_calls: RefCell::new(Vec::new()),
                 spawn_handler: None,
                 fetch_results: HashMap::new(),
@@ -97,14 +108,26 @@ pub mod mock {
         }
 
         /// Add a directory.
-        pub fn with_dir(mut self, path: &str) -> Self {
-            self.dirs.insert(path.to_string());
+        pub fn with_dir(self, path: &str) -> Self {
+            self.dirs.borrow_mut().insert(path.to_string());
             self
         }
 
-        /// Register pre-configured glob results for a pattern.
-        pub fn with_glob_result(mut self, pattern: &str, matches: Vec<String>) -> Self {
-            self.glob_results.insert(pattern.to_string(), matches);
+        /// Set the modification time (ms since epoch) reported for a path.
+        pub fn with_mtime(mut self, path: &str, mtime_ms: u64) -> Self {
+            self.mtimes.insert(path.to_string(), mtime_ms);
+            self
+        }
+
+        /// Set the file mode reported for a path.
+        pub fn with_mode(self, path: &str, mode: u32) -> Self {
+            self.modes.borrow_mut().insert(path.to_string(), mode);
+            self
+        }
+
+        /// Add a symlink at `path` pointing to `target`.
+        pub fn with_symlink(mut self, path: &str, target: &str) -> Self {
+            self.symlinks.insert(path.to_string(), target.to_string());
             self
         }
 
@@ -126,6 +149,11 @@ pub mod mock {
                 .and_then(|data| String::from_utf8(data.clone()).ok())
         }
 
+        /// Retrieve every path passed to `stat`, for test assertions.
+        pub fn get_stat_calls(&self) -> Vec<String> {
+            self.stat_calls.borrow().clone()
+        }
+
         /// Retrieve all recorded spawn calls for test assertions.
         pub fn get_spawn_calls(&self) -> Vec<SpawnCall> {
             self.spawn_calls.borrow().clone()
@@ -143,13 +171,21 @@ pub mod mock {
         }
     }
 
+    /// Deterministic, nonzero inode number for a mock path.
+    fn path_inode(path: &str) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        path.hash(&mut hasher);
+        hasher.finish() | 1
+    }
+
     impl HostInterface for MockHost {
         fn spawn(
             &self,
             program: &str,
             argv0: Option<&str>,
             args: &[&str],
-            _env: &[(&str, &str)],
+            env: &[(&str, &str)],
             _cwd: &str,
             stdin_data: &str,
             stdin_fd: i32,
@@ -173,6 +209,10 @@ pub mod mock {
                 argv0: argv0.map(|s| s.to_string()),
                 args: args.iter().map(|s| s.to_string()).collect(),
                 stdin: effective_stdin.clone(),
+                env: env
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
             });
 
             // Resolve the mock spawn output from handler or static map.
@@ -222,7 +262,9 @@ pub mod mock {
         }
 
         fn stat(&self, path: &str) -> Result<StatInfo, HostError> {
+            self.stat_calls.borrow_mut().push(path.to_string());
             let files = self.files.borrow();
+            let mtime_ms = self.mtimes.get(path).copied().unwrap_or(0);
             if let Some(data) = files.get(path) {
                 Ok(StatInfo {
                     exists: true,
@@ -230,18 +272,31 @@ pub mod mock {
                     is_dir: false,
                     is_symlink: false,
                     size: data.len() as u64,
-                    mode: 0o644,
-                    mtime_ms: 0,
+                    mode: self.modes.borrow().get(path).copied().unwrap_or(0o644),
+                    mtime_ms,
+                    inode: path_inode(path),
                 })
-            } else if self.dirs.contains(path) {
+            } else if self.dirs.borrow().contains(path) {
                 Ok(StatInfo {
                     exists: true,
                     is_file: false,
                     is_dir: true,
                     is_symlink: false,
                     size: 0,
-                    mode: 0o755,
-                    mtime_ms: 0,
+                    mode: self.modes.borrow().get(path).copied().unwrap_or(0o755),
+                    mtime_ms,
+                    inode: path_inode(path),
+                })
+            } else if let Some(target) = self.symlinks.get(path) {
+                Ok(StatInfo {
+                    exists: true,
+                    is_file: false,
+                    is_dir: false,
+                    is_symlink: true,
+                    size: target.len() as u64,
+                    mode: 0o777,
+                    mtime_ms,
+                    inode: path_inode(path),
                 })
             } else {
                 Ok(StatInfo {
@@ -252,6 +307,7 @@ pub mod mock {
                     size: 0,
                     mode: 0,
                     mtime_ms: 0,
+                    inode: 0,
                 })
             }
         }
@@ -294,7 +350,7 @@ pub mod mock {
                     }
                 }
             }
-            for key in &self.dirs {
+            for key in self.dirs.borrow().iter() {
                 if let Some(rest) = key.strip_prefix(&prefix) {
                     if let Some(name) = rest.split('/').next() {
                         if !name.is_empty() {
@@ -308,23 +364,66 @@ pub mod mock {
             Ok(result)
         }
 
-        fn mkdir(&self, _path: &str) -> Result<(), HostError> {
+        fn mkdir(&self, path: &str) -> Result<(), HostError> {
+            self.dirs.borrow_mut().insert(path.to_string());
             Ok(())
         }
 
-        fn remove(&self, _path: &str, _recursive: bool) -> Result<(), HostError> {
+        fn remove(&self, path: &str, recursive: bool) -> Result<(), HostError> {
+            let mut files = self.files.borrow_mut();
+            files.remove(path);
+            if recursive {
+                let prefix = format!("{}/", path.trim_end_matches('/'));
+                files.retain(|key, _| !key.starts_with(&prefix));
+            }
             Ok(())
         }
 
-        fn chmod(&self, _path: &str, _mode: u32) -> Result<(), HostError> {
+        fn chmod(&self, path: &str, mode: u32) -> Result<(), HostError> {
+            if !self.files.borrow().contains_key(path) && !self.dirs.borrow().contains(path) {
+                return Err(HostError::NotFound(path.to_string()));
+            }
+            self.modes.borrow_mut().insert(path.to_string(), mode);
             Ok(())
         }
 
-        fn glob(&self, pattern: &str) -> Result<Vec<String>, HostError> {
-            Ok(self.glob_results.get(pattern).cloned().unwrap_or_default())
+        fn glob(&self, _pattern: &str) -> Result<Vec<String>, HostError> {
+            Ok(Vec::new())
         }
 
-        fn rename(&self, _from: &str, _to: &str) -> Result<(), HostError> {
+        fn rename(&self, from: &str, to: &str) -> Result<(), HostError> {
+            // Move the entry and everything beneath it, modes included.
+            let moved = |key: &str| {
+                if key == from {
+                    Some(to.to_string())
+                } else {
+                    key.strip_prefix(from)
+                        .filter(|rest| rest.starts_with('/'))
+                        .map(|rest| format!("{to}{rest}"))
+                }
+            };
+            let mut files = self.files.borrow_mut();
+            let mut dirs = self.dirs.borrow_mut();
+            if !files.contains_key(from) && !dirs.contains(from) {
+                return Err(HostError::NotFound(from.to_string()));
+            }
+            if from != to {
+                files.remove(to);
+                self.modes.borrow_mut().remove(to);
+            }
+            *files = std::mem::take(&mut *files)
+                .into_iter()
+                .map(|(k, v)| (moved(&k).unwrap_or(k), v))
+                .collect();
+            *dirs = std::mem::take(&mut *dirs)
+                .into_iter()
+                .map(|k| moved(&k).unwrap_or(k))
+                .collect();
+            let mut modes = self.modes.borrow_mut();
+            *modes = std::mem::take(&mut *modes)
+                .into_iter()
+                .map(|(k, v)| (moved(&k).unwrap_or(k), v))
+                .collect();
             Ok(())
         }
 
@@ -333,7 +432,10 @@ pub mod mock {
         }
 
         fn readlink(&self, path: &str) -> Result<String, HostError> {
-            Err(HostError::NotFound(path.to_string()))
+            self.symlinks
+                .get(path)
+                .cloned()
+                .ok_or_else(|| HostError::NotFound(path.to_string()))
         }
 
         fn fetch(
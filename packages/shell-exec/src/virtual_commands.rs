@@ -3,6 +3,7 @@
 //! Command logic runs entirely in the sandbox (Rust). Only I/O crosses to the
 //! host via `HostInterface::fetch` / `register_tool`.
 
+use crate::builtins::normalize_path;
 use crate::control::RunResult;
 use crate::host::{HostInterface, WriteMode};
 use crate::state::ShellState;
@@ -376,11 +377,17 @@ struct PkgInfo {
     size: usize,
     #[serde(rename = "installedAt")]
     installed_at: u64,
+    /// Every VFS path the package wrote, removed again by `pkg remove`.
+    #[serde(default)]
+    files: Vec<String>,
 }
 
 fn cmd_pkg(state: &mut ShellState, host: &dyn HostInterface, args: &[String]) -> RunResult {
     if args.is_empty() {
-        shell_eprint!("{}", "pkg: usage: pkg <install|remove|list|info> [args]\n");
+        shell_eprint!(
+            "{}",
+            "pkg: usage: pkg <add|install|remove|list|info|search> [args]\n"
+        );
         return RunResult::exit(1);
     }
 
@@ -388,7 +395,7 @@ fn cmd_pkg(state: &mut ShellState, host: &dyn HostInterface, args: &[String]) ->
     let sub_args = &args[1..];
 
     match subcmd {
-        "install" => pkg_install(state, host, sub_args),
+        "add" | "install" => pkg_install(state, host, sub_args),
         "remove" => pkg_remove(state, host, sub_args),
         "list" => pkg_list(host),
         "info" => pkg_info(host, sub_args),
@@ -445,16 +452,22 @@ fn extract_host(url: &str) -> Option<String> {
     Some(host.to_string())
 }
 
-/// Extract the package name from a URL (basename without .wasm extension).
+/// Extract the package name from a URL (basename without .wasm/.zip extension).
 fn pkg_name_from_url(url: &str) -> String {
     let path = url.split('?').next().unwrap_or(url);
     let basename = path.rsplit('/').next().unwrap_or("package");
     basename
         .strip_suffix(".wasm")
+        .or_else(|| basename.strip_suffix(".zip"))
         .unwrap_or(basename)
         .to_string()
 }
 
+/// Whether a package URL points at a ZIP archive rather than a single binary.
+fn is_archive_url(url: &str) -> bool {
+    url.split('?').next().unwrap_or(url).ends_with(".zip")
+}
+
 /// Pkg index from the remote codepod registry (pkg-index.json).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct PkgIndex {
@@ -500,12 +513,37 @@ fn fetch_pkg_index(state: &ShellState, host: &dyn HostInterface) -> Result<PkgIn
 }
 
 fn pkg_install(state: &mut ShellState, host: &dyn HostInterface, args: &[String]) -> RunResult {
-    if args.is_empty() {
-        shell_eprint!("{}", "pkg install: no package name or URL specified\n");
-        return RunResult::exit(1);
+    let mut prefix: Option<String> = None;
+    let mut target: Option<&String> = None;
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == "--prefix" {
+            i += 1;
+            match args.get(i) {
+                Some(dir) => prefix = Some(normalize_path(&state.resolve_path(dir))),
+                None => {
+                    shell_eprint!("{}", "pkg install: --prefix requires a directory\n");
+                    return RunResult::exit(1);
+                }
+            }
+        } else if target.is_none() {
+            target = Some(&args[i]);
+        }
+        i += 1;
     }
 
-    let arg = &args[0];
+    let arg = match target {
+        Some(arg) => arg,
+        None => {
+            shell_eprint!("{}", "pkg install: no package name or URL specified\n");
+            return RunResult::exit(1);
+        }
+    };
+
+    if prefix.is_some() && !is_archive_url(arg) {
+        shell_eprint!("{}", "pkg install: --prefix requires a .zip archive URL\n");
+        return RunResult::exit(1);
+    }
 
     // Read policy
     let policy = match read_pkg_policy(host) {
@@ -566,6 +604,7 @@ fn pkg_install(state: &mut ShellState, host: &dyn HostInterface, args: &[String]
         let _ = host.mkdir("/usr/share/pkg");
         let _ = host.mkdir("/usr/share/pkg/bin");
         let mut total_size = 0;
+        let mut files = Vec::new();
 
         for (tool_name, wasm_path) in &entry.tools {
             let url = format!("{base_url}/{wasm_path}");
@@ -596,6 +635,7 @@ fn pkg_install(state: &mut ShellState, host: &dyn HostInterface, args: &[String]
                 return RunResult::exit(1);
             }
             total_size += wasm_bytes.len();
+            files.push(dest);
         }
 
         packages.push(PkgInfo {
@@ -603,6 +643,7 @@ fn pkg_install(state: &mut ShellState, host: &dyn HostInterface, args: &[String]
             url: format!("registry:{name}"),
             size: total_size,
             installed_at: host.time() as u64,
+            files,
         });
         write_pkg_metadata(host, &packages);
 
@@ -671,6 +712,45 @@ fn pkg_install(state: &mut ShellState, host: &dyn HostInterface, args: &[String]
 
     // Ensure directories exist
     let _ = host.mkdir("/usr/share/pkg");
+
+    // Archives are unpacked under a prefix instead of registered as a tool.
+    if is_archive_url(url) {
+        let archive = result.body_bytes();
+        let entries = match crate::wheel::extract_zip(&archive) {
+            Ok(entries) => entries,
+            Err(e) => {
+                shell_eprint!("pkg install: failed to unpack {name}: {e}\n");
+                return RunResult::exit(1);
+            }
+        };
+        let prefix = prefix.unwrap_or_else(|| format!("/usr/share/pkg/{name}"));
+        let _ = host.mkdir(&prefix);
+        let mut files = Vec::new();
+        for (path, content) in &entries {
+            let full_path = format!("{}/{path}", prefix.trim_end_matches('/'));
+            if let Some((parent, _)) = full_path.rsplit_once('/') {
+                let _ = host.mkdir(parent);
+            }
+            if let Err(e) = host.write_file(&full_path, content, WriteMode::Truncate) {
+                shell_eprint!("pkg install: failed to write {full_path}: {e}\n");
+                return RunResult::exit(1);
+            }
+            files.push(full_path);
+        }
+
+        packages.push(PkgInfo {
+            name: name.clone(),
+            url: url.clone(),
+            size: archive.len(),
+            installed_at: host.time() as u64,
+            files,
+        });
+        write_pkg_metadata(host, &packages);
+
+        shell_print!("Installed {name} ({} files in {prefix})\n", entries.len());
+        return RunResult::empty();
+    }
+
     let _ = host.mkdir("/usr/share/pkg/bin");
 
     // Write binary to VFS
@@ -686,6 +766,7 @@ fn pkg_install(state: &mut ShellState, host: &dyn HostInterface, args: &[String]
         url: url.clone(),
         size,
         installed_at: host.time() as u64,
+        files: vec![wasm_path.clone()],
     };
     packages.push(info);
     write_pkg_metadata(host, &packages);
@@ -726,14 +807,20 @@ fn pkg_remove(state: &mut ShellState, host: &dyn HostInterface, args: &[String])
 
     let mut packages = read_pkg_metadata(host);
 
-    if !packages.iter().any(|p| p.name == *name) {
+    let Some(pkg) = packages.iter().find(|p| p.name == *name) else {
         shell_eprint!("pkg remove: '{name}' is not installed\n");
         return RunResult::exit(1);
-    }
+    };
 
-    // Delete binary
-    let wasm_path = format!("/usr/share/pkg/bin/{name}.wasm");
-    let _ = host.remove(&wasm_path, false);
+    // Delete every recorded file; manifests written before files were
+    // tracked only ever installed the single binary.
+    if pkg.files.is_empty() {
+        let _ = host.remove(&format!("/usr/share/pkg/bin/{name}.wasm"), false);
+    } else {
+        for path in &pkg.files {
+            let _ = host.remove(path, false);
+        }
+    }
 
     // Update metadata
     packages.retain(|p| p.name != *name);
@@ -791,10 +878,16 @@ fn pkg_info(host: &dyn HostInterface, args: &[String]) -> RunResult {
     let packages = read_pkg_metadata(host);
     match packages.iter().find(|p| p.name == *name) {
         Some(p) => {
-            let out = format!(
+            let mut out = format!(
                 "Name: {}\nURL: {}\nSize: {} bytes\nInstalled: {}\n",
                 p.name, p.url, p.size, p.installed_at
             );
+            if !p.files.is_empty() {
+                out.push_str("Files:\n");
+                for path in &p.files {
+                    out.push_str(&format!("  {path}\n"));
+                }
+            }
             shell_print!("{}", out);
             RunResult::empty()
         }
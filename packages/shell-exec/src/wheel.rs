@@ -2,6 +2,7 @@
 //!
 //! A Python wheel is a ZIP file. We extract all files, skipping
 //! .dist-info/ and .data/ directories which contain metadata only.
+//! `extract_zip` unpacks plain archives for `pkg add`.
 
 use std::io::{Cursor, Read};
 
@@ -67,6 +68,41 @@ pub fn extract_wheel(data: &[u8]) -> Result<Vec<WheelFile>, String> {
     Ok(files)
 }
 
+/// Extract every file from a ZIP archive as raw bytes (used by `pkg add`).
+///
+/// Entries whose paths would escape the destination (absolute paths or
+/// `..` components) are rejected.
+pub fn extract_zip(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let cursor = Cursor::new(data);
+    let mut archive = zip::ZipArchive::new(cursor).map_err(|e| format!("invalid zip: {e}"))?;
+
+    let mut files = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| format!("zip entry {i}: {e}"))?;
+
+        if entry.is_dir() {
+            continue;
+        }
+
+        let name = entry.name().to_string();
+        if entry.enclosed_name().is_none() {
+            return Err(format!("unsafe path in archive: {name}"));
+        }
+
+        let mut content = Vec::new();
+        entry
+            .read_to_end(&mut content)
+            .map_err(|e| format!("reading {name}: {e}"))?;
+
+        files.push((name, content));
+    }
+
+    Ok(files)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -78,29 +78,33 @@ pub enum Command {
         op: ListOp,
         right: Box<Command>,
     },
-    /// If conditional.
+    /// If conditional: if ...; then ...; fi [redirects].
     If {
         condition: Box<Command>,
         then_body: Box<Command>,
         else_body: Option<Box<Command>>,
+        redirects: Vec<Redirect>,
     },
-    /// For loop.
+    /// For loop: for ...; do ...; done [redirects].
     For {
         var: String,
         words: Vec<Word>,
         body: Box<Command>,
+        redirects: Vec<Redirect>,
     },
-    /// C-style for loop: for ((init; cond; step)) do ... done
+    /// C-style for loop: for ((init; cond; step)) do ... done [redirects]
     CFor {
         init: String,
         cond: String,
         step: String,
         body: Box<Command>,
+        redirects: Vec<Redirect>,
     },
-    /// While loop.
+    /// While loop: while ...; do ...; done [redirects].
     While {
         condition: Box<Command>,
         body: Box<Command>,
+        redirects: Vec<Redirect>,
     },
     /// Subshell: ( commands ) [redirects].
     Subshell {
@@ -120,8 +124,12 @@ pub enum Command {
     Negate { body: Box<Command> },
     /// Function definition.
     Function { name: String, body: Box<Command> },
-    /// Case statement.
-    Case { word: Word, items: Vec<CaseItem> },
+    /// Case statement: case ... esac [redirects].
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+        redirects: Vec<Redirect>,
+    },
     /// Conditional expression: [[ ... ]].
     DoubleBracket { expr: String },
     /// Standalone arithmetic: (( expr )).
@@ -133,4 +141,16 @@ pub enum Command {
 pub struct CaseItem {
     pub patterns: Vec<Word>,
     pub body: Box<Command>,
+    pub terminator: CaseTerminator,
+}
+
+/// What a case arm does after its body runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CaseTerminator {
+    /// `;;` — leave the case statement.
+    Break,
+    /// `;&` — run the next arm's body without testing its patterns.
+    FallThrough,
+    /// `;;&` — go on testing the patterns of the following arms.
+    Continue,
 }
@@ -30,10 +30,20 @@ pub fn lex(input: &str) -> Vec<Token> {
             continue; // don't consume newline, let normal processing handle it
         }
 
-        // Semicolon or ;;
+        // Semicolon, ;; or the case fall-through terminators ;& and ;;&
         if chars[pos] == ';' {
             if pos + 1 < len && chars[pos + 1] == ';' {
-                tokens.push(Token::DoubleSemi);
+                if pos + 2 < len && chars[pos + 2] == '&' {
+                    tokens.push(Token::DoubleSemiAnd);
+                    pos += 3;
+                } else {
+                    tokens.push(Token::DoubleSemi);
+                    pos += 2;
+                }
+                continue;
+            }
+            if pos + 1 < len && chars[pos + 1] == '&' {
+                tokens.push(Token::SemiAnd);
                 pos += 2;
                 continue;
             }
@@ -84,23 +94,16 @@ pub fn lex(input: &str) -> Vec<Token> {
         // In other positions, { and } are literal word characters so that
         // brace expansion patterns like {a,b,c} pass through to the runtime.
         if chars[pos] == '{' || chars[pos] == '}' {
-            let is_command_start = tokens.is_empty()
-                || matches!(
-                    tokens.last(),
-                    Some(Token::Pipe)
-                        | Some(Token::And)
-                        | Some(Token::Or)
-                        | Some(Token::Semi)
-                        | Some(Token::Newline)
-                        | Some(Token::LParen)
-                        | Some(Token::RParen)
-                        | Some(Token::Do)
-                        | Some(Token::Then)
-                        | Some(Token::Else)
-                        | Some(Token::LBrace)
-                        | Some(Token::DoubleSemi)
-                );
-            if is_command_start {
+            // `function name {` also opens a body, as long as `function`
+            // itself began the command.
+            let after_function_name = chars[pos] == '{'
+                && match tokens.as_slice() {
+                    [.., Token::Word(kw), Token::Word(_)] if kw == "function" => {
+                        brace_may_start(tokens.iter().rev().nth(2))
+                    }
+                    _ => false,
+                };
+            if brace_may_start(tokens.last()) || after_function_name {
                 if chars[pos] == '{' {
                     tokens.push(Token::LBrace);
                 } else {
@@ -153,6 +156,8 @@ pub fn lex(input: &str) -> Vec<Token> {
                             | Token::Else
                             | Token::LBrace
                             | Token::DoubleSemi
+                            | Token::SemiAnd
+                            | Token::DoubleSemiAnd
                     );
                 }
             }
@@ -219,6 +224,31 @@ pub fn lex(input: &str) -> Vec<Token> {
             continue;
         }
 
+        // N< file (input on a numbered descriptor)
+        if chars[pos].is_ascii_digit() {
+            let mut end = pos;
+            while end < len && chars[end].is_ascii_digit() {
+                end += 1;
+            }
+            if end < len
+                && chars[end] == '<'
+                && !matches!(chars.get(end + 1), Some('<' | '(' | '&'))
+            {
+                let digits: String = chars[pos..end].iter().collect();
+                if let Ok(fd) = digits.parse::<i32>() {
+                    pos = end + 1;
+                    skip_whitespace(&chars, &mut pos);
+                    let target = read_redirect_target(&chars, &mut pos);
+                    tokens.push(Token::Redirect(if fd == 0 {
+                        RedirectType::StdinFrom(target)
+                    } else {
+                        RedirectType::FdInput(fd, target)
+                    }));
+                    continue;
+                }
+            }
+        }
+
         // Output process substitution: >(cmd)
         if chars[pos] == '>' && pos + 1 < len && chars[pos + 1] == '(' {
             pos += 2;
@@ -298,8 +328,8 @@ pub fn lex(input: &str) -> Vec<Token> {
                 }
                 skip_whitespace(&chars, &mut pos);
 
-                // Read delimiter (may be quoted with ' or ")
-                let (delimiter, _quoted) = read_heredoc_delimiter(&chars, &mut pos);
+                // Read delimiter; any quoting in it disables body expansion
+                let (delimiter, quoted) = read_heredoc_delimiter(&chars, &mut pos);
 
                 // Capture any remaining tokens on this line (e.g. `> /tmp/file`)
                 // before consuming the heredoc body on subsequent lines.
@@ -342,7 +372,7 @@ pub fn lex(input: &str) -> Vec<Token> {
                     }
                 }
 
-                let rtype = match (strip_tabs, _quoted) {
+                let rtype = match (strip_tabs, quoted) {
                     (true, true) => RedirectType::HeredocStripQuoted(content),
                     (true, false) => RedirectType::HeredocStrip(content),
                     (false, true) => RedirectType::HeredocQuoted(content),
@@ -379,6 +409,12 @@ pub fn lex(input: &str) -> Vec<Token> {
         // them into a single word.  POSIX shells treat e.g. test"hello"$VAR
         // as one word.
         let parts = read_compound_word(&chars, &mut pos);
+        if let [WordPart::Literal(text)] = parts.as_slice() {
+            if is_reserved_word(text) && !reserved_word_allowed(text, &tokens) {
+                tokens.push(Token::Word(text.clone()));
+                continue;
+            }
+        }
         if !parts.is_empty() {
             tokens.push(compound_to_token(parts));
         }
@@ -503,27 +539,7 @@ fn read_compound_word(chars: &[char], pos: &mut usize) -> Vec<WordPart> {
                 if *pos + 1 < chars.len() && chars[*pos + 1] == '(' {
                     // Arithmetic: $((...))
                     *pos += 2;
-                    let mut depth = 1;
-                    let mut expr = String::new();
-                    while *pos < chars.len() && depth > 0 {
-                        if chars[*pos] == '(' {
-                            depth += 1;
-                        }
-                        if chars[*pos] == ')' {
-                            depth -= 1;
-                            if depth == 0 {
-                                break;
-                            }
-                        }
-                        expr.push(chars[*pos]);
-                        *pos += 1;
-                    }
-                    if *pos < chars.len() {
-                        *pos += 1; // skip inner )
-                    }
-                    if *pos < chars.len() && chars[*pos] == ')' {
-                        *pos += 1; // skip outer )
-                    }
+                    let expr = read_arithmetic(chars, pos);
                     parts.push(WordPart::ArithmeticExpansion(expr));
                     continue;
                 }
@@ -533,6 +549,13 @@ fn read_compound_word(chars: &[char], pos: &mut usize) -> Vec<WordPart> {
                 parts.push(WordPart::CommandSub(content));
                 continue;
             }
+            if *pos < chars.len() && chars[*pos] == '[' {
+                // Legacy arithmetic: $[...]
+                *pos += 1;
+                let expr = read_bracket_arithmetic(chars, pos);
+                parts.push(WordPart::ArithmeticExpansion(expr));
+                continue;
+            }
             if *pos < chars.len() && chars[*pos] == '{' {
                 // Braced variable: ${...}
                 *pos += 1; // skip '{'
@@ -540,8 +563,8 @@ fn read_compound_word(chars: &[char], pos: &mut usize) -> Vec<WordPart> {
                 parts.push(parse_braced_var(&var));
                 continue;
             }
-            // Special variables: $?, $$, $!, $#, $@, $*, $0-$9
-            if *pos < chars.len() && "?$!#@*".contains(chars[*pos]) {
+            // Special variables: $?, $$, $!, $#, $@, $*, $-, $0-$9
+            if *pos < chars.len() && "?$!#@*-".contains(chars[*pos]) {
                 let var = chars[*pos].to_string();
                 *pos += 1;
                 parts.push(WordPart::Variable(var));
@@ -599,45 +622,36 @@ fn compound_to_token(parts: Vec<WordPart>) -> Token {
             let name = &first[..eq_pos];
             if !name.is_empty() && is_valid_var_name(name) {
                 // Concatenate the value portion: rest of first literal + all remaining parts
-                let after_eq = &first[eq_pos + 1..];
-                let mut value = after_eq.to_string();
+                let mut value = first[eq_pos + 1..].to_string();
                 for part in &parts[1..] {
-                    match part {
-                        WordPart::Literal(s) | WordPart::QuotedLiteral(s) => {
-                            value.push_str(s);
-                        }
-                        _ => {
-                            // For variable/cmdsub in assignment values, embed the
-                            // raw syntax so the evaluator can expand it later.
-                            // This keeps the existing Assignment(name, value) contract.
-                            match part {
-                                WordPart::Variable(v) => {
-                                    value.push('$');
-                                    value.push_str(v);
-                                }
-                                WordPart::CommandSub(c) => {
-                                    value.push_str("$(");
-                                    value.push_str(c);
-                                    value.push(')');
-                                }
-                                WordPart::ParamExpansion { var, op, default } => {
-                                    value.push_str("${");
-                                    value.push_str(var);
-                                    value.push_str(op);
-                                    value.push_str(default);
-                                    value.push('}');
-                                }
-                                WordPart::ArithmeticExpansion(e) => {
-                                    value.push_str("$((");
-                                    value.push_str(e);
-                                    value.push_str("))");
-                                }
-                                _ => {}
-                            }
+                    push_raw_part(&mut value, part);
+                }
+                return Token::Assignment(name.to_string(), value);
+            }
+        }
+    }
+
+    // Array element assignment whose subscript holds expansions:
+    // `arr[$i]=v`, `arr[$((n-1))]=v`. The subscript is kept raw too.
+    if let WordPart::Literal(ref first) = parts[0] {
+        let base = first.find('[').map(|open| &first[..open]);
+        if !first.contains('=') && base.is_some_and(|b| !b.is_empty() && is_valid_var_name(b)) {
+            let mut name = first.clone();
+            for (i, part) in parts.iter().enumerate().skip(1) {
+                if let WordPart::Literal(s) = part {
+                    if let Some(close) = s.find("]=") {
+                        name.push_str(&s[..=close]);
+                        let mut value = s[close + 2..].to_string();
+                        for part in &parts[i + 1..] {
+                            push_raw_part(&mut value, part);
                         }
+                        return Token::Assignment(name, value);
+                    }
+                    if s.contains('=') {
+                        break;
                     }
                 }
-                return Token::Assignment(name.to_string(), value);
+                push_raw_part(&mut name, part);
             }
         }
     }
@@ -646,6 +660,47 @@ fn compound_to_token(parts: Vec<WordPart>) -> Token {
     Token::DoubleQuoted(parts)
 }
 
+/// Append `part` to an assignment's raw text, re-embedding expansions in
+/// their source syntax so the evaluator can expand them later. This keeps
+/// the `Assignment(name, value)` contract of plain strings.
+fn push_raw_part(raw: &mut String, part: &WordPart) {
+    match part {
+        WordPart::Literal(s) => raw.push_str(s),
+        WordPart::QuotedLiteral(s) => {
+            // Quoted text is final; escape it so the later
+            // expansion of the raw value leaves it untouched.
+            for c in s.chars() {
+                if matches!(c, '\\' | '$' | '`') {
+                    raw.push('\\');
+                }
+                raw.push(c);
+            }
+        }
+        WordPart::Variable(v) => {
+            raw.push('$');
+            raw.push_str(v);
+        }
+        WordPart::CommandSub(c) => {
+            raw.push_str("$(");
+            raw.push_str(c);
+            raw.push(')');
+        }
+        WordPart::ParamExpansion { var, op, default } => {
+            raw.push_str("${");
+            raw.push_str(var);
+            raw.push_str(op);
+            raw.push_str(default);
+            raw.push('}');
+        }
+        WordPart::ArithmeticExpansion(e) => {
+            raw.push_str("$((");
+            raw.push_str(e);
+            raw.push_str("))");
+        }
+        _ => {}
+    }
+}
+
 /// Read an unquoted word for a redirect target (stops at whitespace and operators).
 fn read_redirect_target(chars: &[char], pos: &mut usize) -> String {
     let mut result = String::new();
@@ -691,6 +746,131 @@ fn read_balanced_parens(chars: &[char], pos: &mut usize) -> String {
     result
 }
 
+/// Read the body of `$((expr))` with `pos` just past the opening `((`,
+/// balancing nested parentheses. Consumes the closing `))`.
+pub(crate) fn read_arithmetic(chars: &[char], pos: &mut usize) -> String {
+    let mut expr = String::new();
+    let mut depth = 0;
+    while *pos < chars.len() {
+        match chars[*pos] {
+            '(' => depth += 1,
+            ')' if depth == 0 => break,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        expr.push(chars[*pos]);
+        *pos += 1;
+    }
+    for _ in 0..2 {
+        if *pos < chars.len() && chars[*pos] == ')' {
+            *pos += 1;
+        }
+    }
+    expr
+}
+
+/// Read the body of the legacy `$[expr]` form with `pos` just past the
+/// opening `[`. Consumes the closing `]`.
+pub(crate) fn read_bracket_arithmetic(chars: &[char], pos: &mut usize) -> String {
+    let mut expr = String::new();
+    let mut depth = 0;
+    while *pos < chars.len() {
+        match chars[*pos] {
+            '[' => depth += 1,
+            ']' if depth == 0 => {
+                *pos += 1;
+                break;
+            }
+            ']' => depth -= 1,
+            _ => {}
+        }
+        expr.push(chars[*pos]);
+        *pos += 1;
+    }
+    expr
+}
+
+/// Find a quote that is never closed, returning the quote character.
+///
+/// The lexer itself reads an unclosed quote to the end of the input, so
+/// this scans ahead of it with the same rules: backslash escapes, `$'...'`,
+/// backticks nested in double quotes, comments, and here-document bodies
+/// (which may hold stray quotes).
+pub(crate) fn unterminated_quote(input: &str) -> Option<char> {
+    let chars: Vec<char> = input.chars().collect();
+    let len = chars.len();
+    // Open quotes, innermost last. `$` stands for an ANSI-C `$'...'`.
+    let mut open: Vec<char> = Vec::new();
+    let mut heredocs: Vec<String> = Vec::new();
+    let mut i = 0;
+
+    while i < len {
+        let ch = chars[i];
+        match open.last().copied() {
+            Some('\'') => {
+                if ch == '\'' {
+                    open.pop();
+                }
+            }
+            Some(quote) => {
+                let close = if quote == '$' { '\'' } else { quote };
+                if ch == '\\' {
+                    i += 1;
+                } else if ch == close {
+                    open.pop();
+                } else if quote == '"' && ch == '`' {
+                    open.push('`');
+                }
+            }
+            None => match ch {
+                '\\' => i += 1,
+                '\'' | '"' | '`' => open.push(ch),
+                '$' if i + 1 < len && chars[i + 1] == '\'' => {
+                    open.push('$');
+                    i += 1;
+                }
+                '#' if i == 0 || " \t\n;|&(".contains(chars[i - 1]) => {
+                    while i < len && chars[i] != '\n' {
+                        i += 1;
+                    }
+                    continue;
+                }
+                '<' if chars.get(i + 1) == Some(&'<') && chars.get(i + 2) != Some(&'<') => {
+                    i += 2;
+                    if i < len && chars[i] == '-' {
+                        i += 1;
+                    }
+                    skip_whitespace(&chars, &mut i);
+                    let (delimiter, _) = read_heredoc_delimiter(&chars, &mut i);
+                    heredocs.push(delimiter);
+                    continue;
+                }
+                '\n' if !heredocs.is_empty() => {
+                    i += 1;
+                    for delimiter in heredocs.drain(..) {
+                        while i < len {
+                            let start = i;
+                            while i < len && chars[i] != '\n' {
+                                i += 1;
+                            }
+                            let line: String = chars[start..i].iter().collect();
+                            i += 1;
+                            if line.trim() == delimiter {
+                                break;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+
+    open.last().map(|&q| if q == '$' { '\'' } else { q })
+}
+
 /// Read characters until `terminator` is found. Consumes the terminator.
 fn read_until_char(chars: &[char], pos: &mut usize, terminator: char) -> String {
     let mut result = String::new();
@@ -743,27 +923,7 @@ fn lex_double_quoted(chars: &[char], pos: &mut usize) -> Vec<WordPart> {
                 if *pos + 1 < chars.len() && chars[*pos + 1] == '(' {
                     // Arithmetic: $((...))
                     *pos += 2;
-                    let mut depth = 1;
-                    let mut expr = String::new();
-                    while *pos < chars.len() && depth > 0 {
-                        if chars[*pos] == '(' {
-                            depth += 1;
-                        }
-                        if chars[*pos] == ')' {
-                            depth -= 1;
-                            if depth == 0 {
-                                break;
-                            }
-                        }
-                        expr.push(chars[*pos]);
-                        *pos += 1;
-                    }
-                    if *pos < chars.len() {
-                        *pos += 1;
-                    } // skip inner )
-                    if *pos < chars.len() && chars[*pos] == ')' {
-                        *pos += 1;
-                    } // skip outer )
+                    let expr = read_arithmetic(chars, pos);
                     parts.push(WordPart::ArithmeticExpansion(expr));
                     continue;
                 }
@@ -773,6 +933,13 @@ fn lex_double_quoted(chars: &[char], pos: &mut usize) -> Vec<WordPart> {
                 parts.push(WordPart::CommandSub(content));
                 continue;
             }
+            if *pos < chars.len() && chars[*pos] == '[' {
+                // Legacy arithmetic: $[...]
+                *pos += 1;
+                let expr = read_bracket_arithmetic(chars, pos);
+                parts.push(WordPart::ArithmeticExpansion(expr));
+                continue;
+            }
             if *pos < chars.len() && chars[*pos] == '{' {
                 // Braced variable: ${...}
                 *pos += 1;
@@ -780,8 +947,8 @@ fn lex_double_quoted(chars: &[char], pos: &mut usize) -> Vec<WordPart> {
                 parts.push(parse_braced_var(&var));
                 continue;
             }
-            // Special variables: $?, $$, $!, $#, $@, $*
-            if *pos < chars.len() && "?$!#@*".contains(chars[*pos]) {
+            // Special variables: $?, $$, $!, $#, $@, $*, $-
+            if *pos < chars.len() && "?$!#@*-".contains(chars[*pos]) {
                 let var = chars[*pos].to_string();
                 *pos += 1;
                 parts.push(WordPart::Variable(var));
@@ -824,9 +991,14 @@ fn lex_double_quoted(chars: &[char], pos: &mut usize) -> Vec<WordPart> {
         parts.push(WordPart::QuotedLiteral(literal));
     }
 
-    // If completely empty (e.g. ""), return a single empty quoted literal
-    if parts.is_empty() {
-        parts.push(WordPart::QuotedLiteral(String::new()));
+    // Make sure the quoting is visible to the expander: an empty `""` or a
+    // string holding only expansions (e.g. `"$@"`) gets an empty quoted
+    // literal so the word is not split.
+    if !parts
+        .iter()
+        .any(|p| matches!(p, WordPart::QuotedLiteral(_)))
+    {
+        parts.insert(0, WordPart::QuotedLiteral(String::new()));
     }
 
     parts
@@ -863,27 +1035,7 @@ pub fn parse_string_expansion(input: &str) -> Vec<WordPart> {
                 if pos + 1 < chars.len() && chars[pos + 1] == '(' {
                     // Arithmetic: $((...))
                     pos += 2;
-                    let mut depth = 1;
-                    let mut expr = String::new();
-                    while pos < chars.len() && depth > 0 {
-                        if chars[pos] == '(' {
-                            depth += 1;
-                        }
-                        if chars[pos] == ')' {
-                            depth -= 1;
-                            if depth == 0 {
-                                break;
-                            }
-                        }
-                        expr.push(chars[pos]);
-                        pos += 1;
-                    }
-                    if pos < chars.len() {
-                        pos += 1;
-                    }
-                    if pos < chars.len() && chars[pos] == ')' {
-                        pos += 1;
-                    }
+                    let expr = read_arithmetic(&chars, &mut pos);
                     parts.push(WordPart::ArithmeticExpansion(expr));
                     continue;
                 }
@@ -893,13 +1045,20 @@ pub fn parse_string_expansion(input: &str) -> Vec<WordPart> {
                 parts.push(WordPart::CommandSub(content));
                 continue;
             }
+            if pos < chars.len() && chars[pos] == '[' {
+                // Legacy arithmetic: $[...]
+                pos += 1;
+                let expr = read_bracket_arithmetic(&chars, &mut pos);
+                parts.push(WordPart::ArithmeticExpansion(expr));
+                continue;
+            }
             if pos < chars.len() && chars[pos] == '{' {
                 pos += 1;
                 let var = read_until_char(&chars, &mut pos, '}');
                 parts.push(parse_braced_var(&var));
                 continue;
             }
-            if pos < chars.len() && "?$!#@*".contains(chars[pos]) {
+            if pos < chars.len() && "?$!#@*-".contains(chars[pos]) {
                 let var = chars[pos].to_string();
                 pos += 1;
                 parts.push(WordPart::Variable(var));
@@ -963,8 +1122,9 @@ fn read_word(chars: &[char], pos: &mut usize) -> String {
             || ch == '\''
             || ch == '"'
         {
-            // Array assignment: NAME=( ... ) — include parenthesized content in value
-            if ch == '(' && seen_eq {
+            // Array assignment: NAME=( ... ) and extglob groups such as
+            // `@(a|b)` — include the parenthesized content in the word
+            if ch == '(' && (seen_eq || word.ends_with(['?', '*', '+', '@', '!'])) {
                 word.push('(');
                 *pos += 1;
                 let content = read_balanced_parens(chars, pos);
@@ -1097,12 +1257,16 @@ fn classify_word(word: String) -> Token {
 /// Detects parameter expansion operators like `:-`, `:=`, `:+`, `:?`,
 /// case modification (`^^`, `,,`, `^`, `,`), and substring (`:N` or `:N:M`).
 fn parse_braced_var(content: &str) -> WordPart {
-    // Indirect expansion: ${!var} or ${!arr[@]} / ${!arr[*]}
+    // Indirect expansion: ${!var}, array keys ${!arr[@]} / ${!arr[*]},
+    // or variable names by prefix ${!prefix*} / ${!prefix@}
     if let Some(var_name) = content.strip_prefix('!') {
         if !var_name.is_empty()
             && (is_valid_var_name(var_name)
                 || var_name.ends_with("[@]")
-                || var_name.ends_with("[*]"))
+                || var_name.ends_with("[*]")
+                || var_name
+                    .strip_suffix(['*', '@'])
+                    .is_some_and(|p| !p.is_empty() && is_valid_var_name(p)))
         {
             return WordPart::ParamExpansion {
                 var: String::new(),
@@ -1112,11 +1276,22 @@ fn parse_braced_var(content: &str) -> WordPart {
         }
     }
 
+    // Quoting transform: ${var@Q}
+    if let Some(var_name) = content.strip_suffix("@Q") {
+        if is_param_name(var_name) {
+            return WordPart::ParamExpansion {
+                var: var_name.to_string(),
+                op: "@Q".to_string(),
+                default: String::new(),
+            };
+        }
+    }
+
     // Case modification: ${var^^}, ${var,,}, ${var^}, ${var,}
     // Check longest operators first to avoid matching ^ before ^^
     for op in &["^^", ",,", "^", ","] {
         if let Some(var_name) = content.strip_suffix(op) {
-            if !var_name.is_empty() && is_valid_var_name(var_name) {
+            if is_param_name(var_name) {
                 return WordPart::ParamExpansion {
                     var: var_name.to_string(),
                     op: op.to_string(),
@@ -1128,11 +1303,16 @@ fn parse_braced_var(content: &str) -> WordPart {
 
     // Substring: ${var:N} or ${var:N:M} — colon followed by digit
     // Negative offset requires a space: ${var: -N} (to distinguish from ${var:-default})
-    // Must check before :- :+ := :? operators
+    // Must check before :- :+ := :? operators. Whole-array slices
+    // ${arr[@]:N} stay a plain variable so "${arr[@]:N}" splits into words.
     if let Some(colon_pos) = content.find(':') {
         let var_name = &content[..colon_pos];
         let after = &content[colon_pos + 1..];
-        if !var_name.is_empty() && is_valid_var_name(var_name) && !after.is_empty() {
+        if is_param_name(var_name)
+            && !var_name.ends_with("[@]")
+            && !var_name.ends_with("[*]")
+            && !after.is_empty()
+        {
             let first_char = after.as_bytes()[0];
             if first_char.is_ascii_digit() {
                 // Positive offset: ${var:2} or ${var:2:3}
@@ -1170,21 +1350,134 @@ fn parse_braced_var(content: &str) -> WordPart {
     WordPart::Variable(content.to_string())
 }
 
-/// Read a here-document delimiter. May be quoted with ' or ".
+/// Words `classify_word` turns into keyword tokens.
+fn is_reserved_word(word: &str) -> bool {
+    matches!(
+        word,
+        "if" | "then"
+            | "elif"
+            | "else"
+            | "fi"
+            | "for"
+            | "in"
+            | "do"
+            | "done"
+            | "while"
+            | "until"
+            | "break"
+            | "continue"
+            | "case"
+            | "esac"
+    )
+}
+
+/// Whether reserved `word` is a keyword after `tokens`: where a command
+/// may start (including right after another closer, as in `fi done`), or
+/// for `in`, after `for NAME` and `case WORD`. Elsewhere it is an ordinary
+/// argument, as in `echo done` or `grep -w if file`.
+fn reserved_word_allowed(word: &str, tokens: &[Token]) -> bool {
+    if word == "in" {
+        return matches!(tokens, [.., Token::For | Token::Case, _]);
+    }
+    // `for NAME do` iterates over the positional parameters.
+    if word == "do" && matches!(tokens, [.., Token::For, Token::Word(_)]) {
+        return true;
+    }
+    // `case x in esac` has no items.
+    if word == "esac" && matches!(tokens.last(), Some(Token::In)) {
+        return true;
+    }
+    matches!(
+        tokens.last(),
+        None | Some(
+            Token::Pipe
+                | Token::And
+                | Token::Or
+                | Token::Amp
+                | Token::Semi
+                | Token::Newline
+                | Token::LParen
+                | Token::RParen
+                | Token::LBrace
+                | Token::RBrace
+                | Token::Bang
+                | Token::If
+                | Token::Then
+                | Token::Elif
+                | Token::Else
+                | Token::Fi
+                | Token::Do
+                | Token::Done
+                | Token::While
+                | Token::Until
+                | Token::Esac
+                | Token::DoubleSemi
+                | Token::SemiAnd
+                | Token::DoubleSemiAnd
+        )
+    )
+}
+
+/// Whether a `{` or `}` following `prev` (`None` at the start of input) is
+/// in command position and so a reserved word rather than word text.
+fn brace_may_start(prev: Option<&Token>) -> bool {
+    matches!(
+        prev,
+        None | Some(
+            Token::Pipe
+                | Token::And
+                | Token::Or
+                | Token::Semi
+                | Token::Newline
+                | Token::LParen
+                | Token::RParen
+                | Token::Do
+                | Token::Then
+                | Token::Else
+                | Token::LBrace
+                | Token::DoubleSemi
+                | Token::SemiAnd
+                | Token::DoubleSemiAnd
+        )
+    )
+}
+
+/// Read a here-document delimiter, removing quotes. Quoting any part of it
+/// (`'EOF'`, `"EOF"`, `\EOF`, `E"O"F`) disables expansion of the body.
 /// Returns (delimiter, was_quoted).
 fn read_heredoc_delimiter(chars: &[char], pos: &mut usize) -> (String, bool) {
-    if *pos < chars.len() && (chars[*pos] == '\'' || chars[*pos] == '"') {
-        let quote = chars[*pos];
-        *pos += 1;
-        let delim = read_until_char(chars, pos, quote);
-        (delim, true)
-    } else {
-        let mut delim = String::new();
-        while *pos < chars.len() && !chars[*pos].is_whitespace() && chars[*pos] != '\n' {
-            delim.push(chars[*pos]);
-            *pos += 1;
+    let mut delim = String::new();
+    let mut quoted = false;
+    while *pos < chars.len()
+        && !chars[*pos].is_whitespace()
+        && !matches!(chars[*pos], ';' | '|' | '&' | '<' | '>' | '(' | ')')
+    {
+        match chars[*pos] {
+            quote @ ('\'' | '"') => {
+                *pos += 1;
+                delim.push_str(&read_until_char(chars, pos, quote));
+                quoted = true;
+            }
+            '\\' if *pos + 1 < chars.len() => {
+                delim.push(chars[*pos + 1]);
+                *pos += 2;
+                quoted = true;
+            }
+            c => {
+                delim.push(c);
+                *pos += 1;
+            }
         }
-        (delim, false)
+    }
+    (delim, quoted)
+}
+
+/// A variable name, optionally followed by an array subscript: `var`,
+/// `arr[1]`, `map[key]`.
+fn is_param_name(name: &str) -> bool {
+    match name.strip_suffix(']').and_then(|s| s.split_once('[')) {
+        Some((base, _)) => is_valid_var_name(base),
+        None => is_valid_var_name(name),
     }
 }
 
@@ -1291,6 +1584,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn quoted_assignment_value_is_escaped() {
+        assert_eq!(
+            lex(r#"re='a\.b$' x="\$y"$z"#),
+            vec![
+                Token::Assignment("re".into(), r"a\\.b\$".into()),
+                Token::Assignment("x".into(), r"\$y$z".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn special_parameters() {
+        let tokens = lex("echo $$ $- $#");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("echo".into()),
+                Token::Variable("$".into()),
+                Token::Variable("-".into()),
+                Token::Variable("#".into()),
+            ]
+        );
+    }
+
     #[test]
     fn command_substitution() {
         let tokens = lex("echo $(date)");
@@ -1478,6 +1796,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn numbered_fd_input_redirect() {
+        let tokens = lex("exec 3< input.txt; echo 3 <x");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("exec".into()),
+                Token::Redirect(RedirectType::FdInput(3, "input.txt".into())),
+                Token::Semi,
+                Token::Word("echo".into()),
+                Token::Word("3".into()),
+                Token::Redirect(RedirectType::StdinFrom("x".into())),
+            ]
+        );
+    }
+
     #[test]
     fn compound_word_unquoted_and_double_quoted() {
         // test"hello"test → single compound word
@@ -1518,6 +1852,16 @@ mod tests {
         assert_eq!(tokens, vec![Token::Assignment("FOO".into(), "bar".into())]);
     }
 
+    #[test]
+    fn element_assignment_with_expanded_subscript() {
+        assert_eq!(
+            lex("arr[$((i*2))]=$v"),
+            vec![Token::Assignment("arr[$((i*2))]".into(), "$v".into())]
+        );
+        // Not an assignment without `]=`.
+        assert!(matches!(lex("arr[$i]")[..], [Token::DoubleQuoted(_)]));
+    }
+
     #[test]
     fn compound_word_variable_suffix() {
         // $HOME/bin → compound word
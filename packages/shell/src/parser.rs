@@ -1,22 +1,71 @@
-use crate::ast::{Assignment, CaseItem, Command, ListOp, Redirect, Word, WordPart};
-use crate::lexer::lex;
-use crate::token::Token;
+use std::fmt;
+
+use crate::ast::{Assignment, CaseItem, CaseTerminator, Command, ListOp, Redirect, Word, WordPart};
+use crate::lexer::{lex, read_arithmetic, read_bracket_arithmetic, unterminated_quote};
+use crate::token::{RedirectType, Token};
+
+/// A syntax error in shell input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A token that cannot appear where it was found, e.g. a stray `fi`.
+    UnexpectedToken(String),
+    /// The input ended inside a compound command (`if` without `fi`).
+    UnexpectedEof,
+    /// A quote that is never closed.
+    UnterminatedQuote(char),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedToken(t) => write!(f, "syntax error near unexpected token `{t}'"),
+            Self::UnexpectedEof => write!(f, "syntax error: unexpected end of file"),
+            Self::UnterminatedQuote(q) => {
+                write!(f, "unexpected EOF while looking for matching `{q}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Grammar switches that depend on shell options.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// `set -o posix`: only the POSIX `name() { ... }` function definition
+    /// is accepted; the `function name { ... }` keyword form is an error.
+    pub posix: bool,
+}
 
-/// Parse a shell command string into an AST.
-pub fn parse(input: &str) -> Command {
+/// Parse a shell command string into an AST, rejecting malformed input
+/// (unterminated quotes, unbalanced `if`/`fi`, `do`/`done`, parentheses).
+pub fn try_parse(input: &str) -> Result<Command, ParseError> {
+    try_parse_with(input, ParseOptions::default())
+}
+
+/// [`try_parse`] under the given [`ParseOptions`].
+pub fn try_parse_with(input: &str, options: ParseOptions) -> Result<Command, ParseError> {
+    if let Some(quote) = unterminated_quote(input) {
+        return Err(ParseError::UnterminatedQuote(quote));
+    }
     let tokens = lex(input);
-    let mut parser = Parser::new(tokens);
+    let mut parser = Parser::new(tokens, options);
     parser.parse_complete_command()
 }
 
 struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    options: ParseOptions,
 }
 
 impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, pos: 0 }
+    fn new(tokens: Vec<Token>, options: ParseOptions) -> Self {
+        Parser {
+            tokens,
+            pos: 0,
+            options,
+        }
     }
 
     fn peek(&self) -> Option<&Token> {
@@ -29,9 +78,22 @@ impl Parser {
         token
     }
 
-    fn expect(&mut self, expected: &Token) {
-        let token = self.advance();
-        assert_eq!(&token, expected, "expected {:?}, got {:?}", expected, token);
+    fn expect(&mut self, expected: &Token) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(token) if token == expected => {
+                self.advance();
+                Ok(())
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// The error for the current token not fitting the grammar.
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            None => ParseError::UnexpectedEof,
+            Some(token) => ParseError::UnexpectedToken(token_text(token)),
+        }
     }
 
     /// Skip optional semicolons and newlines (used between clauses).
@@ -62,6 +124,8 @@ impl Parser {
                     | Token::RBrace
                     | Token::Esac
                     | Token::DoubleSemi
+                    | Token::SemiAnd
+                    | Token::DoubleSemiAnd
             ),
         }
     }
@@ -100,60 +164,88 @@ impl Parser {
     // ----------------------------------------------------------------
 
     /// complete_command = list
-    fn parse_complete_command(&mut self) -> Command {
+    fn parse_complete_command(&mut self) -> Result<Command, ParseError> {
         self.skip_separators();
-        self.parse_list()
+        if self.peek().is_none() {
+            return Ok(Command::Simple {
+                words: vec![],
+                redirects: vec![],
+                assignments: vec![],
+            });
+        }
+        let cmd = self.parse_list()?;
+        self.skip_separators();
+        // Anything left over is a closer with no opener (`fi`, `done`, `)`).
+        if self.peek().is_some() {
+            return Err(self.unexpected());
+        }
+        Ok(cmd)
+    }
+
+    /// list = and_or ((AMP | SEMI | NEWLINE) and_or)* [AMP | SEMI | NEWLINE]
+    ///
+    /// Separators nest to the right, so `&` backgrounds only the and-or list
+    /// just before it: `a; b & c` is `a; (b & c)`. Semicolons and newlines
+    /// followed by a list terminator or end-of-input are trailing separators,
+    /// not sequence operators.
+    fn parse_list(&mut self) -> Result<Command, ParseError> {
+        self.skip_newlines();
+        let mut items: Vec<(Command, ListOp)> = Vec::new();
+        let mut last = self.parse_and_or()?;
+
+        loop {
+            let op = match self.peek() {
+                Some(Token::Amp) => ListOp::Background,
+                Some(Token::Semi) | Some(Token::Newline) => ListOp::Seq,
+                _ => break,
+            };
+            self.advance(); // consume the separator
+            self.skip_newlines();
+            if self.at_list_terminator() || !self.at_command_start() {
+                // Trailing & with no following command
+                if op == ListOp::Background {
+                    last = Command::List {
+                        left: Box::new(last),
+                        op,
+                        right: Box::new(Command::Simple {
+                            words: vec![],
+                            redirects: vec![],
+                            assignments: vec![],
+                        }),
+                    };
+                }
+                break;
+            }
+            items.push((last, op));
+            last = self.parse_and_or()?;
+        }
+
+        Ok(items
+            .into_iter()
+            .rev()
+            .fold(last, |right, (left, op)| Command::List {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            }))
     }
 
-    /// list = pipeline ((AND | OR | SEMI | NEWLINE) pipeline)*
+    /// and_or = pipeline ((AND | OR) NEWLINE* pipeline)*
     ///
-    /// Left-associative. Semicolons and newlines followed by a list terminator
-    /// or end-of-input are trailing separators, not sequence operators.
-    fn parse_list(&mut self) -> Command {
-        let mut left = self.parse_pipeline();
+    /// Left-associative.
+    fn parse_and_or(&mut self) -> Result<Command, ParseError> {
+        let mut left = self.parse_pipeline()?;
 
         loop {
             let op = match self.peek() {
                 Some(Token::And) => ListOp::And,
                 Some(Token::Or) => ListOp::Or,
-                Some(Token::Amp) => {
-                    self.advance(); // consume &
-                    self.skip_newlines();
-                    // Trailing & with no following command
-                    if self.at_list_terminator() || !self.at_command_start() {
-                        left = Command::List {
-                            left: Box::new(left),
-                            op: ListOp::Background,
-                            right: Box::new(Command::Simple {
-                                words: vec![],
-                                redirects: vec![],
-                                assignments: vec![],
-                            }),
-                        };
-                        break;
-                    }
-                    ListOp::Background
-                }
-                Some(Token::Semi) | Some(Token::Newline) => {
-                    // Peek ahead: if the next meaningful token is a terminator
-                    // or EOF, this semicolon/newline is just a trailing separator.
-                    self.advance(); // consume the semicolon/newline
-                    self.skip_newlines();
-                    if self.at_list_terminator() || !self.at_command_start() {
-                        break;
-                    }
-                    ListOp::Seq
-                }
                 _ => break,
             };
+            self.advance();
+            self.skip_newlines();
 
-            // For And and Or we need to consume the operator token.
-            if !matches!(op, ListOp::Seq | ListOp::Background) {
-                self.advance();
-                self.skip_newlines();
-            }
-
-            let right = self.parse_pipeline();
+            let right = self.parse_pipeline()?;
             left = Command::List {
                 left: Box::new(left),
                 op,
@@ -161,7 +253,7 @@ impl Parser {
             };
         }
 
-        left
+        Ok(left)
     }
 
     /// Skip newlines only (not semicolons).
@@ -172,19 +264,19 @@ impl Parser {
     }
 
     /// pipeline = [BANG] command (PIPE command)*
-    fn parse_pipeline(&mut self) -> Command {
+    fn parse_pipeline(&mut self) -> Result<Command, ParseError> {
         let negated = matches!(self.peek(), Some(Token::Bang));
         if negated {
             self.advance();
         }
 
-        let first = self.parse_command();
+        let first = self.parse_command()?;
         let mut commands = vec![first];
 
         while let Some(Token::Pipe) = self.peek() {
             self.advance(); // consume pipe
             self.skip_newlines();
-            commands.push(self.parse_command());
+            commands.push(self.parse_command()?);
         }
 
         let result = if commands.len() == 1 {
@@ -194,16 +286,16 @@ impl Parser {
         };
 
         if negated {
-            Command::Negate {
+            Ok(Command::Negate {
                 body: Box::new(result),
-            }
+            })
         } else {
-            result
+            Ok(result)
         }
     }
 
     /// command = if_clause | for_clause | while_clause | case_clause | subshell | function_def | simple_command
-    fn parse_command(&mut self) -> Command {
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
         match self.peek() {
             Some(Token::If) => self.parse_if(),
             Some(Token::For) => self.parse_for(),
@@ -214,18 +306,18 @@ impl Parser {
             Some(Token::LBrace) => {
                 self.advance(); // consume {
                 self.skip_separators();
-                let body = self.parse_list();
+                let body = self.parse_list()?;
                 self.skip_separators();
-                self.expect(&Token::RBrace);
+                self.expect(&Token::RBrace)?;
                 let redirects = self.parse_trailing_redirects();
-                Command::BraceGroup {
+                Ok(Command::BraceGroup {
                     body: Box::new(body),
                     redirects,
-                }
+                })
             }
             Some(Token::DoubleBracket(_)) => {
                 if let Token::DoubleBracket(expr) = self.advance() {
-                    Command::DoubleBracket { expr }
+                    Ok(Command::DoubleBracket { expr })
                 } else {
                     unreachable!()
                 }
@@ -233,51 +325,116 @@ impl Parser {
             Some(Token::DoubleParen(_)) => {
                 // Standalone (( expr )) — arithmetic command
                 if let Token::DoubleParen(expr) = self.advance() {
-                    Command::ArithmeticCommand { expr }
+                    Ok(Command::ArithmeticCommand { expr })
                 } else {
                     unreachable!()
                 }
             }
             Some(Token::Break) => {
                 self.advance();
-                Command::Break
+                Ok(Command::Break)
             }
             Some(Token::Continue) => {
                 self.advance();
-                Command::Continue
+                Ok(Command::Continue)
             }
             _ => {
-                // Check for function: name() { ... }
-                if let Some(Token::Word(name)) = self.peek() {
-                    if self.pos + 1 < self.tokens.len()
-                        && self.tokens[self.pos + 1] == Token::LParen
-                        && self.pos + 2 < self.tokens.len()
-                        && self.tokens[self.pos + 2] == Token::RParen
-                    {
+                // POSIX mode has no `function` keyword: `function name {`
+                // and `function name() {` are syntax errors.
+                if self.options.posix
+                    && self.peek() == Some(&Token::Word("function".to_string()))
+                    && matches!(self.tokens.get(self.pos + 1), Some(Token::Word(_)))
+                    && match self.tokens.get(self.pos + 2) {
+                        Some(Token::LBrace | Token::LParen) => true,
+                        Some(Token::Word(w)) => w == "{",
+                        _ => false,
+                    }
+                {
+                    self.pos += 2;
+                    return Err(self.unexpected());
+                }
+                // function name [()] compound
+                if let [Token::Word(kw), Token::Word(name), next, ..] = &self.tokens[self.pos..] {
+                    if kw == "function" && self.starts_function_body(next) {
                         let name = name.clone();
-                        self.pos += 3; // consume name ( )
-                        self.skip_separators();
-                        self.expect(&Token::LBrace);
-                        self.skip_separators();
-                        let body = self.parse_list();
-                        self.skip_separators();
-                        self.expect(&Token::RBrace);
-                        return Command::Function {
-                            name,
-                            body: Box::new(body),
-                        };
+                        self.pos += 2; // consume `function name`
+                        if self.peek() == Some(&Token::LParen) {
+                            self.advance();
+                            self.expect(&Token::RParen)?;
+                        }
+                        return self.parse_function_body(name);
                     }
                 }
+                // name() compound
+                if let [Token::Word(name), Token::LParen, Token::RParen, ..] =
+                    &self.tokens[self.pos..]
+                {
+                    let name = name.clone();
+                    self.pos += 3; // consume name ( )
+                    return self.parse_function_body(name);
+                }
                 self.parse_simple_command()
             }
         }
     }
 
+    /// Whether `token`, following `function name`, can begin the rest of a
+    /// definition: `()`, or a compound command on the same or a later line.
+    fn starts_function_body(&self, token: &Token) -> bool {
+        matches!(
+            token,
+            Token::LParen
+                | Token::LBrace
+                | Token::Newline
+                | Token::If
+                | Token::For
+                | Token::While
+                | Token::Until
+                | Token::Case
+                | Token::DoubleBracket(_)
+                | Token::DoubleParen(_)
+        )
+    }
+
+    /// function_body = newline* compound_command
+    ///
+    /// A `{ ... }` body is stored as its inner list; any other compound
+    /// command (subshell, `if`, loops, `case`, `[[ ]]`, `(( ))`) is stored
+    /// as is.
+    fn parse_function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_separators();
+        let body = match self.peek() {
+            Some(Token::LBrace) => {
+                self.advance();
+                self.skip_separators();
+                let body = self.parse_list()?;
+                self.skip_separators();
+                self.expect(&Token::RBrace)?;
+                body
+            }
+            Some(
+                Token::LParen
+                | Token::If
+                | Token::For
+                | Token::While
+                | Token::Until
+                | Token::Case
+                | Token::DoubleBracket(_)
+                | Token::DoubleParen(_),
+            ) => self.parse_command()?,
+            _ => return Err(self.unexpected()),
+        };
+        Ok(Command::Function {
+            name,
+            body: Box::new(body),
+        })
+    }
+
     /// simple_command = (assignment)* word* (redirect)*
     ///
     /// Assignments come first (before any non-assignment word). Redirects can
     /// appear anywhere but are collected separately.
-    fn parse_simple_command(&mut self) -> Command {
+    fn parse_simple_command(&mut self) -> Result<Command, ParseError> {
         let mut words = Vec::new();
         let mut redirects = Vec::new();
         let mut assignments = Vec::new();
@@ -344,83 +501,101 @@ impl Parser {
             }
         }
 
-        Command::Simple {
+        if words.is_empty() && redirects.is_empty() && assignments.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(Command::Simple {
             words,
             redirects,
             assignments,
-        }
+        })
     }
 
     /// if_clause = IF list SEMI? THEN list (ELIF list SEMI? THEN list)* (ELSE list)? FI
-    fn parse_if(&mut self) -> Command {
-        self.expect(&Token::If);
-        let condition = self.parse_list();
+    fn parse_if(&mut self) -> Result<Command, ParseError> {
+        self.expect(&Token::If)?;
+        let condition = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::Then);
-        let then_body = self.parse_list();
+        self.expect(&Token::Then)?;
+        let then_body = self.parse_list()?;
         self.skip_separators();
 
         let mut else_body = None;
 
         if let Some(Token::Elif) = self.peek() {
             // Treat `elif` as a nested if inside the else branch.
-            else_body = Some(Box::new(self.parse_elif()));
+            else_body = Some(Box::new(self.parse_elif()?));
         } else if let Some(Token::Else) = self.peek() {
             self.advance(); // consume else
-            else_body = Some(Box::new(self.parse_list()));
+            else_body = Some(Box::new(self.parse_list()?));
             self.skip_separators();
         }
 
-        self.expect(&Token::Fi);
+        self.expect(&Token::Fi)?;
+        let redirects = self.parse_trailing_redirects();
 
-        Command::If {
+        Ok(Command::If {
             condition: Box::new(condition),
             then_body: Box::new(then_body),
             else_body,
-        }
+            redirects,
+        })
     }
 
     /// Parse an elif chain as a nested If command (without consuming an outer Fi).
-    fn parse_elif(&mut self) -> Command {
-        self.expect(&Token::Elif);
-        let condition = self.parse_list();
+    fn parse_elif(&mut self) -> Result<Command, ParseError> {
+        self.expect(&Token::Elif)?;
+        let condition = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::Then);
-        let then_body = self.parse_list();
+        self.expect(&Token::Then)?;
+        let then_body = self.parse_list()?;
         self.skip_separators();
 
         let mut else_body = None;
 
         if let Some(Token::Elif) = self.peek() {
-            else_body = Some(Box::new(self.parse_elif()));
+            else_body = Some(Box::new(self.parse_elif()?));
         } else if let Some(Token::Else) = self.peek() {
             self.advance();
-            else_body = Some(Box::new(self.parse_list()));
+            else_body = Some(Box::new(self.parse_list()?));
             self.skip_separators();
         }
 
-        Command::If {
+        Ok(Command::If {
             condition: Box::new(condition),
             then_body: Box::new(then_body),
             else_body,
-        }
+            redirects: vec![],
+        })
     }
 
-    /// for_clause = FOR word IN word* SEMI? DO list DONE
+    /// for_clause = FOR word (IN word*)? SEMI? DO list DONE
     ///           | FOR (( init ; cond ; step )) SEMI? DO list DONE
-    fn parse_for(&mut self) -> Command {
-        self.expect(&Token::For);
+    fn parse_for(&mut self) -> Result<Command, ParseError> {
+        self.expect(&Token::For)?;
 
         // Check for C-style: for (( ... ))
         if matches!(self.peek(), Some(Token::DoubleParen(_))) {
             return self.parse_c_for();
         }
 
-        let var = match self.advance() {
-            Token::Word(w) => w,
-            other => panic!("expected variable name after 'for', got {:?}", other),
+        let var = match self.peek() {
+            Some(Token::Word(w)) => w.clone(),
+            _ => return Err(self.unexpected()),
         };
-        self.expect(&Token::In);
+        self.advance();
+
+        // `for x; do` / `for x do` without an `in` clause loops over "$@".
+        if !matches!(self.peek(), Some(Token::In)) {
+            let words = vec![Word {
+                parts: vec![
+                    WordPart::QuotedLiteral(String::new()),
+                    WordPart::Variable("@".to_string()),
+                ],
+            }];
+            return self.parse_for_body(var, words);
+        }
+        self.advance();
 
         let mut words = Vec::new();
         loop {
@@ -458,25 +633,33 @@ impl Parser {
             }
         }
 
+        self.parse_for_body(var, words)
+    }
+
+    /// The `SEMI? DO list DONE` tail shared by both `for` word-list forms.
+    fn parse_for_body(&mut self, var: String, words: Vec<Word>) -> Result<Command, ParseError> {
         self.skip_separators();
-        self.expect(&Token::Do);
-        let body = self.parse_list();
+        self.expect(&Token::Do)?;
+        let body = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::Done);
+        self.expect(&Token::Done)?;
+        let redirects = self.parse_trailing_redirects();
 
-        Command::For {
+        Ok(Command::For {
             var,
             words,
             body: Box::new(body),
-        }
+            redirects,
+        })
     }
 
     /// c_for = FOR DoubleParen SEMI? DO list DONE
-    fn parse_c_for(&mut self) -> Command {
-        let content = match self.advance() {
-            Token::DoubleParen(s) => s,
-            other => panic!("expected ((...)) after 'for', got {:?}", other),
+    fn parse_c_for(&mut self) -> Result<Command, ParseError> {
+        let content = match self.peek() {
+            Some(Token::DoubleParen(s)) => s.clone(),
+            _ => return Err(self.unexpected()),
         };
+        self.advance();
 
         // Split on ';' to get init, cond, step
         let parts: Vec<&str> = content.splitn(3, ';').collect();
@@ -485,68 +668,74 @@ impl Parser {
         let step = parts.get(2).map(|s| s.trim()).unwrap_or("").to_string();
 
         self.skip_separators();
-        self.expect(&Token::Do);
-        let body = self.parse_list();
+        self.expect(&Token::Do)?;
+        let body = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::Done);
+        self.expect(&Token::Done)?;
+        let redirects = self.parse_trailing_redirects();
 
-        Command::CFor {
+        Ok(Command::CFor {
             init,
             cond,
             step,
             body: Box::new(body),
-        }
+            redirects,
+        })
     }
 
     /// while_clause = WHILE list SEMI? DO list DONE
-    fn parse_while(&mut self) -> Command {
-        self.expect(&Token::While);
-        let condition = self.parse_list();
+    fn parse_while(&mut self) -> Result<Command, ParseError> {
+        self.expect(&Token::While)?;
+        let condition = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::Do);
-        let body = self.parse_list();
+        self.expect(&Token::Do)?;
+        let body = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::Done);
+        self.expect(&Token::Done)?;
+        let redirects = self.parse_trailing_redirects();
 
-        Command::While {
+        Ok(Command::While {
             condition: Box::new(condition),
             body: Box::new(body),
-        }
+            redirects,
+        })
     }
 
     /// until_clause = UNTIL list SEMI? DO list DONE
     /// Desugars to: while ! condition; do body; done
-    fn parse_until(&mut self) -> Command {
-        self.expect(&Token::Until);
-        let condition = self.parse_list();
+    fn parse_until(&mut self) -> Result<Command, ParseError> {
+        self.expect(&Token::Until)?;
+        let condition = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::Do);
-        let body = self.parse_list();
+        self.expect(&Token::Do)?;
+        let body = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::Done);
+        self.expect(&Token::Done)?;
+        let redirects = self.parse_trailing_redirects();
 
-        Command::While {
+        Ok(Command::While {
             condition: Box::new(Command::Negate {
                 body: Box::new(condition),
             }),
             body: Box::new(body),
-        }
+            redirects,
+        })
     }
 
     /// subshell = LPAREN list RPAREN
-    fn parse_subshell(&mut self) -> Command {
-        self.expect(&Token::LParen);
-        let body = self.parse_list();
+    fn parse_subshell(&mut self) -> Result<Command, ParseError> {
+        self.expect(&Token::LParen)?;
+        let body = self.parse_list()?;
         self.skip_separators();
-        self.expect(&Token::RParen);
+        self.expect(&Token::RParen)?;
 
         // Collect trailing redirects: ( cmd ) 2>&1
         let redirects = self.parse_trailing_redirects();
 
-        Command::Subshell {
+        Ok(Command::Subshell {
             body: Box::new(body),
             redirects,
-        }
+        })
     }
 
     /// Parse any trailing redirect tokens (e.g. `2>&1`, `>file`, `<file`).
@@ -561,11 +750,11 @@ impl Parser {
     }
 
     /// case_clause = CASE word IN (case_item)* ESAC
-    /// case_item = pattern (PIPE pattern)* RPAREN list DOUBLE_SEMI
-    fn parse_case(&mut self) -> Command {
-        self.expect(&Token::Case);
-        let word = self.parse_word_token();
-        self.expect(&Token::In);
+    /// case_item = pattern (PIPE pattern)* RPAREN list (DOUBLE_SEMI | SEMI_AND | DOUBLE_SEMI_AND)
+    fn parse_case(&mut self) -> Result<Command, ParseError> {
+        self.expect(&Token::Case)?;
+        let word = self.parse_word_token()?;
+        self.expect(&Token::In)?;
         self.skip_separators();
 
         let mut items = Vec::new();
@@ -576,20 +765,24 @@ impl Parser {
             if matches!(self.peek(), Some(Token::LParen)) {
                 self.advance();
             }
-            patterns.push(self.parse_word_token());
+            patterns.push(self.parse_word_token()?);
             while matches!(self.peek(), Some(Token::Pipe)) {
                 self.advance();
-                patterns.push(self.parse_word_token());
+                patterns.push(self.parse_word_token()?);
             }
-            self.expect(&Token::RParen);
+            self.expect(&Token::RParen)?;
             self.skip_separators();
 
             // Parse body (may be empty)
             let body = if !matches!(
                 self.peek(),
-                Some(Token::DoubleSemi) | Some(Token::Esac) | None
+                Some(Token::DoubleSemi)
+                    | Some(Token::SemiAnd)
+                    | Some(Token::DoubleSemiAnd)
+                    | Some(Token::Esac)
+                    | None
             ) {
-                self.parse_list()
+                self.parse_list()?
             } else {
                 Command::Simple {
                     words: vec![],
@@ -598,89 +791,182 @@ impl Parser {
                 }
             };
 
+            // Expect ;;, ;&, ;;& (or esac)
+            let terminator = match self.peek() {
+                Some(Token::SemiAnd) => CaseTerminator::FallThrough,
+                Some(Token::DoubleSemiAnd) => CaseTerminator::Continue,
+                _ => CaseTerminator::Break,
+            };
+            if matches!(
+                self.peek(),
+                Some(Token::DoubleSemi) | Some(Token::SemiAnd) | Some(Token::DoubleSemiAnd)
+            ) {
+                self.advance();
+                self.skip_separators();
+            }
+
             items.push(CaseItem {
                 patterns,
                 body: Box::new(body),
+                terminator,
             });
-
-            // Expect ;; (or esac)
-            if matches!(self.peek(), Some(Token::DoubleSemi)) {
-                self.advance();
-                self.skip_separators();
-            }
         }
 
-        self.expect(&Token::Esac);
-        Command::Case { word, items }
+        self.expect(&Token::Esac)?;
+        let redirects = self.parse_trailing_redirects();
+        Ok(Command::Case {
+            word,
+            items,
+            redirects,
+        })
     }
 
     /// Parse a single word token (Word, QuotedWord, Variable, DoubleQuoted, CommandSub).
-    fn parse_word_token(&mut self) -> Word {
+    fn parse_word_token(&mut self) -> Result<Word, ParseError> {
         match self.peek() {
             Some(Token::Word(_)) => {
                 if let Token::Word(w) = self.advance() {
-                    Word::literal(&w)
+                    Ok(Word::literal(&w))
                 } else {
                     unreachable!()
                 }
             }
             Some(Token::QuotedWord(_)) => {
                 if let Token::QuotedWord(w) = self.advance() {
-                    Word {
+                    Ok(Word {
                         parts: vec![WordPart::QuotedLiteral(w)],
-                    }
+                    })
                 } else {
                     unreachable!()
                 }
             }
             Some(Token::Variable(_)) => {
                 if let Token::Variable(v) = self.advance() {
-                    Word::variable(&v)
+                    Ok(Word::variable(&v))
                 } else {
                     unreachable!()
                 }
             }
             Some(Token::DoubleQuoted(_)) => {
                 if let Token::DoubleQuoted(parts) = self.advance() {
-                    Word { parts }
+                    Ok(Word { parts })
                 } else {
                     unreachable!()
                 }
             }
             Some(Token::CommandSub(_)) => {
                 if let Token::CommandSub(c) = self.advance() {
-                    Word {
+                    Ok(Word {
                         parts: vec![WordPart::CommandSub(c)],
-                    }
+                    })
                 } else {
                     unreachable!()
                 }
             }
-            other => panic!("expected word, got {:?}", other),
+            _ => Err(self.unexpected()),
         }
     }
 }
 
+/// Source text of a token, as quoted in syntax error messages.
+fn token_text(token: &Token) -> String {
+    let text = match token {
+        Token::Word(w) | Token::QuotedWord(w) => return w.clone(),
+        Token::DoubleQuoted(parts) => {
+            return crate::printer::format_word(&Word {
+                parts: parts.clone(),
+            })
+        }
+        Token::Assignment(name, value) => return format!("{name}={value}"),
+        Token::Variable(v) => return format!("${v}"),
+        Token::CommandSub(c) => return format!("$({c})"),
+        Token::DoubleParen(e) => return format!("(({e}))"),
+        Token::DoubleBracket(e) => return format!("[[{e}]]"),
+        Token::Redirect(r) => match r {
+            RedirectType::StdoutOverwrite(_) => ">",
+            RedirectType::StdoutAppend(_) => ">>",
+            RedirectType::StdinFrom(_) => "<",
+            RedirectType::FdInput(fd, _) => return format!("{fd}<"),
+            RedirectType::StderrOverwrite(_) => "2>",
+            RedirectType::StderrAppend(_) => "2>>",
+            RedirectType::StderrToStdout => "2>&1",
+            RedirectType::BothOverwrite(_) => "&>",
+            RedirectType::HereString(_) => "<<<",
+            RedirectType::Heredoc(_)
+            | RedirectType::HeredocQuoted(_)
+            | RedirectType::HeredocStrip(_)
+            | RedirectType::HeredocStripQuoted(_) => "<<",
+        },
+        Token::Pipe => "|",
+        Token::And => "&&",
+        Token::Amp => "&",
+        Token::Or => "||",
+        Token::Semi => ";",
+        Token::Newline => "newline",
+        Token::LParen => "(",
+        Token::RParen => ")",
+        Token::If => "if",
+        Token::Then => "then",
+        Token::Elif => "elif",
+        Token::Else => "else",
+        Token::Fi => "fi",
+        Token::For => "for",
+        Token::In => "in",
+        Token::Do => "do",
+        Token::Done => "done",
+        Token::While => "while",
+        Token::Until => "until",
+        Token::Break => "break",
+        Token::Continue => "continue",
+        Token::Case => "case",
+        Token::Esac => "esac",
+        Token::DoubleSemi => ";;",
+        Token::SemiAnd => ";&",
+        Token::DoubleSemiAnd => ";;&",
+        Token::Bang => "!",
+        Token::LBrace => "{",
+        Token::RBrace => "}",
+    };
+    text.to_string()
+}
+
 /// Parse a raw string for `$`-expansion patterns, returning `WordPart`s.
 ///
 /// This is used when converting assignment-style tokens (e.g. `name="$1"`)
 /// that appear after the first word in a command (like `local`, `export`).
 /// The lexer flattens the value to a raw string; this re-parses it so that
 /// variable references, command substitutions, etc. are properly expanded.
+///
+/// An array literal (`(a 'b c')`) is left to the builtin to split, so its
+/// quotes and escapes are kept and single-quoted text is not expanded.
 fn parse_raw_dollar_string(raw: &str) -> Vec<WordPart> {
     let chars: Vec<char> = raw.chars().collect();
     let len = chars.len();
     let mut parts: Vec<WordPart> = Vec::new();
     let mut literal = String::new();
     let mut i = 0;
+    let array_literal = raw.starts_with('(') && raw.ends_with(')');
 
     while i < len {
         if chars[i] == '\\' && i + 1 < len {
+            if array_literal {
+                literal.push('\\');
+            }
             literal.push(chars[i + 1]);
             i += 2;
             continue;
         }
 
+        if chars[i] == '\'' && array_literal {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|&c| c == '\'')
+                .map_or(len, |p| i + p + 2);
+            literal.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+
         if chars[i] == '$' && i + 1 < len {
             if !literal.is_empty() {
                 parts.push(WordPart::Literal(std::mem::take(&mut literal)));
@@ -689,26 +975,9 @@ fn parse_raw_dollar_string(raw: &str) -> Vec<WordPart> {
             if chars[i + 1] == '(' {
                 if i + 2 < len && chars[i + 2] == '(' {
                     // $((expr))
-                    let start = i + 3;
-                    let mut depth = 1;
-                    let mut j = start;
-                    while j < len && depth > 0 {
-                        if j + 1 < len && chars[j] == '(' && chars[j + 1] == '(' {
-                            depth += 1;
-                            j += 2;
-                        } else if j + 1 < len && chars[j] == ')' && chars[j + 1] == ')' {
-                            depth -= 1;
-                            if depth == 0 {
-                                break;
-                            }
-                            j += 2;
-                        } else {
-                            j += 1;
-                        }
-                    }
-                    let expr: String = chars[start..j].iter().collect();
+                    i += 3;
+                    let expr = read_arithmetic(&chars, &mut i);
                     parts.push(WordPart::ArithmeticExpansion(expr));
-                    i = if j + 1 < len { j + 2 } else { j };
                 } else {
                     // $(cmd)
                     let start = i + 2;
@@ -728,6 +997,11 @@ fn parse_raw_dollar_string(raw: &str) -> Vec<WordPart> {
                     parts.push(WordPart::CommandSub(cmd));
                     i = j + 1;
                 }
+            } else if chars[i + 1] == '[' {
+                // $[expr]
+                i += 2;
+                let expr = read_bracket_arithmetic(&chars, &mut i);
+                parts.push(WordPart::ArithmeticExpansion(expr));
             } else if chars[i + 1] == '{' {
                 // ${...}
                 let start = i + 2;
@@ -800,7 +1074,7 @@ fn parse_param_expansion(inner: &str) -> WordPart {
     }
 
     let ops = [
-        "%%", "##", "^^", ",,", ":-", ":=", ":+", ":?", "^", "/", "%", "#", ":",
+        "@Q", "%%", "##", "^^", ",,", ":-", ":=", ":+", ":?", "^", "/", "%", "#", ":",
     ];
     for op in ops {
         if let Some(pos) = inner.find(op) {
@@ -824,6 +1098,11 @@ mod tests {
     use super::*;
     use crate::ast::*;
 
+    /// Parse input the test expects to be well-formed.
+    fn parse(input: &str) -> Command {
+        try_parse(input).unwrap()
+    }
+
     #[test]
     fn simple_command() {
         let cmd = parse("echo hello world");
@@ -907,6 +1186,7 @@ mod tests {
                 condition,
                 then_body,
                 else_body,
+                ..
             } => {
                 assert!(else_body.is_none());
                 // Verify the condition is a simple command with "true"
@@ -951,6 +1231,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn for_loop_without_in_iterates_positional_args() {
+        let expected = parse("for x in \"$@\"; do echo $x; done");
+        assert_eq!(parse("for x; do echo $x; done"), expected);
+        assert_eq!(parse("for x do echo $x; done"), expected);
+        assert_eq!(parse("for x\ndo echo $x\ndone"), expected);
+    }
+
     #[test]
     fn while_loop() {
         let cmd = parse("while true; do echo loop; done");
@@ -960,6 +1248,80 @@ mod tests {
         }
     }
 
+    #[test]
+    fn for_loop_with_trailing_redirect() {
+        let cmd = parse("for i in a; do echo $i; done > f");
+        match cmd {
+            Command::For { redirects, .. } => {
+                assert_eq!(
+                    redirects,
+                    vec![Redirect {
+                        redirect_type: crate::token::RedirectType::StdoutOverwrite("f".into()),
+                    }]
+                );
+            }
+            _ => panic!("expected For"),
+        }
+    }
+
+    #[test]
+    fn while_loop_with_stdin_redirect() {
+        let cmd = parse("while read line; do echo $line; done < input.txt");
+        match cmd {
+            Command::While { redirects, .. } => {
+                assert_eq!(
+                    redirects,
+                    vec![Redirect {
+                        redirect_type: crate::token::RedirectType::StdinFrom("input.txt".into()),
+                    }]
+                );
+            }
+            _ => panic!("expected While"),
+        }
+    }
+
+    #[test]
+    fn heredoc_quoted_delimiter_disables_expansion() {
+        use crate::token::RedirectType::*;
+        let heredoc = |src: &str| match parse(src) {
+            Command::Simple { mut redirects, .. } => redirects.remove(0).redirect_type,
+            _ => panic!("expected Simple"),
+        };
+        assert_eq!(heredoc("cat <<EOF\n$x\nEOF"), Heredoc("$x\n".into()));
+        for delimiter in ["'EOF'", "\"EOF\"", "\\EOF", "E\"O\"F"] {
+            let src = format!("cat <<{delimiter}\n$x\nEOF");
+            assert_eq!(heredoc(&src), HeredocQuoted("$x\n".into()), "{delimiter}");
+        }
+        assert_eq!(
+            heredoc("cat <<-'EOF'\n\t$x\n\tEOF"),
+            HeredocStripQuoted("\t$x\n".into())
+        );
+    }
+
+    #[test]
+    fn if_and_case_with_trailing_redirects() {
+        match parse("if true; then echo yes; fi > out 2>&1") {
+            Command::If { redirects, .. } => assert_eq!(redirects.len(), 2),
+            _ => panic!("expected If"),
+        }
+        match parse("case x in x) echo hit;; esac >> log") {
+            Command::Case { redirects, .. } => assert_eq!(redirects.len(), 1),
+            _ => panic!("expected Case"),
+        }
+    }
+
+    #[test]
+    fn redirected_loop_in_pipeline() {
+        let cmd = parse("for i in a b; do echo $i; done 2>/dev/null | cat");
+        match cmd {
+            Command::Pipeline { commands } => match &commands[0] {
+                Command::For { redirects, .. } => assert_eq!(redirects.len(), 1),
+                _ => panic!("expected For"),
+            },
+            _ => panic!("expected Pipeline"),
+        }
+    }
+
     #[test]
     fn subshell() {
         let cmd = parse("( cmd1 ; cmd2 )");
@@ -1085,15 +1447,46 @@ mod tests {
             Command::List {
                 op: ListOp::Background,
                 left,
+                right,
+            } => {
+                assert!(matches!(*left, Command::Simple { .. }));
+                match *right {
+                    Command::List {
+                        op: ListOp::Background,
+                        ..
+                    } => {}
+                    _ => panic!("expected nested Background"),
+                }
+            }
+            _ => panic!("expected outer Background"),
+        }
+    }
+
+    #[test]
+    fn background_applies_to_preceding_and_or_list() {
+        // `a; b && c & d` backgrounds only `b && c`.
+        let cmd = parse("a; b && c & d");
+        let Command::List {
+            op: ListOp::Seq,
+            right,
+            ..
+        } = cmd
+        else {
+            panic!("expected Seq list, got {:?}", cmd)
+        };
+        match *right {
+            Command::List {
+                left,
+                op: ListOp::Background,
                 ..
-            } => match *left {
+            } => assert!(matches!(
+                *left,
                 Command::List {
-                    op: ListOp::Background,
+                    op: ListOp::And,
                     ..
-                } => {}
-                _ => panic!("expected nested Background"),
-            },
-            _ => panic!("expected outer Background"),
+                }
+            )),
+            other => panic!("expected Background list, got {:?}", other),
         }
     }
 
@@ -1144,4 +1537,209 @@ mod tests {
             _ => panic!("expected Simple command"),
         }
     }
+
+    #[test]
+    fn arithmetic_expansion_nested_parens() {
+        let cmd = parse("echo $(( (1+2)*3 ))");
+        match cmd {
+            Command::Simple { words, .. } => {
+                assert_eq!(words.len(), 2);
+                assert_eq!(
+                    words[1].parts,
+                    vec![WordPart::ArithmeticExpansion(" (1+2)*3 ".to_string())]
+                );
+            }
+            _ => panic!("expected Simple command"),
+        }
+    }
+
+    #[test]
+    fn arithmetic_expansion_leading_nested_parens() {
+        let cmd = parse("echo $(((1+2)*(3+4)))x");
+        match cmd {
+            Command::Simple { words, .. } => {
+                assert_eq!(
+                    words[1].parts,
+                    vec![
+                        WordPart::ArithmeticExpansion("(1+2)*(3+4)".to_string()),
+                        WordPart::Literal("x".to_string()),
+                    ]
+                );
+            }
+            _ => panic!("expected Simple command"),
+        }
+    }
+
+    #[test]
+    fn legacy_bracket_arithmetic() {
+        let cmd = parse("echo $[1+2] \"$[ (4-1)*2 ]\"");
+        match cmd {
+            Command::Simple { words, .. } => {
+                assert_eq!(words.len(), 3);
+                assert_eq!(
+                    words[1].parts,
+                    vec![WordPart::ArithmeticExpansion("1+2".to_string())]
+                );
+                assert_eq!(
+                    words[2].parts,
+                    vec![
+                        WordPart::QuotedLiteral(String::new()),
+                        WordPart::ArithmeticExpansion(" (4-1)*2 ".to_string()),
+                    ]
+                );
+            }
+            _ => panic!("expected Simple command"),
+        }
+    }
+
+    #[test]
+    fn local_assignment_with_arithmetic() {
+        let cmd = parse("local n=$(((2+3)*4)) m=$[n+1]");
+        match cmd {
+            Command::Simple { words, .. } => {
+                assert_eq!(words.len(), 3);
+                assert_eq!(
+                    words[1].parts[1],
+                    WordPart::ArithmeticExpansion("(2+3)*4".to_string())
+                );
+                assert_eq!(
+                    words[2].parts[1],
+                    WordPart::ArithmeticExpansion("n+1".to_string())
+                );
+            }
+            _ => panic!("expected Simple command"),
+        }
+    }
+
+    #[test]
+    fn unterminated_if_is_an_error() {
+        assert_eq!(
+            try_parse("if true; then echo x"),
+            Err(ParseError::UnexpectedEof)
+        );
+        assert_eq!(
+            try_parse("while true; do echo x").unwrap_err().to_string(),
+            "syntax error: unexpected end of file"
+        );
+    }
+
+    #[test]
+    fn unmatched_closer_is_an_error() {
+        assert_eq!(
+            try_parse("echo a; fi"),
+            Err(ParseError::UnexpectedToken("fi".into()))
+        );
+        assert_eq!(
+            try_parse("echo a\ndone").unwrap_err().to_string(),
+            "syntax error near unexpected token `done'"
+        );
+        assert_eq!(
+            try_parse("echo a )"),
+            Err(ParseError::UnexpectedToken(")".into()))
+        );
+        assert_eq!(
+            try_parse("if then fi"),
+            Err(ParseError::UnexpectedToken("then".into()))
+        );
+    }
+
+    #[test]
+    fn posix_mode_rejects_function_keyword() {
+        let posix = ParseOptions { posix: true };
+        assert_eq!(
+            try_parse_with("function f { echo hi; }", posix),
+            Err(ParseError::UnexpectedToken("{".into()))
+        );
+        assert_eq!(
+            try_parse_with("function f() { echo hi; }", posix),
+            Err(ParseError::UnexpectedToken("(".into()))
+        );
+        assert!(matches!(
+            try_parse_with("f() { echo hi; }", posix),
+            Ok(Command::Function { .. })
+        ));
+        // `function` is still an ordinary word elsewhere.
+        assert!(try_parse_with("echo function f", posix).is_ok());
+    }
+
+    #[test]
+    fn function_definition_forms() {
+        let braced = Command::Function {
+            name: "f".into(),
+            body: Box::new(parse("echo hi")),
+        };
+        for src in [
+            "f() { echo hi; }",
+            "function f { echo hi; }",
+            "function f() { echo hi; }",
+            "function f\n{\n echo hi\n}",
+        ] {
+            assert_eq!(parse(src), braced, "{src}");
+        }
+        let subshell = Command::Function {
+            name: "f".into(),
+            body: Box::new(parse("(echo hi)")),
+        };
+        assert_eq!(parse("f() (echo hi)"), subshell);
+        assert_eq!(parse("function f() (echo hi)"), subshell);
+        let cond = Command::Function {
+            name: "f".into(),
+            body: Box::new(parse("if true; then echo hi; fi")),
+        };
+        assert_eq!(parse("f()\nif true; then echo hi; fi"), cond);
+        assert_eq!(parse("function f() if true; then echo hi; fi"), cond);
+        assert!(try_parse("f() echo hi").is_err());
+        assert!(try_parse("echo function f").is_ok());
+    }
+
+    #[test]
+    fn reserved_words_are_keywords_only_in_command_position() {
+        for src in [
+            "echo start; echo done",
+            "echo in",
+            "echo then",
+            "grep -w if file.txt; echo rc=$?",
+            "x=1; echo ok; echo esac",
+        ] {
+            assert!(try_parse(src).is_ok(), "{src}");
+        }
+        assert_eq!(
+            parse("echo if then fi"),
+            Command::Simple {
+                words: ["echo", "if", "then", "fi"].map(Word::literal).to_vec(),
+                redirects: vec![],
+                assignments: vec![],
+            }
+        );
+        // Closers still close, even straight after another closer.
+        assert!(try_parse("for i in a; do if x; then y; fi done").is_ok());
+        assert!(try_parse("for x do echo $x; done").is_ok());
+        assert!(try_parse("case x in esac").is_ok());
+        assert!(try_parse("echo x; fi").is_err());
+    }
+
+    #[test]
+    fn unterminated_quote_is_an_error() {
+        assert_eq!(
+            try_parse("echo 'abc"),
+            Err(ParseError::UnterminatedQuote('\''))
+        );
+        assert_eq!(
+            try_parse("echo \"a `b` c"),
+            Err(ParseError::UnterminatedQuote('"'))
+        );
+        assert_eq!(
+            try_parse("echo $'it\\'s"),
+            Err(ParseError::UnterminatedQuote('\''))
+        );
+    }
+
+    #[test]
+    fn quotes_in_comments_and_heredocs_are_ignored() {
+        assert!(try_parse("echo hi # it's fine").is_ok());
+        assert!(try_parse("cat <<EOF\nit's\nEOF\necho \"done\"").is_ok());
+        assert!(try_parse("echo \"it's\" 'a\"b' \\' $'x\\'y'").is_ok());
+        assert!(try_parse("").is_ok());
+        assert!(try_parse("\n# only a comment\n").is_ok());
+    }
 }
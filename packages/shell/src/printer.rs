@@ -0,0 +1,388 @@
+//! Render a parsed `Command` back into shell source.
+//!
+//! The output re-parses to an equivalent AST, but layout is normalised
+//! (one command per line, four-space indents) and quoting around
+//! expansions is not preserved, since the AST does not record it.
+
+use crate::ast::{CaseItem, CaseTerminator, Command, ListOp, Redirect, Word, WordPart};
+use crate::token::RedirectType;
+
+/// Render a function definition the way `declare -f` prints it.
+pub fn format_function(name: &str, body: &Command) -> String {
+    let mut p = Printer::default();
+    p.function(name, body, 0);
+    p.finish()
+}
+
+/// Render a command as shell source.
+pub fn format_command(cmd: &Command) -> String {
+    let mut p = Printer::default();
+    p.command(cmd, 0);
+    p.finish()
+}
+
+/// Render a single word.
+pub fn format_word(word: &Word) -> String {
+    word.parts.iter().map(format_part).collect()
+}
+
+/// Backslash-escape characters that would otherwise end or re-quote an
+/// unquoted word. Glob characters are left alone so patterns still match.
+fn escape_literal(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_whitespace() || "'\"\\;|&<>()$`".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Wrap `s` in single quotes, escaping embedded single quotes.
+fn single_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn format_part(part: &WordPart) -> String {
+    match part {
+        WordPart::Literal(s) => escape_literal(s),
+        WordPart::QuotedLiteral(s) => single_quote(s),
+        WordPart::Variable(v) => format!("${{{v}}}"),
+        WordPart::CommandSub(c) => format!("$({c})"),
+        WordPart::ParamExpansion { var, op, default } => match op.as_str() {
+            "!" => format!("${{!{default}}}"),
+            // A negative offset needs the space to not read as `:-`.
+            ":" if default.starts_with('-') => format!("${{{var}: {default}}}"),
+            "#" if default.is_empty() => format!("${{#{var}}}"),
+            _ => format!("${{{var}{op}{default}}}"),
+        },
+        WordPart::ArithmeticExpansion(e) => format!("$(({e}))"),
+        WordPart::ProcessSub(c) => format!("<({c})"),
+        WordPart::OutputProcessSub(c) => format!(">({c})"),
+    }
+}
+
+/// Accumulates output; here-document bodies are held back until the end
+/// of the line that introduced them.
+#[derive(Default)]
+struct Printer {
+    out: String,
+    heredocs: Vec<String>,
+}
+
+impl Printer {
+    fn finish(mut self) -> String {
+        if !self.heredocs.is_empty() {
+            self.newline(0);
+            self.out.pop();
+        }
+        self.out
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        for body in std::mem::take(&mut self.heredocs) {
+            self.out.push_str(&body);
+            if !body.is_empty() && !body.ends_with('\n') {
+                self.out.push('\n');
+            }
+            self.out.push_str("EOF\n");
+        }
+        self.out.push_str(&" ".repeat(indent));
+    }
+
+    /// Print `body` on its own lines, one level deeper than `indent`.
+    fn block(&mut self, body: &Command, indent: usize) {
+        self.newline(indent + 4);
+        self.command(body, indent + 4);
+        self.newline(indent);
+    }
+
+    fn function(&mut self, name: &str, body: &Command, indent: usize) {
+        self.out.push_str(name);
+        self.out.push_str(" () ");
+        self.newline(indent);
+        self.out.push('{');
+        self.block(body, indent);
+        self.out.push('}');
+    }
+
+    fn command(&mut self, cmd: &Command, indent: usize) {
+        match cmd {
+            Command::Simple {
+                words,
+                redirects,
+                assignments,
+            } => {
+                let mut items: Vec<String> = assignments
+                    .iter()
+                    .map(|a| match a.name.strip_suffix('+') {
+                        Some(name) => format!("{name}+={}", a.value),
+                        None => format!("{}={}", a.name, a.value),
+                    })
+                    .collect();
+                items.extend(words.iter().map(format_word));
+                self.out.push_str(&items.join(" "));
+                self.redirects(redirects, !items.is_empty());
+            }
+            Command::Pipeline { commands } => {
+                for (i, c) in commands.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(" | ");
+                    }
+                    self.command(c, indent);
+                }
+            }
+            Command::List { left, op, right } => {
+                if is_empty(left) {
+                    return self.command(right, indent);
+                }
+                self.command(left, indent);
+                if is_empty(right) {
+                    if *op == ListOp::Background {
+                        self.out.push_str(" &");
+                    }
+                    return;
+                }
+                match op {
+                    ListOp::And => self.out.push_str(" && "),
+                    ListOp::Or => self.out.push_str(" || "),
+                    ListOp::Seq => self.newline(indent),
+                    ListOp::Background => {
+                        self.out.push_str(" &");
+                        self.newline(indent);
+                    }
+                }
+                self.command(right, indent);
+            }
+            Command::If {
+                condition,
+                then_body,
+                else_body,
+                redirects,
+            } => {
+                self.out.push_str("if ");
+                self.if_chain(condition, then_body, else_body.as_deref(), indent);
+                self.out.push_str("fi");
+                self.redirects(redirects, true);
+            }
+            Command::For {
+                var,
+                words,
+                body,
+                redirects,
+            } => {
+                let words: Vec<String> = words.iter().map(format_word).collect();
+                self.out
+                    .push_str(&format!("for {var} in {}; do", words.join(" ")));
+                self.block(body, indent);
+                self.out.push_str("done");
+                self.redirects(redirects, true);
+            }
+            Command::CFor {
+                init,
+                cond,
+                step,
+                body,
+                redirects,
+            } => {
+                self.out
+                    .push_str(&format!("for (({init}; {cond}; {step})); do"));
+                self.block(body, indent);
+                self.out.push_str("done");
+                self.redirects(redirects, true);
+            }
+            Command::While {
+                condition,
+                body,
+                redirects,
+            } => {
+                self.out.push_str("while ");
+                self.command(condition, indent);
+                self.out.push_str("; do");
+                self.block(body, indent);
+                self.out.push_str("done");
+                self.redirects(redirects, true);
+            }
+            Command::Subshell { body, redirects } => {
+                self.out.push_str("( ");
+                self.command(body, indent);
+                self.out.push_str(" )");
+                self.redirects(redirects, true);
+            }
+            Command::BraceGroup { body, redirects } => {
+                self.out.push('{');
+                self.block(body, indent);
+                self.out.push('}');
+                self.redirects(redirects, true);
+            }
+            Command::Break => self.out.push_str("break"),
+            Command::Continue => self.out.push_str("continue"),
+            Command::Negate { body } => {
+                self.out.push_str("! ");
+                self.command(body, indent);
+            }
+            Command::Function { name, body } => self.function(name, body, indent),
+            Command::Case {
+                word,
+                items,
+                redirects,
+            } => {
+                self.out.push_str(&format!("case {} in", format_word(word)));
+                for item in items {
+                    self.case_item(item, indent + 4);
+                }
+                self.newline(indent);
+                self.out.push_str("esac");
+                self.redirects(redirects, true);
+            }
+            Command::DoubleBracket { expr } => self.out.push_str(&format!("[[ {expr} ]]")),
+            Command::ArithmeticCommand { expr } => self.out.push_str(&format!("(( {expr} ))")),
+        }
+    }
+
+    /// Print everything after `if`/`elif` up to (not including) `fi`.
+    fn if_chain(
+        &mut self,
+        condition: &Command,
+        then_body: &Command,
+        else_body: Option<&Command>,
+        indent: usize,
+    ) {
+        self.command(condition, indent);
+        self.out.push_str("; then");
+        self.block(then_body, indent);
+        match else_body {
+            Some(Command::If {
+                condition,
+                then_body,
+                else_body,
+                redirects,
+            }) if redirects.is_empty() => {
+                self.out.push_str("elif ");
+                self.if_chain(condition, then_body, else_body.as_deref(), indent);
+            }
+            Some(body) => {
+                self.out.push_str("else");
+                self.block(body, indent);
+            }
+            None => {}
+        }
+    }
+
+    fn case_item(&mut self, item: &CaseItem, indent: usize) {
+        self.newline(indent);
+        let patterns: Vec<String> = item.patterns.iter().map(format_word).collect();
+        self.out.push_str(&patterns.join(" | "));
+        self.out.push(')');
+        if !is_empty(&item.body) {
+            self.newline(indent + 4);
+            self.command(&item.body, indent + 4);
+        }
+        self.newline(indent);
+        self.out.push_str(match item.terminator {
+            CaseTerminator::Break => ";;",
+            CaseTerminator::FallThrough => ";&",
+            CaseTerminator::Continue => ";;&",
+        });
+    }
+
+    fn redirects(&mut self, redirects: &[Redirect], space: bool) {
+        for (i, r) in redirects.iter().enumerate() {
+            if space || i > 0 {
+                self.out.push(' ');
+            }
+            let text = match &r.redirect_type {
+                RedirectType::StdoutOverwrite(f) => format!("> {f}"),
+                RedirectType::StdoutAppend(f) => format!(">> {f}"),
+                RedirectType::StdinFrom(f) => format!("< {f}"),
+                RedirectType::FdInput(fd, f) => format!("{fd}< {f}"),
+                RedirectType::StderrOverwrite(f) => format!("2> {f}"),
+                RedirectType::StderrAppend(f) => format!("2>> {f}"),
+                RedirectType::StderrToStdout => "2>&1".to_string(),
+                RedirectType::BothOverwrite(f) => format!("&> {f}"),
+                RedirectType::HereString(s) => format!("<<< \"{s}\""),
+                RedirectType::Heredoc(body) => self.heredoc("<<EOF", body),
+                RedirectType::HeredocQuoted(body) => self.heredoc("<<'EOF'", body),
+                RedirectType::HeredocStrip(body) => self.heredoc("<<-EOF", body),
+                RedirectType::HeredocStripQuoted(body) => self.heredoc("<<-'EOF'", body),
+            };
+            self.out.push_str(&text);
+        }
+    }
+
+    fn heredoc(&mut self, op: &str, body: &str) -> String {
+        self.heredocs.push(body.to_string());
+        op.to_string()
+    }
+}
+
+/// Whether `cmd` is the empty simple command the parser leaves behind for
+/// a trailing separator or an empty case arm.
+fn is_empty(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Simple { words, redirects, assignments }
+            if words.is_empty() && redirects.is_empty() && assignments.is_empty()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::try_parse;
+
+    fn parse(src: &str) -> Command {
+        try_parse(src).unwrap()
+    }
+
+    /// Print `src`, and check that printing the re-parsed output is stable.
+    fn round_trip(src: &str) -> String {
+        let printed = format_command(&parse(src));
+        assert_eq!(format_command(&parse(&printed)), printed);
+        printed
+    }
+
+    #[test]
+    fn simple_and_lists() {
+        assert_eq!(round_trip("echo hi | tr a-z A-Z"), "echo hi | tr a-z A-Z");
+        assert_eq!(round_trip("a && b || c"), "a && b || c");
+        assert_eq!(round_trip("a; b"), "a\nb");
+        round_trip("X=1 Y+=2 env > out 2>&1");
+        round_trip("echo 'it'\\''s' \"$HOME\" ${x:-d} $(date) $((1 + 2))");
+    }
+
+    #[test]
+    fn compound_commands() {
+        assert_eq!(
+            round_trip("if a; then b; elif c; then d; else e; fi"),
+            "if a; then\n    b\nelif c; then\n    d\nelse\n    e\nfi"
+        );
+        round_trip("for x in 1 2 3; do echo $x; done");
+        round_trip("for ((i=0; i<3; i++)); do echo $i; done");
+        round_trip("while read l; do echo $l; done < f");
+        round_trip("case $x in a|b) echo ab;; *) ;; esac");
+        round_trip("case $x in @(a|b)*) echo ab;& c) echo c;;& *) ;; esac");
+        round_trip("( cd /tmp; ls ) && { echo ok; }");
+        round_trip("[[ -n $x ]] && (( x > 1 ))");
+        round_trip("f() { g() { echo inner; }; g; }");
+    }
+
+    #[test]
+    fn heredoc_body_follows_its_line() {
+        let printed = round_trip("cat <<EOF\nhello\nEOF\necho end");
+        assert_eq!(printed, "cat <<EOF\nhello\nEOF\necho end");
+    }
+
+    #[test]
+    fn function_layout() {
+        let Command::Function { name, body } = parse("f() { echo hi; echo bye; }") else {
+            panic!("expected function");
+        };
+        assert_eq!(
+            format_function(&name, &body),
+            "f () \n{\n    echo hi\n    echo bye\n}"
+        );
+    }
+}
@@ -51,6 +51,10 @@ pub enum Token {
     Esac,
     /// `;;` — case item terminator.
     DoubleSemi,
+    /// `;&` — case item terminator that falls through to the next body.
+    SemiAnd,
+    /// `;;&` — case item terminator that keeps testing later patterns.
+    DoubleSemiAnd,
     /// `!` — pipeline negation.
     Bang,
     /// `{`
@@ -72,6 +76,8 @@ pub enum RedirectType {
     StdoutAppend(String),
     /// < file
     StdinFrom(String),
+    /// N< file (input on a numbered descriptor, e.g. `exec 3< file`)
+    FdInput(i32, String),
     /// 2> file
     StderrOverwrite(String),
     /// 2>> file